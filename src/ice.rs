@@ -0,0 +1,88 @@
+pub mod candidate;
+pub mod candidate_pair;
+pub mod ice_connection;
+pub mod interfaces;
+pub mod mdns;
+pub mod tcp_socket;
+
+use crate::stun::StunError;
+use crate::turn::TurnError;
+use crate::OctetsError;
+use failure::Fail;
+use openssl::error::ErrorStack;
+
+pub use ice_connection::{
+    ConsentFreshness, IceConnection, IceConnectionState, IceTransportPolicy, TurnOption,
+};
+
+pub type Result<T> = std::result::Result<T, IceError>;
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum IceError {
+    #[fail(display = "Octets manipulate failed: {:?}", error)]
+    OctetsError { error: OctetsError },
+
+    #[fail(display = "OpenSSL failed: {}", reason)]
+    CryptoError { reason: String },
+
+    #[fail(display = "ICE socket failed: {}", reason)]
+    IoError { reason: String },
+
+    #[fail(display = "STUN failed: {:?}", error)]
+    StunError { error: StunError },
+
+    #[fail(display = "TURN failed: {:?}", error)]
+    TurnError { error: TurnError },
+
+    #[fail(display = "No local candidates are gathered.")]
+    NoCandidates,
+
+    #[fail(display = "ICE connection is not connected.")]
+    NotConnected,
+
+    #[fail(display = "ICE connectivity checks timed out.")]
+    Timeout,
+
+    #[fail(display = "ICE connectivity checks failed.")]
+    Failed,
+
+    #[fail(display = "ICE connection is closed.")]
+    Closed,
+
+    #[fail(display = "mDNS message is invalid.")]
+    InvalidMdnsMessage,
+}
+
+impl From<OctetsError> for IceError {
+    fn from(error: OctetsError) -> Self {
+        IceError::OctetsError { error }
+    }
+}
+
+impl From<StunError> for IceError {
+    fn from(error: StunError) -> Self {
+        IceError::StunError { error }
+    }
+}
+
+impl From<TurnError> for IceError {
+    fn from(error: TurnError) -> Self {
+        IceError::TurnError { error }
+    }
+}
+
+impl From<ErrorStack> for IceError {
+    fn from(error: ErrorStack) -> Self {
+        IceError::CryptoError {
+            reason: error.to_string(),
+        }
+    }
+}
+
+impl From<std::io::Error> for IceError {
+    fn from(error: std::io::Error) -> Self {
+        IceError::IoError {
+            reason: error.to_string(),
+        }
+    }
+}
@@ -0,0 +1,308 @@
+// https://tools.ietf.org/html/rfc8445#section-5.1
+
+use crate::stun::message::crc32;
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum TransportType {
+    UDP,
+    TCP,
+}
+
+impl fmt::Display for TransportType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransportType::UDP => write!(f, "udp"),
+            TransportType::TCP => write!(f, "tcp"),
+        }
+    }
+}
+
+// https://tools.ietf.org/html/rfc6544#section-4.5
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum TcpType {
+    Active,
+    Passive,
+    SimultaneousOpen,
+}
+
+impl TcpType {
+    // RFC 6544 4.2のdirection-pref (host candidateの推奨値)
+    pub fn direction_preference(self) -> u16 {
+        match self {
+            TcpType::Active => 6,
+            TcpType::Passive => 4,
+            TcpType::SimultaneousOpen => 2,
+        }
+    }
+}
+
+impl fmt::Display for TcpType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TcpType::Active => write!(f, "active"),
+            TcpType::Passive => write!(f, "passive"),
+            TcpType::SimultaneousOpen => write!(f, "so"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum CandidateType {
+    Host,
+    ServerReflexive,
+    PeerReflexive,
+    Relay,
+}
+
+impl CandidateType {
+    // RFC 8445 5.1.2.2の推奨値
+    pub fn preference(self) -> u32 {
+        match self {
+            CandidateType::Host => 126,
+            CandidateType::PeerReflexive => 110,
+            CandidateType::ServerReflexive => 100,
+            CandidateType::Relay => 0,
+        }
+    }
+}
+
+impl fmt::Display for CandidateType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CandidateType::Host => write!(f, "host"),
+            CandidateType::ServerReflexive => write!(f, "srflx"),
+            CandidateType::PeerReflexive => write!(f, "prflx"),
+            CandidateType::Relay => write!(f, "relay"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Candidate {
+    pub foundation: String,
+    pub component: u16,
+    pub transport: TransportType,
+    pub priority: u32,
+    pub address: SocketAddr,
+    pub typ: CandidateType,
+    pub related_address: Option<SocketAddr>,
+    // TCP candidateだけ (RFC 6544)
+    pub tcp_type: Option<TcpType>,
+}
+
+// UDPを優先するためTCPのtype preferenceは下げる (chromeと同じくhostで90)
+const TCP_TYPE_PREFERENCE_PENALTY: u32 = 36;
+
+// priority = (2^24)*(type preference) + (2^8)*(local preference) + (256 - component ID)
+pub fn compute_priority(typ: CandidateType, local_preference: u16, component: u16) -> u32 {
+    compute_transport_priority(typ, TransportType::UDP, local_preference, component)
+}
+
+pub fn compute_transport_priority(
+    typ: CandidateType,
+    transport: TransportType,
+    local_preference: u16,
+    component: u16,
+) -> u32 {
+    let type_preference = match transport {
+        TransportType::UDP => typ.preference(),
+        TransportType::TCP => typ.preference().saturating_sub(TCP_TYPE_PREFERENCE_PENALTY),
+    };
+    (type_preference << 24) + (u32::from(local_preference) << 8) + (256 - u32::from(component))
+}
+
+// local preference = (2^13)*(direction-pref) + other-pref (RFC 6544 4.2)
+pub fn compute_tcp_local_preference(tcp_type: TcpType, other_preference: u16) -> u16 {
+    (tcp_type.direction_preference() << 13) | (other_preference & 0x1FFF)
+}
+
+// type, base address, STUN/TURN server, transportが同じならfoundationも同じ．
+// 再起動やbuildが変わっても同じ値になるようにCRC-32を使う．
+pub fn compute_foundation(
+    typ: CandidateType,
+    base: IpAddr,
+    server: Option<SocketAddr>,
+    transport: TransportType,
+) -> String {
+    let server = server.map(|server| server.to_string()).unwrap_or_default();
+    let key = format!("{} {} {} {}", typ, base, server, transport);
+    crc32(key.as_bytes()).to_string()
+}
+
+impl Candidate {
+    pub fn new(
+        typ: CandidateType,
+        component: u16,
+        transport: TransportType,
+        address: SocketAddr,
+        base: SocketAddr,
+        server: Option<SocketAddr>,
+        local_preference: u16,
+    ) -> Candidate {
+        Candidate {
+            foundation: compute_foundation(typ, base.ip(), server, transport),
+            component,
+            transport,
+            priority: compute_transport_priority(typ, transport, local_preference, component),
+            address,
+            typ,
+            related_address: if typ == CandidateType::Host {
+                None
+            } else {
+                Some(base)
+            },
+            tcp_type: None,
+        }
+    }
+
+    pub fn host(component: u16, address: SocketAddr, local_preference: u16) -> Candidate {
+        Candidate::new(
+            CandidateType::Host,
+            component,
+            TransportType::UDP,
+            address,
+            address,
+            None,
+            local_preference,
+        )
+    }
+
+    // activeはport 9 (discard) で広告する (RFC 6544 4.5)
+    pub fn tcp_host(
+        component: u16,
+        address: SocketAddr,
+        tcp_type: TcpType,
+        other_preference: u16,
+    ) -> Candidate {
+        let address = match tcp_type {
+            TcpType::Active => SocketAddr::new(address.ip(), 9),
+            _ => address,
+        };
+        let mut candidate = Candidate::new(
+            CandidateType::Host,
+            component,
+            TransportType::TCP,
+            address,
+            address,
+            None,
+            compute_tcp_local_preference(tcp_type, other_preference),
+        );
+        candidate.tcp_type = Some(tcp_type);
+        candidate
+    }
+
+    // connectivity checkで見つかったremoteのpeer reflexive candidate (RFC 8445 7.3.1.3)
+    // TCPではconnectしてきた側なのでactive
+    pub fn peer_reflexive(
+        component: u16,
+        transport: TransportType,
+        address: SocketAddr,
+        priority: u32,
+    ) -> Candidate {
+        Candidate {
+            // remoteのfoundationは分からないので適当な値で良い
+            foundation: compute_foundation(
+                CandidateType::PeerReflexive,
+                address.ip(),
+                None,
+                transport,
+            ),
+            component,
+            transport,
+            priority,
+            address,
+            typ: CandidateType::PeerReflexive,
+            related_address: None,
+            tcp_type: match transport {
+                TransportType::UDP => None,
+                TransportType::TCP => Some(TcpType::Active),
+            },
+        }
+    }
+
+    // RFC 6544 6.2: activeはpassiveと，soはsoとだけpairになる
+    // passiveのpairはconnectされた時に作る
+    pub fn can_pair_with(&self, remote: &Candidate) -> bool {
+        if self.component != remote.component
+            || self.transport != remote.transport
+            || self.address.is_ipv4() != remote.address.is_ipv4()
+        {
+            return false;
+        }
+        match self.transport {
+            TransportType::UDP => true,
+            TransportType::TCP => matches!(
+                (self.tcp_type, remote.tcp_type),
+                (Some(TcpType::Active), Some(TcpType::Passive))
+                    | (
+                        Some(TcpType::SimultaneousOpen),
+                        Some(TcpType::SimultaneousOpen)
+                    )
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn priority_test() {
+        assert_eq!(
+            compute_priority(CandidateType::Host, 65535, 1),
+            2_130_706_431
+        );
+        assert_eq!(compute_priority(CandidateType::Relay, 0, 2), 254);
+
+        let addr = "192.168.0.2:5000".parse().unwrap();
+        let host = Candidate::host(1, addr, 65535);
+        assert_eq!(host.priority, 2_130_706_431);
+        assert_eq!(host.related_address, None);
+
+        // 同じbase addressなら同じfoundation
+        let other = Candidate::host(2, "192.168.0.2:5002".parse().unwrap(), 65535);
+        assert_eq!(host.foundation, other.foundation);
+        let other = Candidate::host(1, "192.168.0.3:5000".parse().unwrap(), 65535);
+        assert_ne!(host.foundation, other.foundation);
+    }
+
+    #[test]
+    fn tcp_candidate_test() {
+        let active = Candidate::tcp_host(
+            1,
+            "192.168.99.58:50000".parse().unwrap(),
+            TcpType::Active,
+            1,
+        );
+        // chromeのtcp host candidateと同じtype preference
+        assert_eq!(active.priority >> 24, 1_518_283_007 >> 24);
+        assert_eq!((active.priority >> 8) & 0xFFFF, (6 << 13) + 1);
+        assert_eq!(active.address, "192.168.99.58:9".parse().unwrap());
+
+        let passive = Candidate::tcp_host(
+            1,
+            "192.168.99.59:50000".parse().unwrap(),
+            TcpType::Passive,
+            1,
+        );
+        assert!(passive.priority < active.priority);
+        let udp = Candidate::host(1, "192.168.99.59:50000".parse().unwrap(), 1);
+        assert!(udp.priority > active.priority);
+
+        assert!(active.can_pair_with(&passive));
+        assert!(!passive.can_pair_with(&active));
+        assert!(!active.can_pair_with(&active));
+        assert!(!udp.can_pair_with(&passive));
+
+        let so = Candidate {
+            tcp_type: Some(TcpType::SimultaneousOpen),
+            ..passive.clone()
+        };
+        assert!(so.can_pair_with(&so));
+        assert!(!so.can_pair_with(&passive));
+    }
+}
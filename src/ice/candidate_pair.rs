@@ -0,0 +1,73 @@
+// https://tools.ietf.org/html/rfc8445#section-6.1.2
+
+use crate::ice::candidate::Candidate;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CandidatePairState {
+    Frozen,
+    Waiting,
+    InProgress,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct CandidatePair {
+    pub local: Candidate,
+    pub remote: Candidate,
+    pub state: CandidatePairState,
+    // controlledがUSE-CANDIDATE付きのrequestを受け取った
+    pub nominated: bool,
+    // 送信に使うlocalのsocket
+    pub(crate) socket: usize,
+}
+
+// pair priority = 2^32*MIN(G,D) + 2*MAX(G,D) + (G>D?1:0)
+// Gはcontrolling側, Dはcontrolled側のcandidateのpriority
+pub fn pair_priority(controlling: u32, controlled: u32) -> u64 {
+    let (g, d) = (u64::from(controlling), u64::from(controlled));
+    (g.min(d) << 32) + 2 * g.max(d) + if g > d { 1 } else { 0 }
+}
+
+impl CandidatePair {
+    pub fn new(local: Candidate, remote: Candidate, socket: usize) -> CandidatePair {
+        CandidatePair {
+            local,
+            remote,
+            state: CandidatePairState::Frozen,
+            nominated: false,
+            socket,
+        }
+    }
+
+    pub fn foundation(&self) -> String {
+        format!("{}:{}", self.local.foundation, self.remote.foundation)
+    }
+
+    pub fn priority(&self, ice_controlling: bool) -> u64 {
+        if ice_controlling {
+            pair_priority(self.local.priority, self.remote.priority)
+        } else {
+            pair_priority(self.remote.priority, self.local.priority)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pair_priority_test() {
+        assert_eq!(pair_priority(1, 1), (1 << 32) + 2);
+        assert_eq!(pair_priority(2, 1), (1 << 32) + 4 + 1);
+        assert_eq!(pair_priority(1, 2), (1 << 32) + 4);
+
+        let local = Candidate::host(1, "127.0.0.1:5000".parse().unwrap(), 65535);
+        let remote = Candidate::host(1, "127.0.0.1:6000".parse().unwrap(), 65534);
+        let pair = CandidatePair::new(local, remote, 0);
+        assert_eq!(pair.state, CandidatePairState::Frozen);
+        // 役割によってGとDが入れ替わる
+        assert_eq!(pair.priority(true), pair.priority(false) + 1);
+    }
+}
@@ -1,34 +1,250 @@
-use std::net::SocketAddr;
-use std::iter;
-use rand::{Rng, thread_rng};
+// https://tools.ietf.org/html/rfc8445
+
+use crate::ice::candidate::{
+    compute_transport_priority, Candidate, CandidateType, TcpType, TransportType,
+};
+use crate::ice::candidate_pair::{CandidatePair, CandidatePairState};
+use crate::ice::interfaces;
+use crate::ice::mdns::Mdns;
+use crate::ice::tcp_socket::TcpSocket;
+use crate::ice::{IceError, Result};
+use crate::stun::message::{self, is_stun_message};
+use crate::stun::{self, Attribute, Message, MessageClass};
+use crate::turn::{TurnClient, TurnError};
+
 use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::iter;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Ta (RFC 8445 14.2)
+const CHECK_INTERVAL: Duration = Duration::from_millis(20);
+// connectivity checkの再送間隔の初期値．再送毎に倍にする
+const CHECK_RTO: Duration = Duration::from_millis(100);
+const CHECK_MAX_RETRANSMITS: u32 = 6;
+// socketを読むthreadがcloseに気付くまでの時間
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const MAX_DATAGRAM_SIZE: usize = 65535;
+// remoteの.localの名前を解決するまで待つ時間
+const MDNS_RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct TurnOption {
+    pub addr: SocketAddr,
+    pub user: String,
+    pub password: String,
+    pub is_ssl: bool,
+    pub transport: TransportType,
+}
+
+// RTCIceTransportPolicy
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IceTransportPolicy {
+    All,
+    Relay,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IceConnectionState {
+    New,
+    Checking,
+    Connected,
+    // consentの応答が続けて無い
+    Disconnected,
+    Failed,
+    Closed,
+}
+
+// consent freshness (RFC 7675) の設定
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ConsentFreshness {
+    // Binding requestを送る間隔．毎回0.8-1.2倍にrandomizeする
+    pub interval: Duration,
+    // 続けてこの回数だけ応答が無ければDisconnected
+    pub max_missed_responses: u32,
+    // 最後の応答からこれだけ経てばconsentを失ってFailed
+    pub timeout: Duration,
+}
+
+impl Default for ConsentFreshness {
+    fn default() -> ConsentFreshness {
+        ConsentFreshness {
+            interval: Duration::from_secs(5),
+            max_missed_responses: 2,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+// host candidateのUDP socket, ICE-TCPの接続とrelay candidateのTURN allocationを同じように扱う
+trait DatagramSocket: Send + Sync {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    fn close(&self) {}
+}
+
+impl DatagramSocket for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+}
 
+impl DatagramSocket for TurnClient {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        TurnClient::send_to(self, buf, addr).map_err(turn_io_error)
+    }
 
-enum TransportType {
-    UDP,
-    TCP,
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        TurnClient::recv_from(self, buf).map_err(turn_io_error)
+    }
 }
 
-struct TurnOption {
-    addr: SocketAddr,
-    user: String,
-    password: String,
-    is_ssl: bool,
-    transport: TransportType,
+impl DatagramSocket for TcpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        TcpSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        TcpSocket::recv_from(self, buf)
+    }
+
+    fn close(&self) {
+        TcpSocket::close(self)
+    }
 }
 
-struct IceConnection {
+fn turn_io_error(error: TurnError) -> io::Error {
+    let kind = match error {
+        TurnError::Timeout => io::ErrorKind::TimedOut,
+        TurnError::Closed | TurnError::NotAllocated => io::ErrorKind::NotConnected,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, error.to_string())
+}
+
+// candidateのbaseになるsocket
+struct LocalSocket {
+    socket: Arc<dyn DatagramSocket>,
+    base: Candidate,
+}
+
+struct Transaction {
+    pair: usize,
+    nominate: bool,
+    // 送った時の役割 (487を受け取った時に使う)
     ice_controlling: bool,
+    request: Vec<u8>,
+    sent_at: Instant,
+    retransmits: u32,
+}
+
+// selectされたpair毎のconsent
+struct Consent {
+    last_response: Instant,
+    pending: Option<[u8; 12]>,
+    missed: u32,
+}
+
+// server reflexive candidateを集めるためのBinding request
+struct GatherTransaction {
+    socket: usize,
+    server: SocketAddr,
+    request: Vec<u8>,
+    sent_at: Instant,
+    requests: u32,
+}
+
+// restart前の状態．offerをrollbackした時に戻す
+struct RestartSnapshot {
+    local_username: String,
+    local_password: String,
+    remote_username: Option<String>,
+    remote_password: Option<String>,
+    previous_credentials: Option<(String, String)>,
+    local_candidates: Vec<Candidate>,
+    remote_candidates: Vec<Candidate>,
+    socket_generation: usize,
+    pair_generation: usize,
+    pairs: usize,
+    gathering_complete: bool,
+    remote_end_of_candidates: bool,
+}
+
+struct AgentState {
+    state: IceConnectionState,
+    ice_controlling: bool,
+    tie_breaker: u64,
     local_username: String,
     local_password: String,
     remote_username: Option<String>,
     remote_password: Option<String>,
+    sockets: Vec<LocalSocket>,
+    local_candidates: Vec<Candidate>,
+    remote_candidates: Vec<Candidate>,
+    pairs: Vec<CandidatePair>,
+    // (pair, USE-CANDIDATEを付けるか)
+    triggered: VecDeque<(usize, bool)>,
+    transactions: HashMap<[u8; 12], Transaction>,
+    gathering: HashMap<[u8; 12], GatherTransaction>,
+    // USE-CANDIDATE付きのcheckを送っているcomponent
+    nominating: HashSet<u16>,
+    selected: HashMap<u16, usize>,
+    components: usize,
+    data: HashMap<u16, Sender<Vec<u8>>>,
+    // trickle ICE (RFC 8838) でcandidateを通知する先．Noneはend-of-candidates
+    candidate_senders: Vec<Sender<Option<Candidate>>>,
+    gathering_complete: bool,
+    // remoteがtrickleする時はend-of-candidatesまでfailedにしない
+    remote_end_of_candidates: bool,
+    state_senders: Vec<Sender<IceConnectionState>>,
+    consent_started: bool,
+    // (transaction id -> component)
+    consent_transactions: HashMap<[u8; 12], u16>,
+    consent: HashMap<u16, Consent>,
+    // ICE restartより前のsocketとpairはselectされたpairで送るためだけに残す
+    socket_generation: usize,
+    pair_generation: usize,
+    // 新しいpairがselectされるまではrestart前のcredentialも受け付ける
+    previous_credentials: Option<(String, String)>,
+    // host candidateのaddressを隠すmDNSの名前
+    hostnames: HashMap<IpAddr, String>,
+    // 名前解決中のremote candidateの数
+    resolving: usize,
+    // restartやcloseの度に増やす．古いgenerationで始めた名前解決の結果は捨てる
+    generation: u64,
+    restart_snapshot: Option<RestartSnapshot>,
+}
+
+struct Shared {
+    agent: Mutex<AgentState>,
+    changed: Condvar,
+}
+
+pub struct IceConnection {
     components: usize,
     stun_server: Option<SocketAddr>,
     turn_server: Option<TurnOption>,
     use_ipv4: bool,
     use_ipv6: bool,
-
+    include_loopback: bool,
+    use_tcp: bool,
+    transport_policy: IceTransportPolicy,
+    consent_freshness: ConsentFreshness,
+    mdns: Option<Arc<Mdns>>,
+    obfuscate_host_candidates: bool,
+    shared: Arc<Shared>,
+    receivers: Mutex<HashMap<u16, Receiver<Vec<u8>>>>,
+    readers: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl IceConnection {
@@ -41,31 +257,1837 @@ impl IceConnection {
         use_ipv6: bool,
     ) -> IceConnection {
         let mut rng = thread_rng();
-        let local_username : String = iter::repeat(())
-            .map(|()| rng.sample(Alphanumeric))
-            .take(4)
-            .collect();
-
-        let local_password : String = iter::repeat(())
-            .map(|()| rng.sample(Alphanumeric))
-            .take(22)
-            .collect();
+        let (local_username, local_password) = random_credentials();
 
         let remote_username = None;
         let remote_password = None;
 
+        let mut data = HashMap::new();
+        let mut receivers = HashMap::new();
+        for component in 1..=components as u16 {
+            let (tx, rx) = mpsc::channel();
+            data.insert(component, tx);
+            receivers.insert(component, rx);
+        }
 
-        IceConnection {
+        let agent = AgentState {
+            state: IceConnectionState::New,
             ice_controlling,
+            tie_breaker: rng.gen(),
             local_username,
             local_password,
             remote_username,
             remote_password,
+            sockets: vec![],
+            local_candidates: vec![],
+            remote_candidates: vec![],
+            pairs: vec![],
+            triggered: VecDeque::new(),
+            transactions: HashMap::new(),
+            gathering: HashMap::new(),
+            nominating: HashSet::new(),
+            selected: HashMap::new(),
+            components,
+            data,
+            candidate_senders: vec![],
+            gathering_complete: false,
+            remote_end_of_candidates: true,
+            state_senders: vec![],
+            consent_started: false,
+            consent_transactions: HashMap::new(),
+            consent: HashMap::new(),
+            socket_generation: 0,
+            pair_generation: 0,
+            previous_credentials: None,
+            hostnames: HashMap::new(),
+            resolving: 0,
+            generation: 0,
+            restart_snapshot: None,
+        };
+
+        IceConnection {
             components,
             stun_server,
             turn_server,
             use_ipv4,
             use_ipv6,
+            include_loopback: false,
+            use_tcp: true,
+            transport_policy: IceTransportPolicy::All,
+            consent_freshness: ConsentFreshness::default(),
+            mdns: None,
+            obfuscate_host_candidates: false,
+            shared: Arc::new(Shared {
+                agent: Mutex::new(agent),
+                changed: Condvar::new(),
+            }),
+            receivers: Mutex::new(receivers),
+            readers: Mutex::new(vec![]),
         }
     }
-}
\ No newline at end of file
+
+    fn agent(&self) -> MutexGuard<'_, AgentState> {
+        self.shared.agent.lock().unwrap()
+    }
+
+    // loopback addressもhost candidateにする (通常は使わない)
+    pub fn set_include_loopback(&mut self, include_loopback: bool) {
+        self.include_loopback = include_loopback;
+    }
+
+    // falseならICE-TCP (RFC 6544) のcandidateを集めない
+    pub fn set_use_tcp(&mut self, use_tcp: bool) {
+        self.use_tcp = use_tcp;
+    }
+
+    // Relayならrelay candidateだけを使う
+    pub fn set_transport_policy(&mut self, transport_policy: IceTransportPolicy) {
+        self.transport_policy = transport_policy;
+    }
+
+    pub fn transport_policy(&self) -> IceTransportPolicy {
+        self.transport_policy
+    }
+
+    pub fn set_consent_freshness(&mut self, consent_freshness: ConsentFreshness) {
+        self.consent_freshness = consent_freshness;
+    }
+
+    pub fn consent_freshness(&self) -> ConsentFreshness {
+        self.consent_freshness
+    }
+
+    // remoteの.localのcandidateをmdnsで解決する
+    // obfuscate_host_candidatesならhost candidateのaddressに名前を付けてpublishする
+    pub fn set_mdns(&mut self, mdns: Arc<Mdns>, obfuscate_host_candidates: bool) {
+        self.mdns = Some(mdns);
+        self.obfuscate_host_candidates = obfuscate_host_candidates;
+    }
+
+    pub fn mdns(&self) -> Option<Arc<Mdns>> {
+        self.mdns.clone()
+    }
+
+    // host candidateのaddressの代わりに通知する名前
+    pub fn local_hostname(&self, ip: IpAddr) -> Option<String> {
+        self.agent().hostnames.get(&ip).cloned()
+    }
+
+    pub fn stun_server(&self) -> Option<SocketAddr> {
+        self.stun_server
+    }
+
+    pub fn turn_server(&self) -> Option<&TurnOption> {
+        self.turn_server.as_ref()
+    }
+
+    pub fn state(&self) -> IceConnectionState {
+        self.agent().state
+    }
+
+    // stateが変わる度に新しいstateを受け取る
+    pub fn subscribe_state(&self) -> Receiver<IceConnectionState> {
+        let (tx, rx) = mpsc::channel();
+        self.agent().state_senders.push(tx);
+        rx
+    }
+
+    pub fn is_controlling(&self) -> bool {
+        self.agent().ice_controlling
+    }
+
+    // offer/answerで役割が決まった時に使う (RFC 8445 6.1.1)
+    pub fn set_controlling(&self, ice_controlling: bool) {
+        self.agent().switch_role(ice_controlling);
+        self.shared.changed.notify_all();
+    }
+
+    pub fn local_username(&self) -> String {
+        self.agent().local_username.clone()
+    }
+
+    pub fn local_password(&self) -> String {
+        self.agent().local_password.clone()
+    }
+
+    pub fn set_remote_credentials(&self, username: &str, password: &str) {
+        let mut agent = self.agent();
+        agent.remote_username = Some(username.to_owned());
+        agent.remote_password = Some(password.to_owned());
+        self.shared.changed.notify_all();
+    }
+
+    pub fn local_candidates(&self) -> Vec<Candidate> {
+        self.agent().local_candidates.clone()
+    }
+
+    // gatherしたlocal candidateを一つずつ受け取り，最後にNoneを受け取る
+    // 既に集めたcandidateも先に届く
+    pub fn subscribe_candidates(&self) -> Receiver<Option<Candidate>> {
+        let (tx, rx) = mpsc::channel();
+        let mut agent = self.agent();
+        for candidate in &agent.local_candidates {
+            let _ = tx.send(Some(candidate.clone()));
+        }
+        if agent.gathering_complete {
+            let _ = tx.send(None);
+        } else {
+            agent.candidate_senders.push(tx);
+        }
+        rx
+    }
+
+    pub fn is_gathering_complete(&self) -> bool {
+        self.agent().gathering_complete
+    }
+
+    pub fn remote_candidates(&self) -> Vec<Candidate> {
+        self.agent().remote_candidates.clone()
+    }
+
+    pub fn selected_pair(&self, component: u16) -> Option<CandidatePair> {
+        let agent = self.agent();
+        agent
+            .selected
+            .get(&component)
+            .map(|index| agent.pairs[*index].clone())
+    }
+
+    // 全てのinterfaceにcomponent毎のsocketをbindしてhost candidateにする
+    // stun_serverがあれば各socketからBinding requestを送ってsrflx candidateも集める
+    // turn_serverがあればcomponent毎にallocationを作ってrelay candidateにする
+    // candidateは見つかった順にsubscribe_candidatesへ通知し，終わったらNoneを通知する
+    pub fn gather_candidates(&self) -> Result<()> {
+        let result = self.gather();
+        self.agent().finish_gathering();
+        result
+    }
+
+    fn gather(&self) -> Result<()> {
+        if self.turn_server.as_ref().map_or(false, |turn| turn.is_ssl) {
+            return Err(TurnError::UnsupportedTransport.into());
+        }
+
+        let addresses = match self.transport_policy {
+            IceTransportPolicy::All => interfaces::interface_addresses()?
+                .into_iter()
+                .map(|address| address.ip)
+                .filter(|ip| (ip.is_ipv4() && self.use_ipv4) || (ip.is_ipv6() && self.use_ipv6))
+                .filter(|ip| !interfaces::is_link_local(ip))
+                .filter(|ip| self.include_loopback || !ip.is_loopback())
+                .collect::<Vec<_>>(),
+            IceTransportPolicy::Relay => vec![],
+        };
+
+        if let (Some(mdns), true) = (&self.mdns, self.obfuscate_host_candidates) {
+            let mut agent = self.agent();
+            for ip in &addresses {
+                if !agent.hostnames.contains_key(ip) {
+                    let hostname = mdns.publish(*ip);
+                    agent.hostnames.insert(*ip, hostname);
+                }
+            }
+        }
+
+        let mut hosts: Vec<(Arc<dyn DatagramSocket>, Candidate)> = vec![];
+        for component in 1..=self.components as u16 {
+            for (i, ip) in addresses.iter().enumerate() {
+                let socket = match UdpSocket::bind(SocketAddr::new(*ip, 0)) {
+                    Ok(socket) => socket,
+                    Err(_) => continue,
+                };
+                socket.set_read_timeout(Some(READ_TIMEOUT))?;
+                let local_preference = 65535u16.saturating_sub(i as u16);
+                let candidate = Candidate::host(component, socket.local_addr()?, local_preference);
+                hosts.push((Arc::new(socket), candidate));
+
+                if !self.use_tcp {
+                    continue;
+                }
+                let other_preference = 8191u16.saturating_sub(i as u16);
+                if let Ok(passive) = TcpSocket::listen(*ip) {
+                    passive.set_read_timeout(Some(READ_TIMEOUT));
+                    let candidate = Candidate::tcp_host(
+                        component,
+                        passive.local_addr(),
+                        TcpType::Passive,
+                        other_preference,
+                    );
+                    hosts.push((Arc::new(passive), candidate));
+                }
+                let active = TcpSocket::active(*ip);
+                active.set_read_timeout(Some(READ_TIMEOUT));
+                let candidate = Candidate::tcp_host(
+                    component,
+                    active.local_addr(),
+                    TcpType::Active,
+                    other_preference,
+                );
+                hosts.push((Arc::new(active), candidate));
+            }
+        }
+
+        if hosts.is_empty() && self.turn_server.is_none() {
+            return Err(IceError::NoCandidates);
+        }
+        let has_hosts = !hosts.is_empty();
+        self.add_local_sockets(hosts);
+
+        // TURNのallocationを待つ間にsrflxのBinding requestを進めておく
+        if let Some(server) = self.stun_server {
+            let mut agent = self.agent();
+            let now = Instant::now();
+            for socket in agent.socket_generation..agent.sockets.len() {
+                let base = &agent.sockets[socket].base;
+                if base.typ != CandidateType::Host || base.transport != TransportType::UDP {
+                    continue;
+                }
+                let base = base.address;
+                if base.is_ipv4() == server.is_ipv4()
+                    && base.ip().is_loopback() == server.ip().is_loopback()
+                {
+                    agent.send_gather_request(socket, server, now)?;
+                }
+            }
+        }
+
+        if let Some(turn) = &self.turn_server {
+            let mut relays: Vec<(Arc<dyn DatagramSocket>, Candidate)> = vec![];
+            for component in 1..=self.components as u16 {
+                // TURN serverに繋がらなくても，host/srflxだけでgatheringを終える
+                let client = match TurnClient::connect(
+                    turn.addr,
+                    turn.transport,
+                    &turn.user,
+                    &turn.password,
+                ) {
+                    Ok(client) => client,
+                    Err(e) => {
+                        log::warn!("skipping TURN server {}: {}", turn.addr, e);
+                        break;
+                    }
+                };
+                client.set_read_timeout(Some(READ_TIMEOUT));
+                let relayed = match client.relayed_address() {
+                    Some(relayed) => relayed,
+                    None => {
+                        log::warn!("skipping TURN server {}: no relayed address", turn.addr);
+                        break;
+                    }
+                };
+                // relay candidateのrelated addressはallocationのmapped address
+                let mapped = client.mapped_address().unwrap_or(relayed);
+                let candidate = Candidate::new(
+                    CandidateType::Relay,
+                    component,
+                    TransportType::UDP,
+                    relayed,
+                    mapped,
+                    Some(turn.addr),
+                    65535,
+                );
+                relays.push((Arc::new(client), candidate));
+            }
+            if !has_hosts && relays.is_empty() {
+                return Err(IceError::NoCandidates);
+            }
+            self.add_local_sockets(relays);
+        }
+
+        // 全てのBinding requestが成功するかtimeoutするまで待つ
+        let mut agent = self.agent();
+        while let Some(next) = agent.retransmit_gathering(Instant::now()) {
+            let wait = next.saturating_duration_since(Instant::now());
+            agent = self.shared.changed.wait_timeout(agent, wait).unwrap().0;
+        }
+
+        Ok(())
+    }
+
+    fn add_local_sockets(&self, bound: Vec<(Arc<dyn DatagramSocket>, Candidate)>) {
+        let mut agent = self.agent();
+        let mut readers = self.readers.lock().unwrap();
+        for (socket, candidate) in bound {
+            let index = agent.sockets.len();
+            agent.sockets.push(LocalSocket {
+                socket: socket.clone(),
+                base: candidate.clone(),
+            });
+            agent.add_local_candidate(candidate);
+
+            let shared = self.shared.clone();
+            readers.push(thread::spawn(move || read_loop(shared, socket, index)));
+        }
+        agent.form_pairs();
+        self.shared.changed.notify_all();
+    }
+
+    pub fn add_remote_candidate(&self, candidate: Candidate) {
+        self.agent().add_remote_candidate(candidate);
+        self.shared.changed.notify_all();
+    }
+
+    // addressが.localの名前のcandidate．名前を解決してからpairにする
+    // candidateのaddressはportだけを使う．mdnsが無ければ使えないので捨てる
+    pub fn add_remote_hostname_candidate(&self, hostname: &str, candidate: Candidate) {
+        let mdns = match &self.mdns {
+            Some(mdns) => mdns.clone(),
+            None => return,
+        };
+        let generation = {
+            let mut agent = self.agent();
+            agent.resolving += 1;
+            agent.generation
+        };
+
+        let hostname = hostname.to_owned();
+        let shared = self.shared.clone();
+        thread::spawn(move || {
+            let resolved = mdns.resolve(&hostname, MDNS_RESOLVE_TIMEOUT);
+            let mut agent = shared.agent.lock().unwrap();
+            // 解決している間にrestartかcloseされた
+            if agent.generation != generation {
+                return;
+            }
+            agent.resolving -= 1;
+            if let Ok(ip) = resolved {
+                let mut candidate = candidate;
+                candidate.address.set_ip(ip);
+                agent.add_remote_candidate(candidate);
+            }
+            shared.changed.notify_all();
+        });
+    }
+
+    // ICE restart (RFC 8445 9)．credentialを作り直してgather_candidatesからやり直す
+    // 新しいpairがselectされるまでは今のpairで送受信を続ける
+    pub fn restart(&self) {
+        let (username, password) = random_credentials();
+        self.restart_with_credentials(&username, &password);
+    }
+
+    // offerに書いたcredentialでrestartする
+    pub fn restart_with_credentials(&self, username: &str, password: &str) {
+        let mut agent = self.agent();
+        agent.restart_snapshot = Some(RestartSnapshot {
+            local_username: agent.local_username.clone(),
+            local_password: agent.local_password.clone(),
+            remote_username: agent.remote_username.clone(),
+            remote_password: agent.remote_password.clone(),
+            previous_credentials: agent.previous_credentials.clone(),
+            local_candidates: agent.local_candidates.clone(),
+            remote_candidates: agent.remote_candidates.clone(),
+            socket_generation: agent.socket_generation,
+            pair_generation: agent.pair_generation,
+            pairs: agent.pairs.len(),
+            gathering_complete: agent.gathering_complete,
+            remote_end_of_candidates: agent.remote_end_of_candidates,
+        });
+        let username = std::mem::replace(&mut agent.local_username, username.to_owned());
+        let password = std::mem::replace(&mut agent.local_password, password.to_owned());
+        agent.previous_credentials = Some((username, password));
+
+        agent.socket_generation = agent.sockets.len();
+        agent.pair_generation = agent.pairs.len();
+        agent.local_candidates.clear();
+        agent.remote_candidates.clear();
+        agent.triggered.clear();
+        agent.transactions.clear();
+        agent.gathering.clear();
+        agent.nominating.clear();
+        agent.gathering_complete = false;
+        agent.resolving = 0;
+        agent.generation += 1;
+        self.shared.changed.notify_all();
+    }
+
+    // 最後のrestartを取り消してrestart前のcredentialとcandidateに戻す
+    // restart後にできたpairは捨てる．socketは閉じずに残すが，そのcandidateはもう通知しない
+    pub fn rollback_restart(&self) {
+        let mut agent = self.agent();
+        let snapshot = match agent.restart_snapshot.take() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        agent.local_username = snapshot.local_username;
+        agent.local_password = snapshot.local_password;
+        agent.remote_username = snapshot.remote_username;
+        agent.remote_password = snapshot.remote_password;
+        agent.previous_credentials = snapshot.previous_credentials;
+        agent.local_candidates = snapshot.local_candidates;
+        agent.remote_candidates = snapshot.remote_candidates;
+        agent.socket_generation = snapshot.socket_generation;
+        agent.pair_generation = snapshot.pair_generation;
+        agent.gathering_complete = snapshot.gathering_complete;
+        agent.remote_end_of_candidates = snapshot.remote_end_of_candidates;
+
+        let pairs = snapshot.pairs;
+        agent.pairs.truncate(pairs);
+        agent.selected.retain(|_, selected| *selected < pairs);
+        let selected = agent.selected.keys().copied().collect::<HashSet<_>>();
+        agent
+            .consent
+            .retain(|component, _| selected.contains(component));
+        agent.triggered.retain(|(pair, _)| *pair < pairs);
+        agent
+            .transactions
+            .retain(|_, transaction| transaction.pair < pairs);
+        agent.gathering.clear();
+        agent.nominating.clear();
+        agent.candidate_senders.clear();
+        agent.resolving = 0;
+        agent.generation += 1;
+        self.shared.changed.notify_all();
+    }
+
+    // 最初のofferをrollbackした時にremoteのcredentialとcandidateを捨てる
+    pub fn reset_remote(&self) {
+        let mut agent = self.agent();
+        agent.remote_username = None;
+        agent.remote_password = None;
+        agent.remote_candidates.clear();
+        let pairs = agent.pair_generation;
+        agent.pairs.truncate(pairs);
+        agent.selected.retain(|_, selected| *selected < pairs);
+        let selected = agent.selected.keys().copied().collect::<HashSet<_>>();
+        agent
+            .consent
+            .retain(|component, _| selected.contains(component));
+        agent.triggered.clear();
+        agent.transactions.clear();
+        agent.nominating.clear();
+        agent.resolving = 0;
+        agent.generation += 1;
+        self.shared.changed.notify_all();
+    }
+
+    // remoteがまだtrickleしている間 (false) は全てのpairが失敗してもfailedにしない
+    pub fn set_remote_end_of_candidates(&self, end_of_candidates: bool) {
+        self.agent().remote_end_of_candidates = end_of_candidates;
+        self.shared.changed.notify_all();
+    }
+
+    // 全てのcomponentでpairがnominateされるまでconnectivity checkを行う
+    // 繋がった後はconsent freshnessのthreadが接続を見張る
+    pub fn connect(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut agent = self.agent();
+        if agent.sockets.is_empty() {
+            return Err(IceError::NoCandidates);
+        }
+        if agent.state == IceConnectionState::New {
+            agent.set_state(IceConnectionState::Checking);
+        }
+
+        let mut next_check = Instant::now();
+        loop {
+            match agent.state {
+                IceConnectionState::Failed => return Err(IceError::Failed),
+                IceConnectionState::Closed => return Err(IceError::Closed),
+                _ => {}
+            }
+            if agent.is_connected() {
+                if !agent.consent_started {
+                    agent.consent_started = true;
+                    let shared = self.shared.clone();
+                    let consent_freshness = self.consent_freshness;
+                    self.readers.lock().unwrap().push(thread::spawn(move || {
+                        consent_loop(shared, consent_freshness)
+                    }));
+                }
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            agent.retransmit(now);
+
+            if now >= next_check {
+                agent.next_check(now)?;
+                next_check = now + CHECK_INTERVAL;
+            }
+
+            if agent.is_failed() {
+                agent.set_state(IceConnectionState::Failed);
+                continue;
+            }
+
+            if now >= deadline {
+                return Err(IceError::Timeout);
+            }
+
+            let wait = (next_check.min(deadline)).saturating_duration_since(now);
+            agent = self.shared.changed.wait_timeout(agent, wait).unwrap().0;
+        }
+    }
+
+    // component 1 (RTP/RTCP mux) の選択されたpairで送る
+    pub fn send(&self, data: &[u8]) -> Result<usize> {
+        self.send_component(1, data)
+    }
+
+    pub fn send_component(&self, component: u16, data: &[u8]) -> Result<usize> {
+        let (socket, addr) = {
+            let agent = self.agent();
+            match agent.state {
+                IceConnectionState::Closed => return Err(IceError::Closed),
+                // consentを失ったら送ってはいけない (RFC 7675 5.1)
+                IceConnectionState::Failed => return Err(IceError::Failed),
+                _ => {}
+            }
+            let pair = agent
+                .selected
+                .get(&component)
+                .map(|index| &agent.pairs[*index])
+                .ok_or(IceError::NotConnected)?;
+            (
+                agent.sockets[pair.socket].socket.clone(),
+                pair.remote.address,
+            )
+        };
+        Ok(socket.send_to(data, addr)?)
+    }
+
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        self.recv_component(1, buf, None)
+    }
+
+    pub fn recv_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.recv_component(1, buf, Some(timeout))
+    }
+
+    pub fn recv_component(
+        &self,
+        component: u16,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> Result<usize> {
+        let receivers = self.receivers.lock().unwrap();
+        let receiver = receivers.get(&component).ok_or(IceError::NotConnected)?;
+        let data = match timeout {
+            Some(timeout) => receiver.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => IceError::Timeout,
+                RecvTimeoutError::Disconnected => IceError::Closed,
+            })?,
+            None => receiver.recv().map_err(|_| IceError::Closed)?,
+        };
+
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    pub fn close(&self) {
+        {
+            let mut agent = self.agent();
+            agent.set_state(IceConnectionState::Closed);
+            agent.data.clear();
+            agent.resolving = 0;
+            agent.generation += 1;
+            for socket in &agent.sockets {
+                socket.socket.close();
+            }
+            self.shared.changed.notify_all();
+        }
+        let readers = self.readers.lock().unwrap().drain(..).collect::<Vec<_>>();
+        for reader in readers {
+            let _ = reader.join();
+        }
+    }
+}
+
+impl Drop for IceConnection {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl AgentState {
+    // local (host) とremoteのcandidateでpairを作る
+    fn form_pairs(&mut self) {
+        for socket in self.socket_generation..self.sockets.len() {
+            let base = &self.sockets[socket].base;
+            for remote in &self.remote_candidates {
+                if !base.can_pair_with(remote) {
+                    continue;
+                }
+                if self.find_pair(socket, remote.address).is_some() {
+                    continue;
+                }
+                self.pairs
+                    .push(CandidatePair::new(base.clone(), remote.clone(), socket));
+            }
+        }
+    }
+
+    // 今のgenerationのpairだけから探す
+    fn find_pair(&self, socket: usize, remote: SocketAddr) -> Option<usize> {
+        self.pairs[self.pair_generation..]
+            .iter()
+            .position(|pair| pair.socket == socket && pair.remote.address == remote)
+            .map(|index| self.pair_generation + index)
+    }
+
+    // triggered checkを優先し，無ければWaiting, Frozenの順で一番priorityの高いpair
+    fn next_check(&mut self, now: Instant) -> Result<()> {
+        if self.remote_username.is_none() {
+            return Ok(());
+        }
+
+        let next = match self.triggered.pop_front() {
+            Some(next) => Some(next),
+            None => [CandidatePairState::Waiting, CandidatePairState::Frozen]
+                .iter()
+                .filter_map(|state| {
+                    (self.pair_generation..self.pairs.len())
+                        .filter(|index| self.pairs[*index].state == *state)
+                        .max_by_key(|index| self.pairs[*index].priority(self.ice_controlling))
+                })
+                .next()
+                .map(|index| (index, false)),
+        };
+
+        if let Some((pair, nominate)) = next {
+            self.send_check(pair, nominate, now)?;
+        }
+        Ok(())
+    }
+
+    fn send_check(&mut self, index: usize, nominate: bool, now: Instant) -> Result<()> {
+        let (transaction_id, request) = match self.binding_request(index, nominate)? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+
+        let pair = &self.pairs[index];
+        let _ = self.sockets[pair.socket]
+            .socket
+            .send_to(&request, pair.remote.address);
+
+        if !nominate {
+            self.pairs[index].state = CandidatePairState::InProgress;
+        }
+        self.transactions.insert(
+            transaction_id,
+            Transaction {
+                pair: index,
+                nominate,
+                ice_controlling: self.ice_controlling,
+                request,
+                sent_at: now,
+                retransmits: 0,
+            },
+        );
+        Ok(())
+    }
+
+    // connectivity checkとconsentのBinding request．remoteのcredentialが無ければNone
+    fn binding_request(&self, index: usize, nominate: bool) -> Result<Option<([u8; 12], Vec<u8>)>> {
+        let (remote_username, remote_password) =
+            match (&self.remote_username, &self.remote_password) {
+                (Some(username), Some(password)) => (username, password),
+                _ => return Ok(None),
+            };
+
+        let pair = &self.pairs[index];
+        // 自分がpeer reflexiveになった時のpriority (RFC 8445 7.1.1)
+        let local_preference = ((pair.local.priority >> 8) & 0xFFFF) as u16;
+        let priority = compute_transport_priority(
+            CandidateType::PeerReflexive,
+            pair.local.transport,
+            local_preference,
+            pair.local.component,
+        );
+
+        let transaction_id = Message::random_transaction_id();
+        let mut request = Message::new(
+            MessageClass::Request,
+            message::METHOD_BINDING,
+            transaction_id,
+        );
+        request.attributes.push(Attribute::Username(format!(
+            "{}:{}",
+            remote_username, self.local_username
+        )));
+        request.attributes.push(Attribute::Priority(priority));
+        if self.ice_controlling {
+            request
+                .attributes
+                .push(Attribute::IceControlling(self.tie_breaker));
+        } else {
+            request
+                .attributes
+                .push(Attribute::IceControlled(self.tie_breaker));
+        }
+        if nominate {
+            request.attributes.push(Attribute::UseCandidate);
+        }
+        let request = request.encode(Some(remote_password.as_bytes()))?;
+        Ok(Some((transaction_id, request)))
+    }
+
+    fn set_state(&mut self, state: IceConnectionState) {
+        if self.state != state {
+            self.state = state;
+            self.state_senders
+                .retain(|sender| sender.send(state).is_ok());
+        }
+    }
+
+    // selectされた全てのpairにconsentのBinding requestを送る (RFC 7675 5.1)
+    fn send_consent_checks(&mut self) {
+        let selected = self
+            .selected
+            .iter()
+            .map(|(component, index)| (*component, *index))
+            .collect::<Vec<_>>();
+        for (component, index) in selected {
+            let (transaction_id, request) = match self.binding_request(index, false) {
+                Ok(Some(request)) => request,
+                _ => continue,
+            };
+            let pair = &self.pairs[index];
+            let _ = self.sockets[pair.socket]
+                .socket
+                .send_to(&request, pair.remote.address);
+
+            let consent = self.consent.entry(component).or_insert_with(|| Consent {
+                last_response: Instant::now(),
+                pending: None,
+                missed: 0,
+            });
+            // 前のrequestの応答がまだ無い
+            if consent.pending.is_some() {
+                consent.missed += 1;
+            }
+            consent.pending = Some(transaction_id);
+            self.consent_transactions.insert(transaction_id, component);
+        }
+    }
+
+    // consentを失ったcomponentがあればFailed，応答が続けて無ければDisconnected
+    fn check_consent(&mut self, consent_freshness: &ConsentFreshness, now: Instant) {
+        if self
+            .consent
+            .values()
+            .any(|consent| now >= consent.last_response + consent_freshness.timeout)
+        {
+            self.set_state(IceConnectionState::Failed);
+            return;
+        }
+        let missed = self
+            .consent
+            .values()
+            .any(|consent| consent.missed >= consent_freshness.max_missed_responses);
+        match self.state {
+            IceConnectionState::Connected if missed => {
+                self.set_state(IceConnectionState::Disconnected)
+            }
+            IceConnectionState::Disconnected if !missed => {
+                self.set_state(IceConnectionState::Connected)
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_consent_response(&mut self, socket: usize, from: SocketAddr, response: &Message) {
+        let component = match self.consent_transactions.remove(&response.transaction_id) {
+            Some(component) => component,
+            None => return,
+        };
+        let symmetric = self
+            .selected
+            .get(&component)
+            .map(|index| &self.pairs[*index])
+            .map_or(false, |pair| {
+                pair.socket == socket && pair.remote.address == from
+            });
+        if response.class != MessageClass::SuccessResponse || !symmetric {
+            return;
+        }
+        if let Some(consent) = self.consent.get_mut(&component) {
+            consent.last_response = Instant::now();
+            consent.missed = 0;
+            if consent.pending == Some(response.transaction_id) {
+                consent.pending = None;
+            }
+        }
+    }
+
+    // 応答の無いcheckを再送し，再送回数を超えたらpairをFailedにする
+    fn retransmit(&mut self, now: Instant) {
+        let mut expired = vec![];
+        for (transaction_id, transaction) in self.transactions.iter_mut() {
+            if now < transaction.sent_at + CHECK_RTO * 2u32.pow(transaction.retransmits) {
+                continue;
+            }
+            if transaction.retransmits >= CHECK_MAX_RETRANSMITS {
+                expired.push(*transaction_id);
+                continue;
+            }
+            let pair = &self.pairs[transaction.pair];
+            let _ = self.sockets[pair.socket]
+                .socket
+                .send_to(&transaction.request, pair.remote.address);
+            transaction.sent_at = now;
+            transaction.retransmits += 1;
+        }
+
+        for transaction_id in expired {
+            if let Some(transaction) = self.transactions.remove(&transaction_id) {
+                let pair = &mut self.pairs[transaction.pair];
+                pair.state = CandidatePairState::Failed;
+                if transaction.nominate {
+                    let component = pair.local.component;
+                    self.nominating.remove(&component);
+                }
+            }
+        }
+    }
+
+    fn send_gather_request(
+        &mut self,
+        socket: usize,
+        server: SocketAddr,
+        now: Instant,
+    ) -> Result<()> {
+        let transaction_id = Message::random_transaction_id();
+        let request = Message::new(
+            MessageClass::Request,
+            message::METHOD_BINDING,
+            transaction_id,
+        )
+        .encode_with(None, &[], true)?;
+
+        let _ = self.sockets[socket].socket.send_to(&request, server);
+        self.gathering.insert(
+            transaction_id,
+            GatherTransaction {
+                socket,
+                server,
+                request,
+                sent_at: now,
+                requests: 1,
+            },
+        );
+        Ok(())
+    }
+
+    // RTOを倍にしながらRc回まで送り，最後はRm*RTO待つ．次に確認すべき時刻を返す
+    fn retransmit_gathering(&mut self, now: Instant) -> Option<Instant> {
+        let mut expired = vec![];
+        let mut next: Option<Instant> = None;
+        for (transaction_id, transaction) in self.gathering.iter_mut() {
+            let mut deadline =
+                transaction.sent_at + stun::retransmission_timeout(transaction.requests);
+
+            if now >= deadline {
+                if transaction.requests >= stun::RC {
+                    expired.push(*transaction_id);
+                    continue;
+                }
+                let _ = self.sockets[transaction.socket]
+                    .socket
+                    .send_to(&transaction.request, transaction.server);
+                transaction.sent_at = now;
+                transaction.requests += 1;
+                deadline = now + stun::retransmission_timeout(transaction.requests);
+            }
+            next = Some(next.map_or(deadline, |next| next.min(deadline)));
+        }
+
+        for transaction_id in expired {
+            self.gathering.remove(&transaction_id);
+        }
+        next
+    }
+
+    fn handle_gather_response(&mut self, transaction: GatherTransaction, response: &Message) {
+        if response.class != MessageClass::SuccessResponse {
+            return;
+        }
+        let mapped = match response
+            .xor_mapped_address()
+            .or_else(|| response.mapped_address())
+        {
+            Some(mapped) => mapped,
+            None => return,
+        };
+
+        let base = &self.sockets[transaction.socket].base;
+        // hostと同じaddressなら冗長なので捨てる
+        if mapped == base.address
+            || self.local_candidates.iter().any(|candidate| {
+                candidate.address == mapped && candidate.component == base.component
+            })
+        {
+            return;
+        }
+
+        let local_preference = ((base.priority >> 8) & 0xFFFF) as u16;
+        let candidate = Candidate::new(
+            CandidateType::ServerReflexive,
+            base.component,
+            base.transport,
+            mapped,
+            base.address,
+            Some(transaction.server),
+            local_preference,
+        );
+        self.add_local_candidate(candidate);
+    }
+
+    fn add_local_candidate(&mut self, candidate: Candidate) {
+        self.candidate_senders
+            .retain(|sender| sender.send(Some(candidate.clone())).is_ok());
+        self.local_candidates.push(candidate);
+    }
+
+    fn add_remote_candidate(&mut self, candidate: Candidate) {
+        if !self.remote_candidates.contains(&candidate) {
+            self.remote_candidates.push(candidate);
+            self.form_pairs();
+        }
+    }
+
+    fn finish_gathering(&mut self) {
+        self.gathering_complete = true;
+        for sender in self.candidate_senders.drain(..) {
+            let _ = sender.send(None);
+        }
+    }
+
+    fn is_failed(&self) -> bool {
+        let pairs = &self.pairs[self.pair_generation..];
+        self.remote_end_of_candidates
+            && self.resolving == 0
+            && !pairs.is_empty()
+            && self.transactions.is_empty()
+            && self.triggered.is_empty()
+            && pairs
+                .iter()
+                .all(|pair| pair.state == CandidatePairState::Failed)
+    }
+
+    // 全てのcomponentで今のgenerationのpairがselectされている
+    fn is_connected(&self) -> bool {
+        self.selected.len() == self.components
+            && self
+                .selected
+                .values()
+                .all(|index| *index >= self.pair_generation)
+    }
+
+    // restart前のpairは新しいpairで置き換える
+    fn select(&mut self, component: u16, index: usize) {
+        self.pairs[index].nominated = true;
+        let replace = self
+            .selected
+            .get(&component)
+            .map_or(true, |selected| *selected < self.pair_generation);
+        if replace {
+            self.selected.insert(component, index);
+            self.consent.remove(&component);
+        }
+        self.nominating.remove(&component);
+        if self.is_connected() {
+            self.previous_credentials = None;
+            self.set_state(IceConnectionState::Connected);
+        }
+    }
+
+    fn switch_role(&mut self, ice_controlling: bool) {
+        if self.ice_controlling != ice_controlling {
+            self.ice_controlling = ice_controlling;
+            self.nominating.clear();
+        }
+    }
+
+    fn respond(&self, socket: usize, to: SocketAddr, response: Message, password: Option<&str>) {
+        if let Ok(bytes) = response.encode(password.map(str::as_bytes)) {
+            let _ = self.sockets[socket].socket.send_to(&bytes, to);
+        }
+    }
+
+    // RFC 8445 7.3
+    fn handle_request(&mut self, socket: usize, from: SocketAddr, request: Message, raw: &[u8]) {
+        if request.method != message::METHOD_BINDING {
+            return;
+        }
+
+        // restart前のcredentialなら前のpasswordで確認して応答する
+        let username = request
+            .username()
+            .and_then(|username| username.split(':').next())
+            .map(str::to_owned);
+        let password = match (&username, &self.previous_credentials) {
+            (Some(username), _) if *username == self.local_username => {
+                Some(self.local_password.clone())
+            }
+            (Some(username), Some((previous_username, previous_password)))
+                if username == previous_username =>
+            {
+                Some(previous_password.clone())
+            }
+            _ => None,
+        };
+        let password = match password {
+            Some(password) if request.check_integrity(raw, password.as_bytes()) => password,
+            _ => {
+                let response = request.error_response(message::ERROR_UNAUTHORIZED, "Unauthorized");
+                self.respond(socket, from, response, None);
+                return;
+            }
+        };
+
+        // role conflict (RFC 8445 7.3.1.1)
+        if self.ice_controlling {
+            if let Some(tie_breaker) = request.ice_controlling() {
+                if self.tie_breaker >= tie_breaker {
+                    let response =
+                        request.error_response(message::ERROR_ROLE_CONFLICT, "Role Conflict");
+                    self.respond(socket, from, response, Some(&password));
+                    return;
+                }
+                self.switch_role(false);
+            }
+        } else if let Some(tie_breaker) = request.ice_controlled() {
+            if self.tie_breaker >= tie_breaker {
+                self.switch_role(true);
+            } else {
+                let response =
+                    request.error_response(message::ERROR_ROLE_CONFLICT, "Role Conflict");
+                self.respond(socket, from, response, Some(&password));
+                return;
+            }
+        }
+
+        let mut response = request.response(MessageClass::SuccessResponse);
+        response.attributes.push(Attribute::XorMappedAddress(from));
+        self.respond(socket, from, response, Some(&password));
+
+        // restart前のsocketにはconsentだけが届く
+        if socket < self.socket_generation {
+            return;
+        }
+
+        // 知らないaddressからのrequestはpeer reflexive candidate (RFC 8445 7.3.1.3)
+        let base = self.sockets[socket].base.clone();
+        let component = base.component;
+        let known = self.remote_candidates.iter().position(|candidate| {
+            candidate.address == from
+                && candidate.component == component
+                && candidate.transport == base.transport
+        });
+        let remote = match known {
+            Some(remote) => remote,
+            None => {
+                let priority = request.priority().unwrap_or(0);
+                self.remote_candidates.push(Candidate::peer_reflexive(
+                    component,
+                    base.transport,
+                    from,
+                    priority,
+                ));
+                self.form_pairs();
+                self.remote_candidates.len() - 1
+            }
+        };
+
+        // passiveのpairはconnectしてきたremoteとの間にだけ作る (RFC 6544 6.2)
+        if base.transport == TransportType::TCP && self.find_pair(socket, from).is_none() {
+            let remote = self.remote_candidates[remote].clone();
+            self.pairs.push(CandidatePair::new(base, remote, socket));
+        }
+
+        let index = match self.find_pair(socket, from) {
+            Some(index) => index,
+            None => return,
+        };
+
+        if request.use_candidate() && !self.ice_controlling {
+            self.pairs[index].nominated = true;
+            if self.pairs[index].state == CandidatePairState::Succeeded {
+                self.select(component, index);
+            }
+        }
+
+        // triggered check (RFC 8445 7.3.1.4)
+        match self.pairs[index].state {
+            CandidatePairState::Succeeded | CandidatePairState::InProgress => {}
+            _ => {
+                self.pairs[index].state = CandidatePairState::Waiting;
+                if !self.triggered.contains(&(index, false)) {
+                    self.triggered.push_back((index, false));
+                }
+            }
+        }
+    }
+
+    // RFC 8445 7.2.5
+    fn handle_response(&mut self, socket: usize, from: SocketAddr, response: Message, raw: &[u8]) {
+        let gathering = self
+            .gathering
+            .get(&response.transaction_id)
+            .map(|transaction| transaction.socket == socket && transaction.server == from);
+        if let Some(valid) = gathering {
+            if valid {
+                if let Some(transaction) = self.gathering.remove(&response.transaction_id) {
+                    self.handle_gather_response(transaction, &response);
+                }
+            }
+            return;
+        }
+
+        let known = self.transactions.contains_key(&response.transaction_id)
+            || self
+                .consent_transactions
+                .contains_key(&response.transaction_id);
+        let valid = match &self.remote_password {
+            Some(password) if known => response.check_integrity(raw, password.as_bytes()),
+            _ => false,
+        };
+        if !valid {
+            return;
+        }
+        if self
+            .consent_transactions
+            .contains_key(&response.transaction_id)
+        {
+            self.handle_consent_response(socket, from, &response);
+            return;
+        }
+        let transaction = match self.transactions.remove(&response.transaction_id) {
+            Some(transaction) => transaction,
+            None => return,
+        };
+        let index = transaction.pair;
+        let component = self.pairs[index].local.component;
+
+        if response.class == MessageClass::ErrorResponse {
+            if transaction.nominate {
+                self.nominating.remove(&component);
+            }
+            if response.error_code() == Some(message::ERROR_ROLE_CONFLICT) {
+                // 送った時と逆の役割にしてやり直す
+                self.switch_role(!transaction.ice_controlling);
+                self.pairs[index].state = CandidatePairState::Waiting;
+                self.triggered.push_front((index, false));
+            } else {
+                self.pairs[index].state = CandidatePairState::Failed;
+            }
+            return;
+        }
+
+        // 送ったaddressとsocketが対称でなければ失敗 (RFC 8445 7.2.5.2.1)
+        let pair = &self.pairs[index];
+        if pair.socket != socket || pair.remote.address != from {
+            self.pairs[index].state = CandidatePairState::Failed;
+            return;
+        }
+
+        // mapped addressが知らないものならlocalのpeer reflexive candidate (RFC 8445 7.2.5.3.1)
+        if let Some(mapped) = response.xor_mapped_address() {
+            if !self
+                .local_candidates
+                .iter()
+                .any(|candidate| candidate.address == mapped)
+            {
+                let base = &self.pairs[index].local;
+                let local_preference = ((base.priority >> 8) & 0xFFFF) as u16;
+                let candidate = Candidate::new(
+                    CandidateType::PeerReflexive,
+                    component,
+                    base.transport,
+                    mapped,
+                    base.address,
+                    None,
+                    local_preference,
+                );
+                self.local_candidates.push(candidate);
+            }
+        }
+
+        self.pairs[index].state = CandidatePairState::Succeeded;
+        let foundation = self.pairs[index].foundation();
+        for pair in self.pairs.iter_mut() {
+            if pair.state == CandidatePairState::Frozen && pair.foundation() == foundation {
+                pair.state = CandidatePairState::Waiting;
+            }
+        }
+
+        if self
+            .selected
+            .get(&component)
+            .map_or(false, |selected| *selected >= self.pair_generation)
+        {
+            return;
+        }
+
+        if self.ice_controlling {
+            if transaction.nominate {
+                self.select(component, index);
+            } else if !self.nominating.contains(&component) {
+                // 最初に成功したpairをnominateする
+                self.nominating.insert(component);
+                self.triggered.push_front((index, true));
+            }
+        } else if self.pairs[index].nominated {
+            self.select(component, index);
+        }
+    }
+
+    fn handle_data(&self, socket: usize, data: &[u8]) {
+        let component = self.sockets[socket].base.component;
+        if let Some(sender) = self.data.get(&component) {
+            let _ = sender.send(data.to_vec());
+        }
+    }
+}
+
+// ufragは4文字，pwdは22文字 (RFC 8445 5.3)
+pub(crate) fn random_credentials() -> (String, String) {
+    let mut rng = thread_rng();
+    let username = iter::repeat(())
+        .map(|()| rng.sample(Alphanumeric))
+        .take(4)
+        .collect();
+    let password = iter::repeat(())
+        .map(|()| rng.sample(Alphanumeric))
+        .take(22)
+        .collect();
+    (username, password)
+}
+
+// 繋がってからcloseかconsentを失うまで動く
+fn consent_loop(shared: Arc<Shared>, consent_freshness: ConsentFreshness) {
+    let mut rng = thread_rng();
+    let mut next_check = Instant::now();
+    let mut agent = shared.agent.lock().unwrap();
+    loop {
+        match agent.state {
+            IceConnectionState::Failed | IceConnectionState::Closed => return,
+            _ => {}
+        }
+
+        let now = Instant::now();
+        if now >= next_check {
+            agent.send_consent_checks();
+            next_check = now + consent_freshness.interval.mul_f64(rng.gen_range(0.8, 1.2));
+        }
+        agent.check_consent(&consent_freshness, now);
+
+        let expiry = agent
+            .consent
+            .values()
+            .map(|consent| consent.last_response + consent_freshness.timeout)
+            .min()
+            .unwrap_or(next_check);
+        let wait = next_check.min(expiry).saturating_duration_since(now);
+        agent = shared.changed.wait_timeout(agent, wait).unwrap().0;
+    }
+}
+
+fn read_loop(shared: Arc<Shared>, socket: Arc<dyn DatagramSocket>, index: usize) {
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(ref e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                if shared.agent.lock().unwrap().state == IceConnectionState::Closed {
+                    return;
+                }
+                continue;
+            }
+            // TURNのallocationが失われた
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotConnected => return,
+            Err(_) => {
+                if shared.agent.lock().unwrap().state == IceConnectionState::Closed {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        let mut agent = shared.agent.lock().unwrap();
+        if agent.state == IceConnectionState::Closed {
+            return;
+        }
+
+        let packet = &buf[..len];
+        if is_stun_message(packet) {
+            let message = match Message::decode(packet) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            match message.class {
+                MessageClass::Request => agent.handle_request(index, from, message, packet),
+                MessageClass::SuccessResponse | MessageClass::ErrorResponse => {
+                    agent.handle_response(index, from, message, packet)
+                }
+                MessageClass::Indication => {}
+            }
+            shared.changed.notify_all();
+        } else {
+            agent.handle_data(index, packet);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ice::mdns::MDNS_GROUP;
+    use crate::turn::{ServerConfig, StaticAuthHandler, StaticRelayAllocator, TurnServer};
+
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    fn loopback_connection(ice_controlling: bool) -> IceConnection {
+        let mut connection = IceConnection::new(ice_controlling, 1, None, None, true, false);
+        connection.set_include_loopback(true);
+        connection.gather_candidates().unwrap();
+        connection
+    }
+
+    fn exchange(a: &IceConnection, b: &IceConnection) {
+        a.set_remote_credentials(&b.local_username(), &b.local_password());
+        b.set_remote_credentials(&a.local_username(), &a.local_password());
+        for candidate in b.local_candidates() {
+            a.add_remote_candidate(candidate);
+        }
+        for candidate in a.local_candidates() {
+            b.add_remote_candidate(candidate);
+        }
+    }
+
+    fn connect(a: IceConnection, b: IceConnection) -> (IceConnection, IceConnection) {
+        exchange(&a, &b);
+        let handle = thread::spawn(move || {
+            b.connect(Duration::from_secs(10)).unwrap();
+            b
+        });
+        a.connect(Duration::from_secs(10)).unwrap();
+        (a, handle.join().unwrap())
+    }
+
+    #[test]
+    fn gather_candidates_test() {
+        let connection = loopback_connection(true);
+        let candidates = connection.local_candidates();
+        assert!(candidates
+            .iter()
+            .any(|candidate| candidate.address.ip().is_loopback()));
+        assert!(candidates
+            .iter()
+            .all(|candidate| candidate.address.is_ipv4()
+                && candidate.typ == CandidateType::Host
+                && candidate.component == 1));
+    }
+
+    #[test]
+    fn trickle_test() {
+        let mut connection = IceConnection::new(true, 1, None, None, true, false);
+        connection.set_include_loopback(true);
+        let candidates = connection.subscribe_candidates();
+
+        let connection = Arc::new(connection);
+        let gatherer = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.gather_candidates())
+        };
+        let mut trickled = vec![];
+        while let Some(candidate) = candidates.recv_timeout(Duration::from_secs(5)).unwrap() {
+            trickled.push(candidate);
+        }
+        gatherer.join().unwrap().unwrap();
+        assert!(!trickled.is_empty());
+        assert_eq!(trickled, connection.local_candidates());
+        assert!(connection.is_gathering_complete());
+
+        // gatherが終わってからsubscribeしても全て届く
+        let late = connection.subscribe_candidates().iter().collect::<Vec<_>>();
+        assert_eq!(late.len(), trickled.len() + 1);
+        assert_eq!(late.last(), Some(&None));
+    }
+
+    // NATの外側から見えるaddressを返すSTUN server．最初のrequestは捨てる
+    fn nat_stun_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            let mut dropped = false;
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                let request = Message::decode(&buf[..len]).unwrap();
+                assert_eq!(request.class, MessageClass::Request);
+                if !dropped {
+                    dropped = true;
+                    continue;
+                }
+                let mapped = SocketAddr::new("203.0.113.1".parse().unwrap(), from.port());
+                let mut response = request.response(MessageClass::SuccessResponse);
+                response
+                    .attributes
+                    .push(Attribute::XorMappedAddress(mapped));
+                let response = response.encode_with(None, &[], true).unwrap();
+                socket.send_to(&response, from).unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn gather_server_reflexive_test() {
+        let server = nat_stun_server();
+        let mut connection = IceConnection::new(true, 1, Some(server), None, true, false);
+        connection.set_include_loopback(true);
+
+        // 最初のrequestは捨てられるので再送されるまで待つ
+        let start = Instant::now();
+        connection.gather_candidates().unwrap();
+        assert!(start.elapsed() >= stun::RTO);
+
+        let candidates = connection.local_candidates();
+        let host = candidates
+            .iter()
+            .find(|candidate| candidate.address.ip().is_loopback())
+            .unwrap();
+        let srflx = candidates
+            .iter()
+            .find(|candidate| candidate.typ == CandidateType::ServerReflexive)
+            .unwrap();
+        assert_eq!(
+            srflx.address,
+            SocketAddr::new("203.0.113.1".parse().unwrap(), host.address.port())
+        );
+        assert_eq!(srflx.related_address, Some(host.address));
+        assert!(srflx.priority < host.priority);
+        assert_ne!(srflx.foundation, host.foundation);
+    }
+
+    #[test]
+    fn ice_connection_test() {
+        let (a, b) = connect(loopback_connection(true), loopback_connection(false));
+        assert_eq!(a.state(), IceConnectionState::Connected);
+        assert_eq!(b.state(), IceConnectionState::Connected);
+        assert!(a.is_controlling());
+        assert!(!b.is_controlling());
+        assert!(a.selected_pair(1).unwrap().nominated);
+
+        a.send(b"hello").unwrap();
+        let mut buf = [0u8; 16];
+        let len = b.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+
+        b.send(b"world").unwrap();
+        let len = a.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+        assert_eq!(&buf[..len], b"world");
+    }
+
+    #[test]
+    fn consent_freshness_test() {
+        let consent_freshness = ConsentFreshness {
+            interval: Duration::from_millis(100),
+            max_missed_responses: 3,
+            timeout: Duration::from_secs(1),
+        };
+        let consent_connection = |ice_controlling| {
+            let mut connection = IceConnection::new(ice_controlling, 1, None, None, true, false);
+            connection.set_include_loopback(true);
+            connection.set_use_tcp(false);
+            connection.set_consent_freshness(consent_freshness);
+            connection.gather_candidates().unwrap();
+            connection
+        };
+        let a = consent_connection(true);
+        let states = a.subscribe_state();
+        let (a, b) = connect(a, consent_connection(false));
+
+        // 応答がある間はtimeoutを過ぎてもConnectedのまま
+        thread::sleep(consent_freshness.timeout * 3 / 2);
+        assert_eq!(a.state(), IceConnectionState::Connected);
+        a.send(b"alive").unwrap();
+
+        // remoteが応答しなくなるとDisconnected，timeoutでFailed
+        b.close();
+        let timeout = Duration::from_secs(5);
+        let mut observed = vec![];
+        while observed.last() != Some(&IceConnectionState::Failed) {
+            observed.push(states.recv_timeout(timeout).unwrap());
+        }
+        assert_eq!(
+            &observed[..2],
+            &[IceConnectionState::Checking, IceConnectionState::Connected]
+        );
+        assert_eq!(
+            &observed[observed.len() - 2..],
+            &[IceConnectionState::Disconnected, IceConnectionState::Failed]
+        );
+        assert_eq!(a.send(b"gone"), Err(IceError::Failed));
+    }
+
+    #[test]
+    fn tcp_test() {
+        // UDPが通らない時のようにTCPのcandidateだけを渡す
+        let a = loopback_connection(true);
+        let b = loopback_connection(false);
+        a.set_remote_credentials(&b.local_username(), &b.local_password());
+        b.set_remote_credentials(&a.local_username(), &a.local_password());
+        let tcp = |connection: &IceConnection| {
+            connection
+                .local_candidates()
+                .into_iter()
+                .filter(|candidate| candidate.transport == TransportType::TCP)
+                .collect::<Vec<_>>()
+        };
+        let (a_tcp, b_tcp) = (tcp(&a), tcp(&b));
+        assert!(a_tcp
+            .iter()
+            .any(|candidate| candidate.tcp_type == Some(TcpType::Active)
+                && candidate.address.port() == 9));
+        assert!(a_tcp
+            .iter()
+            .any(|candidate| candidate.tcp_type == Some(TcpType::Passive)));
+        for candidate in b_tcp {
+            a.add_remote_candidate(candidate);
+        }
+        for candidate in a_tcp {
+            b.add_remote_candidate(candidate);
+        }
+
+        let handle = thread::spawn(move || {
+            b.connect(Duration::from_secs(10)).unwrap();
+            b
+        });
+        a.connect(Duration::from_secs(10)).unwrap();
+        let b = handle.join().unwrap();
+        assert_eq!(
+            a.selected_pair(1).unwrap().local.transport,
+            TransportType::TCP
+        );
+
+        a.send(b"hello").unwrap();
+        let mut buf = [0u8; 16];
+        let len = b.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+
+        b.send(b"world").unwrap();
+        let len = a.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+        assert_eq!(&buf[..len], b"world");
+    }
+
+    #[test]
+    fn restart_test() {
+        let (a, b) = connect(loopback_connection(true), loopback_connection(false));
+        let selected = a.selected_pair(1).unwrap();
+        let username = a.local_username();
+
+        a.restart();
+        b.restart();
+        assert_ne!(a.local_username(), username);
+        assert!(a.local_candidates().is_empty());
+
+        // 新しいpairがselectされるまでは前のpairで送れる
+        let mut buf = [0u8; 16];
+        a.send(b"before").unwrap();
+        let len = b.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+        assert_eq!(&buf[..len], b"before");
+
+        a.gather_candidates().unwrap();
+        b.gather_candidates().unwrap();
+        let (a, b) = connect(a, b);
+        let restarted = a.selected_pair(1).unwrap();
+        assert_ne!(restarted.local.address, selected.local.address);
+        assert_eq!(a.state(), IceConnectionState::Connected);
+
+        b.send(b"after").unwrap();
+        let len = a.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+        assert_eq!(&buf[..len], b"after");
+    }
+
+    #[test]
+    fn rollback_restart_test() {
+        let (a, b) = connect(loopback_connection(true), loopback_connection(false));
+        let username = a.local_username();
+        let candidates = a.local_candidates();
+
+        a.restart();
+        assert_ne!(a.local_username(), username);
+        a.rollback_restart();
+        assert_eq!(a.local_username(), username);
+        assert_eq!(a.local_candidates(), candidates);
+        assert!(a.is_gathering_complete());
+
+        // 前のpairのまま送れる
+        a.send(b"ping").unwrap();
+        let mut buf = [0u8; 16];
+        let len = b.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+    }
+
+    #[test]
+    fn role_conflict_test() {
+        // 両方controllingで始めてもtie-breakerでどちらかがcontrolledになる
+        let (a, b) = connect(loopback_connection(true), loopback_connection(true));
+        assert_ne!(a.is_controlling(), b.is_controlling());
+
+        a.send(b"ping").unwrap();
+        let mut buf = [0u8; 16];
+        let len = b.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+    }
+
+    fn turn_option(addr: SocketAddr, transport: TransportType) -> TurnOption {
+        TurnOption {
+            addr,
+            user: "user".to_owned(),
+            password: "pass".to_owned(),
+            is_ssl: false,
+            transport,
+        }
+    }
+
+    fn relay_test(transport: TransportType) {
+        let mut auth_handler = StaticAuthHandler::new();
+        auth_handler.add_user("user", "pass");
+        let mut server = TurnServer::new(ServerConfig::new(
+            "example.org",
+            Arc::new(auth_handler),
+            Arc::new(StaticRelayAllocator::new("127.0.0.1".parse().unwrap())),
+        ));
+        let listen = "127.0.0.1:0".parse().unwrap();
+        let addr = match transport {
+            TransportType::UDP => server.listen_udp(listen).unwrap(),
+            TransportType::TCP => server.listen_tcp(listen).unwrap(),
+        };
+
+        let mut a = IceConnection::new(
+            true,
+            1,
+            None,
+            Some(turn_option(addr, transport)),
+            true,
+            false,
+        );
+        a.set_transport_policy(IceTransportPolicy::Relay);
+        a.gather_candidates().unwrap();
+        let candidates = a.local_candidates();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].typ, CandidateType::Relay);
+        assert_eq!(candidates[0].transport, TransportType::UDP);
+        assert!(candidates[0].related_address.is_some());
+
+        let (a, b) = connect(a, loopback_connection(false));
+        assert_eq!(a.selected_pair(1).unwrap().local.typ, CandidateType::Relay);
+
+        a.send(b"hello").unwrap();
+        let mut buf = [0u8; 16];
+        let len = b.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+
+        b.send(b"world").unwrap();
+        let len = a.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+        assert_eq!(&buf[..len], b"world");
+    }
+
+    #[test]
+    fn udp_relay_test() {
+        relay_test(TransportType::UDP);
+    }
+
+    #[test]
+    fn tcp_relay_test() {
+        relay_test(TransportType::TCP);
+    }
+
+    #[test]
+    fn turn_over_tls_test() {
+        let mut turn = turn_option("127.0.0.1:5349".parse().unwrap(), TransportType::TCP);
+        turn.is_ssl = true;
+        let connection = IceConnection::new(true, 1, None, Some(turn), true, false);
+        assert_eq!(
+            connection.gather_candidates(),
+            Err(IceError::TurnError {
+                error: TurnError::UnsupportedTransport
+            })
+        );
+    }
+
+    #[test]
+    fn unreachable_turn_server_test() {
+        // 誰もlistenしていないport
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let connection = IceConnection::new(
+            true,
+            1,
+            None,
+            Some(turn_option(addr, TransportType::TCP)),
+            true,
+            false,
+        );
+        let rx = connection.subscribe_candidates();
+        connection.gather_candidates().unwrap();
+        let candidates = connection.local_candidates();
+        assert!(!candidates.is_empty());
+        assert!(candidates.iter().all(|c| c.typ == CandidateType::Host));
+        // gatheringは完了する
+        assert_eq!(rx.try_iter().last(), Some(None));
+
+        let mut relay_only = IceConnection::new(
+            true,
+            1,
+            None,
+            Some(turn_option(addr, TransportType::TCP)),
+            true,
+            false,
+        );
+        relay_only.set_transport_policy(IceTransportPolicy::Relay);
+        assert_eq!(relay_only.gather_candidates(), Err(IceError::NoCandidates));
+    }
+
+    #[test]
+    fn stale_hostname_candidate_test() {
+        let group = SocketAddrV4::new(MDNS_GROUP, 53537);
+        let remote = Mdns::bind(group, Ipv4Addr::LOCALHOST).unwrap();
+        let hostname = remote.publish("127.0.0.1".parse().unwrap());
+
+        let mut connection = IceConnection::new(true, 1, None, None, true, false);
+        let mdns = Arc::new(Mdns::bind(group, Ipv4Addr::LOCALHOST).unwrap());
+        connection.set_mdns(mdns.clone(), false);
+
+        let candidate = Candidate::host(1, "0.0.0.0:5000".parse().unwrap(), 65535);
+        connection.add_remote_hostname_candidate(&hostname, candidate);
+        // 名前解決が終わる前にrestartする
+        connection.restart();
+        assert_eq!(connection.agent().resolving, 0);
+
+        // 解決はできるが，前のgenerationのcandidateなので使わない
+        mdns.resolve(&hostname, Duration::from_secs(5)).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert!(connection.remote_candidates().is_empty());
+        assert_eq!(connection.agent().resolving, 0);
+    }
+
+    #[test]
+    fn not_connected_test() {
+        let connection = IceConnection::new(true, 1, None, None, true, false);
+        assert_eq!(connection.send(b"data"), Err(IceError::NotConnected));
+        assert_eq!(
+            connection.connect(Duration::from_millis(10)),
+            Err(IceError::NoCandidates)
+        );
+        connection.close();
+        assert_eq!(connection.send(b"data"), Err(IceError::Closed));
+    }
+}
@@ -0,0 +1,70 @@
+// host candidateを集めるためにinterfaceのaddressを列挙する
+
+use std::ffi::CStr;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InterfaceAddress {
+    pub name: String,
+    pub ip: IpAddr,
+}
+
+pub fn interface_addresses() -> io::Result<Vec<InterfaceAddress>> {
+    let mut addresses = vec![];
+
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut cursor = ifaddrs;
+    while !cursor.is_null() {
+        let ifaddr = unsafe { &*cursor };
+        cursor = ifaddr.ifa_next;
+
+        if ifaddr.ifa_addr.is_null() || ifaddr.ifa_flags & (libc::IFF_UP as libc::c_uint) == 0 {
+            continue;
+        }
+
+        let ip = match i32::from(unsafe { (*ifaddr.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let addr = unsafe { &*(ifaddr.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe { &*(ifaddr.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr))
+            }
+            _ => continue,
+        };
+
+        let name = unsafe { CStr::from_ptr(ifaddr.ifa_name) }
+            .to_string_lossy()
+            .into_owned();
+        addresses.push(InterfaceAddress { name, ip });
+    }
+
+    unsafe { libc::freeifaddrs(ifaddrs) };
+
+    Ok(addresses)
+}
+
+// fe80::/10 はscope idが必要なので使わない
+pub fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.segments()[0] & 0xFFC0 == 0xFE80,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interface_addresses_test() {
+        let addresses = interface_addresses().unwrap();
+        assert!(addresses.iter().any(|address| address.ip.is_loopback()));
+    }
+}
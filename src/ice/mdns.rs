@@ -0,0 +1,599 @@
+// https://tools.ietf.org/html/rfc6762
+// https://tools.ietf.org/html/draft-ietf-mmusic-mdns-ice-candidates
+
+use crate::ice::{IceError, Result};
+use crate::octets::Octets;
+
+use rand::{thread_rng, Rng};
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_PORT: u16 = 5353;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+// responseではcache-flush，questionではunicast responseを求めるbit
+const CLASS_MASK: u16 = 0x7fff;
+const CACHE_FLUSH: u16 = 0x8000;
+const FLAG_RESPONSE: u16 = 0x8400;
+const TTL: u32 = 120;
+// queryを再送する間隔
+const QUERY_INTERVAL: Duration = Duration::from_secs(1);
+// socketを読むthreadがcloseに気付くまでの時間
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const MAX_MESSAGE_SIZE: usize = 9000;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Question {
+    pub name: String,
+    pub typ: u16,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Answer {
+    pub name: String,
+    pub address: IpAddr,
+    pub ttl: u32,
+}
+
+// A/AAAA以外のrecordは読み飛ばす
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MdnsMessage {
+    pub id: u16,
+    pub is_response: bool,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Answer>,
+}
+
+impl MdnsMessage {
+    pub fn query(names: &[&str]) -> MdnsMessage {
+        MdnsMessage {
+            id: 0,
+            is_response: false,
+            questions: names
+                .iter()
+                .flat_map(|name| {
+                    vec![
+                        Question {
+                            name: name.to_string(),
+                            typ: TYPE_A,
+                        },
+                        Question {
+                            name: name.to_string(),
+                            typ: TYPE_AAAA,
+                        },
+                    ]
+                })
+                .collect(),
+            answers: vec![],
+        }
+    }
+
+    pub fn response(answers: Vec<Answer>) -> MdnsMessage {
+        MdnsMessage {
+            id: 0,
+            is_response: true,
+            questions: vec![],
+            answers,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let names_len: usize = self
+            .questions
+            .iter()
+            .map(|question| name_len(&question.name) + 4)
+            .chain(self.answers.iter().map(|answer| {
+                let rdata_len = if answer.address.is_ipv4() { 4 } else { 16 };
+                name_len(&answer.name) + 10 + rdata_len
+            }))
+            .sum();
+        let mut buf = vec![0u8; 12 + names_len];
+
+        let mut out = Octets::with_slice(&mut buf);
+        out.put_u16(self.id)?;
+        out.put_u16(if self.is_response { FLAG_RESPONSE } else { 0 })?;
+        out.put_u16(self.questions.len() as u16)?;
+        out.put_u16(self.answers.len() as u16)?;
+        out.put_u16(0)?;
+        out.put_u16(0)?;
+        for question in &self.questions {
+            put_name(&mut out, &question.name)?;
+            out.put_u16(question.typ)?;
+            out.put_u16(CLASS_IN)?;
+        }
+        for answer in &self.answers {
+            put_name(&mut out, &answer.name)?;
+            match answer.address {
+                IpAddr::V4(ip) => {
+                    out.put_u16(TYPE_A)?;
+                    out.put_u16(CLASS_IN | CACHE_FLUSH)?;
+                    out.put_u32(answer.ttl)?;
+                    out.put_u16(4)?;
+                    out.put_bytes(&ip.octets())?;
+                }
+                IpAddr::V6(ip) => {
+                    out.put_u16(TYPE_AAAA)?;
+                    out.put_u16(CLASS_IN | CACHE_FLUSH)?;
+                    out.put_u32(answer.ttl)?;
+                    out.put_u16(16)?;
+                    out.put_bytes(&ip.octets())?;
+                }
+            }
+        }
+
+        Ok(buf)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<MdnsMessage> {
+        let mut buf = bytes.to_vec();
+        let mut octets = Octets::with_slice(&mut buf);
+        let id = octets.get_u16()?;
+        let flags = octets.get_u16()?;
+        let questions_count = octets.get_u16()?;
+        let answers_count = octets.get_u16()?;
+        // authority/additionalのrecordも答えとして読む
+        let records_count =
+            answers_count as usize + octets.get_u16()? as usize + octets.get_u16()? as usize;
+
+        let mut questions = vec![];
+        for _ in 0..questions_count {
+            let name = get_name(bytes, &mut octets)?;
+            let typ = octets.get_u16()?;
+            let class = octets.get_u16()? & CLASS_MASK;
+            if class == CLASS_IN {
+                questions.push(Question { name, typ });
+            }
+        }
+
+        let mut answers = vec![];
+        for _ in 0..records_count {
+            let name = get_name(bytes, &mut octets)?;
+            let typ = octets.get_u16()?;
+            let class = octets.get_u16()? & CLASS_MASK;
+            let ttl = octets.get_u32()?;
+            let rdata = octets.get_bytes_with_u16_length()?;
+            let rdata = rdata.as_ref();
+            let address = match (typ, rdata.len()) {
+                (TYPE_A, 4) => IpAddr::V4(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+                (TYPE_AAAA, 16) => {
+                    let mut octets = [0u8; 16];
+                    octets.copy_from_slice(rdata);
+                    IpAddr::V6(Ipv6Addr::from(octets))
+                }
+                _ => continue,
+            };
+            if class == CLASS_IN {
+                answers.push(Answer { name, address, ttl });
+            }
+        }
+
+        Ok(MdnsMessage {
+            id,
+            is_response: flags & 0x8000 != 0,
+            questions,
+            answers,
+        })
+    }
+}
+
+fn name_len(name: &str) -> usize {
+    name.trim_end_matches('.')
+        .split('.')
+        .map(|label| label.len() + 1)
+        .sum::<usize>()
+        + 1
+}
+
+fn put_name(out: &mut Octets, name: &str) -> Result<()> {
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(IceError::InvalidMdnsMessage);
+        }
+        out.put_u8(label.len() as u8)?;
+        out.put_bytes(label.as_bytes())?;
+    }
+    out.put_u8(0)?;
+    Ok(())
+}
+
+// message compression (RFC 1035 4.1.4) のpointerはbytesの中を辿る
+fn get_name(bytes: &[u8], octets: &mut Octets) -> Result<String> {
+    let mut labels = vec![];
+    let mut off = octets.off();
+    let mut jumped = false;
+    // pointerのloopを防ぐ
+    for _ in 0..128 {
+        let len = *bytes.get(off).ok_or(IceError::InvalidMdnsMessage)? as usize;
+        if len & 0xc0 == 0xc0 {
+            let low = *bytes.get(off + 1).ok_or(IceError::InvalidMdnsMessage)? as usize;
+            if !jumped {
+                octets.get_bytes(off + 2 - octets.off())?;
+                jumped = true;
+            }
+            off = ((len & 0x3f) << 8) | low;
+            continue;
+        }
+        if len == 0 {
+            if !jumped {
+                octets.get_bytes(off + 1 - octets.off())?;
+            }
+            return Ok(labels.join("."));
+        }
+        let label = bytes
+            .get(off + 1..off + 1 + len)
+            .ok_or(IceError::InvalidMdnsMessage)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        off += 1 + len;
+    }
+    Err(IceError::InvalidMdnsMessage)
+}
+
+// candidateのaddressを隠すための名前 (UUID version 4)
+pub fn random_hostname() -> String {
+    let mut bytes: [u8; 16] = thread_rng().gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}.local",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+pub fn is_mdns_hostname(address: &str) -> bool {
+    address.to_ascii_lowercase().ends_with(".local")
+}
+
+struct State {
+    // publishした名前
+    published: HashMap<String, IpAddr>,
+    // 受け取った答え
+    resolved: HashMap<String, IpAddr>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+    closed: AtomicBool,
+}
+
+// mDNSのresponderとquerier．一つのsocketで両方を行う
+pub struct Mdns {
+    socket: Arc<UdpSocket>,
+    group: SocketAddrV4,
+    shared: Arc<Shared>,
+    reader: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Mdns {
+    // 224.0.0.251:5353をdefaultのinterfaceで使う
+    pub fn new() -> io::Result<Mdns> {
+        Mdns::bind(
+            SocketAddrV4::new(MDNS_GROUP, MDNS_PORT),
+            Ipv4Addr::UNSPECIFIED,
+        )
+    }
+
+    // 同じhostの他のresponderとportを共有できるようにSO_REUSEPORTでbindする
+    pub fn bind(group: SocketAddrV4, interface: Ipv4Addr) -> io::Result<Mdns> {
+        let socket = reuse_port_socket(group.port())?;
+        socket.join_multicast_v4(group.ip(), &interface)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(255)?;
+        if !interface.is_unspecified() {
+            set_multicast_interface(&socket, interface)?;
+        }
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        let socket = Arc::new(socket);
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                published: HashMap::new(),
+                resolved: HashMap::new(),
+            }),
+            changed: Condvar::new(),
+            closed: AtomicBool::new(false),
+        });
+        let reader = {
+            let socket = socket.clone();
+            let shared = shared.clone();
+            thread::spawn(move || read_loop(shared, socket, group))
+        };
+
+        Ok(Mdns {
+            socket,
+            group,
+            shared,
+            reader: Mutex::new(Some(reader)),
+        })
+    }
+
+    // ipに新しい名前を付けてqueryに答えるようにする
+    pub fn publish(&self, ip: IpAddr) -> String {
+        let hostname = random_hostname();
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .published
+            .insert(hostname.clone(), ip);
+        hostname
+    }
+
+    pub fn unpublish(&self, hostname: &str) {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .published
+            .remove(&hostname.to_ascii_lowercase());
+    }
+
+    // 答えが来るまでQUERY_INTERVAL毎にqueryを送る
+    pub fn resolve(&self, hostname: &str, timeout: Duration) -> Result<IpAddr> {
+        let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+        let query = MdnsMessage::query(&[&hostname]).encode()?;
+        let deadline = Instant::now() + timeout;
+        let mut next_query = Instant::now();
+
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(ip) = state
+                .published
+                .get(&hostname)
+                .or_else(|| state.resolved.get(&hostname))
+            {
+                return Ok(*ip);
+            }
+            if self.shared.closed.load(Ordering::SeqCst) {
+                return Err(IceError::Closed);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(IceError::Timeout);
+            }
+            if now >= next_query {
+                self.socket.send_to(&query, self.group)?;
+                next_query = now + QUERY_INTERVAL;
+            }
+            let wait = next_query.min(deadline).saturating_duration_since(now);
+            state = self.shared.changed.wait_timeout(state, wait).unwrap().0;
+        }
+    }
+
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.shared.changed.notify_all();
+        if let Some(reader) = self.reader.lock().unwrap().take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+impl Drop for Mdns {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn read_loop(shared: Arc<Shared>, socket: Arc<UdpSocket>, group: SocketAddrV4) {
+    let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+    while !shared.closed.load(Ordering::SeqCst) {
+        let len = match socket.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            Err(_) => continue,
+        };
+        let message = match MdnsMessage::decode(&buf[..len]) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+
+        let mut state = shared.state.lock().unwrap();
+        if message.is_response {
+            for answer in message.answers {
+                state
+                    .resolved
+                    .insert(answer.name.to_ascii_lowercase(), answer.address);
+            }
+            shared.changed.notify_all();
+            continue;
+        }
+
+        let answers = message
+            .questions
+            .iter()
+            .filter_map(|question| {
+                let name = question.name.to_ascii_lowercase();
+                let address = *state.published.get(&name)?;
+                let matched = match question.typ {
+                    TYPE_A => address.is_ipv4(),
+                    TYPE_AAAA => address.is_ipv6(),
+                    TYPE_ANY => true,
+                    _ => false,
+                };
+                if matched {
+                    Some(Answer {
+                        name,
+                        address,
+                        ttl: TTL,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        drop(state);
+        if !answers.is_empty() {
+            if let Ok(response) = MdnsMessage::response(answers).encode() {
+                let _ = socket.send_to(&response, group);
+            }
+        }
+    }
+}
+
+fn reuse_port_socket(port: u16) -> io::Result<UdpSocket> {
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // fdはUdpSocketが閉じる
+        let socket = UdpSocket::from_raw_fd(fd);
+        for option in &[libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+            set_option(fd, libc::SOL_SOCKET, *option, &(1 as libc::c_int))?;
+        }
+
+        let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port));
+        let mut sockaddr: libc::sockaddr_in = mem::zeroed();
+        sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+        sockaddr.sin_port = addr.port().to_be();
+        sockaddr.sin_addr.s_addr = libc::INADDR_ANY;
+        if libc::bind(
+            fd,
+            &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+fn set_multicast_interface(socket: &UdpSocket, interface: Ipv4Addr) -> io::Result<()> {
+    let addr = libc::in_addr {
+        s_addr: u32::from(interface).to_be(),
+    };
+    unsafe {
+        set_option(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_MULTICAST_IF,
+            &addr,
+        )
+    }
+}
+
+unsafe fn set_option<T>(
+    fd: libc::c_int,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: &T,
+) -> io::Result<()> {
+    if libc::setsockopt(
+        fd,
+        level,
+        name,
+        value as *const T as *const libc::c_void,
+        mem::size_of::<T>() as libc::socklen_t,
+    ) != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 実際の5353を使う他のresponderに届かないようにloopbackで別のportを使う
+    fn loopback_mdns() -> Mdns {
+        Mdns::bind(
+            SocketAddrV4::new(MDNS_GROUP, 53535),
+            Ipv4Addr::new(127, 0, 0, 1),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn message_test() {
+        let query = MdnsMessage::query(&["a.local"]);
+        let encoded = query.encode().unwrap();
+        assert_eq!(
+            encoded,
+            b"\x00\x00\x00\x00\x00\x02\x00\x00\x00\x00\x00\x00\
+              \x01a\x05local\x00\x00\x01\x00\x01\
+              \x01a\x05local\x00\x00\x1c\x00\x01"
+                .to_vec()
+        );
+        assert_eq!(MdnsMessage::decode(&encoded).unwrap(), query);
+
+        let response = MdnsMessage::response(vec![
+            Answer {
+                name: "a.local".to_owned(),
+                address: "192.168.0.1".parse().unwrap(),
+                ttl: TTL,
+            },
+            Answer {
+                name: "a.local".to_owned(),
+                address: "fe80::1".parse().unwrap(),
+                ttl: TTL,
+            },
+        ]);
+        assert_eq!(
+            MdnsMessage::decode(&response.encode().unwrap()).unwrap(),
+            response
+        );
+
+        // 二つ目のrecordの名前は最初の名前へのpointer
+        let compressed = b"\x00\x00\x84\x00\x00\x00\x00\x02\x00\x00\x00\x00\
+              \x01a\x05local\x00\x00\x01\x80\x01\x00\x00\x00\x78\x00\x04\xc0\xa8\x00\x01\
+              \xc0\x0c\x00\x01\x80\x01\x00\x00\x00\x78\x00\x04\xc0\xa8\x00\x02";
+        let decoded = MdnsMessage::decode(compressed).unwrap();
+        assert_eq!(decoded.answers.len(), 2);
+        assert_eq!(decoded.answers[1].name, "a.local");
+        assert_eq!(
+            decoded.answers[1].address,
+            "192.168.0.2".parse::<IpAddr>().unwrap()
+        );
+
+        assert!(MdnsMessage::decode(&encoded[..20]).is_err());
+    }
+
+    #[test]
+    fn random_hostname_test() {
+        let hostname = random_hostname();
+        assert!(is_mdns_hostname(&hostname));
+        assert_eq!(hostname.len(), 36 + ".local".len());
+        assert_eq!(&hostname[14..15], "4");
+        assert_ne!(hostname, random_hostname());
+    }
+
+    #[test]
+    fn resolve_test() {
+        let a = loopback_mdns();
+        let b = loopback_mdns();
+        let ip: IpAddr = "192.168.0.1".parse().unwrap();
+        let hostname = a.publish(ip);
+
+        assert_eq!(a.resolve(&hostname, Duration::from_secs(1)).unwrap(), ip);
+        assert_eq!(b.resolve(&hostname, Duration::from_secs(5)).unwrap(), ip);
+
+        a.unpublish(&hostname);
+        let unknown = a.publish(ip);
+        a.unpublish(&unknown);
+        assert_eq!(
+            b.resolve(&unknown, Duration::from_millis(300)),
+            Err(IceError::Timeout)
+        );
+    }
+}
@@ -0,0 +1,272 @@
+// https://tools.ietf.org/html/rfc6544
+// https://tools.ietf.org/html/rfc4571
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// acceptを待つthreadがcloseに気付くまでの時間
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+// RFC 4571のLENGTHは16bit
+const MAX_FRAME_SIZE: usize = 65535;
+
+type Packet = (Vec<u8>, SocketAddr);
+
+struct Connections {
+    streams: HashMap<SocketAddr, TcpStream>,
+    connecting: HashSet<SocketAddr>,
+}
+
+struct Shared {
+    connections: Mutex<Connections>,
+    packets: Sender<Packet>,
+    closed: AtomicBool,
+}
+
+// ICE-TCPのcandidateのsocket．接続毎のstreamをまとめてdatagramのように扱う
+// passiveはacceptし，activeは最初に送る時にconnectする
+pub struct TcpSocket {
+    local_addr: SocketAddr,
+    active: bool,
+    shared: Arc<Shared>,
+    packets: Mutex<Receiver<Packet>>,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+// LENGTH (2byte) + data
+pub fn frame(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "packet is too large for RFC 4571 framing",
+        ));
+    }
+    let mut framed = Vec::with_capacity(2 + data.len());
+    framed.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    framed.extend_from_slice(data);
+    Ok(framed)
+}
+
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut length = [0u8; 2];
+    reader.read_exact(&mut length)?;
+    let mut data = vec![0u8; u16::from_be_bytes(length) as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+impl Shared {
+    fn add_stream(self: &Arc<Self>, stream: TcpStream, peer: SocketAddr) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut reader = stream.try_clone()?;
+        {
+            let mut connections = self.connections.lock().unwrap();
+            connections.connecting.remove(&peer);
+            if self.closed.load(Ordering::SeqCst) {
+                let _ = stream.shutdown(Shutdown::Both);
+                return Ok(());
+            }
+            connections.streams.insert(peer, stream);
+        }
+
+        let shared = self.clone();
+        thread::spawn(move || {
+            while let Ok(data) = read_frame(&mut reader) {
+                if shared.packets.send((data, peer)).is_err() {
+                    break;
+                }
+            }
+            shared.connections.lock().unwrap().streams.remove(&peer);
+        });
+        Ok(())
+    }
+}
+
+impl TcpSocket {
+    fn new(local_addr: SocketAddr, active: bool) -> TcpSocket {
+        let (tx, rx) = mpsc::channel();
+        TcpSocket {
+            local_addr,
+            active,
+            shared: Arc::new(Shared {
+                connections: Mutex::new(Connections {
+                    streams: HashMap::new(),
+                    connecting: HashSet::new(),
+                }),
+                packets: tx,
+                closed: AtomicBool::new(false),
+            }),
+            packets: Mutex::new(rx),
+            read_timeout: Mutex::new(None),
+        }
+    }
+
+    // tcptype passive
+    pub fn listen(ip: IpAddr) -> io::Result<TcpSocket> {
+        let listener = TcpListener::bind(SocketAddr::new(ip, 0))?;
+        listener.set_nonblocking(true)?;
+        let socket = TcpSocket::new(listener.local_addr()?, false);
+
+        let shared = socket.shared.clone();
+        thread::spawn(move || {
+            while !shared.closed.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, peer)) => {
+                        let _ = stream
+                            .set_nonblocking(false)
+                            .and_then(|()| shared.add_stream(stream, peer));
+                    }
+                    Err(_) => thread::sleep(ACCEPT_INTERVAL),
+                }
+            }
+        });
+        Ok(socket)
+    }
+
+    // tcptype active．送信元のaddressはOSが選ぶ
+    pub fn active(ip: IpAddr) -> TcpSocket {
+        TcpSocket::new(SocketAddr::new(ip, 0), true)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) {
+        *self.read_timeout.lock().unwrap() = timeout;
+    }
+
+    // 接続が無ければactiveはconnectしてから送る．connectを待たずに返る
+    pub fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if self.shared.closed.load(Ordering::SeqCst) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        let framed = frame(buf)?;
+
+        let mut connections = self.shared.connections.lock().unwrap();
+        if let Some(stream) = connections.streams.get_mut(&addr) {
+            if let Err(e) = stream.write_all(&framed) {
+                connections.streams.remove(&addr);
+                return Err(e);
+            }
+            return Ok(buf.len());
+        }
+        if !self.active {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        // 接続中に送ったものは捨てる (connectivity checkは再送される)
+        if connections.connecting.insert(addr) {
+            let shared = self.shared.clone();
+            thread::spawn(move || {
+                let connected = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+                    .and_then(|mut stream| stream.write_all(&framed).map(|()| stream))
+                    .and_then(|stream| shared.add_stream(stream, addr));
+                if connected.is_err() {
+                    shared.connections.lock().unwrap().connecting.remove(&addr);
+                }
+            });
+        }
+        Ok(buf.len())
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let timeout = *self.read_timeout.lock().unwrap();
+        let packets = self.packets.lock().unwrap();
+        let (data, from) = loop {
+            if self.shared.closed.load(Ordering::SeqCst) {
+                return Err(io::ErrorKind::NotConnected.into());
+            }
+            match timeout {
+                Some(timeout) => match packets.recv_timeout(timeout) {
+                    Ok(packet) => break packet,
+                    Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(io::ErrorKind::NotConnected.into())
+                    }
+                },
+                // closeに気付けるように区切って待つ
+                None => match packets.recv_timeout(ACCEPT_INTERVAL) {
+                    Ok(packet) => break packet,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(io::ErrorKind::NotConnected.into())
+                    }
+                },
+            }
+        };
+
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, from))
+    }
+
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        let mut connections = self.shared.connections.lock().unwrap();
+        for (_, stream) in connections.streams.drain() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frame_test() {
+        let framed = frame(b"hello").unwrap();
+        assert_eq!(framed, b"\x00\x05hello".to_vec());
+        assert_eq!(read_frame(&mut &framed[..]).unwrap(), b"hello".to_vec());
+        assert!(read_frame(&mut &framed[..4]).is_err());
+        assert!(frame(&vec![0u8; MAX_FRAME_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn tcp_socket_test() {
+        let ip = "127.0.0.1".parse().unwrap();
+        let passive = TcpSocket::listen(ip).unwrap();
+        passive.set_read_timeout(Some(Duration::from_secs(5)));
+        let active = TcpSocket::active(ip);
+        active.set_read_timeout(Some(Duration::from_secs(5)));
+
+        // passiveからは接続が無いと送れない
+        let unknown = "127.0.0.1:9".parse().unwrap();
+        assert_eq!(
+            passive.send_to(b"ping", unknown).unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+
+        let mut buf = [0u8; 16];
+        active.send_to(b"ping", passive.local_addr()).unwrap();
+        let (len, from) = passive.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+
+        passive.send_to(b"pong", from).unwrap();
+        let (len, from) = active.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"pong");
+        assert_eq!(from, passive.local_addr());
+
+        active.close();
+        assert_eq!(
+            active.recv_from(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+    }
+}
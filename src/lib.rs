@@ -1,14 +1,28 @@
+// failure_deriveが生成するimplがnon_local_definitionsに引っかかるので許可する
+#![allow(non_local_definitions)]
+
 use failure::Fail;
 
+pub mod ice;
+pub mod media_engine;
+pub mod mediastreamtrack;
 pub mod octets;
 pub mod rtcp;
 pub mod rtp;
 pub mod sdp;
+pub mod srtp;
+pub mod stun;
+pub mod turn;
 
 pub mod rtcpeerconnection;
 
-pub mod rtcrtpparameters;
+pub mod rtccertificate;
 pub mod rtcdtlstransport;
+pub mod rtcicecandidate;
+pub mod rtcrtpparameters;
+pub mod rtcrtpreceiver;
+pub mod rtcrtpsender;
+pub mod rtcrtptransceiver;
 
 pub type Result<T> = std::result::Result<T, OctetsError>;
 //pub type Result<T> = std::result::Result<T, WebrtcError>;
@@ -21,6 +35,28 @@ pub enum WebrtcError {
     RtpError { error: rtp::RtpError },
     #[fail(display = "RTCP failed: {:?}", error)]
     RtcpError { error: rtcp::RtcpError },
+    #[fail(display = "SRTP failed: {:?}", error)]
+    SrtpError { error: srtp::SrtpError },
+    #[fail(display = "DTLS failed: {:?}", error)]
+    DtlsError {
+        error: rtcdtlstransport::RtcDtlsError,
+    },
+    #[fail(display = "ICE failed: {:?}", error)]
+    IceError { error: ice::IceError },
+    #[fail(display = "TURN failed: {:?}", error)]
+    TurnError { error: turn::TurnError },
+    #[fail(display = "ICE candidate failed: {:?}", error)]
+    IceCandidateError {
+        error: rtcicecandidate::RtcIceCandidateError,
+    },
+    #[fail(display = "Media engine failed: {:?}", error)]
+    MediaEngineError {
+        error: media_engine::MediaEngineError,
+    },
+    #[fail(display = "RTCPeerConnection failed: {:?}", error)]
+    PeerConnectionError {
+        error: rtcpeerconnection::RtcPeerConnectionError,
+    },
 }
 
 impl From<OctetsError> for WebrtcError {
@@ -41,6 +77,48 @@ impl From<rtcp::RtcpError> for WebrtcError {
     }
 }
 
+impl From<srtp::SrtpError> for WebrtcError {
+    fn from(error: srtp::SrtpError) -> Self {
+        WebrtcError::SrtpError { error }
+    }
+}
+
+impl From<rtcdtlstransport::RtcDtlsError> for WebrtcError {
+    fn from(error: rtcdtlstransport::RtcDtlsError) -> Self {
+        WebrtcError::DtlsError { error }
+    }
+}
+
+impl From<ice::IceError> for WebrtcError {
+    fn from(error: ice::IceError) -> Self {
+        WebrtcError::IceError { error }
+    }
+}
+
+impl From<turn::TurnError> for WebrtcError {
+    fn from(error: turn::TurnError) -> Self {
+        WebrtcError::TurnError { error }
+    }
+}
+
+impl From<rtcicecandidate::RtcIceCandidateError> for WebrtcError {
+    fn from(error: rtcicecandidate::RtcIceCandidateError) -> Self {
+        WebrtcError::IceCandidateError { error }
+    }
+}
+
+impl From<media_engine::MediaEngineError> for WebrtcError {
+    fn from(error: media_engine::MediaEngineError) -> Self {
+        WebrtcError::MediaEngineError { error }
+    }
+}
+
+impl From<rtcpeerconnection::RtcPeerConnectionError> for WebrtcError {
+    fn from(error: rtcpeerconnection::RtcPeerConnectionError) -> Self {
+        WebrtcError::PeerConnectionError { error }
+    }
+}
+
 /// A Octets error.
 #[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C)]
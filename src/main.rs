@@ -1,34 +1,88 @@
-use std::io::Write;
-use std::net::{TcpListener, TcpStream};
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::process;
+use std::sync::Arc;
+use std::thread;
 
-//use webrtc::packet::{RtpPacket};
+use webrtc::turn::{ServerConfig, StaticAuthHandler, StaticRelayAllocator, TurnServer};
 
-fn main() {
+const USAGE: &str = "usage: webrtc [--listen ADDR] [--relay-ip IP] [--realm REALM] \
+                     [--max-allocations N] --user USER:PASSWORD...";
 
-    /*
-    let p = RtpPacket::new();
-    println!("{:?}",p);
-    let temp : u32 = 100;
-    let temp_ss = unsafe {mem::transmute::<u32,[u8;4]>(temp) };
-    println!("{:?}",temp_ss);
-    */
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
 }
 
-/*
+// STUN/TURN serverをUDPとTCPの同じaddressで動かす
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                let response = b"Hello World";
-                println!("message {:?}",stream.read);
-                stream.write(response).expect("Response failed");
+    let mut listen: SocketAddr = "0.0.0.0:3478".parse().unwrap();
+    let mut relay_ip: Option<IpAddr> = None;
+    let mut realm = "webrtc".to_owned();
+    let mut max_allocations = None;
+    let mut auth_handler = StaticAuthHandler::new();
+    let mut users = 0;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--listen" => listen = value.parse().unwrap_or_else(|_| usage()),
+            "--relay-ip" => relay_ip = Some(value.parse().unwrap_or_else(|_| usage())),
+            "--realm" => realm = value,
+            "--max-allocations" => {
+                max_allocations = Some(value.parse().unwrap_or_else(|_| usage()))
             }
-            Err(e) => {
-                println!("Unable to connect: {}", e);
+            "--user" => {
+                let mut credential = value.splitn(2, ':');
+                match (credential.next(), credential.next()) {
+                    (Some(username), Some(password)) => {
+                        auth_handler.add_user(username, password);
+                        users += 1;
+                    }
+                    _ => usage(),
+                }
             }
+            _ => usage(),
+        }
+    }
+    if users == 0 {
+        usage();
+    }
+
+    // relayのaddressはclientに教えるので0.0.0.0は使えない
+    let relay_ip = relay_ip.unwrap_or_else(|| {
+        if listen.ip().is_unspecified() {
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        } else {
+            listen.ip()
         }
+    });
+
+    let mut config = ServerConfig::new(
+        &realm,
+        Arc::new(auth_handler),
+        Arc::new(StaticRelayAllocator::new(relay_ip)),
+    );
+    if let Some(max_allocations) = max_allocations {
+        config.max_allocations = max_allocations;
+    }
+
+    let mut server = TurnServer::new(config);
+    let udp = server.listen_udp(listen).unwrap_or_else(|e| {
+        eprintln!("failed to listen on udp {}: {}", listen, e);
+        process::exit(1);
+    });
+    let tcp = server.listen_tcp(listen).unwrap_or_else(|e| {
+        eprintln!("failed to listen on tcp {}: {}", listen, e);
+        process::exit(1);
+    });
+    println!(
+        "TURN server listening on udp {} and tcp {}, relaying on {}",
+        udp, tcp, relay_ip
+    );
+
+    loop {
+        thread::park();
     }
 }
-*/
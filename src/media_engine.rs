@@ -0,0 +1,538 @@
+// 使えるcodecとheader extensionの一覧 (pionのMediaEngine)
+// BUNDLEするのでPTとextmapのIDはaudioとvideoで重ならないようにする
+
+use crate::rtcrtpparameters::{
+    RtcRtcpFeedback, RtcRtpCapabilities, RtcRtpCodecCapability, RtcRtpCodecParameters,
+    RtcRtpHeaderExtensionCapability, RtcRtpHeaderExtensionParameters,
+};
+use crate::rtcrtptransceiver::MediaKind;
+
+use failure::Fail;
+
+// one-byte headerで使えるID (RFC 8285 4.2)
+const MAX_HEADER_EXTENSION_ID: usize = 14;
+
+const VIDEO_RTCP_FEEDBACK: [(&str, Option<&str>); 5] = [
+    ("goog-remb", None),
+    ("transport-cc", None),
+    ("ccm", Some("fir")),
+    ("nack", None),
+    ("nack", Some("pli")),
+];
+
+pub type Result<T> = std::result::Result<T, MediaEngineError>;
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum MediaEngineError {
+    #[fail(display = "{} is not an {} codec.", mime_type, kind)]
+    InvalidMimeType { mime_type: String, kind: String },
+
+    #[fail(display = "Payload type {} is already used.", payload_type)]
+    PayloadTypeInUse { payload_type: usize },
+
+    #[fail(display = "Codec has no payload type.")]
+    NoPayloadType,
+
+    #[fail(display = "Payload type {} is out of range.", payload_type)]
+    InvalidPayloadType { payload_type: usize },
+
+    #[fail(display = "No header extension IDs are left.")]
+    NoHeaderExtensionId,
+}
+
+#[derive(Debug, Clone)]
+pub struct MediaEngine {
+    audio_codecs: Vec<RtcRtpCodecParameters>,
+    video_codecs: Vec<RtcRtpCodecParameters>,
+    audio_header_extensions: Vec<RtcRtpHeaderExtensionParameters>,
+    video_header_extensions: Vec<RtcRtpHeaderExtensionParameters>,
+}
+
+impl Default for MediaEngine {
+    fn default() -> Self {
+        let mut media_engine = MediaEngine::new();
+        media_engine.register_default_codecs();
+        media_engine.register_default_header_extensions();
+        media_engine
+    }
+}
+
+impl MediaEngine {
+    // 何も登録されていない
+    pub fn new() -> MediaEngine {
+        MediaEngine {
+            audio_codecs: vec![],
+            video_codecs: vec![],
+            audio_header_extensions: vec![],
+            video_header_extensions: vec![],
+        }
+    }
+
+    // PTはChromeと同じにする
+    // 既に登録したcodecとPTが重なるものは飛ばし，そのrtxも登録しない．他は全て登録する
+    pub fn register_default_codecs(&mut self) {
+        let audio = vec![
+            codec(
+                "audio/opus",
+                48000,
+                Some(2),
+                111,
+                &[("transport-cc", None)],
+                "minptime=10;useinbandfec=1",
+            ),
+            codec("audio/G722", 8000, None, 9, &[], ""),
+            codec("audio/PCMU", 8000, None, 0, &[], ""),
+            codec("audio/PCMA", 8000, None, 8, &[], ""),
+            codec("audio/telephone-event", 48000, None, 110, &[], ""),
+            codec("audio/telephone-event", 8000, None, 126, &[], ""),
+        ];
+
+        let feedback = &VIDEO_RTCP_FEEDBACK;
+        let h264 = |profile_level_id: &str, packetization_mode: usize, payload_type| {
+            let fmtp = format!(
+                "level-asymmetry-allowed=1;packetization-mode={};profile-level-id={}",
+                packetization_mode, profile_level_id
+            );
+            codec("video/H264", 90000, None, payload_type, feedback, &fmtp)
+        };
+        let video = vec![
+            (codec("video/VP8", 90000, None, 96, feedback, ""), Some(97)),
+            (
+                codec("video/VP9", 90000, None, 98, feedback, "profile-id=0"),
+                Some(99),
+            ),
+            (
+                codec("video/VP9", 90000, None, 100, feedback, "profile-id=2"),
+                Some(101),
+            ),
+            // baseline, constrained baseline, main, highの順
+            (h264("42001f", 1, 102), Some(122)),
+            (h264("42001f", 0, 127), Some(121)),
+            (h264("42e01f", 1, 125), Some(107)),
+            (h264("42e01f", 0, 108), Some(109)),
+            (h264("4d001f", 1, 124), Some(120)),
+            (h264("64001f", 1, 123), Some(119)),
+            // Chromeの35はwebrtc_sdpがparseできないので動的PTの空きを使う
+            (
+                codec("video/AV1", 90000, None, 117, feedback, ""),
+                Some(118),
+            ),
+            (codec("video/red", 90000, None, 114, &[], ""), Some(115)),
+            (codec("video/ulpfec", 90000, None, 116, &[], ""), None),
+        ];
+
+        for codec in audio {
+            if let Err(e) = self.register_codec(MediaKind::Audio, codec) {
+                log::debug!("skipping a default audio codec: {}", e);
+            }
+        }
+        for (codec, rtx) in video {
+            let payload_type = codec.payload_type;
+            if let Err(e) = self.register_codec(MediaKind::Video, codec) {
+                log::debug!("skipping a default video codec: {}", e);
+                continue;
+            }
+            if let (Some(apt), Some(rtx)) = (payload_type, rtx) {
+                let fmtp = format!("apt={}", apt);
+                let rtx = self::codec("video/rtx", 90000, None, rtx, &[], &fmtp);
+                if let Err(e) = self.register_codec(MediaKind::Video, rtx) {
+                    log::debug!("skipping a default rtx codec: {}", e);
+                }
+            }
+        }
+    }
+
+    // 登録済みのURIは同じIDのまま．IDが足りなくなったものは飛ばす
+    pub fn register_default_header_extensions(&mut self) {
+        let audio = [
+            "urn:ietf:params:rtp-hdrext:sdes:mid",
+            "urn:ietf:params:rtp-hdrext:ssrc-audio-level",
+            "http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time",
+            "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01",
+        ];
+        let video = [
+            "urn:ietf:params:rtp-hdrext:sdes:mid",
+            "http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time",
+            "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01",
+            "urn:ietf:params:rtp-hdrext:toffset",
+            "urn:3gpp:video-orientation",
+            "urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id",
+            "urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id",
+        ];
+        let extensions = audio
+            .iter()
+            .map(|uri| (MediaKind::Audio, uri))
+            .chain(video.iter().map(|uri| (MediaKind::Video, uri)));
+        for (kind, uri) in extensions {
+            if let Err(e) = self.register_header_extension(kind, uri) {
+                log::debug!("skipping a default header extension {}: {}", uri, e);
+            }
+        }
+    }
+
+    // 後に登録したものほど優先度が低い
+    pub fn register_codec(&mut self, kind: MediaKind, codec: RtcRtpCodecParameters) -> Result<()> {
+        let prefix = format!("{}/", kind);
+        if !codec.mime_type.to_lowercase().starts_with(&prefix) {
+            return Err(MediaEngineError::InvalidMimeType {
+                mime_type: codec.mime_type,
+                kind: kind.to_string(),
+            });
+        }
+        let payload_type = codec.payload_type.ok_or(MediaEngineError::NoPayloadType)?;
+        // RTPのPTは7bit
+        if payload_type > 127 {
+            return Err(MediaEngineError::InvalidPayloadType { payload_type });
+        }
+        if self
+            .audio_codecs
+            .iter()
+            .chain(self.video_codecs.iter())
+            .any(|codec| codec.payload_type == Some(payload_type))
+        {
+            return Err(MediaEngineError::PayloadTypeInUse { payload_type });
+        }
+
+        self.codecs_mut(kind).push(codec);
+        Ok(())
+    }
+
+    // 同じURIはkindが違っても同じIDにする
+    pub fn register_header_extension(&mut self, kind: MediaKind, uri: &str) -> Result<usize> {
+        if let Some(extension) = self
+            .header_extensions(kind)
+            .iter()
+            .find(|extension| extension.uri == uri)
+        {
+            return Ok(extension.id);
+        }
+
+        let registered = self
+            .audio_header_extensions
+            .iter()
+            .chain(self.video_header_extensions.iter())
+            .collect::<Vec<_>>();
+        let id = match registered.iter().find(|extension| extension.uri == uri) {
+            Some(extension) => extension.id,
+            None => (1..=MAX_HEADER_EXTENSION_ID)
+                .find(|id| registered.iter().all(|extension| extension.id != *id))
+                .ok_or(MediaEngineError::NoHeaderExtensionId)?,
+        };
+
+        let extension = RtcRtpHeaderExtensionParameters {
+            id,
+            uri: uri.to_owned(),
+        };
+        match kind {
+            MediaKind::Audio => self.audio_header_extensions.push(extension),
+            MediaKind::Video => self.video_header_extensions.push(extension),
+        }
+        Ok(id)
+    }
+
+    // mime typeが同じcodecを全て消す．それを再送するrtxも消える
+    pub fn remove_codec(&mut self, mime_type: &str) {
+        self.remove_codecs(|codec| codec.mime_type.eq_ignore_ascii_case(mime_type));
+    }
+
+    // PTが同じcodecを消す．それを再送するrtxも消える
+    pub fn remove_codec_by_payload_type(&mut self, payload_type: usize) {
+        self.remove_codecs(|codec| codec.payload_type == Some(payload_type));
+    }
+
+    fn remove_codecs<F>(&mut self, matches: F)
+    where
+        F: Fn(&RtcRtpCodecParameters) -> bool,
+    {
+        for kind in [MediaKind::Audio, MediaKind::Video].iter() {
+            let codecs = self.codecs_mut(*kind);
+            let removed = codecs
+                .iter()
+                .filter(|codec| matches(codec))
+                .filter_map(|codec| codec.payload_type)
+                .map(|payload_type| payload_type.to_string())
+                .collect::<Vec<_>>();
+            codecs.retain(|codec| {
+                !matches(codec)
+                    && !codec
+                        .parameter("apt")
+                        .map_or(false, |apt| removed.iter().any(|removed| removed == apt))
+            });
+        }
+    }
+
+    pub fn remove_header_extension(&mut self, uri: &str) {
+        self.audio_header_extensions
+            .retain(|extension| extension.uri != uri);
+        self.video_header_extensions
+            .retain(|extension| extension.uri != uri);
+    }
+
+    pub fn codecs(&self, kind: MediaKind) -> &[RtcRtpCodecParameters] {
+        match kind {
+            MediaKind::Audio => &self.audio_codecs,
+            MediaKind::Video => &self.video_codecs,
+        }
+    }
+
+    pub fn header_extensions(&self, kind: MediaKind) -> &[RtcRtpHeaderExtensionParameters] {
+        match kind {
+            MediaKind::Audio => &self.audio_header_extensions,
+            MediaKind::Video => &self.video_header_extensions,
+        }
+    }
+
+    // RTCRtpSender.getCapabilities()．rtxのようにPTだけ違うものは一つにまとめる
+    pub fn capabilities(&self, kind: MediaKind) -> RtcRtpCapabilities {
+        let mut codecs: Vec<RtcRtpCodecCapability> = vec![];
+        for codec in self.codecs(kind) {
+            let mut capability = RtcRtpCodecCapability::from(codec);
+            if capability.name().eq_ignore_ascii_case("rtx") {
+                capability.parameters.clear();
+            }
+            if !codecs.contains(&capability) {
+                codecs.push(capability);
+            }
+        }
+        RtcRtpCapabilities {
+            codecs,
+            header_extensions: self
+                .header_extensions(kind)
+                .iter()
+                .map(|extension| RtcRtpHeaderExtensionCapability::new(&extension.uri))
+                .collect(),
+        }
+    }
+
+    fn codecs_mut(&mut self, kind: MediaKind) -> &mut Vec<RtcRtpCodecParameters> {
+        match kind {
+            MediaKind::Audio => &mut self.audio_codecs,
+            MediaKind::Video => &mut self.video_codecs,
+        }
+    }
+}
+
+fn codec(
+    mime_type: &str,
+    clock_rate: u64,
+    channels: Option<usize>,
+    payload_type: usize,
+    rtcp_feedback: &[(&str, Option<&str>)],
+    fmtp: &str,
+) -> RtcRtpCodecParameters {
+    let mut codec = RtcRtpCodecParameters::new(mime_type, clock_rate, channels, payload_type);
+    codec.rtcp_feedback = rtcp_feedback
+        .iter()
+        .map(|(kind, param)| RtcRtcpFeedback::new(kind, *param))
+        .collect();
+    codec.parameters = fmtp
+        .split(';')
+        .filter(|parameter| !parameter.is_empty())
+        .map(|parameter| {
+            let mut pair = parameter.splitn(2, '=');
+            let key = pair.next().unwrap_or_default().to_owned();
+            (key, pair.next().map(str::to_owned))
+        })
+        .collect();
+    codec
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sdp::fixtures::VIDEO_CHROME;
+    use crate::sdp::negotiation::negotiate;
+
+    #[test]
+    fn default_codecs_test() {
+        let media_engine = MediaEngine::default();
+
+        let audio = media_engine.codecs(MediaKind::Audio);
+        assert_eq!(
+            audio
+                .iter()
+                .map(|codec| (codec.payload_type.unwrap(), codec.to_string()))
+                .collect::<Vec<_>>(),
+            vec![
+                (111, "opus/48000/2".to_owned()),
+                (9, "G722/8000".to_owned()),
+                (0, "PCMU/8000".to_owned()),
+                (8, "PCMA/8000".to_owned()),
+                (110, "telephone-event/48000".to_owned()),
+                (126, "telephone-event/8000".to_owned()),
+            ]
+        );
+        assert_eq!(
+            audio[0].fmtp().as_deref(),
+            Some("minptime=10;useinbandfec=1")
+        );
+
+        let video = media_engine.codecs(MediaKind::Video);
+        assert_eq!(video.len(), 23);
+        for codec in video.iter().filter(|codec| codec.name() == "rtx") {
+            let apt = codec.parameter("apt").unwrap().parse().ok();
+            assert!(video.iter().any(|codec| codec.payload_type == apt));
+        }
+        let vp8 = &video[0];
+        assert_eq!(vp8.to_string(), "VP8/90000");
+        assert!(vp8
+            .rtcp_feedback
+            .contains(&RtcRtcpFeedback::new("nack", Some("pli"))));
+        assert!(vp8
+            .rtcp_feedback
+            .contains(&RtcRtcpFeedback::new("ccm", Some("fir"))));
+
+        // PTは重ならない
+        let mut payload_types = audio
+            .iter()
+            .chain(video.iter())
+            .filter_map(|codec| codec.payload_type)
+            .collect::<Vec<_>>();
+        payload_types.sort();
+        payload_types.dedup();
+        assert_eq!(payload_types.len(), audio.len() + video.len());
+
+        // 同じURIは同じID
+        let mid = |kind| {
+            media_engine
+                .header_extensions(kind)
+                .iter()
+                .find(|extension| extension.uri == "urn:ietf:params:rtp-hdrext:sdes:mid")
+                .map(|extension| extension.id)
+        };
+        assert_eq!(mid(MediaKind::Audio), Some(1));
+        assert_eq!(mid(MediaKind::Video), Some(1));
+    }
+
+    #[test]
+    fn register_codec_test() {
+        let mut media_engine = MediaEngine::default();
+
+        let multiopus = RtcRtpCodecParameters::new("audio/multiopus", 48000, Some(6), 111);
+        assert_eq!(
+            media_engine.register_codec(MediaKind::Audio, multiopus.clone()),
+            Err(MediaEngineError::PayloadTypeInUse { payload_type: 111 })
+        );
+        assert_eq!(
+            media_engine.register_codec(MediaKind::Video, multiopus.clone()),
+            Err(MediaEngineError::InvalidMimeType {
+                mime_type: "audio/multiopus".to_owned(),
+                kind: "video".to_owned(),
+            })
+        );
+        assert_eq!(
+            media_engine.register_codec(
+                MediaKind::Audio,
+                RtcRtpCodecParameters {
+                    payload_type: Some(128),
+                    ..multiopus.clone()
+                }
+            ),
+            Err(MediaEngineError::InvalidPayloadType { payload_type: 128 })
+        );
+        let multiopus = RtcRtpCodecParameters {
+            payload_type: Some(63),
+            ..multiopus
+        };
+        media_engine
+            .register_codec(MediaKind::Audio, multiopus.clone())
+            .unwrap();
+        assert_eq!(
+            media_engine.codecs(MediaKind::Audio).last(),
+            Some(&multiopus)
+        );
+
+        // H.264を使わない
+        media_engine.remove_codec("video/H264");
+        let video = media_engine.codecs(MediaKind::Video);
+        assert!(video.iter().all(|codec| codec.name() != "H264"));
+        assert_eq!(video.len(), 11);
+        assert!(video
+            .iter()
+            .filter_map(|codec| codec.parameter("apt"))
+            .all(|apt| ["96", "98", "100", "117", "114"].contains(&apt)));
+
+        // PTで一つだけ消す
+        media_engine.remove_codec_by_payload_type(98);
+        let video = media_engine.codecs(MediaKind::Video);
+        assert!(video.iter().all(|codec| codec.payload_type != Some(98)));
+        assert!(video
+            .iter()
+            .all(|codec| codec.parameter("apt") != Some("98")));
+        assert!(video.iter().any(|codec| codec.payload_type == Some(100)));
+        assert_eq!(video.len(), 9);
+
+        // 先に登録したcodecとPTが重なるdefaultのcodecとそのrtxだけ飛ばす
+        let mut engine = MediaEngine::new();
+        let vp8 = RtcRtpCodecParameters::new("video/VP8", 90000, None, 102);
+        engine
+            .register_codec(MediaKind::Video, vp8.clone())
+            .unwrap();
+        engine.register_default_codecs();
+        let video = engine.codecs(MediaKind::Video);
+        assert_eq!(video[0], vp8);
+        assert!(video
+            .iter()
+            .all(|codec| codec.parameter("apt") != Some("102")));
+        assert!(video.iter().all(|codec| codec.payload_type != Some(122)));
+        assert!(video.iter().any(|codec| codec.payload_type == Some(127)));
+        assert_eq!(
+            video.len(),
+            MediaEngine::default().codecs(MediaKind::Video).len() - 1
+        );
+
+        let id = media_engine
+            .register_header_extension(MediaKind::Audio, "urn:ietf:params:rtp-hdrext:toffset")
+            .unwrap();
+        assert_eq!(
+            Some(id),
+            media_engine
+                .header_extensions(MediaKind::Video)
+                .iter()
+                .find(|extension| extension.uri == "urn:ietf:params:rtp-hdrext:toffset")
+                .map(|extension| extension.id)
+        );
+        media_engine.remove_header_extension("urn:ietf:params:rtp-hdrext:toffset");
+        assert!(media_engine
+            .header_extensions(MediaKind::Audio)
+            .iter()
+            .chain(media_engine.header_extensions(MediaKind::Video).iter())
+            .all(|extension| extension.uri != "urn:ietf:params:rtp-hdrext:toffset"));
+
+        let mut media_engine = MediaEngine::new();
+        for i in 0..MAX_HEADER_EXTENSION_ID {
+            media_engine
+                .register_header_extension(MediaKind::Video, &format!("urn:example:{}", i))
+                .unwrap();
+        }
+        assert_eq!(
+            media_engine.register_header_extension(MediaKind::Audio, "urn:example:audio"),
+            Err(MediaEngineError::NoHeaderExtensionId)
+        );
+    }
+
+    #[test]
+    fn capabilities_test() {
+        let media_engine = MediaEngine::default();
+
+        let capabilities = media_engine.capabilities(MediaKind::Video);
+        let rtx = capabilities
+            .codecs
+            .iter()
+            .filter(|codec| codec.name() == "rtx")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rtx,
+            vec![&RtcRtpCodecCapability::new("video/rtx", 90000, None)]
+        );
+
+        // Chromeのofferの全てのcodecとheader extensionを使える
+        let media = webrtc_sdp::parse_sdp(VIDEO_CHROME, true)
+            .unwrap()
+            .media
+            .remove(0);
+        let parameters = negotiate(&capabilities, &media).unwrap();
+        assert_eq!(parameters.param.codecs.len(), 21);
+        assert_eq!(parameters.param.header_extensions.len(), 7);
+    }
+}
@@ -0,0 +1,30 @@
+// https://www.w3.org/TR/mediacapture-streams/#mediastreamtrack
+
+use crate::rtcrtptransceiver::{random_id, MediaKind};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MediaStreamTrack {
+    kind: MediaKind,
+    id: String,
+}
+
+impl MediaStreamTrack {
+    pub fn new(kind: MediaKind) -> MediaStreamTrack {
+        MediaStreamTrack::with_id(kind, &random_id())
+    }
+
+    pub fn with_id(kind: MediaKind, id: &str) -> MediaStreamTrack {
+        MediaStreamTrack {
+            kind,
+            id: id.to_owned(),
+        }
+    }
+
+    pub fn kind(&self) -> MediaKind {
+        self.kind
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
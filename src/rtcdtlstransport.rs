@@ -1,122 +1,674 @@
-const SRTP_KEY_LEN: usize = 16;
-const SRTP_SALT_LEN: usize = 14;
+use crate::rtccertificate::RtcCertificate;
+use crate::srtp;
+use crate::srtp::protection_profile::ProtectionProfile;
 
-struct RtcDtlsTransport<Transport> {
-    transport: Transport,
+use failure::Fail;
+use foreign_types::ForeignTypeRef;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::ssl::{
+    HandshakeError, Ssl, SslContext, SslMethod, SslOptions, SslRef, SslStream, SslStreamBuilder,
+    SslVerifyMode,
+};
+use openssl::x509::{X509VerifyResult, X509};
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::ptr;
+use std::str::FromStr;
+use std::time::Duration;
+
+// use_srtp extensionで提示するprofile (優先度順)
+const SRTP_PROFILES: [ProtectionProfile; 3] = [
+    ProtectionProfile::AeadAes128Gcm,
+    ProtectionProfile::Aes128CmHmacSha1_80,
+    ProtectionProfile::Aes128CmHmacSha1_32,
+];
+
+// https://tools.ietf.org/html/rfc5764#section-4.2
+const SRTP_EXPORTER_LABEL: &str = "EXTRACTOR-dtls_srtp";
+
+// ICEの上で送るのでIPv6の最小MTUより十分小さくしておく
+const DTLS_MTU: usize = 1200;
+
+// handshake中はこの間隔でreadを打ち切り，再送timerを確認する
+const DTLS_READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+// DTLSv1_handle_timeout()はmacroなのでSSL_ctrlのcommandを直接使う
+const DTLS_CTRL_HANDLE_TIMEOUT: libc::c_int = 74;
+
+pub type Result<T> = std::result::Result<T, RtcDtlsError>;
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum RtcDtlsError {
+    #[fail(display = "OpenSSL failed: {}", reason)]
+    CryptoError { reason: String },
+
+    #[fail(display = "DTLS handshake failed: {}", reason)]
+    HandshakeFailed { reason: String },
+
+    #[fail(display = "Remote certificate does not match the fingerprint.")]
+    FingerprintMismatch,
+
+    #[fail(display = "Certificate or private key is invalid.")]
+    InvalidCertificate,
+
+    #[fail(display = "Fingerprint is invalid.")]
+    InvalidFingerprint,
+
+    #[fail(display = "Fingerprint hash algorithm {} is not supported.", algorithm)]
+    UnsupportedHashAlgorithm { algorithm: String },
+
+    #[fail(display = "SRTP protection profile is not negotiated.")]
+    NoSrtpProfile,
+
+    #[fail(display = "DTLS transport is in invalid state: {:?}", state)]
+    InvalidState { state: RtcDtlsTransportState },
+
+    #[fail(display = "DTLS transport I/O failed: {}", reason)]
+    IoError { reason: String },
+
+    #[fail(display = "SRTP failed: {:?}", error)]
+    SrtpError { error: srtp::SrtpError },
+}
+
+impl From<ErrorStack> for RtcDtlsError {
+    fn from(error: ErrorStack) -> Self {
+        RtcDtlsError::CryptoError {
+            reason: error.to_string(),
+        }
+    }
 }
 
-impl<Transport> RtcDtlsTransport<Transport> {
-    fn new(transport: Transport) -> RtcDtlsTransport<Transport> {
+impl From<srtp::SrtpError> for RtcDtlsError {
+    fn from(error: srtp::SrtpError) -> Self {
+        RtcDtlsError::SrtpError { error }
+    }
+}
+
+// https://www.w3.org/TR/webrtc/#rtcdtlstransportstate-enum
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RtcDtlsTransportState {
+    New,
+    Connecting,
+    Connected,
+    Closed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RtcDtlsRole {
+    Client,
+    Server,
+}
+
+// SDPのa=fingerprint
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RtcDtlsFingerprint {
+    pub algorithm: String,
+    // コロン区切りの大文字16進数
+    pub value: String,
+}
+
+impl RtcDtlsFingerprint {
+    pub fn new(algorithm: &str, value: &str) -> RtcDtlsFingerprint {
+        RtcDtlsFingerprint {
+            algorithm: algorithm.to_lowercase(),
+            value: value.to_uppercase(),
+        }
+    }
+
+    // 証明書のDERをalgorithmでhashする
+    pub fn from_certificate(algorithm: &str, certificate: &X509) -> Result<RtcDtlsFingerprint> {
+        let digest = certificate.digest(hash_algorithm(algorithm)?)?;
+        let value = digest
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        Ok(RtcDtlsFingerprint::new(algorithm, &value))
+    }
+
+    pub fn matches(&self, certificate: &X509) -> bool {
+        match RtcDtlsFingerprint::from_certificate(&self.algorithm, certificate) {
+            Ok(v) => v == *self,
+            Err(_) => false,
+        }
+    }
+}
+
+impl FromStr for RtcDtlsFingerprint {
+    type Err = RtcDtlsError;
+
+    // "sha-256 AB:CD:..."
+    fn from_str(s: &str) -> Result<RtcDtlsFingerprint> {
+        let mut fields = s.split_whitespace();
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(algorithm), Some(value), None) => Ok(RtcDtlsFingerprint::new(algorithm, value)),
+            _ => Err(RtcDtlsError::InvalidFingerprint),
+        }
+    }
+}
+
+impl fmt::Display for RtcDtlsFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.algorithm, self.value)
+    }
+}
+
+fn hash_algorithm(algorithm: &str) -> Result<MessageDigest> {
+    match algorithm.to_lowercase().as_str() {
+        "sha-1" => Ok(MessageDigest::sha1()),
+        "sha-224" => Ok(MessageDigest::sha224()),
+        "sha-256" => Ok(MessageDigest::sha256()),
+        "sha-384" => Ok(MessageDigest::sha384()),
+        "sha-512" => Ok(MessageDigest::sha512()),
+        _ => Err(RtcDtlsError::UnsupportedHashAlgorithm {
+            algorithm: algorithm.to_string(),
+        }),
+    }
+}
+
+// DTLS-SRTPでexportしたkeying material
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RtcSrtpKeys {
+    pub profile: ProtectionProfile,
+    pub client_key: Vec<u8>,
+    pub client_salt: Vec<u8>,
+    pub server_key: Vec<u8>,
+    pub server_salt: Vec<u8>,
+}
+
+impl RtcSrtpKeys {
+    // https://tools.ietf.org/html/rfc5764#section-4.2
+    // client_key | server_key | client_salt | server_salt
+    fn from_keying_material(profile: ProtectionProfile, material: &[u8]) -> RtcSrtpKeys {
+        let key_len = profile.key_len();
+        let salt_len = profile.salt_len();
+
+        let (client_key, rest) = material.split_at(key_len);
+        let (server_key, rest) = rest.split_at(key_len);
+        let (client_salt, server_salt) = rest.split_at(salt_len);
+
+        RtcSrtpKeys {
+            profile,
+            client_key: client_key.to_vec(),
+            client_salt: client_salt.to_vec(),
+            server_key: server_key.to_vec(),
+            server_salt: server_salt.to_vec(),
+        }
+    }
+}
+
+// DTLSを載せるdatagram transport．
+// 1回のwrite/readが1つのdatagramに対応し，readがtimeoutした場合はWouldBlockを返す必要がある．
+pub trait DatagramTransport: Read + Write {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+pub struct RtcDtlsTransport<Transport> {
+    state: RtcDtlsTransportState,
+    certificate: RtcCertificate,
+    role: Option<RtcDtlsRole>,
+    transport: Option<Transport>,
+    stream: Option<SslStream<Transport>>,
+    srtp_keys: Option<RtcSrtpKeys>,
+}
+
+impl<Transport: DatagramTransport> RtcDtlsTransport<Transport> {
+    pub fn new(transport: Transport, certificate: RtcCertificate) -> RtcDtlsTransport<Transport> {
         RtcDtlsTransport {
-            transport: transport,
+            state: RtcDtlsTransportState::New,
+            certificate,
+            role: None,
+            transport: Some(transport),
+            stream: None,
+            srtp_keys: None,
         }
     }
+
+    pub fn state(&self) -> RtcDtlsTransportState {
+        self.state
+    }
+
+    pub fn role(&self) -> Option<RtcDtlsRole> {
+        self.role
+    }
+
+    pub fn certificate(&self) -> &RtcCertificate {
+        &self.certificate
+    }
+
+    pub fn local_fingerprint(&self, algorithm: &str) -> Result<RtcDtlsFingerprint> {
+        self.certificate.fingerprint(algorithm)
+    }
+
+    pub fn remote_certificate(&self) -> Option<X509> {
+        self.stream.as_ref()?.ssl().peer_certificate()
+    }
+
+    // handshakeが終わるまでblockする．
+    // remote_fingerprintsのいずれかに一致する証明書のみを受け入れる．
+    pub fn start(
+        &mut self,
+        role: RtcDtlsRole,
+        remote_fingerprints: &[RtcDtlsFingerprint],
+    ) -> Result<()> {
+        let mut transport = match (self.state, self.transport.take()) {
+            (RtcDtlsTransportState::New, Some(transport)) => transport,
+            (state, _) => return Err(RtcDtlsError::InvalidState { state }),
+        };
+
+        self.state = RtcDtlsTransportState::Connecting;
+        self.role = Some(role);
+
+        let ssl = match self.create_ssl(remote_fingerprints) {
+            Ok(ssl) => ssl,
+            Err(e) => {
+                self.state = RtcDtlsTransportState::Failed;
+                return Err(e);
+            }
+        };
+
+        if let Err(e) = transport.set_read_timeout(Some(DTLS_READ_TIMEOUT)) {
+            self.state = RtcDtlsTransportState::Failed;
+            return Err(io_error(e));
+        }
+
+        let mut builder = SslStreamBuilder::new(ssl, transport);
+        builder.set_dtls_mtu_size(DTLS_MTU);
+
+        let mut result = match role {
+            RtcDtlsRole::Client => builder.connect(),
+            RtcDtlsRole::Server => builder.accept(),
+        };
+
+        // custom BIOではOpenSSLが再送timerを見ないので，readのtimeoutごとに再送させる
+        let mut stream = loop {
+            match result {
+                Ok(stream) => break stream,
+                Err(HandshakeError::WouldBlock(mid)) => {
+                    if let Err(e) = handle_timeout(mid.ssl()) {
+                        self.state = RtcDtlsTransportState::Failed;
+                        return Err(e);
+                    }
+                    result = mid.handshake();
+                }
+                Err(e) => {
+                    self.state = RtcDtlsTransportState::Failed;
+                    return Err(handshake_error(e));
+                }
+            }
+        };
+
+        // handshake後のrecvはblockする
+        if let Err(e) = stream.get_mut().set_read_timeout(None) {
+            self.state = RtcDtlsTransportState::Failed;
+            return Err(io_error(e));
+        }
+
+        if let Some(profile) = stream.ssl().selected_srtp_profile() {
+            let profile = ProtectionProfile::from_id(profile.id().as_raw() as u16)?;
+            let mut material = vec![0u8; (profile.key_len() + profile.salt_len()) * 2];
+            stream
+                .ssl()
+                .export_keying_material(&mut material, SRTP_EXPORTER_LABEL, None)?;
+            self.srtp_keys = Some(RtcSrtpKeys::from_keying_material(profile, &material));
+        }
+
+        self.stream = Some(stream);
+        self.state = RtcDtlsTransportState::Connected;
+
+        Ok(())
+    }
+
+    fn create_ssl(&self, remote_fingerprints: &[RtcDtlsFingerprint]) -> Result<Ssl> {
+        let mut ctx = SslContext::builder(SslMethod::dtls())?;
+        ctx.set_options(SslOptions::NO_DTLSV1);
+        ctx.set_certificate(self.certificate.certificate())?;
+        ctx.set_private_key(self.certificate.private_key())?;
+        ctx.check_private_key()?;
+
+        let profiles = SRTP_PROFILES
+            .iter()
+            .map(|p| p.name())
+            .collect::<Vec<_>>()
+            .join(":");
+        ctx.set_tlsext_use_srtp(&profiles)?;
+
+        // 自己署名証明書なので，chainの検証ではなくfingerprintで検証する
+        let fingerprints = remote_fingerprints.to_vec();
+        ctx.set_verify_callback(
+            SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+            move |_, store| {
+                if store.error_depth() != 0 {
+                    return true;
+                }
+
+                let matched = match store.current_cert() {
+                    Some(cert) => fingerprints.iter().any(|f| f.matches(&cert.to_owned())),
+                    None => false,
+                };
+                if matched {
+                    store.set_error(X509VerifyResult::OK);
+                } else {
+                    store.set_error(X509VerifyResult::APPLICATION_VERIFICATION);
+                }
+                matched
+            },
+        );
+
+        Ok(Ssl::new(&ctx.build())?)
+    }
+
+    pub fn srtp_keys(&self) -> Option<&RtcSrtpKeys> {
+        self.srtp_keys.as_ref()
+    }
+
+    // (送信用, 受信用)のSRTP context
+    pub fn srtp_contexts(&self) -> Result<(srtp::context::Context, srtp::context::Context)> {
+        let keys = self.srtp_keys.as_ref().ok_or(RtcDtlsError::NoSrtpProfile)?;
+
+        let client =
+            srtp::context::Context::new(&keys.client_key, &keys.client_salt, keys.profile)?;
+        let server =
+            srtp::context::Context::new(&keys.server_key, &keys.server_salt, keys.profile)?;
+
+        match self.role {
+            Some(RtcDtlsRole::Client) => Ok((client, server)),
+            Some(RtcDtlsRole::Server) => Ok((server, client)),
+            None => Err(RtcDtlsError::InvalidState { state: self.state }),
+        }
+    }
+
+    // application data (SCTPなど) を送る
+    pub fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let state = self.state;
+        let stream = match (state, self.stream.as_mut()) {
+            (RtcDtlsTransportState::Connected, Some(stream)) => stream,
+            _ => return Err(RtcDtlsError::InvalidState { state }),
+        };
+
+        stream.ssl_write(data).map_err(io_error)
+    }
+
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let state = self.state;
+        let stream = match (state, self.stream.as_mut()) {
+            (RtcDtlsTransportState::Connected, Some(stream)) => stream,
+            _ => return Err(RtcDtlsError::InvalidState { state }),
+        };
+
+        stream.ssl_read(buf).map_err(io_error)
+    }
+
+    // close_notifyを送って閉じる
+    pub fn close(&mut self) {
+        if let Some(mut stream) = self.stream.take() {
+            let _ = stream.shutdown();
+        }
+        self.state = RtcDtlsTransportState::Closed;
+    }
+}
+
+fn io_error<E: fmt::Display>(error: E) -> RtcDtlsError {
+    RtcDtlsError::IoError {
+        reason: error.to_string(),
+    }
+}
+
+// 再送timerが切れていればflightを再送する．再送回数の上限に達した場合はerror．
+fn handle_timeout(ssl: &SslRef) -> Result<()> {
+    let ret = unsafe {
+        openssl_sys::SSL_ctrl(ssl.as_ptr(), DTLS_CTRL_HANDLE_TIMEOUT, 0, ptr::null_mut())
+    };
+    if ret < 0 {
+        return Err(RtcDtlsError::HandshakeFailed {
+            reason: "DTLS handshake timed out".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn handshake_error<S>(error: HandshakeError<S>) -> RtcDtlsError {
+    match error {
+        HandshakeError::Failure(stream)
+            if stream.ssl().verify_result() == X509VerifyResult::APPLICATION_VERIFICATION =>
+        {
+            RtcDtlsError::FingerprintMismatch
+        }
+        HandshakeError::Failure(stream) => RtcDtlsError::HandshakeFailed {
+            reason: stream.error().to_string(),
+        },
+        HandshakeError::SetupFailure(e) => RtcDtlsError::HandshakeFailed {
+            reason: e.to_string(),
+        },
+        HandshakeError::WouldBlock(stream) => RtcDtlsError::HandshakeFailed {
+            reason: stream.error().to_string(),
+        },
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use openssl::ec;
-
-    // #[test]
-    // fn gen_certificate() {
-    //     use openssl::bn::BigNumContext;
-    //     use openssl::ec::*;
-    //     use openssl::nid::Nid;
-    //     use openssl::pkey::PKey;
-
-    //     // get bytes from somewhere, i.e. this will not produce a valid key
-    //     let public_key: Vec<u8> = vec![];
-
-    //     // create an EcKey from the binary form of a EcPoint
-    //     let group = EcGroup::from_curve_name(Nid::SECP256K1).unwrap();
-    //     let mut ctx = BigNumContext::new().unwrap();
-    //     let point = EcPoint::from_bytes(&group, &public_key, &mut ctx).unwrap();
-    //     let key = EcKey::from_public_key(&group, &point);
-    // }
-    /*
-    DTLS STEPS:
-    1. generate private key with SECP256R1 (aiortc, pion)
-        ※ SECP256r1 is called prime256v1 in openssl
-        // https://stackoverflow.com/questions/41950056/openssl1-1-0-b-is-not-support-secp256r1openssl-ecparam-list-curves
-    2. generate x509 certificate
-        2.1 hash is SHA256
-    */
+    use super::*;
+    use crate::rtccertificate::RtcCertificateAlgorithm;
+    use crate::rtp::packet::RtpPacketBuilder;
+
+    use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+    use std::thread;
+    use std::time::Instant;
+
+    // 1回のwriteを1つのdatagramとして相手に渡す
+    struct MemoryTransport {
+        tx: Sender<Vec<u8>>,
+        rx: Receiver<Vec<u8>>,
+        read_timeout: Option<Duration>,
+        // 最初のn個のdatagramを捨てる
+        drop_count: usize,
+    }
+
+    impl Read for MemoryTransport {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let closed = || io::Error::new(io::ErrorKind::ConnectionAborted, "closed");
+            let datagram = match self.read_timeout {
+                Some(timeout) => self.rx.recv_timeout(timeout).map_err(|e| match e {
+                    RecvTimeoutError::Timeout => io::Error::from(io::ErrorKind::WouldBlock),
+                    RecvTimeoutError::Disconnected => closed(),
+                })?,
+                None => self.rx.recv().map_err(|_| closed())?,
+            };
+            let len = datagram.len().min(buf.len());
+            buf[..len].copy_from_slice(&datagram[..len]);
+            Ok(len)
+        }
+    }
+
+    impl Write for MemoryTransport {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.drop_count > 0 {
+                self.drop_count -= 1;
+                return Ok(buf.len());
+            }
+            self.tx
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::new(io::ErrorKind::ConnectionAborted, "closed"))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl DatagramTransport for MemoryTransport {
+        fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+            self.read_timeout = timeout;
+            Ok(())
+        }
+    }
+
+    fn memory_transport_pair() -> (MemoryTransport, MemoryTransport) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        (
+            MemoryTransport {
+                tx: a_tx,
+                rx: a_rx,
+                read_timeout: None,
+                drop_count: 0,
+            },
+            MemoryTransport {
+                tx: b_tx,
+                rx: b_rx,
+                read_timeout: None,
+                drop_count: 0,
+            },
+        )
+    }
+
+    type TestTransport = RtcDtlsTransport<MemoryTransport>;
+
+    fn ecdsa_certificate() -> RtcCertificate {
+        RtcCertificate::generate(RtcCertificateAlgorithm::EcdsaP256).unwrap()
+    }
+
+    fn handshake(
+        mut client: TestTransport,
+        client_expects: RtcDtlsFingerprint,
+        mut server: TestTransport,
+        server_expects: RtcDtlsFingerprint,
+    ) -> ((TestTransport, Result<()>), (TestTransport, Result<()>)) {
+        let server = thread::spawn(move || {
+            let result = server.start(RtcDtlsRole::Server, &[server_expects]);
+            (server, result)
+        });
+        let result = client.start(RtcDtlsRole::Client, &[client_expects]);
+        ((client, result), server.join().unwrap())
+    }
 
     #[test]
-    fn gen_private_key_test() {
-        use chrono::{Date, DateTime, Duration, Utc};
-        use openssl::asn1::Asn1Time;
-        use openssl::bn::{BigNum, MsbOption};
-        use openssl::ec::*;
-        use openssl::error::ErrorStack;
-        use openssl::hash::MessageDigest;
-        use openssl::nid::Nid;
-        use openssl::pkey::PKey;
-        use openssl::pkey::Private;
-        use rand::Rng;
-
-        fn generate_random() -> Result<BigNum, ErrorStack> {
-            let mut big = BigNum::new()?;
-
-            // Max random value, a 130-bits integer, i.e 2^130 - 1
-            // in pion
-
-            // 20 bytes random number using orandom in aiortc
-            // int(binascii.hexlify(data), 16)
-
-            // we create 130-bit odd integer like pion
-            big.rand(130, MsbOption::MAYBE_ZERO, true);
-            Ok(big)
-        }
-
-        //let group = EcGroup::from_curve_name(Nid::SECP256K1).unwrap();
-        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
-        let a = ec::EcKey::<Private>::generate(&group).unwrap();
-        println!("private_key :: {:?}", a.private_key());
-
-        let mut rng = rand::thread_rng();
-
-        // random str is 128bit(16bytes) hex integer string (aiortc)
-        // 16bytes hex string (pion)
-        // so 128/4 = 32 char(0~e)
-        let random_str = (0..32)
-            .map(|_| rng.gen::<u32>() % 16)
-            .map(|n| std::char::from_digit(n, 16))
-            .collect::<Option<String>>()
-            .unwrap();
+    fn fingerprint_parse_test() {
+        let fingerprint: RtcDtlsFingerprint = "sha-256 ab:CD:01".parse().unwrap();
+        assert_eq!(fingerprint, RtcDtlsFingerprint::new("SHA-256", "AB:cd:01"));
+        assert_eq!(fingerprint.to_string(), "sha-256 AB:CD:01");
 
-        // println!("{:?}", random_str);
-        let mut x509_name = openssl::x509::X509NameBuilder::new().unwrap();
-        x509_name.append_entry_by_text("CN", &random_str).unwrap();
-        let x509_name = x509_name.build();
+        assert_eq!(
+            "sha-256".parse::<RtcDtlsFingerprint>(),
+            Err(RtcDtlsError::InvalidFingerprint)
+        );
+        assert_eq!(
+            RtcDtlsFingerprint::from_certificate("md5", ecdsa_certificate().certificate()),
+            Err(RtcDtlsError::UnsupportedHashAlgorithm {
+                algorithm: "md5".to_string()
+            })
+        );
+    }
 
-        let mut x509 = openssl::x509::X509::builder().unwrap();
-        x509.set_subject_name(&x509_name).unwrap();
-        x509.set_issuer_name(&x509_name).unwrap();
-        let pubkey = EcKey::from_public_key(&group, a.public_key())
-            .and_then(|ec| PKey::from_ec_key(ec))
-            .unwrap();
-        x509.set_pubkey(&pubkey).unwrap();
+    #[test]
+    fn dtls_handshake_test() {
+        let (a, b) = memory_transport_pair();
+        let client = RtcDtlsTransport::new(a, ecdsa_certificate());
+        let server = RtcDtlsTransport::new(
+            b,
+            RtcCertificate::generate(RtcCertificateAlgorithm::Rsa2048).unwrap(),
+        );
+        let client_fingerprint = client.local_fingerprint("sha-256").unwrap();
+        let server_fingerprint = server.local_fingerprint("sha-256").unwrap();
 
-        let r = generate_random().and_then(|r| r.to_asn1_integer()).unwrap();
-        x509.set_serial_number(&r).unwrap();
+        let ((mut client, client_result), (mut server, server_result)) =
+            handshake(client, server_fingerprint, server, client_fingerprint);
+        client_result.unwrap();
+        server_result.unwrap();
 
-        // now - 1 day
-        let now: DateTime<Utc> = Utc::now();
-        x509.set_not_before(&Asn1Time::from_unix((now - Duration::days(1)).timestamp()).unwrap())
-            .unwrap();
+        assert_eq!(client.state(), RtcDtlsTransportState::Connected);
+        assert_eq!(server.state(), RtcDtlsTransportState::Connected);
+        assert_eq!(
+            client.remote_certificate().unwrap().to_der().unwrap(),
+            server.certificate().certificate().to_der().unwrap()
+        );
 
-        // now + 30 day
-        x509.set_not_after(&Asn1Time::from_unix((now + Duration::days(30)).timestamp()).unwrap())
-            .unwrap();
+        // 両者で同じkeying materialがexportされる
+        let keys = client.srtp_keys().unwrap();
+        assert_eq!(Some(keys), server.srtp_keys());
+        assert_eq!(keys.profile, ProtectionProfile::AeadAes128Gcm);
+        assert_eq!(keys.client_key.len(), 16);
+        assert_eq!(keys.server_salt.len(), 12);
+        assert_ne!(keys.client_key, keys.server_key);
 
-        x509.sign(&PKey::from_ec_key(a).unwrap(), MessageDigest::sha256())
+        // clientが暗号化したSRTPをserverが復号できる
+        let (mut client_tx, _) = client.srtp_contexts().unwrap();
+        let (_, mut server_rx) = server.srtp_contexts().unwrap();
+        let packet = RtpPacketBuilder::new()
+            .payload_type(111)
+            .sequence_number(1)
+            .ssrc(1234)
+            .payload(vec![0xAB; 20])
+            .build()
             .unwrap();
+        let encrypted = client_tx.protect_rtp(&packet).unwrap();
+        assert_eq!(server_rx.unprotect_rtp(&encrypted).unwrap(), packet);
+
+        // application data
+        client.send(b"hello").unwrap();
+        let mut buf = [0u8; 16];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"hello");
 
-        let x509 = x509.build();
-        println!(
-            "{:?}",
-            x509.to_pem().unwrap().iter().map(|&s| s as char).collect::<String>()
+        client.close();
+        assert_eq!(client.state(), RtcDtlsTransportState::Closed);
+        assert_eq!(
+            client.send(b"hello"),
+            Err(RtcDtlsError::InvalidState {
+                state: RtcDtlsTransportState::Closed
+            })
         );
     }
+
+    #[test]
+    fn dtls_fingerprint_mismatch_test() {
+        let (a, b) = memory_transport_pair();
+        let client = RtcDtlsTransport::new(a, ecdsa_certificate());
+        let server = RtcDtlsTransport::new(b, ecdsa_certificate());
+        let client_fingerprint = client.local_fingerprint("sha-256").unwrap();
+
+        // 別の証明書のfingerprintを期待する
+        let wrong_fingerprint = ecdsa_certificate().fingerprint("sha-256").unwrap();
+
+        let ((client, client_result), (server, server_result)) =
+            handshake(client, wrong_fingerprint, server, client_fingerprint);
+
+        assert_eq!(client_result, Err(RtcDtlsError::FingerprintMismatch));
+        assert_eq!(client.state(), RtcDtlsTransportState::Failed);
+        assert!(server_result.is_err());
+        assert_eq!(server.state(), RtcDtlsTransportState::Failed);
+    }
+
+    #[test]
+    fn dtls_retransmission_test() {
+        let (mut a, b) = memory_transport_pair();
+        // ClientHelloを含む最初のflightが失われる
+        a.drop_count = 1;
+        let client = RtcDtlsTransport::new(a, ecdsa_certificate());
+        let server = RtcDtlsTransport::new(b, ecdsa_certificate());
+        let client_fingerprint = client.local_fingerprint("sha-256").unwrap();
+        let server_fingerprint = server.local_fingerprint("sha-256").unwrap();
+
+        let started = Instant::now();
+        let ((mut client, client_result), (mut server, server_result)) =
+            handshake(client, server_fingerprint, server, client_fingerprint);
+        client_result.unwrap();
+        server_result.unwrap();
+
+        // 再送timer (初期値1秒) が切れてから再送される
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        assert_eq!(client.state(), RtcDtlsTransportState::Connected);
+        assert_eq!(server.state(), RtcDtlsTransportState::Connected);
+
+        server.send(b"hello").unwrap();
+        let mut buf = [0u8; 16];
+        let len = client.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+    }
 }
@@ -0,0 +1,410 @@
+// https://tools.ietf.org/html/rfc8839#section-5.1
+// https://w3c.github.io/webrtc-pc/#rtcicecandidate-interface
+
+pub use crate::ice::candidate::TcpType as RtcIceTcpCandidateType;
+use crate::ice::candidate::{compute_priority, Candidate, CandidateType, TransportType};
+use crate::ice::mdns::is_mdns_hostname;
+use failure::Fail;
+use serde_json::{json, Value};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+pub type Result<T> = std::result::Result<T, RtcIceCandidateError>;
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum RtcIceCandidateError {
+    #[fail(display = "ICE candidate is invalid: {}", candidate)]
+    InvalidCandidate { candidate: String },
+
+    #[fail(display = "RTCIceCandidateInit is invalid: {}", reason)]
+    InvalidCandidateInit { reason: String },
+
+    #[fail(display = "ICE candidate address {} is not an IP address.", address)]
+    UnresolvedAddress { address: String },
+}
+
+impl FromStr for RtcIceTcpCandidateType {
+    type Err = RtcIceCandidateError;
+
+    fn from_str(s: &str) -> Result<RtcIceTcpCandidateType> {
+        match s {
+            "active" => Ok(RtcIceTcpCandidateType::Active),
+            "passive" => Ok(RtcIceTcpCandidateType::Passive),
+            "so" => Ok(RtcIceTcpCandidateType::SimultaneousOpen),
+            _ => Err(invalid(s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RtcIceCandidate {
+    pub foundation: String,
+    pub component: u16,
+    pub transport: TransportType,
+    pub priority: u32,
+    // IP addressかFQDN (mDNSの.localなど)
+    pub address: String,
+    pub port: u16,
+    pub typ: CandidateType,
+    pub related_address: Option<String>,
+    pub related_port: Option<u16>,
+    pub tcp_type: Option<RtcIceTcpCandidateType>,
+    // generation, network-id, network-costなど．順序を保って書き戻す
+    pub extensions: Vec<(String, String)>,
+    // 以下はRTCIceCandidateInitでだけ運ばれる
+    pub sdp_mid: Option<String>,
+    pub sdp_m_line_index: Option<u16>,
+    pub username_fragment: Option<String>,
+}
+
+fn invalid(candidate: &str) -> RtcIceCandidateError {
+    RtcIceCandidateError::InvalidCandidate {
+        candidate: candidate.to_owned(),
+    }
+}
+
+impl RtcIceCandidate {
+    // priorityはRFC 8445 5.1.2.1の式で計算する
+    pub fn new(
+        foundation: &str,
+        component: u16,
+        transport: TransportType,
+        address: SocketAddr,
+        typ: CandidateType,
+        local_preference: u16,
+    ) -> RtcIceCandidate {
+        RtcIceCandidate {
+            foundation: foundation.to_owned(),
+            component,
+            transport,
+            priority: compute_priority(typ, local_preference, component),
+            address: address.ip().to_string(),
+            port: address.port(),
+            typ,
+            related_address: None,
+            related_port: None,
+            tcp_type: None,
+            extensions: vec![],
+            sdp_mid: None,
+            sdp_m_line_index: None,
+            username_fragment: None,
+        }
+    }
+
+    pub fn from_candidate(candidate: &Candidate) -> RtcIceCandidate {
+        RtcIceCandidate {
+            foundation: candidate.foundation.clone(),
+            component: candidate.component,
+            transport: candidate.transport,
+            priority: candidate.priority,
+            address: candidate.address.ip().to_string(),
+            port: candidate.address.port(),
+            typ: candidate.typ,
+            related_address: candidate
+                .related_address
+                .map(|related| related.ip().to_string()),
+            related_port: candidate.related_address.map(|related| related.port()),
+            tcp_type: candidate.tcp_type,
+            extensions: vec![],
+            sdp_mid: None,
+            sdp_m_line_index: None,
+            username_fragment: None,
+        }
+    }
+
+    // addressがIP addressでなければ (mDNSなど) 名前解決してから使う
+    pub fn to_candidate(&self) -> Result<Candidate> {
+        let ip = self.address.parse::<IpAddr>().map_err(|_| {
+            RtcIceCandidateError::UnresolvedAddress {
+                address: self.address.clone(),
+            }
+        })?;
+        Ok(self.to_candidate_with_ip(ip))
+    }
+
+    // addressが名前 (mDNSの.localなど) の時は解決したipを使う
+    pub fn to_candidate_with_ip(&self, ip: IpAddr) -> Candidate {
+        let related_address = match (&self.related_address, self.related_port) {
+            (Some(address), Some(port)) => address
+                .parse::<IpAddr>()
+                .ok()
+                .map(|ip| SocketAddr::new(ip, port)),
+            _ => None,
+        };
+
+        Candidate {
+            foundation: self.foundation.clone(),
+            component: self.component,
+            transport: self.transport,
+            priority: self.priority,
+            address: SocketAddr::new(ip, self.port),
+            typ: self.typ,
+            related_address,
+            tcp_type: self.tcp_type,
+        }
+    }
+
+    pub fn is_mdns(&self) -> bool {
+        is_mdns_hostname(&self.address)
+    }
+
+    pub fn extension(&self, name: &str) -> Option<&str> {
+        self.extensions
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // "a=candidate:..."
+    pub fn to_sdp(&self) -> String {
+        format!("a={}", self)
+    }
+
+    // RTCIceCandidateInitのJSON
+    pub fn to_json(&self) -> String {
+        json!({
+            "candidate": self.to_string(),
+            "sdpMid": self.sdp_mid,
+            "sdpMLineIndex": self.sdp_m_line_index,
+            "usernameFragment": self.username_fragment,
+        })
+        .to_string()
+    }
+
+    pub fn from_json(s: &str) -> Result<RtcIceCandidate> {
+        let init_error = |reason: &str| RtcIceCandidateError::InvalidCandidateInit {
+            reason: reason.to_owned(),
+        };
+        let value: Value = serde_json::from_str(s).map_err(|e| init_error(&e.to_string()))?;
+
+        let mut candidate = value
+            .get("candidate")
+            .and_then(Value::as_str)
+            .ok_or_else(|| init_error("candidate is missing"))?
+            .parse::<RtcIceCandidate>()?;
+
+        let optional_string = |key: &str| match value.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(init_error(&format!("{} is not a string", key))),
+        };
+        candidate.sdp_mid = optional_string("sdpMid")?;
+        candidate.username_fragment = optional_string("usernameFragment")?;
+        candidate.sdp_m_line_index = match value.get("sdpMLineIndex") {
+            None | Some(Value::Null) => None,
+            Some(index) => Some(
+                index
+                    .as_u64()
+                    .filter(|index| *index <= u64::from(u16::MAX))
+                    .ok_or_else(|| init_error("sdpMLineIndex is not an unsigned short"))?
+                    as u16,
+            ),
+        };
+        Ok(candidate)
+    }
+}
+
+impl FromStr for RtcIceCandidate {
+    type Err = RtcIceCandidateError;
+
+    // "a=candidate:" と "candidate:" のどちらで始まっていても良い
+    fn from_str(s: &str) -> Result<RtcIceCandidate> {
+        let line = s.trim();
+        let line = line.strip_prefix("a=").unwrap_or(line);
+        let line = line.strip_prefix("candidate:").unwrap_or(line);
+
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.len() < 8 || fields[6] != "typ" || fields.len() % 2 != 0 {
+            return Err(invalid(s));
+        }
+
+        let transport = match fields[2].to_lowercase().as_str() {
+            "udp" => TransportType::UDP,
+            "tcp" => TransportType::TCP,
+            _ => return Err(invalid(s)),
+        };
+        let typ = match fields[7] {
+            "host" => CandidateType::Host,
+            "srflx" => CandidateType::ServerReflexive,
+            "prflx" => CandidateType::PeerReflexive,
+            "relay" => CandidateType::Relay,
+            _ => return Err(invalid(s)),
+        };
+
+        let mut candidate = RtcIceCandidate {
+            foundation: fields[0].to_owned(),
+            component: fields[1].parse().map_err(|_| invalid(s))?,
+            transport,
+            priority: fields[3].parse().map_err(|_| invalid(s))?,
+            address: fields[4].to_owned(),
+            port: fields[5].parse().map_err(|_| invalid(s))?,
+            typ,
+            related_address: None,
+            related_port: None,
+            tcp_type: None,
+            extensions: vec![],
+            sdp_mid: None,
+            sdp_m_line_index: None,
+            username_fragment: None,
+        };
+
+        for pair in fields[8..].chunks(2) {
+            let (name, value) = (pair[0], pair[1]);
+            match name {
+                "raddr" => candidate.related_address = Some(value.to_owned()),
+                "rport" => candidate.related_port = Some(value.parse().map_err(|_| invalid(s))?),
+                "tcptype" => candidate.tcp_type = Some(value.parse().map_err(|_| invalid(s))?),
+                _ => candidate
+                    .extensions
+                    .push((name.to_owned(), value.to_owned())),
+            }
+        }
+        Ok(candidate)
+    }
+}
+
+// "candidate:..." (RTCIceCandidate.candidate)
+impl fmt::Display for RtcIceCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "candidate:{} {} {} {} {} {} typ {}",
+            self.foundation,
+            self.component,
+            self.transport,
+            self.priority,
+            self.address,
+            self.port,
+            self.typ
+        )?;
+        if let Some(related_address) = &self.related_address {
+            write!(f, " raddr {}", related_address)?;
+        }
+        if let Some(related_port) = self.related_port {
+            write!(f, " rport {}", related_port)?;
+        }
+        if let Some(tcp_type) = self.tcp_type {
+            write!(f, " tcptype {}", tcp_type)?;
+        }
+        for (name, value) in &self.extensions {
+            write!(f, " {} {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let line = "a=candidate:3496416974 1 tcp 1518283007 2a02:a03f:3eb0:e000:b0aa:d60a:cff2:933c 9 typ host tcptype active generation 0 network-id 2 network-cost 10";
+        let candidate: RtcIceCandidate = line.parse().unwrap();
+        assert_eq!(candidate.foundation, "3496416974");
+        assert_eq!(candidate.component, 1);
+        assert_eq!(candidate.transport, TransportType::TCP);
+        assert_eq!(candidate.priority, 1_518_283_007);
+        assert_eq!(candidate.address, "2a02:a03f:3eb0:e000:b0aa:d60a:cff2:933c");
+        assert_eq!(candidate.port, 9);
+        assert_eq!(candidate.typ, CandidateType::Host);
+        assert_eq!(candidate.tcp_type, Some(RtcIceTcpCandidateType::Active));
+        assert_eq!(candidate.extension("network-cost"), Some("10"));
+        assert_eq!(candidate.to_sdp(), line);
+
+        let srflx: RtcIceCandidate =
+            "candidate:842163049 1 udp 1677729535 203.0.113.1 61665 typ srflx raddr 192.168.0.2 rport 61665 generation 0 ufrag 5+Ix"
+                .parse()
+                .unwrap();
+        assert_eq!(srflx.typ, CandidateType::ServerReflexive);
+        assert_eq!(srflx.related_address.as_deref(), Some("192.168.0.2"));
+        assert_eq!(srflx.related_port, Some(61665));
+        let candidate = srflx.to_candidate().unwrap();
+        assert_eq!(candidate.address, "203.0.113.1:61665".parse().unwrap());
+        assert_eq!(
+            candidate.related_address,
+            Some("192.168.0.2:61665".parse().unwrap())
+        );
+        assert_eq!(
+            RtcIceCandidate::from_candidate(&candidate).to_candidate(),
+            Ok(candidate)
+        );
+
+        let mdns: RtcIceCandidate =
+            "candidate:1 1 UDP 2122252543 4e1c7b1e-0d3b-4b6e-9b0e-2f6c4c3f0c1d.local 50000 typ host"
+                .parse()
+                .unwrap();
+        assert_eq!(mdns.transport, TransportType::UDP);
+        assert!(mdns.to_candidate().is_err());
+
+        for invalid in &[
+            "candidate:1 1 udp 2122252543 192.168.0.2 50000 host",
+            "candidate:1 1 sctp 2122252543 192.168.0.2 50000 typ host",
+            "candidate:1 1 udp 2122252543 192.168.0.2 50000 typ nat",
+            "candidate:1 1 udp 2122252543 192.168.0.2 50000 typ host generation",
+            "candidate:1 1 udp 2122252543 192.168.0.2 50000 typ host tcptype none",
+        ] {
+            assert!(invalid.parse::<RtcIceCandidate>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn chrome_test() {
+        // chromeのofferのcandidateはそのまま書き戻せる
+        let lines = crate::sdp::fixtures::AUDIO_CHROME
+            .lines()
+            .filter(|line| line.starts_with("a=candidate:"))
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        for line in lines {
+            let candidate: RtcIceCandidate = line.parse().unwrap();
+            assert_eq!(candidate.to_sdp(), line);
+        }
+    }
+
+    #[test]
+    fn priority_test() {
+        // chromeのhost candidate (local preference 32552)
+        let candidate = RtcIceCandidate::new(
+            "1039001212",
+            1,
+            TransportType::UDP,
+            "192.168.99.58:45076".parse().unwrap(),
+            CandidateType::Host,
+            32552,
+        );
+        assert_eq!(candidate.priority, 2_122_262_783);
+        assert_eq!(
+            candidate.to_string(),
+            "candidate:1039001212 1 udp 2122262783 192.168.99.58 45076 typ host"
+        );
+    }
+
+    #[test]
+    fn json_test() {
+        let json = r#"{"candidate":"candidate:1039001212 1 udp 2122194687 192.168.99.58 45076 typ host generation 0 network-id 1 network-cost 10","sdpMid":"audio","sdpMLineIndex":0,"usernameFragment":"5+Ix"}"#;
+        let candidate = RtcIceCandidate::from_json(json).unwrap();
+        assert_eq!(candidate.sdp_mid.as_deref(), Some("audio"));
+        assert_eq!(candidate.sdp_m_line_index, Some(0));
+        assert_eq!(candidate.username_fragment.as_deref(), Some("5+Ix"));
+        assert_eq!(
+            RtcIceCandidate::from_json(&candidate.to_json()).unwrap(),
+            candidate
+        );
+
+        let json = r#"{"candidate":"candidate:1 1 udp 1 192.168.0.2 9 typ host","sdpMid":null}"#;
+        let candidate = RtcIceCandidate::from_json(json).unwrap();
+        assert_eq!(candidate.sdp_mid, None);
+        assert_eq!(candidate.sdp_m_line_index, None);
+        let value: Value = serde_json::from_str(&candidate.to_json()).unwrap();
+        assert_eq!(value["sdpMLineIndex"], Value::Null);
+
+        assert!(RtcIceCandidate::from_json(r#"{"sdpMid":"0"}"#).is_err());
+        assert!(RtcIceCandidate::from_json(
+            r#"{"candidate":"candidate:1 1 udp 1 192.168.0.2 9 typ host","sdpMLineIndex":-1}"#
+        )
+        .is_err());
+        assert!(RtcIceCandidate::from_json("candidate").is_err());
+    }
+}
@@ -1,10 +1,69 @@
-use std::net::*;
+use crate::ice::candidate::{Candidate, CandidateType};
+use crate::ice::ice_connection::random_credentials;
+use crate::ice::{self, IceConnection};
+use crate::media_engine::MediaEngine;
+use crate::mediastreamtrack::MediaStreamTrack;
+use crate::rtccertificate::{RtcCertificate, RtcCertificateAlgorithm};
+use crate::rtcdtlstransport::{RtcDtlsFingerprint, RtcDtlsRole};
+use crate::rtcicecandidate::{RtcIceCandidate, RtcIceCandidateError};
+use crate::rtcrtpparameters::RtcRtcpFeedback;
+use crate::rtcrtpsender::RtcRtpSender;
+use crate::rtcrtptransceiver::{
+    random_id, MediaKind, NegotiationState, RtcRtpTransceiver, RtcRtpTransceiverDirection,
+};
+use crate::sdp::negotiation::{fmtp_attribute, negotiate};
+use crate::sdp::write_sdp;
+use crate::WebrtcError;
+use failure::Fail;
+use rand::{thread_rng, Rng};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use webrtc_sdp::address::ExplicitlyTypedAddress;
+use webrtc_sdp::attribute_type::*;
+use webrtc_sdp::media_type::*;
 use webrtc_sdp::*;
 
+pub type Result<T> = std::result::Result<T, RtcPeerConnectionError>;
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum RtcPeerConnectionError {
+    #[fail(display = "RTCPeerConnection is in an invalid state: {}", reason)]
+    InvalidState { reason: String },
+
+    #[fail(display = "Session description is invalid: {}", reason)]
+    InvalidSessionDescription { reason: String },
+
+    #[fail(display = "ICE username fragment {} is unknown.", username_fragment)]
+    UnknownUsernameFragment { username_fragment: String },
+
+    #[fail(display = "ICE candidate failed: {:?}", error)]
+    IceCandidateError { error: RtcIceCandidateError },
+
+    #[fail(display = "Track {} is already added.", id)]
+    TrackAlreadyAdded { id: String },
+
+    #[fail(display = "Invalid modification: {}", reason)]
+    InvalidModification { reason: String },
+
+    #[fail(display = "Cannot {} in signaling state {:?}.", operation, state)]
+    InvalidStateTransition {
+        state: RTCSignalingState,
+        operation: String,
+    },
+}
+
+impl From<RtcIceCandidateError> for RtcPeerConnectionError {
+    fn from(error: RtcIceCandidateError) -> Self {
+        RtcPeerConnectionError::IceCandidateError { error }
+    }
+}
+
 //https://developer.mozilla.org/en-US/docs/Web/API/RTCPeerConnection/iceConnectionState
 
-enum IceConnectionState {
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IceConnectionState {
     New,
     Checking,
     Connected,
@@ -14,75 +73,2213 @@ enum IceConnectionState {
     Closed,
 }
 
+// Completedは区別しない
+impl From<ice::IceConnectionState> for IceConnectionState {
+    fn from(state: ice::IceConnectionState) -> Self {
+        match state {
+            ice::IceConnectionState::New => IceConnectionState::New,
+            ice::IceConnectionState::Checking => IceConnectionState::Checking,
+            ice::IceConnectionState::Connected => IceConnectionState::Connected,
+            ice::IceConnectionState::Disconnected => IceConnectionState::Disconnected,
+            ice::IceConnectionState::Failed => IceConnectionState::Failed,
+            ice::IceConnectionState::Closed => IceConnectionState::Closed,
+        }
+    }
+}
+
 //https://developer.mozilla.org/en-US/docs/Web/API/RTCPeerConnection/iceGatheringState
 
-enum IceGatheringState {
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IceGatheringState {
     New,
     Gathering,
     Completed,
 }
 
-enum RTCSessionDescription {
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RTCSessionDescription {
     Offer(String),
     Answer(String),
     Pranswer(String), //http://iwashi.co/2016/04/03/webrtc-pranswer
     Rollback,
 }
 
-enum RTCSignalingState {
+impl RTCSessionDescription {
+    fn type_name(&self) -> &'static str {
+        match self {
+            RTCSessionDescription::Offer(_) => "offer",
+            RTCSessionDescription::Answer(_) => "answer",
+            RTCSessionDescription::Pranswer(_) => "pranswer",
+            RTCSessionDescription::Rollback => "rollback",
+        }
+    }
+
+    pub fn sdp(&self) -> Option<&str> {
+        match self {
+            RTCSessionDescription::Offer(sdp)
+            | RTCSessionDescription::Answer(sdp)
+            | RTCSessionDescription::Pranswer(sdp) => Some(sdp),
+            RTCSessionDescription::Rollback => None,
+        }
+    }
+}
+
+// https://w3c.github.io/webrtc-pc/#dom-rtcofferoptions
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct RTCOfferOptions {
+    pub ice_restart: bool,
+}
+
+// https://w3c.github.io/webrtc-pc/#rtcsignalingstate-enum
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RTCSignalingState {
     Stable,
     HaveLocalOffer,
     HaveRemoteOffer,
     HaveLocalPranswer,
-    HaveRemotePranwer,
+    HaveRemotePranswer,
     Closed,
 }
 
-struct IceConnection;
+impl RTCSignalingState {
+    // JSEP (RFC 8829) 3.2 の状態遷移．rollbackは別に扱う
+    fn next(self, local: bool, description: &RTCSessionDescription) -> Option<RTCSignalingState> {
+        use RTCSignalingState::*;
+        match (self, local, description) {
+            (Stable, true, RTCSessionDescription::Offer(_))
+            | (HaveLocalOffer, true, RTCSessionDescription::Offer(_)) => Some(HaveLocalOffer),
+            (Stable, false, RTCSessionDescription::Offer(_))
+            | (HaveRemoteOffer, false, RTCSessionDescription::Offer(_)) => Some(HaveRemoteOffer),
+            (HaveRemoteOffer, true, RTCSessionDescription::Pranswer(_))
+            | (HaveLocalPranswer, true, RTCSessionDescription::Pranswer(_)) => {
+                Some(HaveLocalPranswer)
+            }
+            (HaveLocalOffer, false, RTCSessionDescription::Pranswer(_))
+            | (HaveRemotePranswer, false, RTCSessionDescription::Pranswer(_)) => {
+                Some(HaveRemotePranswer)
+            }
+            (HaveRemoteOffer, true, RTCSessionDescription::Answer(_))
+            | (HaveLocalPranswer, true, RTCSessionDescription::Answer(_))
+            | (HaveLocalOffer, false, RTCSessionDescription::Answer(_))
+            | (HaveRemotePranswer, false, RTCSessionDescription::Answer(_)) => Some(Stable),
+            // offerを出した (受けた) 側だけがrollbackできる
+            (HaveLocalOffer, _, RTCSessionDescription::Rollback)
+            | (HaveRemoteOffer, _, RTCSessionDescription::Rollback) => Some(Stable),
+            _ => None,
+        }
+    }
+}
 
-struct RTCPeerConnection {
+// create_sdpで作るdescriptionの種類
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum DescriptionType {
+    Offer,
+    Answer,
+    Pranswer,
+}
+
+// have-local-offer/have-remote-offerに入る前の状態．rollbackで戻す (JSEP 5.7)
+struct RollbackState {
+    local_username: String,
+    remote_username: Option<String>,
+    remote_password: Option<String>,
+    ice_controlling: bool,
+    ice_restart_pending: bool,
     ice_gathering_state: IceGatheringState,
-    ice_connection: Option<IceConnection>,
+    remote_setup: Option<String>,
+    remote_sections: Vec<RemoteMediaSection>,
+    dtls_role: Option<RtcDtlsRole>,
+    remote_fingerprints: Vec<RtcDtlsFingerprint>,
+    transceivers: Vec<NegotiationState>,
+    // remoteのofferで作ったtransceiverの位置
+    created: Vec<usize>,
+}
+
+// remoteのofferのm-section
+#[derive(Clone)]
+enum RemoteMediaSection {
+    // このmidのtransceiverで答える
+    Transceiver(String),
+    // 扱えない種類のm-section (m=applicationなど)．answerではport 0でrejectする
+    Unsupported {
+        line: SdpMediaLine,
+        mid: Option<String>,
+    },
+}
+
+// trickle ICE (RFC 8838) でcandidateを受け取る．Noneはend-of-candidates
+type IceCandidateCallback = Box<dyn FnMut(Option<RtcIceCandidate>) + Send>;
+type IceConnectionStateCallback = Box<dyn FnMut(IceConnectionState) + Send>;
+
+pub struct RTCPeerConnection {
+    ice_gathering_state: Arc<Mutex<IceGatheringState>>,
+    ice_connection: Arc<IceConnection>,
+    on_ice_candidate: Arc<Mutex<Option<IceCandidateCallback>>>,
+    on_ice_connection_state_change: Arc<Mutex<Option<IceConnectionStateCallback>>>,
+    signaling_state: RTCSignalingState,
+    current_local_description: Option<RTCSessionDescription>,
+    pending_local_description: Option<RTCSessionDescription>,
+    current_remote_description: Option<RTCSessionDescription>,
+    pending_remote_description: Option<RTCSessionDescription>,
+    remote_username: Option<String>,
+    remote_password: Option<String>,
+    // create_offerでICE restartを頼まれた時のcredential．set_local_descriptionでrestartする
+    pending_ice_credentials: Option<(String, String)>,
+    // 自分から始めたICE restartのanswerを待っている
+    ice_restart_pending: bool,
+    // remoteのa=setup (answerのsetupを決める)
+    remote_setup: Option<String>,
+    // remoteのofferのm-sectionの順 (answerはこの順に作る)
+    remote_sections: Vec<RemoteMediaSection>,
+    dtls_role: Option<RtcDtlsRole>,
+    remote_fingerprints: Vec<RtcDtlsFingerprint>,
+    certificate: RtcCertificate,
+    media_engine: MediaEngine,
+    transceivers: Vec<RtcRtpTransceiver>,
+    // remoteのofferのうちanswerでrejectするm-sectionのmid．stopするのはanswerを適用した時
+    rejected_mids: HashSet<String>,
+    rollback_state: Option<RollbackState>,
+    // RTCPのCNAMEは全てのtransceiverで同じにする
+    cname: String,
+    session_id: u64,
+    session_version: u64,
+    // 内容が変わった時だけsession versionを上げるため，前に作ったdescription (o=を除く)
+    last_local_sdp: Option<String>,
+    workers: Vec<JoinHandle<()>>,
 }
 
 impl RTCPeerConnection {
-    pub fn new() -> RTCPeerConnection {
+    pub fn new() -> std::result::Result<RTCPeerConnection, WebrtcError> {
+        RTCPeerConnection::with_ice_connection(IceConnection::new(true, 1, None, None, true, true))
+    }
+
+    // STUN/TURN serverなどを設定したIceConnectionを使う
+    pub fn with_ice_connection(
+        ice_connection: IceConnection,
+    ) -> std::result::Result<RTCPeerConnection, WebrtcError> {
+        let certificate = RtcCertificate::generate(RtcCertificateAlgorithm::EcdsaP256)?;
+        Ok(RTCPeerConnection::with_certificate(
+            ice_connection,
+            certificate,
+        ))
+    }
+
+    pub fn with_certificate(
+        ice_connection: IceConnection,
+        certificate: RtcCertificate,
+    ) -> RTCPeerConnection {
+        let states = ice_connection.subscribe_state();
+        let on_ice_connection_state_change: Arc<Mutex<Option<IceConnectionStateCallback>>> =
+            Arc::new(Mutex::new(None));
+
+        // consent freshnessによるDisconnected/Failedもここで通知される
+        let callback = on_ice_connection_state_change.clone();
+        let notifier = thread::spawn(move || {
+            for state in states.iter() {
+                if let Some(callback) = callback.lock().unwrap().as_mut() {
+                    callback(state.into());
+                }
+                if state == ice::IceConnectionState::Closed {
+                    return;
+                }
+            }
+        });
+
         RTCPeerConnection {
-            ice_connection: None,
-            ice_gathering_state: IceGatheringState::New,
+            ice_gathering_state: Arc::new(Mutex::new(IceGatheringState::New)),
+            ice_connection: Arc::new(ice_connection),
+            on_ice_candidate: Arc::new(Mutex::new(None)),
+            on_ice_connection_state_change,
+            signaling_state: RTCSignalingState::Stable,
+            current_local_description: None,
+            pending_local_description: None,
+            current_remote_description: None,
+            pending_remote_description: None,
+            remote_username: None,
+            remote_password: None,
+            pending_ice_credentials: None,
+            ice_restart_pending: false,
+            remote_setup: None,
+            remote_sections: vec![],
+            dtls_role: None,
+            remote_fingerprints: vec![],
+            certificate,
+            media_engine: MediaEngine::default(),
+            transceivers: vec![],
+            rejected_mids: HashSet::new(),
+            rollback_state: None,
+            cname: random_id(),
+            // JSEP 5.2.1: 63bitに収まる乱数
+            session_id: thread_rng().gen_range(0, 1 << 63),
+            session_version: 0,
+            last_local_sdp: None,
+            workers: vec![notifier],
+        }
+    }
+
+    pub fn certificate(&self) -> &RtcCertificate {
+        &self.certificate
+    }
+
+    // add_transceiverより前にcodecを登録したり消したりする
+    pub fn media_engine_mut(&mut self) -> &mut MediaEngine {
+        &mut self.media_engine
+    }
+
+    pub fn add_transceiver(
+        &mut self,
+        kind: MediaKind,
+        direction: RtcRtpTransceiverDirection,
+    ) -> Result<&mut RtcRtpTransceiver> {
+        if self.signaling_state == RTCSignalingState::Closed {
+            return Err(RtcPeerConnectionError::InvalidState {
+                reason: "peer connection is closed".to_owned(),
+            });
         }
+        self.transceivers
+            .push(RtcRtpTransceiver::new(kind, direction, &self.media_engine));
+        Ok(self.transceivers.last_mut().unwrap())
+    }
+
+    // 同じkindでまだ送信に使っていないtransceiverがあれば使い回す (JSEP 5.2.2)
+    pub fn add_track(
+        &mut self,
+        track: MediaStreamTrack,
+        stream_ids: &[&str],
+    ) -> Result<&RtcRtpSender> {
+        if self.signaling_state == RTCSignalingState::Closed {
+            return Err(RtcPeerConnectionError::InvalidState {
+                reason: "peer connection is closed".to_owned(),
+            });
+        }
+        if self
+            .transceivers
+            .iter()
+            .any(|transceiver| transceiver.sender().track() == Some(&track))
+        {
+            return Err(RtcPeerConnectionError::TrackAlreadyAdded {
+                id: track.id().to_owned(),
+            });
+        }
+
+        let reusable = self.transceivers.iter().position(|transceiver| {
+            transceiver.kind() == track.kind()
+                && !transceiver.is_stopped()
+                && !transceiver.sender().is_used()
+        });
+        let transceiver = match reusable {
+            Some(i) => {
+                let transceiver = &mut self.transceivers[i];
+                let direction = transceiver.direction();
+                transceiver.set_direction(match direction {
+                    RtcRtpTransceiverDirection::Recvonly => RtcRtpTransceiverDirection::Sendrecv,
+                    RtcRtpTransceiverDirection::Inactive => RtcRtpTransceiverDirection::Sendonly,
+                    direction => direction,
+                });
+                transceiver
+            }
+            None => self.add_transceiver(track.kind(), RtcRtpTransceiverDirection::Sendrecv)?,
+        };
+        let stream_ids = stream_ids.iter().map(|id| id.to_string()).collect();
+        transceiver.sender_mut().set_track(track, stream_ids);
+        Ok(transceiver.sender())
+    }
+
+    pub fn transceivers(&self) -> &[RtcRtpTransceiver] {
+        &self.transceivers
+    }
+
+    pub fn transceivers_mut(&mut self) -> &mut [RtcRtpTransceiver] {
+        &mut self.transceivers
+    }
+
+    pub fn ice_connection(&self) -> Arc<IceConnection> {
+        self.ice_connection.clone()
+    }
+
+    pub fn ice_gathering_state(&self) -> IceGatheringState {
+        *self.ice_gathering_state.lock().unwrap()
+    }
+
+    pub fn ice_connection_state(&self) -> IceConnectionState {
+        self.ice_connection.state().into()
+    }
+
+    pub fn on_ice_connection_state_change<F>(&self, callback: F)
+    where
+        F: FnMut(IceConnectionState) + Send + 'static,
+    {
+        *self.on_ice_connection_state_change.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    pub fn signaling_state(&self) -> RTCSignalingState {
+        self.signaling_state
+    }
+
+    pub fn local_description(&self) -> Option<&RTCSessionDescription> {
+        self.pending_local_description
+            .as_ref()
+            .or(self.current_local_description.as_ref())
+    }
+
+    pub fn current_local_description(&self) -> Option<&RTCSessionDescription> {
+        self.current_local_description.as_ref()
+    }
+
+    pub fn pending_local_description(&self) -> Option<&RTCSessionDescription> {
+        self.pending_local_description.as_ref()
+    }
+
+    pub fn remote_description(&self) -> Option<&RTCSessionDescription> {
+        self.pending_remote_description
+            .as_ref()
+            .or(self.current_remote_description.as_ref())
+    }
+
+    pub fn current_remote_description(&self) -> Option<&RTCSessionDescription> {
+        self.current_remote_description.as_ref()
+    }
+
+    pub fn pending_remote_description(&self) -> Option<&RTCSessionDescription> {
+        self.pending_remote_description.as_ref()
+    }
+
+    // answerのa=setupで決まったDTLSの役割
+    pub fn dtls_role(&self) -> Option<RtcDtlsRole> {
+        self.dtls_role
+    }
+
+    // remoteのa=fingerprint．DTLSのhandshakeで証明書を検証する
+    pub fn remote_fingerprints(&self) -> &[RtcDtlsFingerprint] {
+        &self.remote_fingerprints
+    }
+
+    // candidateが見つかる度に呼ばれ，gatherが終わるとNoneで呼ばれる
+    // set_local_descriptionより前に設定する
+    pub fn on_ice_candidate<F>(&self, callback: F)
+    where
+        F: FnMut(Option<RtcIceCandidate>) + Send + 'static,
+    {
+        *self.on_ice_candidate.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    pub fn create_answer(&mut self) -> Result<RTCSessionDescription> {
+        self.check_answerable("create an answer")?;
+        let sdp = self.create_sdp(DescriptionType::Answer);
+        Ok(RTCSessionDescription::Answer(write_sdp(&sdp)))
     }
 
-    pub fn create_answer(&self) -> RTCSessionDescription {
-        let sdp = self.create_sdp();
-        RTCSessionDescription::Answer(sdp.to_string())
+    pub fn create_offer(&mut self) -> Result<RTCSessionDescription> {
+        self.create_offer_with_options(RTCOfferOptions::default())
     }
 
-    pub fn create_offer(&mut self) -> RTCSessionDescription {
-        self.ice_gathering_state = IceGatheringState::Gathering;
-        //self.gather_candidates();
-        self.ice_gathering_state = IceGatheringState::Completed;
+    pub fn create_offer_with_options(
+        &mut self,
+        options: RTCOfferOptions,
+    ) -> Result<RTCSessionDescription> {
+        if self.signaling_state == RTCSignalingState::Closed {
+            return Err(RtcPeerConnectionError::InvalidStateTransition {
+                state: self.signaling_state,
+                operation: "create an offer".to_owned(),
+            });
+        }
+        // 前のrestartのanswerを待っている間は同じcredentialのまま
+        if options.ice_restart
+            && !self.ice_restart_pending
+            && self.pending_ice_credentials.is_none()
+        {
+            self.pending_ice_credentials = Some(random_credentials());
+        }
 
-        let sdp = self.create_sdp();
-        RTCSessionDescription::Offer(sdp.to_string())
+        let sdp = self.create_sdp(DescriptionType::Offer);
+        Ok(RTCSessionDescription::Offer(write_sdp(&sdp)))
     }
 
-    pub fn create_pranswer(&self) -> RTCSessionDescription {
+    pub fn create_pranswer(&mut self) -> Result<RTCSessionDescription> {
         //http://iwashi.co/2016/04/03/webrtc-pranswer
-        let sdp = self.create_sdp();
-        RTCSessionDescription::Pranswer(sdp.to_string().replace("a=sendrecv", "a=inactive"))
+        self.check_answerable("create a pranswer")?;
+        let sdp = self.create_sdp(DescriptionType::Pranswer);
+        Ok(RTCSessionDescription::Pranswer(write_sdp(&sdp)))
     }
 
-    //fn gather_candidates(&mut self)
+    fn check_answerable(&self, operation: &str) -> Result<()> {
+        match self.signaling_state {
+            RTCSignalingState::HaveRemoteOffer | RTCSignalingState::HaveLocalPranswer => Ok(()),
+            state => Err(RtcPeerConnectionError::InvalidStateTransition {
+                state,
+                operation: operation.to_owned(),
+            }),
+        }
+    }
 
-    fn create_sdp(&self) -> SdpSession {
-        // TODO: implement
-        let sdp_origin = SdpOrigin {
-            username: "Test".to_string(),
-            session_id: 0,
-            session_version: 0,
-            unicast_addr: ExplicitlyTypedAddress::Ip("127.0.0.1".parse().unwrap()),
+    fn transition(
+        &self,
+        local: bool,
+        description: &RTCSessionDescription,
+    ) -> Result<RTCSignalingState> {
+        self.signaling_state
+            .next(local, description)
+            .ok_or_else(|| RtcPeerConnectionError::InvalidStateTransition {
+                state: self.signaling_state,
+                operation: format!(
+                    "set {} {}",
+                    if local { "local" } else { "remote" },
+                    description.type_name()
+                ),
+            })
+    }
+
+    // create_offer/create_answerで作ったdescriptionを適用してcandidateのgatherを始める
+    pub fn set_local_description(&mut self, description: RTCSessionDescription) -> Result<()> {
+        let next = self.transition(true, &description)?;
+        let sdp = match description.sdp() {
+            Some(sdp) => parse_sdp(sdp)?,
+            None => {
+                self.rollback();
+                return Ok(());
+            }
+        };
+
+        // 作った後にcredentialを変えることはできない
+        let username = ice_attribute(&sdp, |attribute| match attribute {
+            SdpAttribute::IceUfrag(ufrag) => Some(ufrag.clone()),
+            _ => None,
+        });
+        let typ = match description {
+            RTCSessionDescription::Offer(_) => DescriptionType::Offer,
+            _ => DescriptionType::Answer,
+        };
+        let (local_username, _) = self.local_credentials(typ);
+        if username.as_ref() != Some(&local_username) {
+            return Err(invalid_description(
+                "a=ice-ufrag does not match the local ICE credentials",
+            ));
+        }
+        let setup = ice_attribute(&sdp, |attribute| match attribute {
+            SdpAttribute::Setup(setup) => Some(setup.to_string()),
+            _ => None,
+        });
+
+        match description {
+            RTCSessionDescription::Offer(_) => {
+                self.save_rollback_state();
+                if let Some((username, password)) = self.pending_ice_credentials.take() {
+                    self.ice_connection
+                        .restart_with_credentials(&username, &password);
+                    *self.ice_gathering_state.lock().unwrap() = IceGatheringState::New;
+                    self.ice_restart_pending = true;
+                }
+                // offerに書いたmidをtransceiverに割り当てる
+                for (transceiver, media) in self.transceivers.iter_mut().zip(&sdp.media) {
+                    if let Some(SdpAttribute::Mid(mid)) = media.get_attribute(SdpAttributeType::Mid)
+                    {
+                        transceiver.set_mid(Some(mid.clone()));
+                    }
+                }
+                // offerを出した側がcontrolling (RFC 8445 6.1.1)
+                self.ice_connection.set_controlling(true);
+            }
+            _ => {
+                self.dtls_role = match setup.as_deref() {
+                    Some("active") => Some(RtcDtlsRole::Client),
+                    Some("passive") => Some(RtcDtlsRole::Server),
+                    _ => return Err(invalid_description("a=setup must be active or passive")),
+                };
+            }
+        }
+
+        if let RTCSessionDescription::Answer(_) = description {
+            for media in &sdp.media {
+                if let Some(transceiver) = self.media_transceiver(media) {
+                    let direction = media_direction(media);
+                    if direction == RtcRtpTransceiverDirection::Stopped {
+                        transceiver.stop();
+                    }
+                    transceiver.set_current_direction(direction);
+                }
+            }
+        }
+
+        // port 0のm-sectionはBUNDLEされていない．m-sectionが無ければmidは付けない
+        let bundle_tag = sdp
+            .media
+            .iter()
+            .enumerate()
+            .filter(|(_, media)| media.get_port() != 0)
+            .find_map(
+                |(index, media)| match media.get_attribute(SdpAttributeType::Mid) {
+                    Some(SdpAttribute::Mid(mid)) => Some((mid.clone(), index as u16)),
+                    _ => None,
+                },
+            );
+        self.gather_candidates(bundle_tag);
+
+        match description {
+            RTCSessionDescription::Answer(_) => {
+                self.current_local_description = Some(description);
+                self.pending_local_description = None;
+                if let Some(remote) = self.pending_remote_description.take() {
+                    self.current_remote_description = Some(remote);
+                }
+                self.rejected_mids.clear();
+                self.rollback_state = None;
+            }
+            _ => self.pending_local_description = Some(description),
+        }
+        self.signaling_state = next;
+        Ok(())
+    }
+
+    pub fn set_remote_description(&mut self, description: RTCSessionDescription) -> Result<()> {
+        let next = self.transition(false, &description)?;
+        let (sdp, candidates) = match description.sdp() {
+            Some(sdp) => {
+                let (sdp, candidates) = split_candidates(sdp);
+                (parse_sdp(&sdp)?, candidates)
+            }
+            None => {
+                self.rollback();
+                return Ok(());
+            }
+        };
+
+        let username = ice_attribute(&sdp, |attribute| match attribute {
+            SdpAttribute::IceUfrag(ufrag) => Some(ufrag.clone()),
+            _ => None,
+        })
+        .ok_or_else(|| invalid_description("a=ice-ufrag is missing"))?;
+        let password = ice_attribute(&sdp, |attribute| match attribute {
+            SdpAttribute::IcePwd(pwd) => Some(pwd.clone()),
+            _ => None,
+        })
+        .ok_or_else(|| invalid_description("a=ice-pwd is missing"))?;
+        let trickle = sdp_attributes(&sdp).any(|attribute| match attribute {
+            SdpAttribute::IceOptions(options) => options.iter().any(|option| option == "trickle"),
+            _ => false,
+        });
+        let end_of_candidates = sdp_attributes(&sdp)
+            .any(|attribute| matches!(attribute, SdpAttribute::EndOfCandidates));
+        let ice_lite =
+            sdp_attributes(&sdp).any(|attribute| matches!(attribute, SdpAttribute::IceLite));
+        let fingerprints = sdp_attributes(&sdp)
+            .filter_map(|attribute| match attribute {
+                SdpAttribute::Fingerprint(fingerprint) => Some(RtcDtlsFingerprint::new(
+                    &fingerprint.hash_algorithm.to_string(),
+                    &fingerprint
+                        .fingerprint
+                        .iter()
+                        .map(|byte| format!("{:02X}", byte))
+                        .collect::<Vec<_>>()
+                        .join(":"),
+                )),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        if fingerprints.is_empty() {
+            return Err(invalid_description("a=fingerprint is missing"));
+        }
+        let setup = ice_attribute(&sdp, |attribute| match attribute {
+            SdpAttribute::Setup(setup) => Some(setup.to_string()),
+            _ => None,
+        });
+
+        match description {
+            RTCSessionDescription::Offer(_) => {
+                self.save_rollback_state();
+                self.apply_remote_offer(&sdp)?;
+                // ice-liteはcontrolledにしかなれない (RFC 8445 6.1.1)
+                self.ice_connection.set_controlling(ice_lite);
+            }
+            _ => {
+                self.dtls_role = match setup.as_deref() {
+                    Some("active") => Some(RtcDtlsRole::Server),
+                    Some("passive") => Some(RtcDtlsRole::Client),
+                    _ => return Err(invalid_description("a=setup must be active or passive")),
+                };
+            }
+        }
+        self.apply_remote_media(&sdp, &description);
+
+        // credentialが変わっていればremoteがICE restartした
+        let restarted = self
+            .remote_username
+            .as_deref()
+            .map_or(false, |u| u != username)
+            || self
+                .remote_password
+                .as_deref()
+                .map_or(false, |p| p != password);
+        if restarted && !self.ice_restart_pending {
+            self.restart_ice();
+        }
+        if let RTCSessionDescription::Answer(_) = description {
+            self.ice_restart_pending = false;
+        }
+
+        self.ice_connection
+            .set_remote_credentials(&username, &password);
+        // trickleしないremoteのcandidateはdescriptionに全て含まれている
+        self.ice_connection
+            .set_remote_end_of_candidates(!trickle || end_of_candidates);
+        for candidate in candidates {
+            // 解決できないaddressのcandidateは無視する
+            let _ = self.add_remote_candidate(&candidate);
+        }
+
+        self.remote_setup = setup;
+        self.remote_fingerprints = fingerprints.into_iter().collect();
+        self.remote_username = Some(username);
+        self.remote_password = Some(password);
+        match description {
+            RTCSessionDescription::Answer(_) => {
+                self.current_remote_description = Some(description);
+                self.pending_remote_description = None;
+                if let Some(local) = self.pending_local_description.take() {
+                    self.current_local_description = Some(local);
+                }
+                self.rollback_state = None;
+            }
+            _ => self.pending_remote_description = Some(description),
+        }
+        self.signaling_state = next;
+        Ok(())
+    }
+
+    // JSEP 5.10．midが同じか，まだmidの無い同じkindのtransceiverに対応させ，無ければ作る
+    fn apply_remote_offer(&mut self, sdp: &SdpSession) -> Result<()> {
+        let mut sections = vec![];
+        let mut mids = vec![];
+        for media in &sdp.media {
+            let mid = match media.get_attribute(SdpAttributeType::Mid) {
+                Some(SdpAttribute::Mid(mid)) => Some(mid.clone()),
+                _ => None,
+            };
+            let kind = match media.get_type() {
+                SdpMediaValue::Audio => MediaKind::Audio,
+                SdpMediaValue::Video => MediaKind::Video,
+                // 扱えないm-sectionもanswerの同じ位置に残す (JSEP 5.3.1)
+                _ => {
+                    sections.push(RemoteMediaSection::Unsupported {
+                        line: SdpMediaLine {
+                            media: media.get_type().clone(),
+                            port: 0,
+                            port_count: 0,
+                            proto: media.get_proto().clone(),
+                            formats: media.get_formats().clone(),
+                        },
+                        mid,
+                    });
+                    continue;
+                }
+            };
+            let mid = mid.ok_or_else(|| invalid_description("a=mid is missing"))?;
+            sections.push(RemoteMediaSection::Transceiver(mid.clone()));
+            mids.push((kind, mid));
+        }
+
+        for (kind, mid) in &mids {
+            if self
+                .transceivers
+                .iter()
+                .any(|transceiver| transceiver.mid() == Some(mid))
+            {
+                continue;
+            }
+            let unassociated = self.transceivers.iter_mut().find(|transceiver| {
+                transceiver.mid().is_none()
+                    && transceiver.kind() == *kind
+                    && transceiver.direction() != RtcRtpTransceiverDirection::Stopped
+            });
+            let transceiver = match unassociated {
+                Some(transceiver) => transceiver,
+                None => {
+                    if let Some(state) = self.rollback_state.as_mut() {
+                        state.created.push(self.transceivers.len());
+                    }
+                    self.add_transceiver(*kind, RtcRtpTransceiverDirection::Recvonly)?
+                }
+            };
+            transceiver.set_mid(Some(mid.clone()));
+        }
+        self.remote_sections = sections;
+        Ok(())
+    }
+
+    // m-section毎にcodecをnegotiateして向きを決める
+    fn apply_remote_media(&mut self, sdp: &SdpSession, description: &RTCSessionDescription) {
+        let offer = matches!(description, RTCSessionDescription::Offer(_));
+        if offer {
+            self.rejected_mids.clear();
+        }
+        let mut rejected = vec![];
+        for media in &sdp.media {
+            let transceiver = match self.media_transceiver(media) {
+                Some(transceiver) => transceiver,
+                None => continue,
+            };
+            let direction = media_direction(media);
+            if offer {
+                transceiver.set_remote_direction(direction);
+            }
+
+            let parameters = match direction {
+                RtcRtpTransceiverDirection::Stopped => None,
+                _ => negotiate(&transceiver.capabilities(), media).ok(),
+            };
+            // 使えるcodecが無ければm-sectionをrejectする
+            // offerならanswerをport 0で作るだけで，rollbackできるようにまだstopしない
+            // pranswerでもanswerが来るまではstopしない
+            if parameters.is_none() {
+                match description {
+                    RTCSessionDescription::Answer(_) => transceiver.stop(),
+                    RTCSessionDescription::Offer(_) => {
+                        if let Some(mid) = transceiver.mid() {
+                            rejected.push(mid.to_owned());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            transceiver.receiver_mut().set_parameters(parameters);
+            if let RTCSessionDescription::Answer(_) = description {
+                let current_direction = if transceiver.is_stopped() {
+                    RtcRtpTransceiverDirection::Stopped
+                } else {
+                    direction.reverse()
+                };
+                transceiver.set_current_direction(current_direction);
+            }
+        }
+        self.rejected_mids.extend(rejected);
+    }
+
+    fn media_transceiver(&mut self, media: &SdpMedia) -> Option<&mut RtcRtpTransceiver> {
+        let mid = match media.get_attribute(SdpAttributeType::Mid) {
+            Some(SdpAttribute::Mid(mid)) => mid,
+            _ => return None,
+        };
+        self.transceivers
+            .iter_mut()
+            .find(|transceiver| transceiver.mid() == Some(mid))
+    }
+
+    // stableからofferに入る時だけ保存する．have-*-offerのままofferを置き換えても最初の状態に戻す
+    fn save_rollback_state(&mut self) {
+        if self.signaling_state != RTCSignalingState::Stable {
+            return;
+        }
+        self.rollback_state = Some(RollbackState {
+            local_username: self.ice_connection.local_username(),
+            remote_username: self.remote_username.clone(),
+            remote_password: self.remote_password.clone(),
+            ice_controlling: self.ice_connection.is_controlling(),
+            ice_restart_pending: self.ice_restart_pending,
+            ice_gathering_state: *self.ice_gathering_state.lock().unwrap(),
+            remote_setup: self.remote_setup.clone(),
+            remote_sections: self.remote_sections.clone(),
+            dtls_role: self.dtls_role,
+            remote_fingerprints: self.remote_fingerprints.clone(),
+            transceivers: self
+                .transceivers
+                .iter()
+                .map(RtcRtpTransceiver::negotiation_state)
+                .collect(),
+            created: vec![],
+        });
+    }
+
+    // offerを取り消してstableに戻す
+    fn rollback(&mut self) {
+        if let Some(state) = self.rollback_state.take() {
+            // ICE restartしていればrestart前のcredentialとcandidateに戻す
+            if self.ice_connection.local_username() != state.local_username {
+                self.ice_connection.rollback_restart();
+            }
+            match (&state.remote_username, &state.remote_password) {
+                (Some(username), Some(password)) => self
+                    .ice_connection
+                    .set_remote_credentials(username, password),
+                _ => self.ice_connection.reset_remote(),
+            }
+            self.ice_connection.set_controlling(state.ice_controlling);
+            *self.ice_gathering_state.lock().unwrap() = state.ice_gathering_state;
+            self.remote_username = state.remote_username;
+            self.remote_password = state.remote_password;
+            self.ice_restart_pending = state.ice_restart_pending;
+            self.remote_setup = state.remote_setup;
+            self.remote_sections = state.remote_sections;
+            self.dtls_role = state.dtls_role;
+            self.remote_fingerprints = state.remote_fingerprints;
+
+            // remoteのofferで作ったtransceiverは，trackを付けていなければ消す
+            for index in state.created.into_iter().rev() {
+                if !self.transceivers[index].sender().is_used() {
+                    self.transceivers.remove(index);
+                }
+            }
+            let saved = state.transceivers.len();
+            for transceiver in &mut self.transceivers[saved..] {
+                transceiver.reset_negotiation_state();
+            }
+            for (transceiver, saved) in self.transceivers.iter_mut().zip(state.transceivers) {
+                transceiver.restore_negotiation_state(saved);
+            }
+        }
+        self.pending_ice_credentials = None;
+        self.rejected_mids.clear();
+        self.pending_local_description = None;
+        self.pending_remote_description = None;
+        self.signaling_state = RTCSignalingState::Stable;
+    }
+
+    // remoteからtrickleされたcandidateを追加する．Noneはend-of-candidates
+    pub fn add_ice_candidate(&self, candidate: Option<RtcIceCandidate>) -> Result<()> {
+        if self.remote_description().is_none() {
+            return Err(RtcPeerConnectionError::InvalidState {
+                reason: "remote description is not set".to_owned(),
+            });
+        }
+
+        let candidate = match candidate {
+            Some(candidate) => candidate,
+            None => {
+                self.ice_connection.set_remote_end_of_candidates(true);
+                return Ok(());
+            }
+        };
+        if let Some(username_fragment) = &candidate.username_fragment {
+            if Some(username_fragment) != self.remote_username.as_ref() {
+                return Err(RtcPeerConnectionError::UnknownUsernameFragment {
+                    username_fragment: username_fragment.clone(),
+                });
+            }
+        }
+        self.add_remote_candidate(&candidate)
+    }
+
+    // .localの名前はIceConnectionがmDNSで解決してから追加する
+    fn add_remote_candidate(&self, candidate: &RtcIceCandidate) -> Result<()> {
+        if candidate.is_mdns() && self.ice_connection.mdns().is_some() {
+            let unresolved = candidate.to_candidate_with_ip(Ipv4Addr::UNSPECIFIED.into());
+            self.ice_connection
+                .add_remote_hostname_candidate(&candidate.address, unresolved);
+        } else {
+            self.ice_connection
+                .add_remote_candidate(candidate.to_candidate()?);
+        }
+        Ok(())
+    }
+
+    pub fn close(&mut self) {
+        self.signaling_state = RTCSignalingState::Closed;
+        self.ice_connection.close();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+
+    // 次のset_local_descriptionで新しいcredentialとcandidateを集め直す
+    fn restart_ice(&mut self) {
+        let mut state = self.ice_gathering_state.lock().unwrap();
+        if *state != IceGatheringState::New {
+            self.ice_connection.restart();
+            *state = IceGatheringState::New;
+        }
+    }
+
+    // 最初の一度 (とICE restartの後) だけgatherを始め，見つかったcandidateをon_ice_candidateに渡す
+    fn gather_candidates(&mut self, bundle_tag: Option<(String, u16)>) {
+        {
+            let mut state = self.ice_gathering_state.lock().unwrap();
+            if *state != IceGatheringState::New {
+                return;
+            }
+            *state = IceGatheringState::Gathering;
+        }
+
+        let candidates = self.ice_connection.subscribe_candidates();
+        let ice_connection = self.ice_connection.clone();
+        self.workers.push(thread::spawn(move || {
+            // 失敗してもend-of-candidatesは通知される
+            let _ = ice_connection.gather_candidates();
+        }));
+
+        let username_fragment = self.ice_connection.local_username();
+        let state = self.ice_gathering_state.clone();
+        let callback = self.on_ice_candidate.clone();
+        let ice_connection = self.ice_connection.clone();
+        self.workers.push(thread::spawn(move || {
+            for candidate in candidates.iter() {
+                let candidate = candidate.map(|candidate| {
+                    let mut candidate = obfuscate(&ice_connection, &candidate);
+                    // 全てのmediaをBUNDLEするので最初のm-lineのtransportだけ
+                    candidate.sdp_mid = bundle_tag.as_ref().map(|(mid, _)| mid.clone());
+                    candidate.sdp_m_line_index = bundle_tag.as_ref().map(|(_, index)| *index);
+                    candidate.username_fragment = Some(username_fragment.clone());
+                    candidate
+                });
+                if candidate.is_none() {
+                    *state.lock().unwrap() = IceGatheringState::Completed;
+                }
+                if let Some(callback) = callback.lock().unwrap().as_mut() {
+                    callback(candidate);
+                }
+            }
+        }));
+    }
+
+    // JSEP (RFC 8829) 5.2, 5.3．transceiver毎にm-sectionを作り全てBUNDLEする
+    // candidateはtrickleするので含めない
+    fn create_sdp(&mut self, typ: DescriptionType) -> SdpSession {
+        let transceivers = match typ {
+            DescriptionType::Offer => (0..self.transceivers.len()).collect(),
+            // answerはofferのm-sectionと同じ順にする (JSEP 5.3.1)
+            _ => self
+                .remote_sections
+                .iter()
+                .filter_map(|section| match section {
+                    RemoteMediaSection::Transceiver(mid) => self.transceiver_position(mid),
+                    RemoteMediaSection::Unsupported { .. } => None,
+                })
+                .collect::<Vec<_>>(),
+        };
+
+        let mut sdp = SdpSession::new(0, self.origin(), "-".to_owned());
+        sdp.set_timing(SdpTiming { start: 0, stop: 0 });
+        // midはset_local_descriptionで割り当てる
+        let mids = match typ {
+            DescriptionType::Offer => self.offer_mids(),
+            _ => self
+                .transceivers
+                .iter()
+                .map(|transceiver| transceiver.mid().map(str::to_owned))
+                .collect(),
+        };
+        // rejectしたm-sectionはBUNDLEしない
+        let bundle = transceivers
+            .iter()
+            .filter(|i| !self.is_rejected(&self.transceivers[**i], mids[**i].as_deref(), typ))
+            .filter_map(|i| mids[*i].clone())
+            .collect::<Vec<_>>();
+        let mut attributes = vec![];
+        let bundled = !bundle.is_empty();
+        if bundled {
+            attributes.push(SdpAttribute::Group(SdpAttributeGroup {
+                semantics: SdpAttributeGroupSemantic::Bundle,
+                tags: bundle,
+            }));
+        }
+        attributes.push(SdpAttribute::IceOptions(vec!["trickle".to_owned()]));
+        attributes.push(SdpAttribute::MsidSemantic(SdpAttributeMsidSemantic {
+            semantic: "WMS".to_owned(),
+            msids: vec![],
+        }));
+        // BUNDLEするm-sectionが無い時はtransportの情報をsessionに載せる
+        if !bundled {
+            sdp.set_connection(SdpConnection {
+                address: ExplicitlyTypedAddress::Ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                ttl: None,
+                amount: None,
+            });
+            attributes.extend(self.transport_attributes(typ));
+        }
+        for attribute in attributes {
+            sdp.add_attribute(attribute).unwrap();
+        }
+
+        let media = match typ {
+            DescriptionType::Offer => transceivers
+                .iter()
+                .map(|i| self.create_media(&self.transceivers[*i], mids[*i].as_deref(), typ))
+                .collect(),
+            _ => self
+                .remote_sections
+                .iter()
+                .filter_map(|section| match section {
+                    RemoteMediaSection::Transceiver(mid) => self
+                        .transceiver_position(mid)
+                        .map(|i| self.create_media(&self.transceivers[i], mids[i].as_deref(), typ)),
+                    RemoteMediaSection::Unsupported { line, mid } => {
+                        Some(unsupported_media(line, mid.as_deref()))
+                    }
+                })
+                .collect(),
+        };
+        sdp.extend_media(media);
+
+        // 内容が変わった時だけsession versionを上げる (JSEP 5.2.2)
+        let body = write_sdp(&sdp);
+        let body = body.splitn(3, "\r\n").nth(2).unwrap_or_default().to_owned();
+        if self
+            .last_local_sdp
+            .as_ref()
+            .map_or(false, |last| *last != body)
+        {
+            self.session_version += 1;
+            sdp.origin = self.origin();
+        }
+        self.last_local_sdp = Some(body);
+        sdp
+    }
+
+    fn transceiver_position(&self, mid: &str) -> Option<usize> {
+        self.transceivers
+            .iter()
+            .position(|transceiver| transceiver.mid() == Some(mid))
+    }
+
+    // midの無いtransceiverには0から順に使われていない数字を割り当てる
+    fn offer_mids(&self) -> Vec<Option<String>> {
+        let mut used = self
+            .transceivers
+            .iter()
+            .filter_map(|transceiver| transceiver.mid().map(str::to_owned))
+            .collect::<HashSet<_>>();
+        let mut next_mid = 0;
+        let mut mids = vec![];
+        for transceiver in &self.transceivers {
+            let mid = match transceiver.mid() {
+                Some(mid) => mid.to_owned(),
+                None => {
+                    while used.contains(&next_mid.to_string()) {
+                        next_mid += 1;
+                    }
+                    used.insert(next_mid.to_string());
+                    next_mid.to_string()
+                }
+            };
+            mids.push(Some(mid));
+        }
+        mids
+    }
+
+    // stopしたか，answerでrejectするm-section
+    fn is_rejected(
+        &self,
+        transceiver: &RtcRtpTransceiver,
+        mid: Option<&str>,
+        typ: DescriptionType,
+    ) -> bool {
+        transceiver.is_stopped()
+            || (typ != DescriptionType::Offer
+                && mid.map_or(false, |mid| self.rejected_mids.contains(mid)))
+    }
+
+    // offerならcreate_offerで作ったICE restartのcredential
+    fn local_credentials(&self, typ: DescriptionType) -> (String, String) {
+        match (&self.pending_ice_credentials, typ) {
+            (Some(credentials), DescriptionType::Offer) => credentials.clone(),
+            _ => (
+                self.ice_connection.local_username(),
+                self.ice_connection.local_password(),
+            ),
+        }
+    }
+
+    fn origin(&self) -> SdpOrigin {
+        SdpOrigin {
+            username: "-".to_owned(),
+            session_id: self.session_id,
+            session_version: self.session_version,
+            // JSEP 5.2.1: addressはnull addressにする
+            unicast_addr: ExplicitlyTypedAddress::Ip(Ipv4Addr::UNSPECIFIED.into()),
+        }
+    }
+
+    // ICEのcredentialとDTLSのfingerprint, setup
+    fn transport_attributes(&self, typ: DescriptionType) -> Vec<SdpAttribute> {
+        let (username, password) = self.local_credentials(typ);
+        let mut attributes = vec![
+            SdpAttribute::IceUfrag(username),
+            SdpAttribute::IcePwd(password),
+        ];
+        if let Ok(fingerprint) = self.certificate.fingerprint("sha-256") {
+            attributes.push(SdpAttribute::Fingerprint(SdpAttributeFingerprint {
+                hash_algorithm: SdpAttributeFingerprintHashType::Sha256,
+                fingerprint: fingerprint
+                    .value
+                    .split(':')
+                    .filter_map(|byte| u8::from_str_radix(byte, 16).ok())
+                    .collect(),
+            }));
+        }
+        // answerはremoteがactiveでなければactiveになる (JSEP 5.3.1)
+        let setup = match (typ, self.remote_setup.as_deref()) {
+            (DescriptionType::Offer, _) => SdpAttributeSetup::Actpass,
+            (_, Some("active")) => SdpAttributeSetup::Passive,
+            _ => SdpAttributeSetup::Active,
+        };
+        attributes.push(SdpAttribute::Setup(setup));
+        attributes
+    }
+
+    fn create_media(
+        &self,
+        transceiver: &RtcRtpTransceiver,
+        mid: Option<&str>,
+        typ: DescriptionType,
+    ) -> SdpMedia {
+        let stopped = self.is_rejected(transceiver, mid, typ);
+        let mut media = SdpMedia::new(SdpMediaLine {
+            media: match transceiver.kind() {
+                MediaKind::Audio => SdpMediaValue::Audio,
+                MediaKind::Video => SdpMediaValue::Video,
+            },
+            // port 0でrejectする (JSEP 5.2.2)
+            port: if stopped { 0 } else { 9 },
+            port_count: 0,
+            proto: SdpProtocolValue::UdpTlsRtpSavpf,
+            formats: SdpFormatList::Integers(vec![]),
+        });
+        media
+            .set_connection(SdpConnection {
+                address: ExplicitlyTypedAddress::Ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                ttl: None,
+                amount: None,
+            })
+            .unwrap();
+
+        let mut attributes = if stopped {
+            vec![]
+        } else {
+            self.transport_attributes(typ)
+        };
+        if let Some(mid) = mid {
+            attributes.push(SdpAttribute::Mid(mid.to_owned()));
+        }
+        // answerではofferとnegotiateしたcodecとIDを使う
+        let parameters = match typ {
+            DescriptionType::Offer => None,
+            _ => transceiver.receiver().parameters(),
+        };
+        let (codecs, header_extensions) = match parameters {
+            Some(parameters) => (
+                parameters.param.codecs.as_slice(),
+                parameters.param.header_extensions.as_slice(),
+            ),
+            None => (transceiver.codecs(), transceiver.header_extensions()),
+        };
+        for extension in header_extensions {
+            attributes.push(SdpAttribute::Extmap(SdpAttributeExtmap {
+                id: extension.id as u16,
+                direction: None,
+                url: extension.uri.clone(),
+                extension_attributes: None,
+            }));
+        }
+        // pranswerではまだメディアを流さない
+        let direction = match typ {
+            DescriptionType::Offer => transceiver.direction(),
+            DescriptionType::Answer => transceiver.direction().answer(
+                transceiver
+                    .remote_direction()
+                    .unwrap_or(RtcRtpTransceiverDirection::Sendrecv),
+            ),
+            DescriptionType::Pranswer => RtcRtpTransceiverDirection::Inactive,
+        };
+        attributes.push(match direction {
+            RtcRtpTransceiverDirection::Sendrecv => SdpAttribute::Sendrecv,
+            RtcRtpTransceiverDirection::Sendonly => SdpAttribute::Sendonly,
+            RtcRtpTransceiverDirection::Recvonly => SdpAttribute::Recvonly,
+            RtcRtpTransceiverDirection::Inactive | RtcRtpTransceiverDirection::Stopped => {
+                SdpAttribute::Inactive
+            }
+        });
+        let (stream_id, track_id) = transceiver.sender().msid();
+        if direction.has_send() {
+            attributes.push(SdpAttribute::Msid(SdpAttributeMsid {
+                id: stream_id.clone(),
+                appdata: Some(track_id.clone()),
+            }));
+        }
+        attributes.push(SdpAttribute::RtcpMux);
+        if transceiver.kind() == MediaKind::Video {
+            attributes.push(SdpAttribute::RtcpRsize);
+        }
+        for attribute in attributes {
+            media.add_attribute(attribute).unwrap();
+        }
+
+        for codec in codecs {
+            let payload_type = match codec.payload_type {
+                Some(payload_type) => payload_type as u8,
+                None => continue,
+            };
+            media
+                .add_codec(SdpAttributeRtpmap {
+                    payload_type,
+                    codec_name: codec.name(),
+                    frequency: codec.clock_rate as u32,
+                    channels: codec.channels.filter(|channels| *channels == 2).map(|_| 2),
+                })
+                .unwrap();
+            for feedback in &codec.rtcp_feedback {
+                if let Some(feedback) = rtcp_feedback_attribute(payload_type, feedback) {
+                    media.add_attribute(feedback).unwrap();
+                }
+            }
+            if !codec.parameters.is_empty() {
+                media
+                    .add_attribute(fmtp_attribute(payload_type, &codec.parameters))
+                    .unwrap();
+            }
+        }
+
+        if direction.has_send() {
+            let ssrc = transceiver.sender().ssrc();
+            let ssrc_attributes = vec![
+                ("cname", self.cname.clone()),
+                ("msid", format!("{} {}", stream_id, track_id)),
+            ];
+            for (attribute, value) in ssrc_attributes {
+                media
+                    .add_attribute(SdpAttribute::Ssrc(SdpAttributeSsrc {
+                        id: ssrc,
+                        attribute: Some(attribute.to_owned()),
+                        value: Some(value),
+                    }))
+                    .unwrap();
+            }
+        }
+        media
+    }
+}
+
+// offerと同じmedia, proto, formatでport 0にする (JSEP 5.3.1)
+fn unsupported_media(line: &SdpMediaLine, mid: Option<&str>) -> SdpMedia {
+    let mut media = SdpMedia::new(line.clone());
+    media
+        .set_connection(SdpConnection {
+            address: ExplicitlyTypedAddress::Ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            ttl: None,
+            amount: None,
+        })
+        .unwrap();
+    if let Some(mid) = mid {
+        media
+            .add_attribute(SdpAttribute::Mid(mid.to_owned()))
+            .unwrap();
+    }
+    media
+}
+
+// port 0はrejectされたm-section
+fn media_direction(media: &SdpMedia) -> RtcRtpTransceiverDirection {
+    if media.get_port() == 0 {
+        return RtcRtpTransceiverDirection::Stopped;
+    }
+    media
+        .get_attributes()
+        .iter()
+        .find_map(|attribute| match attribute {
+            SdpAttribute::Sendrecv => Some(RtcRtpTransceiverDirection::Sendrecv),
+            SdpAttribute::Sendonly => Some(RtcRtpTransceiverDirection::Sendonly),
+            SdpAttribute::Recvonly => Some(RtcRtpTransceiverDirection::Recvonly),
+            SdpAttribute::Inactive => Some(RtcRtpTransceiverDirection::Inactive),
+            _ => None,
+        })
+        .unwrap_or(RtcRtpTransceiverDirection::Sendrecv)
+}
+
+// webrtc_sdpは読めないa=candidateが1つでもあるとdescription全体を読めないので，candidateは取り除いて自分で読む
+// 読めないcandidateは無視する
+fn split_candidates(sdp: &str) -> (String, Vec<RtcIceCandidate>) {
+    let mut rest = String::new();
+    let mut candidates = vec![];
+    for line in sdp.lines() {
+        if !line.starts_with("a=candidate:") {
+            rest.push_str(line);
+            rest.push_str("\r\n");
+            continue;
+        }
+        match line.parse::<RtcIceCandidate>() {
+            Ok(candidate) => candidates.push(candidate),
+            Err(e) => log::debug!("skipping a remote candidate: {}", e),
+        }
+    }
+    (rest, candidates)
+}
+
+fn parse_sdp(sdp: &str) -> Result<SdpSession> {
+    webrtc_sdp::parse_sdp(sdp, false).map_err(|e| invalid_description(&e.to_string()))
+}
+
+fn invalid_description(reason: &str) -> RtcPeerConnectionError {
+    RtcPeerConnectionError::InvalidSessionDescription {
+        reason: reason.to_owned(),
+    }
+}
+
+// BUNDLEするのでsessionと全てのm-sectionの属性をまとめて見る
+fn sdp_attributes(sdp: &SdpSession) -> impl Iterator<Item = &SdpAttribute> {
+    sdp.attribute
+        .iter()
+        .chain(sdp.media.iter().flat_map(|media| media.get_attributes()))
+}
+
+// sessionか最初に見つかったm-sectionの値
+fn ice_attribute<T, F>(sdp: &SdpSession, f: F) -> Option<T>
+where
+    F: Fn(&SdpAttribute) -> Option<T>,
+{
+    sdp_attributes(sdp).find_map(f)
+}
+
+fn rtcp_feedback_attribute(payload_type: u8, feedback: &RtcRtcpFeedback) -> Option<SdpAttribute> {
+    let feedback_type = match feedback.kind.as_str() {
+        "ack" => SdpAttributeRtcpFbType::Ack,
+        "ccm" => SdpAttributeRtcpFbType::Ccm,
+        "nack" => SdpAttributeRtcpFbType::Nack,
+        "trr-int" => SdpAttributeRtcpFbType::TrrInt,
+        "goog-remb" => SdpAttributeRtcpFbType::Remb,
+        "transport-cc" => SdpAttributeRtcpFbType::TransCC,
+        _ => return None,
+    };
+    Some(SdpAttribute::Rtcpfb(SdpAttributeRtcpFb {
+        payload_type: SdpAttributePayloadType::PayloadType(payload_type),
+        feedback_type,
+        parameter: feedback.param.clone().unwrap_or_default(),
+        extra: String::new(),
+    }))
+}
+
+impl Drop for RTCPeerConnection {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+// mDNSの名前があればhost candidateのaddressを隠す
+// 他のcandidateのrelated addressも隠すので0.0.0.0にする
+fn obfuscate(ice_connection: &IceConnection, candidate: &Candidate) -> RtcIceCandidate {
+    let mut obfuscated = RtcIceCandidate::from_candidate(candidate);
+    if candidate.typ == CandidateType::Host {
+        if let Some(hostname) = ice_connection.local_hostname(candidate.address.ip()) {
+            obfuscated.address = hostname;
+        }
+    } else if let Some(related_address) = candidate.related_address {
+        if ice_connection
+            .local_hostname(related_address.ip())
+            .is_some()
+        {
+            obfuscated.related_address = Some(Ipv4Addr::UNSPECIFIED.to_string());
+            obfuscated.related_port = Some(0);
+        }
+    }
+    obfuscated
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ice::mdns::{Mdns, MDNS_GROUP};
+    use std::net::SocketAddrV4;
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::Duration;
+
+    fn loopback_ice_connection(ice_controlling: bool) -> IceConnection {
+        let mut ice_connection = IceConnection::new(ice_controlling, 1, None, None, true, false);
+        ice_connection.set_include_loopback(true);
+        ice_connection
+    }
+
+    fn loopback_peer_connection(
+        ice_controlling: bool,
+    ) -> (RTCPeerConnection, Receiver<Option<RtcIceCandidate>>) {
+        peer_connection(loopback_ice_connection(ice_controlling))
+    }
+
+    // 実際の5353に届かないようにloopbackの別のportを使う
+    fn mdns_peer_connection(
+        ice_controlling: bool,
+    ) -> (RTCPeerConnection, Receiver<Option<RtcIceCandidate>>) {
+        let mdns = Mdns::bind(SocketAddrV4::new(MDNS_GROUP, 53536), Ipv4Addr::LOCALHOST).unwrap();
+        let mut ice_connection = loopback_ice_connection(ice_controlling);
+        ice_connection.set_mdns(Arc::new(mdns), true);
+        peer_connection(ice_connection)
+    }
+
+    fn peer_connection(
+        ice_connection: IceConnection,
+    ) -> (RTCPeerConnection, Receiver<Option<RtcIceCandidate>>) {
+        let peer_connection = RTCPeerConnection::with_ice_connection(ice_connection).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        peer_connection.on_ice_candidate(move |candidate| {
+            let _ = tx.send(candidate);
+        });
+        (peer_connection, rx)
+    }
+
+    // end-of-candidatesまで渡して，渡したcandidateの数を返す
+    fn trickle(from: &Receiver<Option<RtcIceCandidate>>, to: &RTCPeerConnection) -> usize {
+        let mut trickled = 0;
+        loop {
+            let candidate = from.recv_timeout(Duration::from_secs(5)).unwrap();
+            if let Some(candidate) = &candidate {
+                // m-sectionが無ければmidもm-lineの位置も無い
+                assert_eq!(
+                    candidate.sdp_mid.is_some(),
+                    candidate.sdp_m_line_index.is_some()
+                );
+                trickled += 1;
+            }
+            let end_of_candidates = candidate.is_none();
+            to.add_ice_candidate(candidate).unwrap();
+            if end_of_candidates {
+                return trickled;
+            }
+        }
+    }
+
+    // aがofferしてbがanswerする
+    fn negotiate(a: &mut RTCPeerConnection, b: &mut RTCPeerConnection) {
+        let offer = a.create_offer().unwrap();
+        a.set_local_description(offer.clone()).unwrap();
+        b.set_remote_description(offer).unwrap();
+        let answer = b.create_answer().unwrap();
+        b.set_local_description(answer.clone()).unwrap();
+        a.set_remote_description(answer).unwrap();
+    }
+
+    fn connect(a: &RTCPeerConnection, b: &RTCPeerConnection) {
+        let b_ice_connection = b.ice_connection();
+        let handle = thread::spawn(move || b_ice_connection.connect(Duration::from_secs(10)));
+        a.ice_connection().connect(Duration::from_secs(10)).unwrap();
+        handle.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn trickle_ice_test() {
+        let (mut a, a_candidates) = loopback_peer_connection(true);
+        let (mut b, b_candidates) = loopback_peer_connection(false);
+
+        let offer = a.create_offer().unwrap();
+        assert!(offer.sdp().unwrap().contains("a=ice-options:trickle\r\n"));
+        assert_eq!(a.ice_gathering_state(), IceGatheringState::New);
+        a.set_local_description(offer.clone()).unwrap();
+        assert_ne!(a.ice_gathering_state(), IceGatheringState::New);
+        b.set_remote_description(offer).unwrap();
+        let answer = b.create_answer().unwrap();
+        b.set_local_description(answer.clone()).unwrap();
+        a.set_remote_description(answer).unwrap();
+        assert_eq!(a.dtls_role(), Some(RtcDtlsRole::Server));
+        assert_eq!(b.dtls_role(), Some(RtcDtlsRole::Client));
+        assert_eq!(
+            a.remote_fingerprints(),
+            &[b.certificate().fingerprint("sha-256").unwrap()]
+        );
+        assert!(a.ice_connection().is_controlling());
+        assert!(!b.ice_connection().is_controlling());
+
+        assert!(trickle(&a_candidates, &b) > 0);
+        assert!(trickle(&b_candidates, &a) > 0);
+        assert_eq!(a.ice_gathering_state(), IceGatheringState::Completed);
+        assert_eq!(b.ice_gathering_state(), IceGatheringState::Completed);
+
+        let (tx, states) = mpsc::channel();
+        a.on_ice_connection_state_change(move |state| {
+            let _ = tx.send(state);
+        });
+
+        connect(&a, &b);
+        assert_eq!(a.ice_connection_state(), IceConnectionState::Connected);
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            states.recv_timeout(timeout),
+            Ok(IceConnectionState::Checking)
+        );
+        assert_eq!(
+            states.recv_timeout(timeout),
+            Ok(IceConnectionState::Connected)
+        );
+
+        a.close();
+        assert_eq!(states.recv_timeout(timeout), Ok(IceConnectionState::Closed));
+    }
+
+    #[test]
+    fn ice_restart_test() {
+        let (mut a, a_candidates) = loopback_peer_connection(true);
+        let (mut b, b_candidates) = loopback_peer_connection(false);
+        negotiate(&mut a, &mut b);
+        trickle(&a_candidates, &b);
+        trickle(&b_candidates, &a);
+        connect(&a, &b);
+        let selected = a.ice_connection().selected_pair(1).unwrap();
+        let (a_username, b_username) = (
+            a.ice_connection().local_username(),
+            b.ice_connection().local_username(),
+        );
+
+        let offer = a
+            .create_offer_with_options(RTCOfferOptions { ice_restart: true })
+            .unwrap();
+        a.set_local_description(offer.clone()).unwrap();
+        let a_restarted = a.ice_connection().local_username();
+        assert_ne!(a_restarted, a_username);
+        assert!(offer
+            .sdp()
+            .unwrap()
+            .contains(&format!("a=ice-ufrag:{}\r\n", a_restarted)));
+
+        // credentialが変わったのでbもrestartする
+        b.set_remote_description(offer).unwrap();
+        assert_ne!(b.ice_connection().local_username(), b_username);
+        let answer = b.create_answer().unwrap();
+        b.set_local_description(answer.clone()).unwrap();
+        a.set_remote_description(answer).unwrap();
+
+        // 新しいpairが決まるまでは前のpairで送れる
+        a.ice_connection().send(b"before").unwrap();
+        let mut buf = [0u8; 16];
+        let len = b
+            .ice_connection()
+            .recv_timeout(&mut buf, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(&buf[..len], b"before");
+
+        assert!(trickle(&a_candidates, &b) > 0);
+        assert!(trickle(&b_candidates, &a) > 0);
+        connect(&a, &b);
+        let restarted = a.ice_connection().selected_pair(1).unwrap();
+        assert_ne!(restarted.local.address, selected.local.address);
+
+        b.ice_connection().send(b"after").unwrap();
+        let len = a
+            .ice_connection()
+            .recv_timeout(&mut buf, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(&buf[..len], b"after");
+    }
+
+    #[test]
+    fn mdns_test() {
+        let (mut a, a_candidates) = mdns_peer_connection(true);
+        let (mut b, b_candidates) = mdns_peer_connection(false);
+        negotiate(&mut a, &mut b);
+
+        while let Some(candidate) = a_candidates.recv_timeout(Duration::from_secs(5)).unwrap() {
+            assert!(candidate.is_mdns(), "{}", candidate);
+            let parsed: RtcIceCandidate = candidate.to_string().parse().unwrap();
+            assert_eq!(parsed.address, candidate.address);
+            b.add_ice_candidate(Some(candidate)).unwrap();
+        }
+        b.add_ice_candidate(None).unwrap();
+        assert!(trickle(&b_candidates, &a) > 0);
+
+        connect(&a, &b);
+        // 名前は解決されてaのaddressになっている
+        let addresses = a
+            .ice_connection()
+            .local_candidates()
+            .iter()
+            .map(|candidate| candidate.address)
+            .collect::<Vec<_>>();
+        let selected = b.ice_connection().selected_pair(1).unwrap();
+        assert!(addresses.contains(&selected.remote.address));
+        assert!(b
+            .ice_connection()
+            .remote_candidates()
+            .iter()
+            .all(|candidate| addresses.contains(&candidate.address)));
+
+        a.close();
+        b.close();
+    }
+
+    #[test]
+    fn create_offer_test() {
+        let (mut a, _) = loopback_peer_connection(true);
+        a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        a.add_transceiver(MediaKind::Video, RtcRtpTransceiverDirection::Recvonly)
+            .unwrap();
+
+        let offer = a.create_offer().unwrap();
+        let sdp = offer.sdp().unwrap();
+        let session = webrtc_sdp::parse_sdp(sdp, true).unwrap();
+        assert_eq!(session.media.len(), 2);
+        let lines = sdp.lines().collect::<Vec<_>>();
+        let ssrc = a.transceivers()[0].sender().ssrc();
+        let (stream_id, track_id) = a.transceivers()[0].sender().msid();
+        let fingerprint = a.certificate().fingerprint("sha-256").unwrap();
+        let expected = vec![
+            format!("o=- {} 0 IN IP4 0.0.0.0", a.session_id),
+            "a=group:BUNDLE 0 1".to_owned(),
+            "a=ice-options:trickle".to_owned(),
+            "m=audio 9 UDP/TLS/RTP/SAVPF 111 9 0 8 110 126".to_owned(),
+            "c=IN IP4 0.0.0.0".to_owned(),
+            format!("a=ice-ufrag:{}", a.ice_connection().local_username()),
+            format!("a=fingerprint:sha-256 {}", fingerprint.value),
+            "a=setup:actpass".to_owned(),
+            "a=mid:0".to_owned(),
+            "a=extmap:1 urn:ietf:params:rtp-hdrext:sdes:mid".to_owned(),
+            format!("a=msid:{} {}", stream_id, track_id),
+            "a=rtcp-mux".to_owned(),
+            "a=rtpmap:111 opus/48000/2".to_owned(),
+            "a=rtcp-fb:111 transport-cc".to_owned(),
+            "a=fmtp:111 useinbandfec=1;minptime=10".to_owned(),
+            format!("a=ssrc:{} cname:{}", ssrc, a.cname),
+            "m=video 9 UDP/TLS/RTP/SAVPF 96 97 98 99 100 101 102 122 127 121 125 107 108 109 124 120 123 119 117 118 114 115 116".to_owned(),
+            "a=mid:1".to_owned(),
+            "a=recvonly".to_owned(),
+            "a=rtcp-fb:96 nack pli".to_owned(),
+            "a=fmtp:97 apt=96".to_owned(),
+            "a=fmtp:125 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f"
+                .to_owned(),
+        ];
+        for line in &expected {
+            assert!(lines.contains(&line.as_str()), "{} is missing", line);
+        }
+        // 受信だけのm-sectionにはSSRCが無い
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|line| line.starts_with("a=ssrc:"))
+                .count(),
+            2
+        );
+        assert!(lines.iter().all(|line| !line.is_empty()));
+
+        // 内容が同じならsession versionは変わらない
+        let origin = |description: &RTCSessionDescription| {
+            let sdp = webrtc_sdp::parse_sdp(description.sdp().unwrap(), true).unwrap();
+            (sdp.origin.session_id, sdp.origin.session_version)
+        };
+        let (session_id, session_version) = origin(&offer);
+        assert_eq!(
+            origin(&a.create_offer().unwrap()),
+            (session_id, session_version)
+        );
+        a.add_transceiver(MediaKind::Video, RtcRtpTransceiverDirection::Sendonly)
+            .unwrap();
+        let offer = a.create_offer().unwrap();
+        assert_eq!(origin(&offer), (session_id, session_version + 1));
+        assert!(offer.sdp().unwrap().contains("a=group:BUNDLE 0 1 2\r\n"));
+    }
+
+    #[test]
+    fn gather_test() {
+        let (mut a, a_candidates) = loopback_peer_connection(true);
+
+        // 最初のm-sectionはrejectされているのでBUNDLEするのは2つ目
+        a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap()
+            .stop();
+        a.add_transceiver(MediaKind::Video, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        let offer = a.create_offer().unwrap();
+        a.set_local_description(offer).unwrap();
+        let candidate = a_candidates
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert_eq!(candidate.sdp_mid.as_deref(), Some("1"));
+        assert_eq!(candidate.sdp_m_line_index, Some(1));
+    }
+
+    #[test]
+    fn create_answer_test() {
+        let (mut a, _) = loopback_peer_connection(true);
+        let (mut b, _) = loopback_peer_connection(false);
+        a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        b.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+
+        b.set_remote_description(a.create_offer().unwrap()).unwrap();
+        let pranswer = b.create_pranswer().unwrap();
+        let sdp = pranswer.sdp().unwrap();
+        assert!(webrtc_sdp::parse_sdp(sdp, true).is_ok());
+        assert!(sdp.contains("a=inactive\r\n"));
+        assert!(!sdp.contains("a=sendrecv\r\n"));
+        assert!(!sdp.contains("a=ssrc:"));
+
+        let answer = b.create_answer().unwrap();
+        let sdp = answer.sdp().unwrap();
+        assert!(webrtc_sdp::parse_sdp(sdp, true).is_ok());
+        assert!(sdp.contains("a=setup:active\r\n"));
+        assert!(sdp.contains("a=sendrecv\r\n"));
+        // offerで使われたtransceiverにはそのmidが付く
+        assert_eq!(b.transceivers()[0].mid(), Some("0"));
+    }
+
+    #[test]
+    fn signaling_state_test() {
+        let (mut a, _) = loopback_peer_connection(true);
+        let (mut b, _) = loopback_peer_connection(false);
+        a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        assert_eq!(a.signaling_state(), RTCSignalingState::Stable);
+
+        // stableではanswerを作れない
+        match b.create_answer() {
+            Err(RtcPeerConnectionError::InvalidStateTransition { state, .. }) => {
+                assert_eq!(state, RTCSignalingState::Stable)
+            }
+            result => panic!("{:?}", result),
+        }
+
+        let offer = a.create_offer().unwrap();
+        a.set_local_description(offer.clone()).unwrap();
+        assert_eq!(a.signaling_state(), RTCSignalingState::HaveLocalOffer);
+        assert_eq!(a.pending_local_description(), Some(&offer));
+        assert!(a.current_local_description().is_none());
+        assert!(a.set_remote_description(offer.clone()).is_err());
+
+        // remoteのofferで作られたtransceiverはrollbackで消える
+        b.set_remote_description(offer.clone()).unwrap();
+        assert_eq!(b.signaling_state(), RTCSignalingState::HaveRemoteOffer);
+        assert_eq!(b.transceivers().len(), 1);
+        assert_eq!(
+            b.transceivers()[0].direction(),
+            RtcRtpTransceiverDirection::Recvonly
+        );
+        b.set_remote_description(RTCSessionDescription::Rollback)
+            .unwrap();
+        assert_eq!(b.signaling_state(), RTCSignalingState::Stable);
+        assert!(b.transceivers().is_empty());
+        assert!(b.remote_description().is_none());
+        assert!(b
+            .set_local_description(RTCSessionDescription::Rollback)
+            .is_err());
+
+        b.set_remote_description(offer.clone()).unwrap();
+        let pranswer = b.create_pranswer().unwrap();
+        b.set_local_description(pranswer.clone()).unwrap();
+        assert_eq!(b.signaling_state(), RTCSignalingState::HaveLocalPranswer);
+        a.set_remote_description(pranswer).unwrap();
+        assert_eq!(a.signaling_state(), RTCSignalingState::HaveRemotePranswer);
+
+        let answer = b.create_answer().unwrap();
+        // 他のpeerのdescriptionはlocalに使えない
+        assert!(a.set_local_description(answer.clone()).is_err());
+        b.set_local_description(answer.clone()).unwrap();
+        a.set_remote_description(answer.clone()).unwrap();
+        for pc in &[&a, &b] {
+            assert_eq!(pc.signaling_state(), RTCSignalingState::Stable);
+            assert!(pc.pending_local_description().is_none());
+            assert!(pc.pending_remote_description().is_none());
+        }
+        assert_eq!(a.current_local_description(), Some(&offer));
+        assert_eq!(a.current_remote_description(), Some(&answer));
+
+        // stableでanswerは受け取れない
+        assert_eq!(
+            a.set_remote_description(answer),
+            Err(RtcPeerConnectionError::InvalidStateTransition {
+                state: RTCSignalingState::Stable,
+                operation: "set remote answer".to_owned(),
+            })
+        );
+
+        // 自分のofferをrollbackしても前のdescriptionは残る
+        let offer = a.create_offer().unwrap();
+        a.set_local_description(offer).unwrap();
+        a.set_local_description(RTCSessionDescription::Rollback)
+            .unwrap();
+        assert_eq!(a.signaling_state(), RTCSignalingState::Stable);
+        assert!(a.current_local_description().is_some());
+
+        a.close();
+        assert_eq!(a.signaling_state(), RTCSignalingState::Closed);
+        assert!(a.create_offer().is_err());
+    }
+
+    #[test]
+    fn rollback_test() {
+        let (mut a, a_candidates) = loopback_peer_connection(true);
+        let (mut b, b_candidates) = loopback_peer_connection(false);
+        a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        negotiate(&mut a, &mut b);
+        trickle(&a_candidates, &b);
+        trickle(&b_candidates, &a);
+
+        let b_username = b.ice_connection().local_username();
+        let remote_username = b.remote_username.clone();
+        let remote_fingerprints = b.remote_fingerprints().to_vec();
+        let dtls_role = b.dtls_role();
+        let parameters = b.transceivers()[0].receiver().parameters().cloned();
+        let current_direction = b.transceivers()[0].current_direction();
+
+        // 別のpeerのofferはcredentialもfingerprintも違うのでbはICE restartする
+        let (mut c, _) = loopback_peer_connection(true);
+        c.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendonly)
+            .unwrap();
+        c.add_transceiver(MediaKind::Video, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        let offer = c.create_offer().unwrap();
+        c.set_local_description(offer.clone()).unwrap();
+        b.set_remote_description(offer).unwrap();
+        assert_ne!(b.ice_connection().local_username(), b_username);
+        assert_ne!(b.remote_username, remote_username);
+        assert_ne!(b.remote_fingerprints(), &remote_fingerprints[..]);
+        assert_eq!(b.transceivers().len(), 2);
+
+        b.set_remote_description(RTCSessionDescription::Rollback)
+            .unwrap();
+        assert_eq!(b.ice_connection().local_username(), b_username);
+        assert_eq!(b.remote_username, remote_username);
+        assert_eq!(b.remote_fingerprints(), &remote_fingerprints[..]);
+        assert_eq!(b.dtls_role(), dtls_role);
+        assert_eq!(b.ice_gathering_state(), IceGatheringState::Completed);
+        assert_eq!(b.transceivers().len(), 1);
+        let transceiver = &b.transceivers()[0];
+        assert_eq!(transceiver.mid(), Some("0"));
+        assert_eq!(transceiver.receiver().parameters().cloned(), parameters);
+        assert_eq!(transceiver.current_direction(), current_direction);
+        assert_eq!(
+            transceiver.direction(),
+            RtcRtpTransceiverDirection::Recvonly
+        );
+
+        // ICE restartと新しいmidも自分のofferのrollbackで取り消す
+        let a_username = a.ice_connection().local_username();
+        a.add_transceiver(MediaKind::Video, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        let offer = a
+            .create_offer_with_options(RTCOfferOptions { ice_restart: true })
+            .unwrap();
+        assert_eq!(a.ice_connection().local_username(), a_username);
+        assert_eq!(a.transceivers()[1].mid(), None);
+        a.set_local_description(offer).unwrap();
+        assert_ne!(a.ice_connection().local_username(), a_username);
+        assert_eq!(a.transceivers()[1].mid(), Some("1"));
+
+        a.set_local_description(RTCSessionDescription::Rollback)
+            .unwrap();
+        assert_eq!(a.ice_connection().local_username(), a_username);
+        assert_eq!(a.transceivers()[1].mid(), None);
+        assert!(!a.ice_restart_pending);
+        assert_eq!(a.ice_gathering_state(), IceGatheringState::Completed);
+
+        // rollbackした後もnegotiateできる
+        negotiate(&mut a, &mut b);
+        assert_eq!(b.transceivers().len(), 2);
+        assert_eq!(b.ice_connection().local_username(), b_username);
+    }
+
+    #[test]
+    fn add_track_test() {
+        let (mut a, _) = loopback_peer_connection(true);
+        let (mut b, _) = loopback_peer_connection(false);
+        let track = MediaStreamTrack::new(MediaKind::Audio);
+        let sender = a.add_track(track.clone(), &["stream"]).unwrap();
+        assert_eq!(sender.track(), Some(&track));
+        assert_eq!(
+            a.add_track(track.clone(), &[]).err(),
+            Some(RtcPeerConnectionError::TrackAlreadyAdded {
+                id: track.id().to_owned()
+            })
+        );
+
+        let offer = a.create_offer().unwrap();
+        assert!(offer
+            .sdp()
+            .unwrap()
+            .contains(&format!("a=msid:stream {}\r\n", track.id())));
+        a.set_local_description(offer.clone()).unwrap();
+        b.set_remote_description(offer).unwrap();
+        assert_eq!(b.transceivers().len(), 1);
+        assert_eq!(
+            b.transceivers()[0].direction(),
+            RtcRtpTransceiverDirection::Recvonly
+        );
+
+        // remoteのofferで作られたtransceiverで送る
+        b.add_track(MediaStreamTrack::new(MediaKind::Audio), &[])
+            .unwrap();
+        assert_eq!(b.transceivers().len(), 1);
+        assert_eq!(
+            b.transceivers()[0].direction(),
+            RtcRtpTransceiverDirection::Sendrecv
+        );
+        let answer = b.create_answer().unwrap();
+        assert!(answer.sdp().unwrap().contains("a=sendrecv\r\n"));
+        b.set_local_description(answer.clone()).unwrap();
+        a.set_remote_description(answer).unwrap();
+
+        for pc in &[&a, &b] {
+            let transceiver = &pc.transceivers()[0];
+            assert_eq!(
+                transceiver.current_direction(),
+                Some(RtcRtpTransceiverDirection::Sendrecv)
+            );
+            let parameters = transceiver.receiver().parameters().unwrap();
+            assert_eq!(parameters.param.codecs[0].payload_type, Some(111));
+            assert_eq!(parameters.param.mux_id, "0");
+        }
+        // aのtrackをbが受信する
+        assert_eq!(
+            b.transceivers()[0]
+                .receiver()
+                .parameters()
+                .unwrap()
+                .decoding[0]
+                .0
+                .ssrc,
+            a.transceivers()[0].sender().ssrc()
+        );
+
+        // 一度送信に使ったtransceiverは使わない
+        a.add_track(MediaStreamTrack::new(MediaKind::Audio), &[])
+            .unwrap();
+        assert_eq!(a.transceivers().len(), 2);
+
+        a.close();
+        match a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv) {
+            Err(RtcPeerConnectionError::InvalidState { .. }) => {}
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+        assert_eq!(a.transceivers().len(), 2);
+    }
+
+    #[test]
+    fn direction_test() {
+        let (mut a, _) = loopback_peer_connection(true);
+        let (mut b, _) = loopback_peer_connection(false);
+        a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendonly)
+            .unwrap();
+        a.add_transceiver(MediaKind::Video, RtcRtpTransceiverDirection::Recvonly)
+            .unwrap();
+        b.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        b.add_transceiver(MediaKind::Video, RtcRtpTransceiverDirection::Recvonly)
+            .unwrap();
+
+        negotiate(&mut a, &mut b);
+        let current_directions = |pc: &RTCPeerConnection| {
+            pc.transceivers()
+                .iter()
+                .map(|transceiver| transceiver.current_direction())
+                .collect::<Vec<_>>()
         };
-        SdpSession::new(0, sdp_origin, "".to_string())
+        assert_eq!(
+            current_directions(&a),
+            vec![
+                Some(RtcRtpTransceiverDirection::Sendonly),
+                Some(RtcRtpTransceiverDirection::Inactive)
+            ]
+        );
+        assert_eq!(
+            current_directions(&b),
+            vec![
+                Some(RtcRtpTransceiverDirection::Recvonly),
+                Some(RtcRtpTransceiverDirection::Inactive)
+            ]
+        );
+        // 受信しかしないanswerにはSSRCが無い
+        let answer = b.current_local_description().unwrap().sdp().unwrap();
+        assert!(!answer.contains("a=ssrc:"));
+    }
+
+    #[test]
+    fn stop_test() {
+        let (mut a, _) = loopback_peer_connection(true);
+        let (mut b, _) = loopback_peer_connection(false);
+        a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        a.add_transceiver(MediaKind::Video, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        negotiate(&mut a, &mut b);
+
+        a.transceivers_mut()[1].stop();
+        a.transceivers_mut()[1].set_direction(RtcRtpTransceiverDirection::Sendrecv);
+        assert!(a.transceivers()[1].is_stopped());
+        let offer = a.create_offer().unwrap();
+        let sdp = offer.sdp().unwrap();
+        assert!(webrtc_sdp::parse_sdp(sdp, true).is_ok());
+        assert!(sdp.contains("a=group:BUNDLE 0\r\n"));
+        assert!(sdp.contains("m=video 0 UDP/TLS/RTP/SAVPF"));
+
+        a.set_local_description(offer.clone()).unwrap();
+        b.set_remote_description(offer).unwrap();
+        // answerを適用するまではstopしない
+        assert!(!b.transceivers()[1].is_stopped());
+        let answer = b.create_answer().unwrap();
+        assert!(answer
+            .sdp()
+            .unwrap()
+            .contains("m=video 0 UDP/TLS/RTP/SAVPF"));
+        b.set_local_description(answer.clone()).unwrap();
+        assert!(b.transceivers()[1].is_stopped());
+        a.set_remote_description(answer).unwrap();
+        // bのtransceiverはofferで作られたので受信だけ
+        assert_eq!(
+            a.transceivers()[0].current_direction(),
+            Some(RtcRtpTransceiverDirection::Sendonly)
+        );
+        assert_eq!(
+            b.transceivers()[0].current_direction(),
+            Some(RtcRtpTransceiverDirection::Recvonly)
+        );
+        for pc in &[&a, &b] {
+            assert_eq!(
+                pc.transceivers()[1].current_direction(),
+                Some(RtcRtpTransceiverDirection::Stopped)
+            );
+            assert!(pc.transceivers()[1].receiver().parameters().is_none());
+        }
+    }
+
+    #[test]
+    fn reject_test() {
+        let (mut a, _) = loopback_peer_connection(true);
+        let (mut b, _) = loopback_peer_connection(false);
+        a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        a.add_transceiver(MediaKind::Video, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+        // bは音声を扱えない
+        for mime_type in &["audio/opus", "audio/G722", "audio/PCMU", "audio/PCMA"] {
+            b.media_engine_mut().remove_codec(mime_type);
+        }
+        b.media_engine_mut().remove_codec("video/VP9");
+        b.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Recvonly)
+            .unwrap();
+
+        // rejectはanswerを適用するまで決まらないのでrollbackできる
+        let offer = a.create_offer().unwrap();
+        a.set_local_description(offer.clone()).unwrap();
+        b.set_remote_description(offer).unwrap();
+        let answer = b.create_answer().unwrap();
+        assert!(answer
+            .sdp()
+            .unwrap()
+            .contains("m=audio 0 UDP/TLS/RTP/SAVPF"));
+        assert!(!b.transceivers()[0].is_stopped());
+        b.set_remote_description(RTCSessionDescription::Rollback)
+            .unwrap();
+        assert!(!b.transceivers()[0].is_stopped());
+        assert_eq!(b.transceivers()[0].mid(), None);
+        assert_eq!(b.transceivers().len(), 1);
+
+        b.set_remote_description(a.local_description().unwrap().clone())
+            .unwrap();
+        // pranswerではまだstopしない
+        let pranswer = b.create_pranswer().unwrap();
+        assert!(pranswer
+            .sdp()
+            .unwrap()
+            .contains("m=audio 0 UDP/TLS/RTP/SAVPF"));
+        b.set_local_description(pranswer.clone()).unwrap();
+        a.set_remote_description(pranswer).unwrap();
+        assert!(!a.transceivers()[0].is_stopped());
+        assert!(!b.transceivers()[0].is_stopped());
+
+        let answer = b.create_answer().unwrap();
+        b.set_local_description(answer.clone()).unwrap();
+        a.set_remote_description(answer).unwrap();
+        assert!(b.transceivers()[0].is_stopped());
+        let answer = a.remote_description().unwrap().sdp().unwrap();
+        assert!(answer.contains("m=audio 0 UDP/TLS/RTP/SAVPF"));
+        assert!(answer.contains("a=group:BUNDLE 1\r\n"));
+        // answerのcodecはofferのPTのまま両方で使えるもの
+        assert!(!answer.contains("VP9/90000"));
+        assert!(answer.contains("a=rtpmap:96 VP8/90000\r\n"));
+        assert!(a.transceivers()[0].is_stopped());
+        assert_eq!(
+            a.transceivers()[1].current_direction(),
+            Some(RtcRtpTransceiverDirection::Sendonly)
+        );
+        let codecs = &a.transceivers()[1]
+            .receiver()
+            .parameters()
+            .unwrap()
+            .param
+            .codecs;
+        assert!(codecs.iter().all(|codec| codec.name() != "VP9"));
+    }
+
+    #[test]
+    fn add_ice_candidate_test() {
+        let (mut a, _) = loopback_peer_connection(true);
+        let (mut b, _) = loopback_peer_connection(false);
+        let mut candidate: RtcIceCandidate = "candidate:1 1 udp 2122262783 127.0.0.1 9 typ host"
+            .parse()
+            .unwrap();
+
+        match b.add_ice_candidate(Some(candidate.clone())) {
+            Err(RtcPeerConnectionError::InvalidState { .. }) => {}
+            result => panic!("{:?}", result),
+        }
+
+        b.set_remote_description(a.create_offer().unwrap()).unwrap();
+        b.add_ice_candidate(Some(candidate.clone())).unwrap();
+        assert_eq!(
+            b.ice_connection().remote_candidates(),
+            vec![candidate.to_candidate().unwrap()]
+        );
+
+        candidate.username_fragment = Some("unknown".to_owned());
+        assert_eq!(
+            b.add_ice_candidate(Some(candidate)),
+            Err(RtcPeerConnectionError::UnknownUsernameFragment {
+                username_fragment: "unknown".to_owned()
+            })
+        );
+        b.add_ice_candidate(None).unwrap();
+
+        match b.set_remote_description(RTCSessionDescription::Offer("v=0\r\n".to_owned())) {
+            Err(RtcPeerConnectionError::InvalidSessionDescription { .. }) => {}
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn remote_candidates_test() {
+        let (mut a, _) = loopback_peer_connection(true);
+        let (mut b, _) = loopback_peer_connection(false);
+        a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+
+        // 読めないcandidateがあってもdescriptionは使える
+        let candidate: RtcIceCandidate = "candidate:1 1 udp 2122262783 127.0.0.1 9 typ host"
+            .parse()
+            .unwrap();
+        let sdp = a.create_offer().unwrap().sdp().unwrap().replace(
+            "a=mid:0\r\n",
+            "a=mid:0\r\n\
+             a=candidate:1 1 udp 2122262783 127.0.0.1 9 typ host\r\n\
+             a=candidate:2 1 udp 2122262783 127.0.0.1 10 typ foo\r\n\
+             a=candidate:3 1 sctp 2122262783 127.0.0.1 11 typ host\r\n",
+        );
+        b.set_remote_description(RTCSessionDescription::Offer(sdp))
+            .unwrap();
+        assert_eq!(
+            b.ice_connection().remote_candidates(),
+            vec![candidate.to_candidate().unwrap()]
+        );
+    }
+
+    #[test]
+    fn unsupported_media_test() {
+        let (mut a, _) = loopback_peer_connection(true);
+        let (mut b, _) = loopback_peer_connection(false);
+        a.add_transceiver(MediaKind::Audio, RtcRtpTransceiverDirection::Sendrecv)
+            .unwrap();
+
+        // 扱えないm-sectionも同じ順にport 0で答える
+        let sdp = a.create_offer().unwrap().sdp().unwrap().to_owned()
+            + "m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+               c=IN IP4 0.0.0.0\r\n\
+               a=mid:1\r\n\
+               a=sctp-port:5000\r\n";
+        b.set_remote_description(RTCSessionDescription::Offer(sdp))
+            .unwrap();
+        let answer = b.create_answer().unwrap();
+        let sdp = answer.sdp().unwrap();
+        let session = webrtc_sdp::parse_sdp(sdp, true).unwrap();
+        assert_eq!(session.media.len(), 2);
+        assert_eq!(*session.media[0].get_type(), SdpMediaValue::Audio);
+        assert_eq!(session.media[0].get_port(), 9);
+        assert_eq!(*session.media[1].get_type(), SdpMediaValue::Application);
+        assert_eq!(session.media[1].get_port(), 0);
+        assert!(sdp.contains("m=application 0 UDP/DTLS/SCTP webrtc-datachannel\r\n"));
+        assert!(sdp.contains("a=mid:1\r\n"));
+        assert!(sdp.contains("a=group:BUNDLE 0\r\n"));
+        b.set_local_description(answer).unwrap();
     }
 }
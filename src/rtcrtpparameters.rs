@@ -1,69 +1,165 @@
+use std::fmt;
 
+// fmtpのparameter．値の無いもの (telephone-eventの0-16など) はNone
+pub type RtcRtpCodecParameter = (String, Option<String>);
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpCodecCapability {
-    mime_type: String,
+    pub mime_type: String,
     // "The codec MIME media type/subtype, for instance `'audio/PCMU'`."
-    clock_rate: u64,
+    pub clock_rate: u64,
     // "The codec clock rate expressed in Hertz."
-    channels: Option<usize>,
+    pub channels: Option<usize>,
     // "The number of channels supported (e.g. two for stereo)."
-    // parameters: OrderedDict = field(default_factory=OrderedDict)
+    pub rtcp_feedback: Vec<RtcRtcpFeedback>,
+    // "Transport layer and codec-specific feedback messages for this codec."
+    pub parameters: Vec<RtcRtpCodecParameter>,
     // "Codec-specific parameters available for signaling."
 }
 
 impl RtcRtpCodecCapability {
-    fn name(&self) -> String {
+    pub fn new(mime_type: &str, clock_rate: u64, channels: Option<usize>) -> RtcRtpCodecCapability {
+        RtcRtpCodecCapability {
+            mime_type: mime_type.to_owned(),
+            clock_rate,
+            channels,
+            rtcp_feedback: vec![],
+            parameters: vec![],
+        }
+    }
+
+    pub fn name(&self) -> String {
         self.mime_type.split('/').collect::<Vec<&str>>()[1].to_string()
     }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| value.as_deref())
+    }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpCodecParameters {
-    mime_type: String,
+    pub mime_type: String,
     // "The codec MIME media type/subtype, for instance `'audio/PCMU'`."
-    clock_rate: u64,
+    pub clock_rate: u64,
     // "The codec clock rate expressed in Hertz."
-    channels: Option<usize>,
+    pub channels: Option<usize>,
     // "The number of channels supported (e.g. two for stereo)."
-    payload_type: Option<usize>,
+    pub payload_type: Option<usize>,
     // "The value that goes in the RTP Payload Type Field."
-    rtcp_feedback: Vec<RtcRtcpFeedback>,
+    pub rtcp_feedback: Vec<RtcRtcpFeedback>,
     // "Transport layer and codec-specific feedback messages for this codec."
-    // parameters: OrderedDict = field(default_factory=OrderedDict)
+    pub parameters: Vec<RtcRtpCodecParameter>,
     // "Codec-specific parameters available for signaling."
 }
 
 impl RtcRtpCodecParameters {
-    fn name(&self) -> String {
+    pub fn new(
+        mime_type: &str,
+        clock_rate: u64,
+        channels: Option<usize>,
+        payload_type: usize,
+    ) -> RtcRtpCodecParameters {
+        RtcRtpCodecParameters {
+            mime_type: mime_type.to_owned(),
+            clock_rate,
+            channels,
+            payload_type: Some(payload_type),
+            rtcp_feedback: vec![],
+            parameters: vec![],
+        }
+    }
+
+    pub fn name(&self) -> String {
         self.mime_type.split('/').collect::<Vec<&str>>()[1].to_string()
     }
 
-    fn to_string(&self) -> String {
-        let s = format!("{}/{}", self.name(), self.clock_rate);
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    // a=fmtpの値 (例: minptime=10;useinbandfec=1)
+    pub fn fmtp(&self) -> Option<String> {
+        if self.parameters.is_empty() {
+            return None;
+        }
+        let parameters = self
+            .parameters
+            .iter()
+            .map(|(key, value)| match value {
+                Some(value) => format!("{}={}", key, value),
+                None => key.clone(),
+            })
+            .collect::<Vec<_>>();
+        Some(parameters.join(";"))
+    }
+}
+
+// PTを除いたもの
+impl From<&RtcRtpCodecParameters> for RtcRtpCodecCapability {
+    fn from(codec: &RtcRtpCodecParameters) -> Self {
+        RtcRtpCodecCapability {
+            mime_type: codec.mime_type.clone(),
+            clock_rate: codec.clock_rate,
+            channels: codec.channels,
+            rtcp_feedback: codec.rtcp_feedback.clone(),
+            parameters: codec.parameters.clone(),
+        }
+    }
+}
+
+// a=rtpmapのencoding (例: opus/48000/2)
+impl fmt::Display for RtcRtpCodecParameters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.name(), self.clock_rate)?;
         if self.channels == Some(2) {
-            format!("{}/{}", s, 2)
-        } else {
-            s
+            write!(f, "/{}", 2)?;
         }
+        Ok(())
     }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpRtxParameters {
-    ssrc: u32,
+    pub payload_type: usize,
+    // a=fmtpのaptでcodecに対応付けられたrtxのPT
+    pub ssrc: Option<u32>,
+    // a=ssrc-group:FIDで対応付けられたSSRC
 }
 
-pub struct RtcRtpCodingParameters{
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RtcRtpCodingParameters {
     pub ssrc: u32,
     pub payload_type: usize,
     pub rtx: Option<RtcRtpRtxParameters>,
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpDecodingParameters(pub RtcRtpCodingParameters);
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpEncodingParameters(pub RtcRtpCodingParameters);
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpHeaderExtensionCapability {
     pub uri: String,
     // "The URI of the RTP header extension."
 }
 
+impl RtcRtpHeaderExtensionCapability {
+    pub fn new(uri: &str) -> RtcRtpHeaderExtensionCapability {
+        RtcRtpHeaderExtensionCapability {
+            uri: uri.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpHeaderExtensionParameters {
     pub id: usize,
     // "The value that goes in the packet."
@@ -71,16 +167,28 @@ pub struct RtcRtpHeaderExtensionParameters {
     // "The URI of the RTP header extension."
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpCapabilities {
     pub codecs: Vec<RtcRtpCodecCapability>,
     pub header_extensions: Vec<RtcRtpHeaderExtensionCapability>,
 }
 
-pub struct RtcRtcpFeedback{
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RtcRtcpFeedback {
     pub kind: String,
     pub param: Option<String>,
 }
 
+impl RtcRtcpFeedback {
+    pub fn new(kind: &str, param: Option<&str>) -> RtcRtcpFeedback {
+        RtcRtcpFeedback {
+            kind: kind.to_owned(),
+            param: param.map(str::to_owned),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtcpParameters {
     pub cname: Option<String>,
     // "The Canonical Name (CNAME) used by RTCP."
@@ -90,6 +198,7 @@ pub struct RtcRtcpParameters {
     // "The Synchronization Source identifier."
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpParameter {
     pub codecs: Vec<RtcRtpCodecParameters>,
     pub header_extensions: Vec<RtcRtpHeaderExtensionParameters>,
@@ -99,11 +208,13 @@ pub struct RtcRtpParameter {
     // "Parameters to configure RTCP."
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpReceiveParameters {
     pub param: RtcRtpParameter,
     pub decoding: Vec<RtcRtpDecodingParameters>,
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RtcRtpSendParameters {
     pub param: RtcRtpParameter,
     pub decoding: Vec<RtcRtpEncodingParameters>,
@@ -0,0 +1,33 @@
+// https://www.w3.org/TR/webrtc/#rtcrtpreceiver-interface
+
+use crate::mediastreamtrack::MediaStreamTrack;
+use crate::rtcrtpparameters::RtcRtpReceiveParameters;
+use crate::rtcrtptransceiver::MediaKind;
+
+pub struct RtcRtpReceiver {
+    // remoteから届くmediaのtrack．transceiverと一緒に作られる
+    track: MediaStreamTrack,
+    // remoteのm-sectionとnegotiateした結果
+    parameters: Option<RtcRtpReceiveParameters>,
+}
+
+impl RtcRtpReceiver {
+    pub fn new(kind: MediaKind) -> RtcRtpReceiver {
+        RtcRtpReceiver {
+            track: MediaStreamTrack::new(kind),
+            parameters: None,
+        }
+    }
+
+    pub fn track(&self) -> &MediaStreamTrack {
+        &self.track
+    }
+
+    pub fn parameters(&self) -> Option<&RtcRtpReceiveParameters> {
+        self.parameters.as_ref()
+    }
+
+    pub(crate) fn set_parameters(&mut self, parameters: Option<RtcRtpReceiveParameters>) {
+        self.parameters = parameters;
+    }
+}
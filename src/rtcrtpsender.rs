@@ -1,5 +1,71 @@
+// https://www.w3.org/TR/webrtc/#rtcrtpsender-interface
 
+use crate::mediastreamtrack::MediaStreamTrack;
+use crate::rtcrtptransceiver::{random_id, MediaKind};
 
-struct RtcRtpSender{
-    kind : DtlsTransport
-}
\ No newline at end of file
+use rand::{thread_rng, Rng};
+
+pub struct RtcRtpSender {
+    kind: MediaKind,
+    track: Option<MediaStreamTrack>,
+    stream_ids: Vec<String>,
+    // 送信するRTPのSSRC
+    ssrc: u32,
+    // trackが無い時のa=msidのtrack id
+    track_id: String,
+    // 一度でもtrackを送ったsenderはadd_trackで使い回さない
+    used: bool,
+}
+
+impl RtcRtpSender {
+    pub fn new(kind: MediaKind) -> RtcRtpSender {
+        RtcRtpSender {
+            kind,
+            track: None,
+            stream_ids: vec![],
+            ssrc: thread_rng().gen(),
+            track_id: random_id(),
+            used: false,
+        }
+    }
+
+    pub fn kind(&self) -> MediaKind {
+        self.kind
+    }
+
+    pub fn track(&self) -> Option<&MediaStreamTrack> {
+        self.track.as_ref()
+    }
+
+    pub(crate) fn set_track(&mut self, track: MediaStreamTrack, stream_ids: Vec<String>) {
+        self.track = Some(track);
+        self.stream_ids = stream_ids;
+        self.used = true;
+    }
+
+    pub(crate) fn is_used(&self) -> bool {
+        self.used
+    }
+
+    pub fn stream_ids(&self) -> &[String] {
+        &self.stream_ids
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    // a=msidの値．streamが無ければ"-"
+    pub fn msid(&self) -> (String, String) {
+        let stream_id = self
+            .stream_ids
+            .first()
+            .cloned()
+            .unwrap_or_else(|| "-".to_owned());
+        let track_id = match &self.track {
+            Some(track) => track.id().to_owned(),
+            None => self.track_id.clone(),
+        };
+        (stream_id, track_id)
+    }
+}
@@ -0,0 +1,357 @@
+// https://www.w3.org/TR/webrtc/#rtcrtptransceiver-interface
+
+use crate::media_engine::MediaEngine;
+use crate::rtcpeerconnection::{Result, RtcPeerConnectionError};
+use crate::rtcrtpparameters::{
+    RtcRtpCapabilities, RtcRtpCodecCapability, RtcRtpCodecParameters,
+    RtcRtpHeaderExtensionCapability, RtcRtpHeaderExtensionParameters, RtcRtpReceiveParameters,
+};
+use crate::rtcrtpreceiver::RtcRtpReceiver;
+use crate::rtcrtpsender::RtcRtpSender;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::fmt;
+use std::iter;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MediaKind {
+    Audio,
+    Video,
+}
+
+impl fmt::Display for MediaKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MediaKind::Audio => write!(f, "audio"),
+            MediaKind::Video => write!(f, "video"),
+        }
+    }
+}
+
+// https://www.w3.org/TR/webrtc/#dom-rtcrtptransceiverdirection
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RtcRtpTransceiverDirection {
+    Sendrecv,
+    Sendonly,
+    Recvonly,
+    Inactive,
+    Stopped,
+}
+
+impl RtcRtpTransceiverDirection {
+    pub fn has_send(self) -> bool {
+        matches!(
+            self,
+            RtcRtpTransceiverDirection::Sendrecv | RtcRtpTransceiverDirection::Sendonly
+        )
+    }
+
+    pub fn has_recv(self) -> bool {
+        matches!(
+            self,
+            RtcRtpTransceiverDirection::Sendrecv | RtcRtpTransceiverDirection::Recvonly
+        )
+    }
+
+    fn from_flags(send: bool, recv: bool) -> RtcRtpTransceiverDirection {
+        match (send, recv) {
+            (true, true) => RtcRtpTransceiverDirection::Sendrecv,
+            (true, false) => RtcRtpTransceiverDirection::Sendonly,
+            (false, true) => RtcRtpTransceiverDirection::Recvonly,
+            (false, false) => RtcRtpTransceiverDirection::Inactive,
+        }
+    }
+
+    // 相手から見た向き
+    pub fn reverse(self) -> RtcRtpTransceiverDirection {
+        match self {
+            RtcRtpTransceiverDirection::Stopped => RtcRtpTransceiverDirection::Stopped,
+            _ => RtcRtpTransceiverDirection::from_flags(self.has_recv(), self.has_send()),
+        }
+    }
+
+    // offerされた向きに対するanswerの向き (JSEP 5.3.1)
+    // 相手が受信する時だけ送信し，相手が送信する時だけ受信する
+    pub fn answer(self, offered: RtcRtpTransceiverDirection) -> RtcRtpTransceiverDirection {
+        if self == RtcRtpTransceiverDirection::Stopped
+            || offered == RtcRtpTransceiverDirection::Stopped
+        {
+            return RtcRtpTransceiverDirection::Stopped;
+        }
+        RtcRtpTransceiverDirection::from_flags(
+            self.has_send() && offered.has_recv(),
+            self.has_recv() && offered.has_send(),
+        )
+    }
+}
+
+impl fmt::Display for RtcRtpTransceiverDirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let direction = match self {
+            RtcRtpTransceiverDirection::Sendrecv => "sendrecv",
+            RtcRtpTransceiverDirection::Sendonly => "sendonly",
+            RtcRtpTransceiverDirection::Recvonly => "recvonly",
+            RtcRtpTransceiverDirection::Inactive => "inactive",
+            RtcRtpTransceiverDirection::Stopped => "stopped",
+        };
+        write!(f, "{}", direction)
+    }
+}
+
+// offer/answerで変わる状態．offerをrollbackした時に戻す
+#[derive(Debug, Clone)]
+pub(crate) struct NegotiationState {
+    direction: RtcRtpTransceiverDirection,
+    current_direction: Option<RtcRtpTransceiverDirection>,
+    remote_direction: Option<RtcRtpTransceiverDirection>,
+    mid: Option<String>,
+    receive_parameters: Option<RtcRtpReceiveParameters>,
+}
+
+// m-section一つ分の送受信
+pub struct RtcRtpTransceiver {
+    kind: MediaKind,
+    direction: RtcRtpTransceiverDirection,
+    // offer/answerで決まった向き．まだ決まっていなければNone
+    current_direction: Option<RtcRtpTransceiverDirection>,
+    // remoteのofferの向き．answerの向きはこれで決まる
+    remote_direction: Option<RtcRtpTransceiverDirection>,
+    mid: Option<String>,
+    // media_engineに登録されていたcodec．set_codec_preferencesはこの中からしか選べない
+    registered_codecs: Vec<RtcRtpCodecParameters>,
+    codecs: Vec<RtcRtpCodecParameters>,
+    header_extensions: Vec<RtcRtpHeaderExtensionParameters>,
+    sender: RtcRtpSender,
+    receiver: RtcRtpReceiver,
+}
+
+impl RtcRtpTransceiver {
+    // codecとheader extensionはmedia_engineに登録されているもの全て
+    pub fn new(
+        kind: MediaKind,
+        direction: RtcRtpTransceiverDirection,
+        media_engine: &MediaEngine,
+    ) -> RtcRtpTransceiver {
+        RtcRtpTransceiver {
+            kind,
+            direction,
+            current_direction: None,
+            remote_direction: None,
+            mid: None,
+            registered_codecs: media_engine.codecs(kind).to_vec(),
+            codecs: media_engine.codecs(kind).to_vec(),
+            header_extensions: media_engine.header_extensions(kind).to_vec(),
+            sender: RtcRtpSender::new(kind),
+            receiver: RtcRtpReceiver::new(kind),
+        }
+    }
+
+    pub fn kind(&self) -> MediaKind {
+        self.kind
+    }
+
+    pub fn direction(&self) -> RtcRtpTransceiverDirection {
+        self.direction
+    }
+
+    // stopしたtransceiverの向きは変えられない
+    pub fn set_direction(&mut self, direction: RtcRtpTransceiverDirection) {
+        if !self.is_stopped() {
+            self.direction = direction;
+        }
+    }
+
+    pub fn current_direction(&self) -> Option<RtcRtpTransceiverDirection> {
+        self.current_direction
+    }
+
+    pub(crate) fn set_current_direction(&mut self, direction: RtcRtpTransceiverDirection) {
+        self.current_direction = Some(direction);
+    }
+
+    pub(crate) fn remote_direction(&self) -> Option<RtcRtpTransceiverDirection> {
+        self.remote_direction
+    }
+
+    pub(crate) fn set_remote_direction(&mut self, direction: RtcRtpTransceiverDirection) {
+        self.remote_direction = Some(direction);
+    }
+
+    // 次のofferとanswerでport 0のm-sectionになる
+    pub fn stop(&mut self) {
+        self.direction = RtcRtpTransceiverDirection::Stopped;
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.direction == RtcRtpTransceiverDirection::Stopped
+    }
+
+    pub fn sender(&self) -> &RtcRtpSender {
+        &self.sender
+    }
+
+    pub(crate) fn sender_mut(&mut self) -> &mut RtcRtpSender {
+        &mut self.sender
+    }
+
+    pub fn receiver(&self) -> &RtcRtpReceiver {
+        &self.receiver
+    }
+
+    pub(crate) fn receiver_mut(&mut self) -> &mut RtcRtpReceiver {
+        &mut self.receiver
+    }
+
+    // offerを作るまではNone
+    pub fn mid(&self) -> Option<&str> {
+        self.mid.as_deref()
+    }
+
+    pub(crate) fn set_mid(&mut self, mid: Option<String>) {
+        self.mid = mid;
+    }
+
+    pub(crate) fn negotiation_state(&self) -> NegotiationState {
+        NegotiationState {
+            direction: self.direction,
+            current_direction: self.current_direction,
+            remote_direction: self.remote_direction,
+            mid: self.mid.clone(),
+            receive_parameters: self.receiver.parameters().cloned(),
+        }
+    }
+
+    pub(crate) fn restore_negotiation_state(&mut self, state: NegotiationState) {
+        self.direction = state.direction;
+        self.current_direction = state.current_direction;
+        self.remote_direction = state.remote_direction;
+        self.mid = state.mid;
+        self.receiver.set_parameters(state.receive_parameters);
+    }
+
+    // まだnegotiateしていない状態に戻す．向きはそのまま
+    pub(crate) fn reset_negotiation_state(&mut self) {
+        self.current_direction = None;
+        self.remote_direction = None;
+        self.mid = None;
+        self.receiver.set_parameters(None);
+    }
+
+    pub fn codecs(&self) -> &[RtcRtpCodecParameters] {
+        &self.codecs
+    }
+
+    // 優先する順に並べる．空なら登録されている全てのcodecに戻す
+    pub fn set_codec_preferences(&mut self, codecs: Vec<RtcRtpCodecParameters>) -> Result<()> {
+        if codecs.is_empty() {
+            self.codecs = self.registered_codecs.clone();
+            return Ok(());
+        }
+        // rtcp-fbは減らしてもよいが，PTとfmtpは登録されているものと同じでなければならない
+        let unknown = codecs.iter().find(|codec| {
+            !self.registered_codecs.iter().any(|registered| {
+                registered.mime_type.eq_ignore_ascii_case(&codec.mime_type)
+                    && registered.clock_rate == codec.clock_rate
+                    && registered.channels == codec.channels
+                    && registered.payload_type == codec.payload_type
+                    && registered.parameters == codec.parameters
+            })
+        });
+        if let Some(codec) = unknown {
+            return Err(RtcPeerConnectionError::InvalidModification {
+                reason: format!("{} is not registered in the media engine", codec.mime_type),
+            });
+        }
+        self.codecs = codecs;
+        Ok(())
+    }
+
+    pub fn header_extensions(&self) -> &[RtcRtpHeaderExtensionParameters] {
+        &self.header_extensions
+    }
+
+    // remoteのm-sectionとnegotiateする時に使う
+    pub fn capabilities(&self) -> RtcRtpCapabilities {
+        RtcRtpCapabilities {
+            codecs: self
+                .codecs
+                .iter()
+                .map(RtcRtpCodecCapability::from)
+                .collect(),
+            header_extensions: self
+                .header_extensions
+                .iter()
+                .map(|extension| RtcRtpHeaderExtensionCapability::new(&extension.uri))
+                .collect(),
+        }
+    }
+}
+
+pub(crate) fn random_id() -> String {
+    let mut rng = thread_rng();
+    iter::repeat(())
+        .map(|()| rng.sample(Alphanumeric))
+        .take(16)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn answer_direction_test() {
+        use RtcRtpTransceiverDirection::*;
+
+        let directions = [Sendrecv, Sendonly, Recvonly, Inactive];
+        let expected = [
+            // local \ offered: sendrecv, sendonly, recvonly, inactive
+            [Sendrecv, Recvonly, Sendonly, Inactive],
+            [Sendonly, Inactive, Sendonly, Inactive],
+            [Recvonly, Recvonly, Inactive, Inactive],
+            [Inactive, Inactive, Inactive, Inactive],
+        ];
+        for (local, expected) in directions.iter().zip(expected.iter()) {
+            for (offered, expected) in directions.iter().zip(expected.iter()) {
+                assert_eq!(local.answer(*offered), *expected, "{} {}", local, offered);
+            }
+        }
+        assert_eq!(Sendrecv.answer(Stopped), Stopped);
+        assert_eq!(Stopped.answer(Sendrecv), Stopped);
+
+        assert_eq!(Sendonly.reverse(), Recvonly);
+        assert_eq!(Recvonly.reverse(), Sendonly);
+        assert_eq!(Sendrecv.reverse(), Sendrecv);
+        assert_eq!(Inactive.reverse(), Inactive);
+    }
+
+    #[test]
+    fn set_codec_preferences_test() {
+        let media_engine = MediaEngine::default();
+        let mut transceiver = RtcRtpTransceiver::new(
+            MediaKind::Video,
+            RtcRtpTransceiverDirection::Sendrecv,
+            &media_engine,
+        );
+        let registered = transceiver.codecs().to_vec();
+
+        // 登録されているcodecの並べ替えと絞り込みはできる
+        let preferred = vec![registered[2].clone(), registered[0].clone()];
+        transceiver
+            .set_codec_preferences(preferred.clone())
+            .unwrap();
+        assert_eq!(transceiver.codecs(), &preferred[..]);
+
+        // 登録されていないcodecは使えない
+        let mut unknown = registered[0].clone();
+        unknown.payload_type = Some(127);
+        match transceiver.set_codec_preferences(vec![unknown]) {
+            Err(RtcPeerConnectionError::InvalidModification { .. }) => {}
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(transceiver.codecs(), &preferred[..]);
+
+        transceiver.set_codec_preferences(vec![]).unwrap();
+        assert_eq!(transceiver.codecs(), &registered[..]);
+    }
+}
@@ -1,5 +1,7 @@
+pub mod codecs;
 pub mod packet;
 pub mod packetizer;
+pub mod sample_builder;
 
 use crate::OctetsError;
 use failure::Fail;
@@ -39,6 +41,33 @@ pub enum RtpError {
 
     #[fail(display = "rtp two-byte header extension is truncated.")]
     TruncatedTwoByteHeaderExtension,
+
+    #[fail(display = "rtp header extension element id or length is invalid.")]
+    InvalidHeaderExtensionElement,
+
+    #[fail(display = "RTP packet has more than 15 CSRCs.")]
+    TooManyCsrc,
+
+    #[fail(display = "RTP payload type must be 7bit.")]
+    InvalidPayloadType,
+
+    #[fail(display = "RTP payload is too short.")]
+    PayloadTooShort,
+
+    #[fail(display = "VP9 payload descriptor is invalid.")]
+    InvalidVp9Descriptor,
+
+    #[fail(display = "H.264 NAL unit type {} is not supported.", nalu_type)]
+    UnhandledNaluType { nalu_type: u8 },
+
+    #[fail(display = "STAP-A NAL unit size is larger than the payload.")]
+    InvalidStapASize,
+
+    #[fail(display = "AV1 OBU element is truncated.")]
+    TruncatedObuElement,
+
+    #[fail(display = "LEB128 value is invalid.")]
+    InvalidLeb128,
 }
 
 impl From<OctetsError> for RtpError {
@@ -0,0 +1,26 @@
+use crate::rtp::Result;
+
+pub mod av1;
+pub mod h264;
+pub mod opus;
+pub mod vp8;
+pub mod vp9;
+
+// Payloader splits a media frame into RTP payloads which fit in the given size.
+pub trait Payloader {
+    fn payload(&mut self, mtu: usize, frame: &[u8]) -> Vec<Vec<u8>>;
+}
+
+// Depacketizer converts RTP payloads back into codec bitstream.
+// 1つのframeが複数のpacketに跨る場合は，partitionのhead/tailで境界を判定する．
+pub trait Depacketizer {
+    // payloadからcodec headerを取り除いたdataを返す．
+    // Fragmentの途中の場合は空のVecを返す．
+    fn depacketize(&mut self, payload: &[u8]) -> Result<Vec<u8>>;
+
+    // payloadがframeの先頭fragmentかどうか
+    fn is_partition_head(&self, payload: &[u8]) -> bool;
+
+    // payloadがframeの最後のfragmentかどうか
+    fn is_partition_tail(&self, marker: bool, payload: &[u8]) -> bool;
+}
@@ -0,0 +1,424 @@
+// https://aomediacodec.github.io/av1-rtp-spec/
+
+/*
+    AV1 Aggregation Header
+
+    +-+-+-+-+-+-+-+-+
+    |Z|Y| W |N|-|-|-|
+    +-+-+-+-+-+-+-+-+
+
+    Z: 最初のOBU elementが前のpacketからの続きであれば1
+    Y: 最後のOBU elementが次のpacketに続くのであれば1
+    W: OBU elementの数 (0の場合は全elementにLEB128のlengthが付く)
+    N: coded video sequenceの最初のpacketであれば1
+
+    OBU Header
+
+    +-+-+-+-+-+-+-+-+
+    |F| type  |X|S|-|
+    +-+-+-+-+-+-+-+-+
+*/
+
+use crate::rtp::codecs::{Depacketizer, Payloader};
+use crate::rtp::{Result, RtpError};
+
+const AV1_AGGREGATION_HEADER_SIZE: usize = 1;
+
+const Z_BITMASK: u8 = 0x80;
+const Y_BITMASK: u8 = 0x40;
+const W_BITMASK: u8 = 0x30;
+const N_BITMASK: u8 = 0x08;
+
+const OBU_TYPE_BITMASK: u8 = 0x78;
+const OBU_EXTENSION_BITMASK: u8 = 0x04;
+const OBU_HAS_SIZE_BITMASK: u8 = 0x02;
+
+const OBU_TYPE_SEQUENCE_HEADER: u8 = 1;
+const OBU_TYPE_TEMPORAL_DELIMITER: u8 = 2;
+const OBU_TYPE_TILE_LIST: u8 = 8;
+
+pub fn leb128_size(mut value: usize) -> usize {
+    let mut size = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        size += 1;
+    }
+    size
+}
+
+pub fn write_leb128(mut value: usize, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// (value, 読み込んだbyte数)を返す
+pub fn read_leb128(bytes: &[u8]) -> Result<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, b) in bytes.iter().enumerate().take(8) {
+        value |= ((b & 0x7F) as usize) << (i * 7);
+        if (b & 0x80) == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(RtpError::InvalidLeb128)
+}
+
+fn obu_type(header: u8) -> u8 {
+    (header & OBU_TYPE_BITMASK) >> 3
+}
+
+// Low overhead bitstream formatのframeをOBU element (size fieldを除いたOBU)に分解する．
+fn split_obus(frame: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut elements = vec![];
+    let mut idx = 0;
+
+    while idx < frame.len() {
+        let header = frame[idx];
+        let header_size = if (header & OBU_EXTENSION_BITMASK) > 0 {
+            2
+        } else {
+            1
+        };
+        if idx + header_size > frame.len() {
+            return Err(RtpError::TruncatedObuElement);
+        }
+        let headers = &frame[idx..idx + header_size];
+        idx += header_size;
+
+        let size = if (header & OBU_HAS_SIZE_BITMASK) > 0 {
+            let (size, n) = read_leb128(&frame[idx..])?;
+            idx += n;
+            size
+        } else {
+            frame.len() - idx
+        };
+        if idx + size > frame.len() {
+            return Err(RtpError::TruncatedObuElement);
+        }
+
+        // temporal delimiterとtile listは送らない
+        let t = obu_type(header);
+        if t != OBU_TYPE_TEMPORAL_DELIMITER && t != OBU_TYPE_TILE_LIST {
+            let mut element = headers.to_vec();
+            element[0] &= !OBU_HAS_SIZE_BITMASK;
+            element.extend_from_slice(&frame[idx..idx + size]);
+            elements.push(element);
+        }
+        idx += size;
+    }
+
+    Ok(elements)
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Av1Payloader;
+
+impl Payloader for Av1Payloader {
+    fn payload(&mut self, mtu: usize, frame: &[u8]) -> Vec<Vec<u8>> {
+        // aggregation header + length + 1byte以上のdataが入らなければならない
+        if frame.is_empty() || mtu <= AV1_AGGREGATION_HEADER_SIZE + 1 {
+            return vec![];
+        }
+
+        let elements = match split_obus(frame) {
+            Ok(v) => v,
+            Err(_) => return vec![],
+        };
+
+        let new_sequence = elements
+            .iter()
+            .any(|e| obu_type(e[0]) == OBU_TYPE_SEQUENCE_HEADER);
+
+        let mut payloads = vec![];
+        let mut out = vec![0u8];
+        let mut continued = false;
+
+        for element in &elements {
+            let mut rest = &element[..];
+            while !rest.is_empty() {
+                let remaining = mtu - out.len();
+                let fits = remaining >= leb128_size(rest.len()) + rest.len();
+
+                if fits {
+                    write_leb128(rest.len(), &mut out);
+                    out.extend_from_slice(rest);
+                    rest = &[];
+                    continue;
+                }
+
+                // 残りの領域に入るだけ詰めて，次のpacketに続ける
+                let mut fragment_size = remaining.saturating_sub(leb128_size(remaining));
+                while fragment_size > 0 && leb128_size(fragment_size) + fragment_size > remaining {
+                    fragment_size -= 1;
+                }
+
+                if fragment_size > 0 {
+                    write_leb128(fragment_size, &mut out);
+                    out.extend_from_slice(&rest[..fragment_size]);
+                    rest = &rest[fragment_size..];
+                    out[0] |= Y_BITMASK;
+                }
+
+                let next_continued = fragment_size > 0;
+                if out.len() > AV1_AGGREGATION_HEADER_SIZE {
+                    if continued {
+                        out[0] |= Z_BITMASK;
+                    }
+                    payloads.push(out);
+                }
+                out = vec![0u8];
+                continued = next_continued;
+            }
+        }
+
+        if out.len() > AV1_AGGREGATION_HEADER_SIZE {
+            if continued {
+                out[0] |= Z_BITMASK;
+            }
+            payloads.push(out);
+        }
+
+        if new_sequence {
+            if let Some(first) = payloads.first_mut() {
+                first[0] |= N_BITMASK;
+            }
+        }
+
+        payloads
+    }
+}
+
+// 出力はOBU headerにsize fieldを付けたlow overhead bitstream format
+#[derive(Debug, Default, Clone)]
+pub struct Av1Depacketizer {
+    // 次のpacketに続くOBU element
+    obu_buffer: Option<Vec<u8>>,
+}
+
+impl Av1Depacketizer {
+    fn push_obu(element: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let header = match element.first() {
+            Some(v) => *v,
+            None => return Ok(()),
+        };
+        let header_size = if (header & OBU_EXTENSION_BITMASK) > 0 {
+            2
+        } else {
+            1
+        };
+        if element.len() < header_size {
+            return Err(RtpError::TruncatedObuElement);
+        }
+
+        if (header & OBU_HAS_SIZE_BITMASK) > 0 {
+            out.extend_from_slice(element);
+            return Ok(());
+        }
+
+        out.push(header | OBU_HAS_SIZE_BITMASK);
+        out.extend_from_slice(&element[1..header_size]);
+        write_leb128(element.len() - header_size, out);
+        out.extend_from_slice(&element[header_size..]);
+        Ok(())
+    }
+}
+
+impl Depacketizer for Av1Depacketizer {
+    fn depacketize(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        if payload.len() <= AV1_AGGREGATION_HEADER_SIZE {
+            return Err(RtpError::PayloadTooShort);
+        }
+
+        let z = (payload[0] & Z_BITMASK) > 0;
+        let y = (payload[0] & Y_BITMASK) > 0;
+        let w = ((payload[0] & W_BITMASK) >> 4) as usize;
+
+        // 前のpacketを受け取れていない場合は続きのelementを捨てる
+        if !z {
+            self.obu_buffer = None;
+        }
+
+        let mut elements = vec![];
+        let mut idx = AV1_AGGREGATION_HEADER_SIZE;
+        while idx < payload.len() {
+            let is_last = w != 0 && elements.len() == w - 1;
+            let size = if is_last {
+                payload.len() - idx
+            } else {
+                let (size, n) = read_leb128(&payload[idx..])?;
+                idx += n;
+                size
+            };
+
+            if idx + size > payload.len() {
+                return Err(RtpError::TruncatedObuElement);
+            }
+            elements.push(&payload[idx..idx + size]);
+            idx += size;
+        }
+
+        let count = elements.len();
+        let mut out = vec![];
+        for (i, element) in elements.into_iter().enumerate() {
+            let obu = if i == 0 && z {
+                match self.obu_buffer.take() {
+                    Some(mut buffer) => {
+                        buffer.extend_from_slice(element);
+                        buffer
+                    }
+                    None => continue,
+                }
+            } else {
+                element.to_vec()
+            };
+
+            if i == count - 1 && y {
+                self.obu_buffer = Some(obu);
+                break;
+            }
+
+            Av1Depacketizer::push_obu(&obu, &mut out)?;
+        }
+
+        Ok(out)
+    }
+
+    fn is_partition_head(&self, payload: &[u8]) -> bool {
+        match payload.first() {
+            Some(v) => (v & Z_BITMASK) == 0,
+            None => false,
+        }
+    }
+
+    fn is_partition_tail(&self, marker: bool, _payload: &[u8]) -> bool {
+        marker
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn leb128_test() {
+        for &(value, encoded) in &[
+            (0usize, &[0x00u8][..]),
+            (5, &[0x05]),
+            (127, &[0x7F]),
+            (128, &[0x80, 0x01]),
+            (300, &[0xAC, 0x02]),
+            (16384, &[0x80, 0x80, 0x01]),
+        ] {
+            let mut out = vec![];
+            write_leb128(value, &mut out);
+            assert_eq!(out, encoded.to_vec());
+            assert_eq!(leb128_size(value), encoded.len());
+            assert_eq!(read_leb128(encoded).unwrap(), (value, encoded.len()));
+        }
+
+        assert_eq!(read_leb128(&[0x80, 0x80]), Err(RtpError::InvalidLeb128));
+    }
+
+    #[test]
+    fn av1_payload_test() {
+        let mut payloader = Av1Payloader;
+
+        assert!(payloader.payload(100, &[]).is_empty());
+        assert!(payloader.payload(2, &[0x32, 0x01, 0x00]).is_empty());
+
+        // temporal delimiter + sequence header + frame
+        let frame = [
+            0x12, 0x00, // temporal delimiter
+            0x0A, 0x03, 0x00, 0x00, 0x00, // sequence header
+            0x32, 0x04, 0x10, 0x11, 0x12, 0x13, // frame
+        ];
+        let payloads = payloader.payload(100, &frame);
+        assert_eq!(
+            payloads,
+            vec![vec![
+                0x08, // N=1
+                0x04, 0x08, 0x00, 0x00, 0x00, // sequence header without size field
+                0x05, 0x30, 0x10, 0x11, 0x12, 0x13, // frame without size field
+            ]]
+        );
+
+        // OBUがmtuを超える場合は分割する
+        let payloads = payloader.payload(5, &[0x32, 0x06, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15]);
+        assert_eq!(
+            payloads,
+            vec![
+                vec![0x40, 0x03, 0x30, 0x10, 0x11],
+                vec![0xC0, 0x03, 0x12, 0x13, 0x14],
+                vec![0x80, 0x01, 0x15],
+            ]
+        );
+    }
+
+    #[test]
+    fn av1_depacketize_test() {
+        let mut depacketizer = Av1Depacketizer::default();
+
+        assert_eq!(
+            depacketizer.depacketize(&[0x00]),
+            Err(RtpError::PayloadTooShort)
+        );
+        assert_eq!(
+            depacketizer.depacketize(&[0x00, 0x05, 0x30]),
+            Err(RtpError::TruncatedObuElement)
+        );
+
+        // W=2: 最後のelementはlength無し
+        let raw = [0x28, 0x04, 0x08, 0x00, 0x00, 0x00, 0x30, 0x10, 0x11];
+        assert_eq!(
+            depacketizer.depacketize(&raw).unwrap(),
+            vec![0x0A, 0x03, 0x00, 0x00, 0x00, 0x32, 0x02, 0x10, 0x11]
+        );
+
+        // Fragmented OBU
+        assert!(depacketizer.is_partition_head(&[0x40, 0x03, 0x30, 0x10, 0x11]));
+        assert!(!depacketizer.is_partition_head(&[0xC0, 0x03, 0x12, 0x13, 0x14]));
+        assert!(depacketizer
+            .depacketize(&[0x40, 0x03, 0x30, 0x10, 0x11])
+            .unwrap()
+            .is_empty());
+        assert!(depacketizer
+            .depacketize(&[0xC0, 0x03, 0x12, 0x13, 0x14])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            depacketizer.depacketize(&[0x80, 0x01, 0x15]).unwrap(),
+            vec![0x32, 0x06, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15]
+        );
+
+        // 先頭を失ったfragmentは捨てる
+        assert!(depacketizer
+            .depacketize(&[0x80, 0x01, 0x15])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn av1_round_trip_test() {
+        let mut frame = vec![0x0A, 0x03, 0x00, 0x00, 0x00];
+        let obu_payload: Vec<u8> = (0..1000).map(|v| v as u8).collect();
+        frame.push(0x32);
+        write_leb128(obu_payload.len(), &mut frame);
+        frame.extend_from_slice(&obu_payload);
+
+        let mut payloader = Av1Payloader;
+        let mut depacketizer = Av1Depacketizer::default();
+
+        let payloads = payloader.payload(300, &frame);
+        assert!(payloads.len() > 1);
+        assert!(payloads.iter().all(|p| p.len() <= 300));
+
+        let mut out = vec![];
+        for payload in &payloads {
+            out.extend(depacketizer.depacketize(payload).unwrap());
+        }
+        assert_eq!(out, frame);
+    }
+}
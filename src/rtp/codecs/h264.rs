@@ -0,0 +1,438 @@
+// https://tools.ietf.org/html/rfc6184
+
+/*
+    NAL Unit Header
+
+    +---------------+
+    |0|1|2|3|4|5|6|7|
+    +-+-+-+-+-+-+-+-+
+    |F|NRI|  Type   |
+    +---------------+
+
+    FU-A
+
+    0                   1                   2                   3
+    0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+    | FU indicator  |   FU header   |                               |
+    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+                               |
+    |                         FU payload                            |
+    |                               +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+    |                               :...OPTIONAL RTP padding        |
+    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+
+    FU header
+    +---------------+
+    |0|1|2|3|4|5|6|7|
+    +-+-+-+-+-+-+-+-+
+    |S|E|R|  Type   |
+    +---------------+
+*/
+
+use crate::rtp::codecs::{Depacketizer, Payloader};
+use crate::rtp::{Result, RtpError};
+
+const STAPA_NALU_TYPE: u8 = 24;
+const FUA_NALU_TYPE: u8 = 28;
+const SPS_NALU_TYPE: u8 = 7;
+const PPS_NALU_TYPE: u8 = 8;
+const AUD_NALU_TYPE: u8 = 9;
+const FILLER_NALU_TYPE: u8 = 12;
+
+const FUA_HEADER_SIZE: usize = 2;
+const STAPA_HEADER_SIZE: usize = 1;
+const STAPA_NALU_LENGTH_SIZE: usize = 2;
+
+const NALU_TYPE_BITMASK: u8 = 0x1F;
+const NALU_REF_IDC_BITMASK: u8 = 0x60;
+const FU_START_BITMASK: u8 = 0x80;
+const FU_END_BITMASK: u8 = 0x40;
+
+const ANNEXB_NALU_START_CODE: [u8; 4] = [0x00, 0x00, 0x00, 0x01];
+
+// Annex-B形式のbyte列からNAL unitを切り出す (start codeは含まない)
+fn emit_nalus(frame: &[u8]) -> Vec<&[u8]> {
+    // (start codeの開始位置, NAL unitの開始位置)
+    let mut start_codes = vec![];
+
+    let mut zeros = 0;
+    for (i, b) in frame.iter().enumerate() {
+        match *b {
+            0 => zeros += 1,
+            1 if zeros >= 2 => {
+                let prefix = if zeros >= 3 { 3 } else { 2 };
+                start_codes.push((i - prefix, i + 1));
+                zeros = 0;
+            }
+            _ => zeros = 0,
+        }
+    }
+
+    if start_codes.is_empty() {
+        return vec![frame];
+    }
+
+    let mut nalus = vec![];
+    if start_codes[0].0 > 0 {
+        nalus.push(&frame[..start_codes[0].0]);
+    }
+
+    for (i, &(_, begin)) in start_codes.iter().enumerate() {
+        let end = match start_codes.get(i + 1) {
+            Some(&(next, _)) => next,
+            None => frame.len(),
+        };
+        if begin < end {
+            nalus.push(&frame[begin..end]);
+        }
+    }
+
+    nalus
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct H264Payloader {
+    sps_nalu: Option<Vec<u8>>,
+    pps_nalu: Option<Vec<u8>>,
+}
+
+impl H264Payloader {
+    fn payload_nalu(&mut self, mtu: usize, nalu: &[u8], payloads: &mut Vec<Vec<u8>>) {
+        if nalu.is_empty() {
+            return;
+        }
+
+        let nalu_type = nalu[0] & NALU_TYPE_BITMASK;
+        let nalu_ref_idc = nalu[0] & NALU_REF_IDC_BITMASK;
+
+        match nalu_type {
+            AUD_NALU_TYPE | FILLER_NALU_TYPE => return,
+            SPS_NALU_TYPE => {
+                self.sps_nalu = Some(nalu.to_vec());
+                return;
+            }
+            PPS_NALU_TYPE => {
+                self.pps_nalu = Some(nalu.to_vec());
+                return;
+            }
+            _ => {}
+        }
+
+        // SPS/PPSは次のNAL unitの前にSTAP-Aでまとめて送る
+        if let (Some(sps), Some(pps)) = (&self.sps_nalu, &self.pps_nalu) {
+            let stapa_size = STAPA_HEADER_SIZE + 2 * STAPA_NALU_LENGTH_SIZE + sps.len() + pps.len();
+            if stapa_size <= mtu {
+                // NRIはSPS/PPSのうち大きい方を使う
+                let nri = (sps[0] & NALU_REF_IDC_BITMASK).max(pps[0] & NALU_REF_IDC_BITMASK);
+                let mut out = vec![STAPA_NALU_TYPE | nri];
+                for item in &[sps, pps] {
+                    out.push((item.len() >> 8) as u8);
+                    out.push(item.len() as u8);
+                    out.extend_from_slice(item);
+                }
+                payloads.push(out);
+            } else {
+                payloads.push(sps.clone());
+                payloads.push(pps.clone());
+            }
+            self.sps_nalu = None;
+            self.pps_nalu = None;
+        }
+
+        // Single NAL Unit Packet
+        if nalu.len() <= mtu {
+            payloads.push(nalu.to_vec());
+            return;
+        }
+
+        // FU-A
+        if mtu <= FUA_HEADER_SIZE {
+            return;
+        }
+        let max_fragment_size = mtu - FUA_HEADER_SIZE;
+
+        // NAL unit headerはFU indicator/headerで表現するので，先頭1byteは除く
+        let fragments = nalu[1..].chunks(max_fragment_size);
+        let count = fragments.len();
+
+        for (i, fragment) in fragments.enumerate() {
+            let mut fu_header = nalu_type;
+            if i == 0 {
+                fu_header |= FU_START_BITMASK;
+            }
+            if i == count - 1 {
+                fu_header |= FU_END_BITMASK;
+            }
+
+            let mut out = Vec::with_capacity(FUA_HEADER_SIZE + fragment.len());
+            out.push(FUA_NALU_TYPE | nalu_ref_idc);
+            out.push(fu_header);
+            out.extend_from_slice(fragment);
+            payloads.push(out);
+        }
+    }
+}
+
+impl Payloader for H264Payloader {
+    fn payload(&mut self, mtu: usize, frame: &[u8]) -> Vec<Vec<u8>> {
+        let mut payloads = vec![];
+        if frame.is_empty() || mtu == 0 {
+            return payloads;
+        }
+
+        for nalu in emit_nalus(frame) {
+            self.payload_nalu(mtu, nalu, &mut payloads);
+        }
+
+        payloads
+    }
+}
+
+// 出力はAnnex-B形式 (start code + NAL unit)
+#[derive(Debug, Default, Clone)]
+pub struct H264Depacketizer {
+    // FU-Aの途中のfragment
+    fua_buffer: Option<Vec<u8>>,
+}
+
+impl Depacketizer for H264Depacketizer {
+    fn depacketize(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        if payload.len() <= 1 {
+            return Err(RtpError::PayloadTooShort);
+        }
+
+        let nalu_type = payload[0] & NALU_TYPE_BITMASK;
+
+        match nalu_type {
+            1..=23 => {
+                let mut out = ANNEXB_NALU_START_CODE.to_vec();
+                out.extend_from_slice(payload);
+                Ok(out)
+            }
+            STAPA_NALU_TYPE => {
+                let mut out = vec![];
+                let mut idx = STAPA_HEADER_SIZE;
+                while idx < payload.len() {
+                    if idx + STAPA_NALU_LENGTH_SIZE > payload.len() {
+                        return Err(RtpError::InvalidStapASize);
+                    }
+                    let size = (payload[idx] as usize) << 8 | payload[idx + 1] as usize;
+                    idx += STAPA_NALU_LENGTH_SIZE;
+
+                    if idx + size > payload.len() {
+                        return Err(RtpError::InvalidStapASize);
+                    }
+
+                    out.extend_from_slice(&ANNEXB_NALU_START_CODE);
+                    out.extend_from_slice(&payload[idx..idx + size]);
+                    idx += size;
+                }
+                Ok(out)
+            }
+            FUA_NALU_TYPE => {
+                if payload.len() < FUA_HEADER_SIZE {
+                    return Err(RtpError::PayloadTooShort);
+                }
+
+                let fu_header = payload[1];
+                if (fu_header & FU_START_BITMASK) > 0 {
+                    self.fua_buffer = Some(vec![]);
+                }
+
+                // 先頭fragmentを受け取っていない場合は捨てる
+                let buffer = match self.fua_buffer.as_mut() {
+                    Some(v) => v,
+                    None => return Ok(vec![]),
+                };
+                buffer.extend_from_slice(&payload[FUA_HEADER_SIZE..]);
+
+                if (fu_header & FU_END_BITMASK) == 0 {
+                    return Ok(vec![]);
+                }
+
+                let nalu_ref_idc = payload[0] & NALU_REF_IDC_BITMASK;
+                let fragmented_nalu_type = fu_header & NALU_TYPE_BITMASK;
+
+                let mut out = ANNEXB_NALU_START_CODE.to_vec();
+                out.push(nalu_ref_idc | fragmented_nalu_type);
+                out.extend(self.fua_buffer.take().unwrap_or_default());
+                Ok(out)
+            }
+            _ => Err(RtpError::UnhandledNaluType { nalu_type }),
+        }
+    }
+
+    fn is_partition_head(&self, payload: &[u8]) -> bool {
+        if payload.len() < 2 {
+            return false;
+        }
+
+        if payload[0] & NALU_TYPE_BITMASK == FUA_NALU_TYPE {
+            return (payload[1] & FU_START_BITMASK) > 0;
+        }
+
+        true
+    }
+
+    fn is_partition_tail(&self, marker: bool, _payload: &[u8]) -> bool {
+        marker
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // captured from chrome: STAP-A with SPS and PPS
+    const STAPA_PACKET: [u8; 25] = [
+        0x78, 0x00, 0x0f, 0x67, 0x42, 0xc0, 0x1f, 0x1a, 0x32, 0x35, 0x01, 0x40, 0x7a, 0x40, 0x3c,
+        0x22, 0x11, 0xa8, 0x00, 0x05, 0x68, 0x1a, 0x34, 0xe3, 0xc8,
+    ];
+    const STAPA_ANNEXB: [u8; 28] = [
+        0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0xc0, 0x1f, 0x1a, 0x32, 0x35, 0x01, 0x40, 0x7a, 0x40,
+        0x3c, 0x22, 0x11, 0xa8, 0x00, 0x00, 0x00, 0x01, 0x68, 0x1a, 0x34, 0xe3, 0xc8,
+    ];
+
+    #[test]
+    fn emit_nalus_test() {
+        let frame = [
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0x00, 0x00, 0x01, 0x68, 0x1a, 0x00, 0x00, 0x01,
+            0x65, 0x88,
+        ];
+        assert_eq!(
+            emit_nalus(&frame),
+            vec![&[0x67, 0x42][..], &[0x68, 0x1a][..], &[0x65, 0x88][..]]
+        );
+
+        // start codeが無い場合はそのまま1つのNAL unit
+        assert_eq!(emit_nalus(&[0x65, 0x88]), vec![&[0x65, 0x88][..]]);
+    }
+
+    #[test]
+    fn h264_payload_test() {
+        let mut payloader = H264Payloader::default();
+
+        assert!(payloader.payload(1, &[]).is_empty());
+        assert!(payloader.payload(0, &[0x90]).is_empty());
+
+        // Single NAL Unit Packet
+        let payloads = payloader.payload(5, &[0x00, 0x00, 0x01, 0x61, 0x90, 0x90]);
+        assert_eq!(payloads, vec![vec![0x61, 0x90, 0x90]]);
+
+        // AUDとFiller dataは送らない
+        let payloads = payloader.payload(5, &[0x00, 0x00, 0x01, 0x09, 0xF0]);
+        assert!(payloads.is_empty());
+        let payloads = payloader.payload(5, &[0x00, 0x00, 0x01, 0x0C, 0xFF]);
+        assert!(payloads.is_empty());
+
+        // FU-A
+        let nalu: Vec<u8> = vec![
+            0x00, 0x00, 0x00, 0x01, 0x65, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+        ];
+        let payloads = payloader.payload(5, &nalu);
+        assert_eq!(
+            payloads,
+            vec![
+                vec![0x7c, 0x85, 0x01, 0x02, 0x03],
+                vec![0x7c, 0x05, 0x04, 0x05, 0x06],
+                vec![0x7c, 0x45, 0x07],
+            ]
+        );
+    }
+
+    #[test]
+    fn h264_payload_stapa_test() {
+        let mut payloader = H264Payloader::default();
+
+        let mut frame = STAPA_ANNEXB.to_vec();
+        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x84]);
+
+        let payloads = payloader.payload(1200, &frame);
+        assert_eq!(
+            payloads,
+            vec![STAPA_PACKET.to_vec(), vec![0x65, 0x88, 0x84]]
+        );
+
+        // STAP-Aがmtuに収まらない場合は別々に送る
+        let payloads = payloader.payload(16, &frame);
+        assert_eq!(payloads.len(), 3);
+        assert_eq!(payloads[0], STAPA_ANNEXB[4..19].to_vec());
+        assert_eq!(payloads[1], STAPA_ANNEXB[23..].to_vec());
+    }
+
+    #[test]
+    fn h264_depacketize_test() {
+        let mut depacketizer = H264Depacketizer::default();
+
+        assert_eq!(
+            depacketizer.depacketize(&[]),
+            Err(RtpError::PayloadTooShort)
+        );
+        assert_eq!(
+            depacketizer.depacketize(&[0x09]),
+            Err(RtpError::PayloadTooShort)
+        );
+
+        // FU-B is not supported
+        assert_eq!(
+            depacketizer.depacketize(&[0x7d, 0x85, 0x00, 0x01, 0x90]),
+            Err(RtpError::UnhandledNaluType { nalu_type: 29 })
+        );
+
+        // Single NAL Unit Packet
+        assert_eq!(
+            depacketizer.depacketize(&[0x61, 0x90, 0x90]).unwrap(),
+            vec![0x00, 0x00, 0x00, 0x01, 0x61, 0x90, 0x90]
+        );
+
+        // STAP-A
+        assert_eq!(
+            depacketizer.depacketize(&STAPA_PACKET).unwrap(),
+            STAPA_ANNEXB.to_vec()
+        );
+        assert_eq!(
+            depacketizer.depacketize(&STAPA_PACKET[..20]),
+            Err(RtpError::InvalidStapASize)
+        );
+
+        // FU-A
+        assert!(depacketizer.is_partition_head(&[0x7c, 0x85, 0x01]));
+        assert!(!depacketizer.is_partition_head(&[0x7c, 0x05, 0x04]));
+        assert!(depacketizer
+            .depacketize(&[0x7c, 0x85, 0x01, 0x02, 0x03])
+            .unwrap()
+            .is_empty());
+        assert!(depacketizer
+            .depacketize(&[0x7c, 0x05, 0x04, 0x05, 0x06])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            depacketizer.depacketize(&[0x7c, 0x45, 0x07]).unwrap(),
+            vec![0x00, 0x00, 0x00, 0x01, 0x65, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07]
+        );
+
+        // 先頭fragmentが無いFU-Aは捨てる
+        assert!(depacketizer
+            .depacketize(&[0x7c, 0x45, 0x07])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn h264_round_trip_test() {
+        let mut frame = STAPA_ANNEXB.to_vec();
+        frame.extend_from_slice(&ANNEXB_NALU_START_CODE);
+        frame.push(0x65);
+        frame.extend((0..3000).map(|v| (v % 251) as u8 + 1));
+
+        let mut payloader = H264Payloader::default();
+        let mut depacketizer = H264Depacketizer::default();
+
+        let mut out = vec![];
+        for payload in payloader.payload(1188, &frame) {
+            assert!(payload.len() <= 1188);
+            out.extend(depacketizer.depacketize(&payload).unwrap());
+        }
+        assert_eq!(out, frame);
+    }
+}
@@ -0,0 +1,69 @@
+// https://tools.ietf.org/html/rfc7587
+
+use crate::rtp::codecs::{Depacketizer, Payloader};
+use crate::rtp::{Result, RtpError};
+
+// Opusは1 frameを1 packetにそのまま詰める (fragmentしない)
+#[derive(Debug, Default, Clone)]
+pub struct OpusPayloader;
+
+impl Payloader for OpusPayloader {
+    fn payload(&mut self, _mtu: usize, frame: &[u8]) -> Vec<Vec<u8>> {
+        if frame.is_empty() {
+            return vec![];
+        }
+        vec![frame.to_vec()]
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct OpusDepacketizer;
+
+impl Depacketizer for OpusDepacketizer {
+    fn depacketize(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        if payload.is_empty() {
+            return Err(RtpError::PayloadTooShort);
+        }
+        Ok(payload.to_vec())
+    }
+
+    fn is_partition_head(&self, _payload: &[u8]) -> bool {
+        true
+    }
+
+    fn is_partition_tail(&self, _marker: bool, _payload: &[u8]) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn opus_payload_test() {
+        let mut payloader = OpusPayloader;
+
+        assert!(payloader.payload(1, &[]).is_empty());
+
+        // mtuに関係なく1 packetになる
+        let payloads = payloader.payload(1, &[0x90, 0x90, 0x90]);
+        assert_eq!(payloads, vec![vec![0x90, 0x90, 0x90]]);
+    }
+
+    #[test]
+    fn opus_depacketize_test() {
+        let mut depacketizer = OpusDepacketizer;
+
+        assert_eq!(
+            depacketizer.depacketize(&[]),
+            Err(RtpError::PayloadTooShort)
+        );
+
+        // captured from chrome (TOC byte 0x78: SILK-only WB 20ms)
+        let raw = [0x78, 0x0b, 0xe4, 0xc1, 0x36, 0xec, 0xc5, 0x8d];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), raw.to_vec());
+        assert!(depacketizer.is_partition_head(&raw));
+        assert!(depacketizer.is_partition_tail(false, &raw));
+    }
+}
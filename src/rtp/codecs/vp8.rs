@@ -0,0 +1,304 @@
+// https://tools.ietf.org/html/rfc7741
+
+/*
+    VP8 Payload Descriptor
+
+         0 1 2 3 4 5 6 7
+        +-+-+-+-+-+-+-+-+
+        |X|R|N|S|R| PID | (REQUIRED)
+        +-+-+-+-+-+-+-+-+
+   X:   |I|L|T|K| RSV   | (OPTIONAL)
+        +-+-+-+-+-+-+-+-+
+   I:   |M| PictureID   | (OPTIONAL)
+        +-+-+-+-+-+-+-+-+
+        |   PictureID   |
+        +-+-+-+-+-+-+-+-+
+   L:   |   TL0PICIDX   | (OPTIONAL)
+        +-+-+-+-+-+-+-+-+
+   T/K: |TID|Y| KEYIDX  | (OPTIONAL)
+        +-+-+-+-+-+-+-+-+
+*/
+
+use crate::rtp::codecs::{Depacketizer, Payloader};
+use crate::rtp::{Result, RtpError};
+
+const VP8_HEADER_SIZE: usize = 1;
+
+#[derive(Debug, Default, Clone)]
+pub struct Vp8Payloader {
+    pub enable_picture_id: bool,
+    picture_id: u16,
+}
+
+impl Vp8Payloader {
+    pub fn new(enable_picture_id: bool) -> Vp8Payloader {
+        Vp8Payloader {
+            enable_picture_id,
+            picture_id: 0,
+        }
+    }
+}
+
+impl Payloader for Vp8Payloader {
+    fn payload(&mut self, mtu: usize, frame: &[u8]) -> Vec<Vec<u8>> {
+        let header_size = if !self.enable_picture_id {
+            VP8_HEADER_SIZE
+        } else if self.picture_id < 128 {
+            VP8_HEADER_SIZE + 2
+        } else {
+            VP8_HEADER_SIZE + 3
+        };
+
+        if frame.is_empty() || mtu <= header_size {
+            return vec![];
+        }
+
+        let max_fragment_size = mtu - header_size;
+
+        let payloads = frame
+            .chunks(max_fragment_size)
+            .enumerate()
+            .map(|(i, fragment)| {
+                let mut out = vec![0u8; header_size];
+                if i == 0 {
+                    // S bit: start of VP8 partition
+                    out[0] = 0x10;
+                }
+
+                match header_size - VP8_HEADER_SIZE {
+                    2 => {
+                        out[0] |= 0x80;
+                        out[1] |= 0x80;
+                        out[2] |= (self.picture_id & 0x7F) as u8;
+                    }
+                    3 => {
+                        out[0] |= 0x80;
+                        out[1] |= 0x80;
+                        out[2] |= 0x80 | ((self.picture_id >> 8) & 0x7F) as u8;
+                        out[3] |= (self.picture_id & 0xFF) as u8;
+                    }
+                    _ => {}
+                }
+
+                out.extend_from_slice(fragment);
+                out
+            })
+            .collect();
+
+        self.picture_id = (self.picture_id + 1) & 0x7FFF;
+
+        payloads
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Vp8Depacketizer {
+    // required header
+    pub x: bool,
+    pub n: bool,
+    pub s: bool,
+    pub pid: u8,
+
+    // extended control bits
+    pub i: bool,
+    pub l: bool,
+    pub t: bool,
+    pub k: bool,
+
+    // optional extension
+    pub picture_id: u16,
+    pub tl0_pic_idx: u8,
+    pub tid: u8,
+    pub y: bool,
+    pub key_idx: u8,
+}
+
+impl Depacketizer for Vp8Depacketizer {
+    fn depacketize(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        if payload.len() < 4 {
+            return Err(RtpError::PayloadTooShort);
+        }
+
+        let mut idx = 0;
+        let first = payload[idx];
+        self.x = (first & 0x80) > 0;
+        self.n = (first & 0x20) > 0;
+        self.s = (first & 0x10) > 0;
+        self.pid = first & 0x07;
+
+        if self.x {
+            idx += 1;
+            let second = payload[idx];
+            self.i = (second & 0x80) > 0;
+            self.l = (second & 0x40) > 0;
+            self.t = (second & 0x20) > 0;
+            self.k = (second & 0x10) > 0;
+        } else {
+            self.i = false;
+            self.l = false;
+            self.t = false;
+            self.k = false;
+        }
+
+        if self.i {
+            idx += 1;
+            if (payload[idx] & 0x80) > 0 {
+                // M bit: 15bit PictureID
+                self.picture_id = u16::from(payload[idx] & 0x7F) << 8;
+                idx += 1;
+                self.picture_id |= u16::from(payload[idx]);
+            } else {
+                self.picture_id = u16::from(payload[idx]);
+            }
+        }
+
+        if idx + 1 >= payload.len() && (self.l || self.t || self.k) {
+            return Err(RtpError::PayloadTooShort);
+        }
+
+        if self.l {
+            idx += 1;
+            self.tl0_pic_idx = payload[idx];
+        }
+
+        if self.t || self.k {
+            idx += 1;
+            if idx >= payload.len() {
+                return Err(RtpError::PayloadTooShort);
+            }
+            self.tid = payload[idx] >> 6;
+            self.y = (payload[idx] & 0x20) > 0;
+            self.key_idx = payload[idx] & 0x1F;
+        }
+
+        idx += 1;
+        if idx >= payload.len() {
+            return Err(RtpError::PayloadTooShort);
+        }
+
+        Ok(payload[idx..].to_vec())
+    }
+
+    fn is_partition_head(&self, payload: &[u8]) -> bool {
+        match payload.first() {
+            Some(v) => (v & 0x10) > 0,
+            None => false,
+        }
+    }
+
+    fn is_partition_tail(&self, marker: bool, _payload: &[u8]) -> bool {
+        marker
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vp8_payload_test() {
+        let mut payloader = Vp8Payloader::default();
+
+        assert!(payloader.payload(2, &[]).is_empty());
+        assert!(payloader.payload(1, &[0x90]).is_empty());
+
+        let payloads = payloader.payload(2, &[0x90, 0x90, 0x90]);
+        assert_eq!(
+            payloads,
+            vec![vec![0x10, 0x90], vec![0x00, 0x90], vec![0x00, 0x90]]
+        );
+    }
+
+    #[test]
+    fn vp8_payload_picture_id_test() {
+        let mut payloader = Vp8Payloader::new(true);
+        payloader.picture_id = 0x20;
+
+        let payloads = payloader.payload(5, &[0x90, 0x90, 0x90]);
+        assert_eq!(
+            payloads,
+            vec![
+                vec![0x90, 0x80, 0x20, 0x90, 0x90],
+                vec![0x80, 0x80, 0x20, 0x90]
+            ]
+        );
+
+        // 2 bytes PictureID
+        payloader.picture_id = 0x1234;
+        let payloads = payloader.payload(6, &[0x90, 0x90, 0x90]);
+        assert_eq!(
+            payloads,
+            vec![
+                vec![0x90, 0x80, 0x92, 0x34, 0x90, 0x90],
+                vec![0x80, 0x80, 0x92, 0x34, 0x90]
+            ]
+        );
+
+        // PictureIDは15bitでwrapする
+        payloader.picture_id = 0x7FFF;
+        payloader.payload(6, &[0x90]);
+        assert_eq!(payloader.picture_id, 0);
+    }
+
+    #[test]
+    fn vp8_depacketize_test() {
+        let mut depacketizer = Vp8Depacketizer::default();
+
+        // Payload smaller than header size
+        assert_eq!(
+            depacketizer.depacketize(&[0x00, 0x11, 0x22]),
+            Err(RtpError::PayloadTooShort)
+        );
+
+        // Normal packet
+        let raw = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x90];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), raw[1..].to_vec());
+
+        // Header size, only X
+        let raw = [0x80, 0x00, 0x00, 0x00];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), vec![0x00, 0x00]);
+
+        // Header size, X and I, 15bit PictureID
+        let raw = [0x80, 0x80, 0x81, 0x00, 0x00];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), vec![0x00]);
+        assert_eq!(depacketizer.picture_id, 0x0100);
+
+        // Header size, X and L
+        let raw = [0x80, 0x40, 0x00, 0x00];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), vec![0x00]);
+
+        // Header size, X and T
+        let raw = [0x80, 0x20, 0x00, 0x00];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), vec![0x00]);
+
+        // Header size, X and K
+        let raw = [0x80, 0x10, 0x00, 0x00];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), vec![0x00]);
+
+        // Header size, all flags and no payload
+        let raw = [0xff, 0xff, 0x00, 0x00];
+        assert_eq!(
+            depacketizer.depacketize(&raw),
+            Err(RtpError::PayloadTooShort)
+        );
+    }
+
+    #[test]
+    fn vp8_round_trip_test() {
+        let frame: Vec<u8> = (0..100).collect();
+        let mut payloader = Vp8Payloader::new(true);
+        let mut depacketizer = Vp8Depacketizer::default();
+
+        let payloads = payloader.payload(30, &frame);
+        assert!(depacketizer.is_partition_head(&payloads[0]));
+        assert!(!depacketizer.is_partition_head(&payloads[1]));
+
+        let mut out = vec![];
+        for payload in &payloads {
+            out.extend(depacketizer.depacketize(payload).unwrap());
+            assert!(depacketizer.i);
+            assert_eq!(depacketizer.picture_id, 0);
+        }
+        assert_eq!(out, frame);
+    }
+}
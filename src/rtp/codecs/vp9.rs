@@ -0,0 +1,418 @@
+// https://tools.ietf.org/html/draft-ietf-payload-vp9-16
+
+/*
+    VP9 Payload Descriptor (flexible mode)
+
+         0 1 2 3 4 5 6 7
+        +-+-+-+-+-+-+-+-+
+        |I|P|L|F|B|E|V|Z| (REQUIRED)
+        +-+-+-+-+-+-+-+-+
+   I:   |M| PICTURE ID  | (REQUIRED)
+        +-+-+-+-+-+-+-+-+
+   M:   | EXTENDED PID  | (RECOMMENDED)
+        +-+-+-+-+-+-+-+-+
+   L:   | TID |U| SID |D| (CONDITIONALLY RECOMMENDED)
+        +-+-+-+-+-+-+-+-+                             -\
+   P,F: | P_DIFF      |N| (CONDITIONALLY REQUIRED)    - up to 3 times
+        +-+-+-+-+-+-+-+-+                             -/
+   V:   | SS            |
+        | ..            |
+        +-+-+-+-+-+-+-+-+
+*/
+
+use crate::rtp::codecs::{Depacketizer, Payloader};
+use crate::rtp::{Result, RtpError};
+use rand::Rng;
+
+// Flexible modeで15bitのPictureIDを使う
+const VP9_HEADER_SIZE: usize = 3;
+const MAX_SPATIAL_LAYERS: usize = 5;
+const MAX_VP9_REF_PICS: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct Vp9Payloader {
+    picture_id: u16,
+}
+
+impl Vp9Payloader {
+    pub fn new() -> Vp9Payloader {
+        let mut rng = rand::thread_rng();
+        Vp9Payloader {
+            picture_id: rng.gen::<u16>() & 0x7FFF,
+        }
+    }
+}
+
+impl Default for Vp9Payloader {
+    fn default() -> Self {
+        Vp9Payloader::new()
+    }
+}
+
+impl Payloader for Vp9Payloader {
+    fn payload(&mut self, mtu: usize, frame: &[u8]) -> Vec<Vec<u8>> {
+        if frame.is_empty() || mtu <= VP9_HEADER_SIZE {
+            return vec![];
+        }
+
+        let max_fragment_size = mtu - VP9_HEADER_SIZE;
+        let count = frame.chunks(max_fragment_size).len();
+
+        let payloads = frame
+            .chunks(max_fragment_size)
+            .enumerate()
+            .map(|(i, fragment)| {
+                // I=1, F=1
+                let mut b0 = 0x90;
+                if i == 0 {
+                    b0 |= 0x08; // B: start of a frame
+                }
+                if i == count - 1 {
+                    b0 |= 0x04; // E: end of a frame
+                }
+
+                let mut out = vec![
+                    b0,
+                    (self.picture_id >> 8) as u8 | 0x80,
+                    self.picture_id as u8,
+                ];
+                out.extend_from_slice(fragment);
+                out
+            })
+            .collect();
+
+        self.picture_id = (self.picture_id + 1) & 0x7FFF;
+
+        payloads
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Vp9Depacketizer {
+    // required header
+    pub i: bool, // PictureID is present
+    pub p: bool, // Inter-picture predicted frame
+    pub l: bool, // Layer indices is present
+    pub f: bool, // Flexible mode
+    pub b: bool, // Start of a frame
+    pub e: bool, // End of a frame
+    pub v: bool, // Scalability structure (SS) data present
+    pub z: bool, // Not a reference frame for upper spatial layers
+
+    // optional header
+    pub picture_id: u16,
+    pub tid: u8,
+    pub u: bool,
+    pub sid: u8,
+    pub d: bool,
+    pub tl0_pic_idx: u8,
+    pub p_diff: Vec<u8>,
+
+    // scalability structure
+    pub ns: u8,
+    pub y: bool,
+    pub g: bool,
+    pub width: Vec<u16>,
+    pub height: Vec<u16>,
+    pub ng: u8,
+    pub pg_tid: Vec<u8>,
+    pub pg_u: Vec<bool>,
+    pub pg_p_diff: Vec<Vec<u8>>,
+}
+
+fn get(payload: &[u8], idx: usize) -> Result<u8> {
+    payload.get(idx).cloned().ok_or(RtpError::PayloadTooShort)
+}
+
+impl Vp9Depacketizer {
+    fn parse_picture_id(&mut self, payload: &[u8], mut idx: usize) -> Result<usize> {
+        let b = get(payload, idx)?;
+        self.picture_id = u16::from(b & 0x7F);
+        if (b & 0x80) > 0 {
+            idx += 1;
+            self.picture_id = (self.picture_id << 8) | u16::from(get(payload, idx)?);
+        }
+        Ok(idx + 1)
+    }
+
+    fn parse_layer_info(&mut self, payload: &[u8], mut idx: usize) -> Result<usize> {
+        let b = get(payload, idx)?;
+        self.tid = b >> 5;
+        self.u = (b & 0x10) > 0;
+        self.sid = (b >> 1) & 0x7;
+        self.d = (b & 0x01) > 0;
+
+        if self.sid as usize >= MAX_SPATIAL_LAYERS {
+            return Err(RtpError::InvalidVp9Descriptor);
+        }
+        idx += 1;
+
+        // non-flexible modeではTL0PICIDXが続く
+        if !self.f {
+            self.tl0_pic_idx = get(payload, idx)?;
+            idx += 1;
+        }
+        Ok(idx)
+    }
+
+    fn parse_ref_indices(&mut self, payload: &[u8], mut idx: usize) -> Result<usize> {
+        loop {
+            let b = get(payload, idx)?;
+            self.p_diff.push(b >> 1);
+            idx += 1;
+
+            if (b & 0x01) == 0 {
+                break;
+            }
+            if self.p_diff.len() >= MAX_VP9_REF_PICS {
+                return Err(RtpError::InvalidVp9Descriptor);
+            }
+        }
+        Ok(idx)
+    }
+
+    /*
+         Scalability structure (SS):
+
+         +-+-+-+-+-+-+-+-+
+    V:   | N_S |Y|G|-|-|-|
+         +-+-+-+-+-+-+-+-+              -\
+    Y:   |     WIDTH     | (OPTIONAL)    .
+         +               +               .
+         |               | (OPTIONAL)    .
+         +-+-+-+-+-+-+-+-+               . - N_S + 1 times
+         |     HEIGHT    | (OPTIONAL)    .
+         +               +               .
+         |               | (OPTIONAL)    .
+         +-+-+-+-+-+-+-+-+              -/
+    G:   |      N_G      | (OPTIONAL)
+         +-+-+-+-+-+-+-+-+                           -\
+    N_G: |  T  |U| R |-|-| (OPTIONAL)                 .
+         +-+-+-+-+-+-+-+-+              -\            . - N_G times
+         |    P_DIFF     | (OPTIONAL)    . - R times  .
+         +-+-+-+-+-+-+-+-+              -/           -/
+     */
+    fn parse_ss_data(&mut self, payload: &[u8], mut idx: usize) -> Result<usize> {
+        let b = get(payload, idx)?;
+        self.ns = (b >> 5) + 1;
+        self.y = (b & 0x10) > 0;
+        self.g = (b & 0x08) > 0;
+        idx += 1;
+
+        self.width.clear();
+        self.height.clear();
+        if self.y {
+            for _ in 0..self.ns {
+                self.width
+                    .push(u16::from(get(payload, idx)?) << 8 | u16::from(get(payload, idx + 1)?));
+                self.height.push(
+                    u16::from(get(payload, idx + 2)?) << 8 | u16::from(get(payload, idx + 3)?),
+                );
+                idx += 4;
+            }
+        }
+
+        self.pg_tid.clear();
+        self.pg_u.clear();
+        self.pg_p_diff.clear();
+        self.ng = 0;
+        if self.g {
+            self.ng = get(payload, idx)?;
+            idx += 1;
+
+            for _ in 0..self.ng {
+                let b = get(payload, idx)?;
+                self.pg_tid.push(b >> 5);
+                self.pg_u.push((b & 0x10) > 0);
+                let r = (b >> 2) & 0x3;
+                idx += 1;
+
+                let mut p_diff = vec![];
+                for _ in 0..r {
+                    p_diff.push(get(payload, idx)?);
+                    idx += 1;
+                }
+                self.pg_p_diff.push(p_diff);
+            }
+        }
+
+        Ok(idx)
+    }
+}
+
+impl Depacketizer for Vp9Depacketizer {
+    fn depacketize(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        if payload.is_empty() {
+            return Err(RtpError::PayloadTooShort);
+        }
+
+        let b0 = payload[0];
+        self.i = (b0 & 0x80) > 0;
+        self.p = (b0 & 0x40) > 0;
+        self.l = (b0 & 0x20) > 0;
+        self.f = (b0 & 0x10) > 0;
+        self.b = (b0 & 0x08) > 0;
+        self.e = (b0 & 0x04) > 0;
+        self.v = (b0 & 0x02) > 0;
+        self.z = (b0 & 0x01) > 0;
+        self.p_diff.clear();
+
+        let mut idx = 1;
+
+        if self.i {
+            idx = self.parse_picture_id(payload, idx)?;
+        }
+
+        if self.l {
+            idx = self.parse_layer_info(payload, idx)?;
+        }
+
+        if self.f && self.p {
+            idx = self.parse_ref_indices(payload, idx)?;
+        }
+
+        if self.v {
+            idx = self.parse_ss_data(payload, idx)?;
+        }
+
+        if idx > payload.len() {
+            return Err(RtpError::PayloadTooShort);
+        }
+
+        Ok(payload[idx..].to_vec())
+    }
+
+    fn is_partition_head(&self, payload: &[u8]) -> bool {
+        match payload.first() {
+            Some(v) => (v & 0x08) > 0,
+            None => false,
+        }
+    }
+
+    fn is_partition_tail(&self, marker: bool, _payload: &[u8]) -> bool {
+        marker
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vp9_payload_test() {
+        let mut payloader = Vp9Payloader { picture_id: 0x1234 };
+
+        assert!(payloader.payload(4, &[]).is_empty());
+        assert!(payloader.payload(3, &[0x01]).is_empty());
+
+        let payloads = payloader.payload(5, &[0x01, 0x02, 0x03, 0x04, 0x05]);
+        assert_eq!(
+            payloads,
+            vec![
+                vec![0x98, 0x92, 0x34, 0x01, 0x02],
+                vec![0x90, 0x92, 0x34, 0x03, 0x04],
+                vec![0x94, 0x92, 0x34, 0x05],
+            ]
+        );
+
+        // 1 packetに収まる場合はB/E両方が立つ
+        let payloads = payloader.payload(10, &[0x01]);
+        assert_eq!(payloads, vec![vec![0x9C, 0x92, 0x35, 0x01]]);
+
+        payloader.picture_id = 0x7FFF;
+        payloader.payload(10, &[0x01]);
+        assert_eq!(payloader.picture_id, 0);
+    }
+
+    #[test]
+    fn vp9_depacketize_test() {
+        let mut depacketizer = Vp9Depacketizer::default();
+
+        assert_eq!(
+            depacketizer.depacketize(&[]),
+            Err(RtpError::PayloadTooShort)
+        );
+
+        // Non-flexible, 7bit PictureID
+        let raw = [0x80, 0x02, 0xAA];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), vec![0xAA]);
+        assert_eq!(depacketizer.picture_id, 0x02);
+
+        // 15bit PictureID
+        let raw = [0x80, 0x81, 0xFF, 0xAA];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), vec![0xAA]);
+        assert_eq!(depacketizer.picture_id, 0x01FF);
+
+        // 15bit PictureID is truncated
+        let raw = [0x80, 0x81];
+        assert_eq!(
+            depacketizer.depacketize(&raw),
+            Err(RtpError::PayloadTooShort)
+        );
+
+        // Layer indices (non-flexible mode has TL0PICIDX)
+        let raw = [0x20, 0x76, 0x13, 0xAA];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), vec![0xAA]);
+        assert_eq!(depacketizer.tid, 3);
+        assert!(depacketizer.u);
+        assert_eq!(depacketizer.sid, 3);
+        assert!(!depacketizer.d);
+        assert_eq!(depacketizer.tl0_pic_idx, 0x13);
+
+        // Flexible mode with reference indices
+        let raw = [0xD0, 0x02, 0x03, 0x05, 0x08, 0xAA];
+        assert_eq!(depacketizer.depacketize(&raw).unwrap(), vec![0xAA]);
+        assert_eq!(depacketizer.p_diff, vec![1, 2, 4]);
+
+        // Too many reference indices
+        let raw = [0xD0, 0x02, 0x03, 0x05, 0x09, 0xAA];
+        assert_eq!(
+            depacketizer.depacketize(&raw),
+            Err(RtpError::InvalidVp9Descriptor)
+        );
+    }
+
+    #[test]
+    fn vp9_depacketize_ss_test() {
+        let mut depacketizer = Vp9Depacketizer::default();
+
+        // keyframe with scalability structure (640x360)
+        let raw = [
+            0x8A, 0x80, 0x01, // I, B, V / PictureID 1
+            0x18, // N_S=0, Y, G
+            0x02, 0x80, 0x01, 0x68, // 640x360
+            0x01, // N_G=1
+            0x04, 0x01, // T=0, U=0, R=1 / P_DIFF=1
+            0x82, 0x49, 0x83, // VP9 frame header
+        ];
+        assert_eq!(
+            depacketizer.depacketize(&raw).unwrap(),
+            vec![0x82, 0x49, 0x83]
+        );
+        assert!(depacketizer.v);
+        assert_eq!(depacketizer.ns, 1);
+        assert_eq!(depacketizer.width, vec![640]);
+        assert_eq!(depacketizer.height, vec![360]);
+        assert_eq!(depacketizer.ng, 1);
+        assert_eq!(depacketizer.pg_p_diff, vec![vec![1]]);
+        assert!(depacketizer.is_partition_head(&raw));
+    }
+
+    #[test]
+    fn vp9_round_trip_test() {
+        let frame: Vec<u8> = (0..200).map(|v| v as u8).collect();
+        let mut payloader = Vp9Payloader::new();
+        let mut depacketizer = Vp9Depacketizer::default();
+
+        let payloads = payloader.payload(64, &frame);
+        assert_eq!(payloads.len(), 4);
+
+        let mut out = vec![];
+        for (i, payload) in payloads.iter().enumerate() {
+            out.extend(depacketizer.depacketize(payload).unwrap());
+            assert_eq!(depacketizer.b, i == 0);
+            assert_eq!(depacketizer.e, i == payloads.len() - 1);
+        }
+        assert_eq!(out, frame);
+    }
+}
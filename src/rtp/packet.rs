@@ -4,7 +4,7 @@
 use crate::octets;
 use crate::rtp::{Result, RtpError};
 
-use crate::rtcrtpparameters::{RtcRtpParameter};
+use crate::rtcrtpparameters::RtcRtpParameter;
 
 /*
     The RTP header has the following format:
@@ -45,14 +45,22 @@ use crate::rtcrtpparameters::{RtcRtpParameter};
     |                          data                                 |
     +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 */
-fn unpack_header_extension(
+pub fn unpack_header_extension(
     bytes: &mut octets::Octets,
     profile: u16,
-) -> Result<(Vec<(u8, Vec<u8>)>)> {
+) -> Result<Vec<(u8, Vec<u8>)>> {
     // OctetsはRTP Header Extension で作り直されたものを使用する予定
     // こうすることで，RTP Header Extensionのサイズを超過しないようにする
     // TODO : Vec<(u8, octets::Octets)>で返せるようにする．．
-    if profile != 0xBEDE && profile != 0x1000 {
+    let (extensions, _) = unpack_elements(bytes, profile)?;
+    Ok(extensions)
+}
+
+// (id, value)のelement列と，ID=15で解析を打ち切ったかどうか
+type UnpackedElements = (Vec<(u8, Vec<u8>)>, bool);
+
+fn unpack_elements(bytes: &mut octets::Octets, profile: u16) -> Result<UnpackedElements> {
+    if !is_rfc8285_profile(profile) {
         return Err(RtpError::InvalidHeaderExtensionProfile);
     }
 
@@ -66,16 +74,19 @@ fn unpack_header_extension(
         }
 
         let (id, length) = match profile {
-            0xBEDE => ((octet & 0xf0) >> 4, (octet & 0x0f) + 1),
-            0x1000 => {
+            0xBEDE => {
+                // ID=15 is reserved, stop parsing
+                if octet >> 4 == 0x0F {
+                    return Ok((extensions, true));
+                }
+                ((octet & 0xf0) >> 4, (octet & 0x0f) + 1)
+            }
+            _ => {
                 let length = bytes
                     .get_u8()
                     .map_err(|_| RtpError::TruncatedTwoByteHeaderExtension)?;
                 (octet, length)
             }
-            _ => {
-                return Err(RtpError::InvalidHeaderExtensionProfile);
-            }
         };
 
         // length check
@@ -88,100 +99,421 @@ fn unpack_header_extension(
         extensions.push((id, payload));
     }
 
-    Ok(extensions)
+    Ok((extensions, false))
 }
 
-struct HeaderExtensions {
-    abs_send_time: Option<usize>, // TODO: Change time format?
-    audio_level: Option<usize>,
-    mid: Option<usize>,
-    repaired_rtp_stream_id: Option<usize>,
-    rtp_stream_id: Option<usize>,
-    transmission_offset: Option<usize>,
-    transport_sequence_number: Option<usize>,
+// one-byte headerで表現できない場合はtwo-byte headerを使う．
+// 返り値は(profile, 32bit境界までpaddingされたextension)
+pub fn pack_header_extension(extensions: &[(u8, Vec<u8>)]) -> Result<(u16, Vec<u8>)> {
+    if extensions.is_empty() {
+        return Ok((0, vec![]));
+    }
+    // two-byte headerでも長さは255bytesまで
+    if extensions
+        .iter()
+        .any(|(id, value)| *id == 0 || value.len() > 255)
+    {
+        return Err(RtpError::InvalidHeaderExtensionElement);
+    }
+
+    if needs_two_byte(extensions) {
+        Ok((0x1000, pack_elements(extensions, true)))
+    } else {
+        Ok((0xBEDE, pack_elements(extensions, false)))
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HeaderExtensions {
+    // 24bit 6.18 fixed point (seconds)
+    pub abs_send_time: Option<u32>,
+    // (voice activity, level in -dBov)
+    pub audio_level: Option<(bool, u8)>,
+    pub mid: Option<String>,
+    pub repaired_rtp_stream_id: Option<String>,
+    pub rtp_stream_id: Option<String>,
+    // 24bit signed
+    pub transmission_offset: Option<i32>,
+    pub transport_sequence_number: Option<u16>,
 }
 
 impl HeaderExtensions {
     pub fn new() -> HeaderExtensions {
-        HeaderExtensions {
-            abs_send_time: None,
-            audio_level: None,
-            mid: None,
-            repaired_rtp_stream_id: None,
-            rtp_stream_id: None,
-            transmission_offset: None,
-            transport_sequence_number: None,
-        }
+        HeaderExtensions::default()
+    }
+
+    pub fn abs_send_time_secs(&self) -> Option<f64> {
+        self.abs_send_time
+            .map(|v| f64::from(v) / f64::from(1u32 << 18))
     }
 }
 
-struct HeaderExtensionsMap {
-    ids: HeaderExtensions
+// 各extensionのnegotiateされたID
+#[derive(Debug, Default, Clone, PartialEq)]
+struct HeaderExtensionIds {
+    abs_send_time: Option<u8>,
+    audio_level: Option<u8>,
+    mid: Option<u8>,
+    repaired_rtp_stream_id: Option<u8>,
+    rtp_stream_id: Option<u8>,
+    transmission_offset: Option<u8>,
+    transport_sequence_number: Option<u8>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HeaderExtensionsMap {
+    ids: HeaderExtensionIds,
 }
 
 impl HeaderExtensionsMap {
-    fn configure(&mut self, param: RtcRtpParameter) -> () {
-        for ext in param.header_extensions {
+    pub fn new() -> HeaderExtensionsMap {
+        HeaderExtensionsMap::default()
+    }
+
+    pub fn configure(&mut self, param: &RtcRtpParameter) {
+        for ext in &param.header_extensions {
+            // IDは1~255
+            if ext.id == 0 || ext.id > 255 {
+                continue;
+            }
+            let id = Some(ext.id as u8);
+
             match ext.uri.as_str() {
-                "urn:ietf:params:rtp-hdrext:sdes:mid" => { self.ids.mid = Some(ext.id); }
+                "urn:ietf:params:rtp-hdrext:sdes:mid" => {
+                    self.ids.mid = id;
+                }
                 "urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id" => {
-                    self.ids.repaired_rtp_stream_id = Some(ext.id);
+                    self.ids.repaired_rtp_stream_id = id;
                 }
                 "urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id" => {
-                    self.ids.rtp_stream_id = Some(ext.id);
+                    self.ids.rtp_stream_id = id;
                 }
                 "http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time" => {
-                    self.ids.abs_send_time = Some(ext.id);
+                    self.ids.abs_send_time = id;
                 }
                 "urn:ietf:params:rtp-hdrext:toffset" => {
-                    self.ids.transmission_offset = Some(ext.id);
+                    self.ids.transmission_offset = id;
                 }
                 "urn:ietf:params:rtp-hdrext:ssrc-audio-level" => {
-                    self.ids.audio_level = Some(ext.id);
+                    self.ids.audio_level = id;
                 }
                 "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01" => {
-                    self.ids.transport_sequence_number = Some(ext.id);
+                    self.ids.transport_sequence_number = id;
                 }
                 _ => {
-                    // unsupported extensions
-                    unreachable!();
+                    // unsupported extensions are ignored
                 }
             }
         }
     }
 
-    fn get(&self, bytes: &mut octets::Octets, profile: u16) -> HeaderExtensions {
+    pub fn get(&self, bytes: &mut octets::Octets, profile: u16) -> Result<HeaderExtensions> {
+        let elements = unpack_header_extension(bytes, profile)?;
+        Ok(self.decode(&elements))
+    }
+
+    // RtpHeaderExtensionのelementから値を取り出す
+    pub fn read(&self, extension: &RtpHeaderExtension) -> HeaderExtensions {
+        self.decode(extension.elements())
+    }
+
+    fn decode(&self, elements: &[(u8, Vec<u8>)]) -> HeaderExtensions {
         let mut values = HeaderExtensions::new();
-        for (x_id, x_value) in unpack_header_extension(bytes, profile).unwrap() {
-            let x_id = x_id as usize;
-            // if let Some(v) = self.ids.mid {
-            //     if x_id == v {
-            //         values.mid = Some(String::from_utf8(x_value).unwrap());
-            //     }
-            // }
+
+        for (x_id, x_value) in elements {
+            let x_id = Some(*x_id);
+
+            if x_id == self.ids.mid {
+                values.mid = String::from_utf8(x_value.clone()).ok();
+            } else if x_id == self.ids.repaired_rtp_stream_id {
+                values.repaired_rtp_stream_id = String::from_utf8(x_value.clone()).ok();
+            } else if x_id == self.ids.rtp_stream_id {
+                values.rtp_stream_id = String::from_utf8(x_value.clone()).ok();
+            } else if x_id == self.ids.abs_send_time {
+                if x_value.len() == 3 {
+                    values.abs_send_time = Some(
+                        u32::from(x_value[0]) << 16
+                            | u32::from(x_value[1]) << 8
+                            | u32::from(x_value[2]),
+                    );
+                }
+            } else if x_id == self.ids.transmission_offset {
+                if x_value.len() == 3 {
+                    // 24bit signed integerを符号拡張する
+                    let v = i32::from(x_value[0]) << 24
+                        | i32::from(x_value[1]) << 16
+                        | i32::from(x_value[2]) << 8;
+                    values.transmission_offset = Some(v >> 8);
+                }
+            } else if x_id == self.ids.audio_level {
+                if let Some(v) = x_value.first() {
+                    values.audio_level = Some(((v & 0x80) > 0, v & 0x7F));
+                }
+            } else if x_id == self.ids.transport_sequence_number && x_value.len() == 2 {
+                values.transport_sequence_number =
+                    Some(u16::from(x_value[0]) << 8 | u16::from(x_value[1]));
+            }
         }
-        unimplemented!()
+
+        values
+    }
+
+    // mid, ridが255bytesを超えるとInvalidHeaderExtensionElement
+    pub fn set(&self, values: &HeaderExtensions) -> Result<(u16, Vec<u8>)> {
+        pack_header_extension(&self.encode(values))
+    }
+
+    // 値のあるextensionだけを書き換え，それ以外のelementはそのまま残す
+    pub fn write(
+        &self,
+        values: &HeaderExtensions,
+        extension: &mut RtpHeaderExtension,
+    ) -> Result<()> {
+        for (id, value) in self.encode(values) {
+            extension.set(id, value)?;
+        }
+        Ok(())
+    }
+
+    fn encode(&self, values: &HeaderExtensions) -> Vec<(u8, Vec<u8>)> {
+        let mut extensions = vec![];
+
+        if let (Some(id), Some(v)) = (self.ids.mid, &values.mid) {
+            extensions.push((id, v.as_bytes().to_vec()));
+        }
+        if let (Some(id), Some(v)) = (
+            self.ids.repaired_rtp_stream_id,
+            &values.repaired_rtp_stream_id,
+        ) {
+            extensions.push((id, v.as_bytes().to_vec()));
+        }
+        if let (Some(id), Some(v)) = (self.ids.rtp_stream_id, &values.rtp_stream_id) {
+            extensions.push((id, v.as_bytes().to_vec()));
+        }
+        if let (Some(id), Some(v)) = (self.ids.abs_send_time, values.abs_send_time) {
+            extensions.push((id, vec![(v >> 16) as u8, (v >> 8) as u8, v as u8]));
+        }
+        if let (Some(id), Some(v)) = (self.ids.transmission_offset, values.transmission_offset) {
+            extensions.push((id, vec![(v >> 16) as u8, (v >> 8) as u8, v as u8]));
+        }
+        if let (Some(id), Some((vad, level))) = (self.ids.audio_level, values.audio_level) {
+            let vad = if vad { 0x80 } else { 0x00 };
+            extensions.push((id, vec![vad | (level & 0x7F)]));
+        }
+        if let (Some(id), Some(v)) = (
+            self.ids.transport_sequence_number,
+            values.transport_sequence_number,
+        ) {
+            extensions.push((id, vec![(v >> 8) as u8, v as u8]));
+        }
+
+        extensions
     }
 }
 
+// RFC 8285のprofileかどうか (two-byte headerの下位4bitはappbits)
+fn is_rfc8285_profile(profile: u16) -> bool {
+    profile == 0xBEDE || is_two_byte_profile(profile)
+}
+
+fn is_two_byte_profile(profile: u16) -> bool {
+    profile & 0xFFF0 == 0x1000
+}
+
+// one-byte headerで表現できないelementがあるか
+fn needs_two_byte(elements: &[(u8, Vec<u8>)]) -> bool {
+    !elements
+        .iter()
+        .all(|(id, value)| *id >= 1 && *id <= 14 && !value.is_empty() && value.len() <= 16)
+}
+
+// 32bit境界までpaddingしたサイズ
+fn elements_size(elements: &[(u8, Vec<u8>)], two_byte: bool) -> usize {
+    let header_size = if two_byte { 2 } else { 1 };
+    let size: usize = elements
+        .iter()
+        .map(|(_, value)| header_size + value.len())
+        .sum();
+    (size + 3) / 4 * 4
+}
+
+// elementはone-byteかtwo-byteのheaderで表現できるものでなければならない
+fn pack_elements(elements: &[(u8, Vec<u8>)], two_byte: bool) -> Vec<u8> {
+    debug_assert!(if two_byte {
+        elements
+            .iter()
+            .all(|(id, value)| *id != 0 && value.len() <= 255)
+    } else {
+        !needs_two_byte(elements)
+    });
+    let mut out = Vec::with_capacity(elements_size(elements, two_byte));
+    for (id, value) in elements {
+        if two_byte {
+            out.push(*id);
+            out.push(value.len() as u8);
+        } else {
+            out.push((id << 4) | (value.len() as u8 - 1));
+        }
+        out.extend_from_slice(value);
+    }
+
+    while out.len() % 4 != 0 {
+        out.push(0);
+    }
+
+    out
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
-struct RtpHeaderExtension {
+enum ExtensionBody {
+    // RFC 8285のelement (id, value)
+    Elements(Vec<(u8, Vec<u8>)>),
+    // 解釈できないprofileのextensionはそのまま保持する
+    Opaque(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RtpHeaderExtension {
     profile: u16,
-    //payload : Vec<u8>, // bytes array
-    payload: Vec<u32>, // 4bytes array
+    body: ExtensionBody,
+}
+
+impl Default for RtpHeaderExtension {
+    fn default() -> Self {
+        RtpHeaderExtension::new()
+    }
 }
 
 impl RtpHeaderExtension {
+    // elementを持たないRFC 8285のextension
+    pub fn new() -> RtpHeaderExtension {
+        RtpHeaderExtension {
+            profile: 0xBEDE,
+            body: ExtensionBody::Elements(vec![]),
+        }
+    }
+
+    // payloadは32bit境界までpaddingされている必要がある．
+    // RFC 8285として解釈できないものはopaqueなデータとして保持する．
+    pub fn with_payload(profile: u16, payload: Vec<u8>) -> Result<RtpHeaderExtension> {
+        if payload.len() % 4 != 0 || payload.len() / 4 > 0xFFFF {
+            return Err(RtpError::InvalidPacketHeaderExtensionSize);
+        }
+
+        let body = if is_rfc8285_profile(profile) {
+            let mut buf = payload.clone();
+            let mut bytes = octets::Octets::with_slice(&mut buf);
+            // ID=15以降を捨てないように，打ち切った場合もopaqueとして保持する
+            match unpack_elements(&mut bytes, profile) {
+                Ok((elements, false)) => ExtensionBody::Elements(elements),
+                _ => ExtensionBody::Opaque(payload),
+            }
+        } else {
+            ExtensionBody::Opaque(payload)
+        };
+
+        Ok(RtpHeaderExtension { profile, body })
+    }
+
+    // elementの内容に応じて0xBEDEか0x1000を選ぶ．
+    // 受信時にtwo-byte headerだったものはappbitsを含めてそのまま使う．
+    pub fn profile(&self) -> u16 {
+        match self.body {
+            ExtensionBody::Elements(ref elements) => {
+                if is_two_byte_profile(self.profile) {
+                    self.profile
+                } else if needs_two_byte(elements) {
+                    0x1000
+                } else {
+                    0xBEDE
+                }
+            }
+            ExtensionBody::Opaque(_) => self.profile,
+        }
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        match self.body {
+            ExtensionBody::Elements(_) => false,
+            ExtensionBody::Opaque(_) => true,
+        }
+    }
+
+    // opaqueなextensionの場合は空
+    pub fn elements(&self) -> &[(u8, Vec<u8>)] {
+        match self.body {
+            ExtensionBody::Elements(ref elements) => elements,
+            ExtensionBody::Opaque(_) => &[],
+        }
+    }
+
+    pub fn get(&self, id: u8) -> Option<&[u8]> {
+        self.elements()
+            .iter()
+            .find(|(x_id, _)| *x_id == id)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    // 既にあるIDは位置を変えずに値だけ置き換える
+    pub fn set(&mut self, id: u8, value: Vec<u8>) -> Result<()> {
+        if id == 0 || value.len() > 255 {
+            return Err(RtpError::InvalidHeaderExtensionElement);
+        }
+
+        let elements = match self.body {
+            ExtensionBody::Elements(ref mut elements) => elements,
+            ExtensionBody::Opaque(_) => return Err(RtpError::InvalidHeaderExtensionProfile),
+        };
+
+        match elements.iter_mut().find(|(x_id, _)| *x_id == id) {
+            Some(element) => element.1 = value,
+            None => elements.push((id, value)),
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: u8) -> Option<Vec<u8>> {
+        let elements = match self.body {
+            ExtensionBody::Elements(ref mut elements) => elements,
+            ExtensionBody::Opaque(_) => return None,
+        };
+
+        let idx = elements.iter().position(|(x_id, _)| *x_id == id)?;
+        Some(elements.remove(idx).1)
+    }
+
+    // extensionのpayloadをbyte列で返す (32bit境界までpaddingされる)
+    pub fn payload(&self) -> Vec<u8> {
+        match self.body {
+            ExtensionBody::Elements(ref elements) => {
+                pack_elements(elements, is_two_byte_profile(self.profile()))
+            }
+            ExtensionBody::Opaque(ref payload) => payload.clone(),
+        }
+    }
+
+    pub fn marshal_size(&self) -> usize {
+        let payload_size = match self.body {
+            ExtensionBody::Elements(ref elements) => {
+                elements_size(elements, is_two_byte_profile(self.profile()))
+            }
+            ExtensionBody::Opaque(ref payload) => payload.len(),
+        };
+        4 + payload_size
+    }
+
     // 構造体に代入されたデータをBinaryに変換
     pub fn to_bytes(&self, out: &mut octets::Octets) -> Result<()> {
-        out.put_u16(self.profile)?;
-        out.put_u16(self.payload.len() as u16)?;
-        //out.put_bytes(self.payload.as_slice())?;
-
-        for item in &self.payload {
-            out.put_u32(*item)?;
+        let payload = self.payload();
+        if payload.len() / 4 > 0xFFFF {
+            return Err(RtpError::InvalidPacketHeaderExtensionSize);
         }
 
+        out.put_u16(self.profile())?;
+        out.put_u16((payload.len() / 4) as u16)?;
+        out.put_bytes(&payload)?;
+
         Ok(())
     }
 
@@ -189,37 +521,88 @@ impl RtpHeaderExtension {
         let profile = bytes.get_u16()?;
         let length = bytes.get_u16()?;
 
-        //let payload = bytes.get_bytes(length as usize * 4)?.to_vec();
-
-        //let payload = bytes.get_bytes(length as usize * 4)?;
-
-        let mut payload = vec![];
-        for _ in 0..length {
-            payload.push(bytes.get_u32()?);
+        if bytes.cap() < length as usize * 4 {
+            return Err(RtpError::InvalidPacketHeaderExtensionSize);
         }
 
-        Ok(RtpHeaderExtension { profile, payload })
+        let payload = bytes.get_bytes(length as usize * 4)?.to_vec();
+
+        RtpHeaderExtension::with_payload(profile, payload)
     }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct RtpHeader {
-    version: u8, // 2bit default value is 2
+    pub(crate) version: u8, // 2bit default value is 2
     //padding : bool, // 1bit
-    padding: Option<u8>,
+    pub(crate) padding: Option<u8>,
     //extension : bool, // 1bit
-    extension: Option<RtpHeaderExtension>,
+    pub(crate) extension: Option<RtpHeaderExtension>,
     //csrc_count : u8, // 4bit count of CSRC
-    marker: bool,     // 1bit
-    payload_type: u8, // 7bit
-    sequence_number: u16,
-    timestamp: u32,
-    ssrc: u32,
+    pub(crate) marker: bool,     // 1bit
+    pub(crate) payload_type: u8, // 7bit
+    pub(crate) sequence_number: u16,
+    pub(crate) timestamp: u32,
+    pub(crate) ssrc: u32,
     //csrc : Vec<u8>,
-    csrc: Vec<u32>,
+    pub(crate) csrc: Vec<u32>,
 }
 
 impl RtpHeader {
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn padding(&self) -> Option<u8> {
+        self.padding
+    }
+
+    pub fn extension(&self) -> Option<&RtpHeaderExtension> {
+        self.extension.as_ref()
+    }
+
+    // 転送時に一部のextensionだけを書き換える場合に使う
+    pub fn extension_mut(&mut self) -> Option<&mut RtpHeaderExtension> {
+        self.extension.as_mut()
+    }
+
+    pub fn set_extension(&mut self, extension: Option<RtpHeaderExtension>) {
+        self.extension = extension;
+    }
+
+    pub fn marker(&self) -> bool {
+        self.marker
+    }
+
+    pub fn payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    pub fn sequence_number(&self) -> u16 {
+        self.sequence_number
+    }
+
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    pub fn csrc(&self) -> &[u32] {
+        &self.csrc
+    }
+
+    // to_bytesで書き込まれるbyte数
+    pub fn marshal_size(&self) -> usize {
+        let extension_size = match self.extension {
+            Some(ref v) => v.marshal_size(),
+            None => 0,
+        };
+        12 + self.csrc.len() * 4 + extension_size
+    }
+
     // 構造体に代入されたデータをBinaryに変換
     pub fn to_bytes(&self, out: &mut octets::Octets) -> Result<()> {
         let csrc_count = self.csrc.len() as u8;
@@ -278,7 +661,7 @@ impl RtpHeader {
         };
 
         let extension = (first & 0b00010000) > 0;
-        let csrc_count = first & 0b00000111;
+        let csrc_count = first & 0b00001111;
 
         let second = bytes.get_u8().map_err(|_| RtpError::PacketHeaderTooShort)?;
 
@@ -318,10 +701,8 @@ impl RtpHeader {
             });
         }
 
-        //extension offset = header bytes length exclude headers extension.
-        let extension_offset = ((3 + csrc_count) * 4) as usize; // 32bit row buffer * 4
-
-        if bytes.cap() < (extension_offset) {
+        // extension header (profile + length) must follow CSRCs.
+        if bytes.cap() < 4 {
             return Err(RtpError::InvalidPacketHeaderExtensionSize);
         }
 
@@ -341,12 +722,29 @@ impl RtpHeader {
 
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct RtpPacket {
-    header: RtpHeader,
+    pub(crate) header: RtpHeader,
     //padding_length : u8,
-    payload: Vec<u8>, // byte array : data
+    pub(crate) payload: Vec<u8>, // byte array : data
 }
 
 impl RtpPacket {
+    pub fn header(&self) -> &RtpHeader {
+        &self.header
+    }
+
+    pub fn header_mut(&mut self) -> &mut RtpHeader {
+        &mut self.header
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    // to_bytesで書き込まれるbyte数 (paddingを含む)
+    pub fn marshal_size(&self) -> usize {
+        self.header.marshal_size() + self.payload.len() + self.header.padding.unwrap_or(0) as usize
+    }
+
     pub fn to_bytes(&self, out: &mut octets::Octets) -> Result<()> {
         self.header.to_bytes(out)?;
 
@@ -355,6 +753,10 @@ impl RtpPacket {
         }
 
         if let Some(padding_length) = self.header.padding {
+            // padding lengthは自身を含むので0にはならない
+            if padding_length == 0 {
+                return Err(RtpError::InvalidPacketPaddingLength);
+            }
             for _ in 0..(padding_length as usize - 1) {
                 out.put_u8(0)?; // padding
             }
@@ -374,7 +776,7 @@ impl RtpPacket {
 
         let payload = match header.padding {
             Some(v) => {
-                if v as usize > bytes.cap() {
+                if v == 0 || v as usize > bytes.cap() {
                     return Err(RtpError::InvalidPacketPaddingLength);
                 }
                 bytes.get_bytes(bytes.cap() - v as usize)?.to_vec()
@@ -386,6 +788,136 @@ impl RtpPacket {
     }
 }
 
+// 送信用のRtpPacketを組み立てる．build時にheaderの値を検証する．
+#[derive(Debug, Clone)]
+pub struct RtpPacketBuilder {
+    version: u8,
+    padding: Option<u8>,
+    extension: Option<(u16, Vec<u8>)>,
+    marker: bool,
+    payload_type: u8,
+    sequence_number: u16,
+    timestamp: u32,
+    ssrc: u32,
+    csrc: Vec<u32>,
+    payload: Vec<u8>,
+}
+
+impl Default for RtpPacketBuilder {
+    fn default() -> Self {
+        RtpPacketBuilder::new()
+    }
+}
+
+impl RtpPacketBuilder {
+    pub fn new() -> RtpPacketBuilder {
+        RtpPacketBuilder {
+            version: 2,
+            padding: None,
+            extension: None,
+            marker: false,
+            payload_type: 0,
+            sequence_number: 0,
+            timestamp: 0,
+            ssrc: 0,
+            csrc: vec![],
+            payload: vec![],
+        }
+    }
+
+    pub fn version(mut self, version: u8) -> Self {
+        self.version = version;
+        self
+    }
+
+    // padding_lengthは最後のpadding length byte自身を含む
+    pub fn padding(mut self, padding_length: u8) -> Self {
+        self.padding = Some(padding_length);
+        self
+    }
+
+    // payloadは32bit境界までpaddingされたextension (HeaderExtensionsMap::setの出力)
+    pub fn extension(mut self, profile: u16, payload: Vec<u8>) -> Self {
+        self.extension = Some((profile, payload));
+        self
+    }
+
+    pub fn header_extension(mut self, extension: &RtpHeaderExtension) -> Self {
+        self.extension = Some((extension.profile(), extension.payload()));
+        self
+    }
+
+    pub fn marker(mut self, marker: bool) -> Self {
+        self.marker = marker;
+        self
+    }
+
+    pub fn payload_type(mut self, payload_type: u8) -> Self {
+        self.payload_type = payload_type;
+        self
+    }
+
+    pub fn sequence_number(mut self, sequence_number: u16) -> Self {
+        self.sequence_number = sequence_number;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u32) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn ssrc(mut self, ssrc: u32) -> Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    pub fn csrc(mut self, csrc: Vec<u32>) -> Self {
+        self.csrc = csrc;
+        self
+    }
+
+    pub fn payload(mut self, payload: Vec<u8>) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    pub fn build(self) -> Result<RtpPacket> {
+        if self.version != 2 {
+            return Err(RtpError::InvalidPacketVersion);
+        }
+        if self.csrc.len() > 15 {
+            return Err(RtpError::TooManyCsrc);
+        }
+        if self.payload_type > 0x7F {
+            return Err(RtpError::InvalidPayloadType);
+        }
+        if self.padding == Some(0) {
+            return Err(RtpError::InvalidPacketPaddingLength);
+        }
+
+        let extension = match self.extension {
+            Some((profile, payload)) => Some(RtpHeaderExtension::with_payload(profile, payload)?),
+            None => None,
+        };
+
+        Ok(RtpPacket {
+            header: RtpHeader {
+                version: self.version,
+                padding: self.padding,
+                extension,
+                marker: self.marker,
+                payload_type: self.payload_type,
+                sequence_number: self.sequence_number,
+                timestamp: self.timestamp,
+                ssrc: self.ssrc,
+                csrc: self.csrc,
+            },
+            payload: self.payload,
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -405,10 +937,9 @@ mod test {
         let parsed_header = RtpHeader {
             version: 2,
             padding: None,
-            extension: Option::Some(RtpHeaderExtension {
-                profile: 1,
-                payload: vec![0xFFFFFFFF], //vec![0xFF, 0xFF, 0xFF, 0xFF],
-            }),
+            extension: Option::Some(
+                RtpHeaderExtension::with_payload(1, vec![0xFF, 0xFF, 0xFF, 0xFF]).unwrap(),
+            ),
             marker: true,
             payload_type: 96,
             sequence_number: 27023,
@@ -419,13 +950,13 @@ mod test {
         //assert_eq!(header, parsed_header);
 
         let mut pack_buf = [0; 1500];
-        let mut offset = 0;
-        {
+        let offset = {
             let mut packed_octets = octets::Octets::with_slice(&mut pack_buf);
-            parsed_header.to_bytes(&mut packed_octets);
-            offset = packed_octets.off();
-        }
+            parsed_header.to_bytes(&mut packed_octets).unwrap();
+            packed_octets.off()
+        };
 
+        assert_eq!(offset, 20);
         assert_eq!(raw_packet[..offset], pack_buf[..offset]);
     }
 
@@ -469,10 +1000,9 @@ mod test {
             header: RtpHeader {
                 version: 2,
                 padding: None,
-                extension: Option::Some(RtpHeaderExtension {
-                    profile: 1,
-                    payload: vec![0xFFFFFFFF], //vec![0xFF, 0xFF, 0xFF, 0xFF],
-                }),
+                extension: Option::Some(
+                    RtpHeaderExtension::with_payload(1, vec![0xFF, 0xFF, 0xFF, 0xFF]).unwrap(),
+                ),
                 marker: true,
                 payload_type: 96,
                 sequence_number: 27023,
@@ -485,13 +1015,13 @@ mod test {
         assert_eq!(packet, parsed_packet);
 
         let mut pack_buf = [0; 1500];
-        let mut offset = 0;
-        {
+        let offset = {
             let mut packed_octets = octets::Octets::with_slice(&mut pack_buf);
-            parsed_packet.to_bytes(&mut packed_octets);
-            offset = packed_octets.off();
-        }
+            parsed_packet.to_bytes(&mut packed_octets).unwrap();
+            packed_octets.off()
+        };
 
+        assert_eq!(offset, raw_packet.len());
         assert_eq!(raw_packet, pack_buf[..offset]);
     }
 
@@ -517,4 +1047,428 @@ mod test {
 
         assert!(RtpPacket::from_bytes(&mut invalid_length_octets).is_err());
     }
+
+    fn extensions_map() -> HeaderExtensionsMap {
+        use crate::rtcrtpparameters::*;
+
+        let uris = [
+            "urn:ietf:params:rtp-hdrext:sdes:mid",
+            "urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id",
+            "urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id",
+            "http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time",
+            "urn:ietf:params:rtp-hdrext:toffset",
+            "urn:ietf:params:rtp-hdrext:ssrc-audio-level",
+            "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01",
+            "urn:3gpp:video-orientation",
+        ];
+
+        let param = RtcRtpParameter {
+            codecs: vec![],
+            header_extensions: uris
+                .iter()
+                .enumerate()
+                .map(|(i, uri)| RtcRtpHeaderExtensionParameters {
+                    id: i + 1,
+                    uri: uri.to_string(),
+                })
+                .collect(),
+            mux_id: "".to_string(),
+            rtcp: RtcRtcpParameters {
+                cname: None,
+                mux: true,
+                ssrc: None,
+            },
+        };
+
+        let mut map = HeaderExtensionsMap::new();
+        map.configure(&param);
+        map
+    }
+
+    #[test]
+    fn header_extensions_map_get_test() {
+        let map = extensions_map();
+
+        let mut raw = [
+            0x10, 0x30, // mid = "0"
+            0x42, 0x12, 0x34, 0x56, // abs-send-time
+            0x52, 0xFF, 0xFF, 0xFE, // toffset = -2
+            0x60, 0x9E, // audio level: voice activity, 30 -dBov
+            0x71, 0x01, 0x02, // transport-wide sequence number
+            0x80, 0xAA, // video orientation (not supported)
+            0x90, 0xBB, // unknown ID
+            0x00, // padding
+        ];
+        let mut raw_octets = octets::Octets::with_slice(&mut raw);
+
+        let values = map.get(&mut raw_octets, 0xBEDE).unwrap();
+        assert_eq!(
+            values,
+            HeaderExtensions {
+                abs_send_time: Some(0x123456),
+                audio_level: Some((true, 30)),
+                mid: Some("0".to_string()),
+                repaired_rtp_stream_id: None,
+                rtp_stream_id: None,
+                transmission_offset: Some(-2),
+                transport_sequence_number: Some(0x0102),
+            }
+        );
+        assert_eq!(
+            values.abs_send_time_secs(),
+            Some(0x123456 as f64 / 262144.0)
+        );
+
+        // 不明なprofile
+        let mut raw = [0x10, 0x30, 0x00, 0x00];
+        let mut raw_octets = octets::Octets::with_slice(&mut raw);
+        assert_eq!(
+            map.get(&mut raw_octets, 0x1234),
+            Err(RtpError::InvalidHeaderExtensionProfile)
+        );
+
+        // ID=15以降は読まない
+        let mut raw = [0x10, 0x30, 0xF0, 0x71, 0x01, 0x02, 0x00, 0x00];
+        let mut raw_octets = octets::Octets::with_slice(&mut raw);
+        let values = map.get(&mut raw_octets, 0xBEDE).unwrap();
+        assert_eq!(values.mid, Some("0".to_string()));
+        assert_eq!(values.transport_sequence_number, None);
+    }
+
+    #[test]
+    fn header_extensions_map_set_test() {
+        let map = extensions_map();
+
+        let values = HeaderExtensions {
+            abs_send_time: Some(0x123456),
+            audio_level: Some((false, 127)),
+            mid: Some("audio".to_string()),
+            repaired_rtp_stream_id: None,
+            rtp_stream_id: Some("hi".to_string()),
+            transmission_offset: Some(-100),
+            transport_sequence_number: Some(65535),
+        };
+
+        let (profile, mut raw) = map.set(&values).unwrap();
+        assert_eq!(profile, 0xBEDE);
+        assert_eq!(raw.len() % 4, 0);
+        assert_eq!(&raw[..6], &[0x14, b'a', b'u', b'd', b'i', b'o']);
+
+        let mut raw_octets = octets::Octets::with_slice(&mut raw);
+        assert_eq!(map.get(&mut raw_octets, profile).unwrap(), values);
+
+        // 16bytesを超える値はtwo-byte headerになる
+        let values = HeaderExtensions {
+            mid: Some("a-very-long-media-id".to_string()),
+            audio_level: Some((true, 10)),
+            ..Default::default()
+        };
+
+        let (profile, mut raw) = map.set(&values).unwrap();
+        assert_eq!(profile, 0x1000);
+        assert_eq!(&raw[..3], &[0x01, 20, b'a']);
+        assert_eq!(&raw[22..24], &[0x06, 0x01]);
+        assert_eq!(raw.len(), 28);
+
+        let mut raw_octets = octets::Octets::with_slice(&mut raw);
+        assert_eq!(map.get(&mut raw_octets, profile).unwrap(), values);
+
+        assert_eq!(HeaderExtensionsMap::new().set(&values), Ok((0, vec![])));
+
+        // 255bytesを超える値はtwo-byte headerでも書けない
+        let values = HeaderExtensions {
+            mid: Some("a".repeat(256)),
+            ..Default::default()
+        };
+        assert_eq!(
+            map.set(&values),
+            Err(RtpError::InvalidHeaderExtensionElement)
+        );
+        let values = HeaderExtensions {
+            mid: Some("a".repeat(255)),
+            ..Default::default()
+        };
+        let (profile, raw) = map.set(&values).unwrap();
+        assert_eq!(profile, 0x1000);
+        assert_eq!(&raw[..2], &[0x01, 255]);
+
+        // 空の値はtwo-byte headerになる
+        let values = HeaderExtensions {
+            mid: Some(String::new()),
+            ..Default::default()
+        };
+        assert_eq!(map.set(&values), Ok((0x1000, vec![0x01, 0x00, 0x00, 0x00])));
+    }
+
+    #[test]
+    fn rtp_packet_builder_test() {
+        let (profile, extension) = extensions_map()
+            .set(&HeaderExtensions {
+                mid: Some("0".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let packet = RtpPacketBuilder::new()
+            .marker(true)
+            .payload_type(111)
+            .sequence_number(27023)
+            .timestamp(3653407706)
+            .ssrc(476325762)
+            .csrc(vec![1, 2])
+            .extension(profile, extension)
+            .payload(vec![0x98, 0x36, 0xbe, 0x88, 0x9e])
+            .padding(3)
+            .build()
+            .unwrap();
+
+        assert_eq!(packet.header().version(), 2);
+        assert!(packet.header().marker());
+        assert_eq!(packet.header().payload_type(), 111);
+        assert_eq!(packet.header().sequence_number(), 27023);
+        assert_eq!(packet.header().timestamp(), 3653407706);
+        assert_eq!(packet.header().ssrc(), 476325762);
+        assert_eq!(packet.header().csrc(), &[1, 2]);
+        assert_eq!(packet.header().padding(), Some(3));
+        assert_eq!(packet.payload(), &[0x98, 0x36, 0xbe, 0x88, 0x9e]);
+
+        let extension = packet.header().extension().unwrap();
+        assert_eq!(extension.profile(), 0xBEDE);
+        assert_eq!(extension.payload(), vec![0x10, 0x30, 0x00, 0x00]);
+
+        // 12 + csrc 8 + extension 8 + payload 5 + padding 3
+        assert_eq!(packet.marshal_size(), 36);
+
+        let mut buf = vec![0u8; packet.marshal_size()];
+        {
+            let mut out = octets::Octets::with_slice(&mut buf);
+            packet.to_bytes(&mut out).unwrap();
+            assert_eq!(out.cap(), 0);
+        }
+
+        let parsed = RtpPacket::from_slice(&mut buf).unwrap();
+        assert_eq!(parsed, packet);
+    }
+
+    #[test]
+    fn rtp_packet_builder_invalid_test() {
+        assert_eq!(
+            RtpPacketBuilder::new().version(1).build(),
+            Err(RtpError::InvalidPacketVersion)
+        );
+        assert_eq!(
+            RtpPacketBuilder::new().csrc(vec![0; 16]).build(),
+            Err(RtpError::TooManyCsrc)
+        );
+        assert_eq!(
+            RtpPacketBuilder::new().payload_type(128).build(),
+            Err(RtpError::InvalidPayloadType)
+        );
+        assert_eq!(
+            RtpPacketBuilder::new().padding(0).build(),
+            Err(RtpError::InvalidPacketPaddingLength)
+        );
+        assert_eq!(
+            RtpPacketBuilder::new()
+                .extension(0xBEDE, vec![0x10, 0x30])
+                .build(),
+            Err(RtpError::InvalidPacketHeaderExtensionSize)
+        );
+
+        // 15 CSRCはparseできる
+        let packet = RtpPacketBuilder::new()
+            .csrc((0..15).collect())
+            .payload(vec![0xAA])
+            .build()
+            .unwrap();
+        let mut buf = vec![0u8; packet.marshal_size()];
+        packet
+            .to_bytes(&mut octets::Octets::with_slice(&mut buf))
+            .unwrap();
+        assert_eq!(RtpPacket::from_slice(&mut buf).unwrap(), packet);
+    }
+
+    #[test]
+    fn rtp_header_extension_elements_test() {
+        let mut raw = [
+            0xBE, 0xDE, 0x00, 0x02, // profile, length
+            0x10, 0x30, // id 1 = "0"
+            0x71, 0x01, 0x02, // id 7
+            0x00, 0x00, 0x00, // padding
+        ];
+        let mut raw_octets = octets::Octets::with_slice(&mut raw);
+        let mut extension = RtpHeaderExtension::from_bytes(&mut raw_octets).unwrap();
+
+        assert!(!extension.is_opaque());
+        assert_eq!(extension.profile(), 0xBEDE);
+        assert_eq!(extension.get(1), Some(&b"0"[..]));
+        assert_eq!(extension.get(7), Some(&[0x01, 0x02][..]));
+        assert_eq!(extension.get(2), None);
+
+        // 値を置き換えても順番は変わらない
+        extension.set(1, b"ab".to_vec()).unwrap();
+        assert_eq!(
+            extension.payload(),
+            vec![0x11, b'a', b'b', 0x71, 0x01, 0x02, 0x00, 0x00]
+        );
+        assert_eq!(extension.marshal_size(), 12);
+
+        // one-byte headerで表せないelementを追加するとtwo-byte headerになる
+        extension.set(20, vec![]).unwrap();
+        assert_eq!(extension.profile(), 0x1000);
+        assert_eq!(
+            extension.payload(),
+            vec![0x01, 0x02, b'a', b'b', 0x07, 0x02, 0x01, 0x02, 20, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(extension.marshal_size(), 16);
+
+        assert_eq!(extension.remove(20), Some(vec![]));
+        assert_eq!(extension.remove(20), None);
+        assert_eq!(extension.profile(), 0xBEDE);
+
+        assert_eq!(
+            extension.set(0, vec![0x00]),
+            Err(RtpError::InvalidHeaderExtensionElement)
+        );
+        assert_eq!(
+            extension.set(1, vec![0x00; 256]),
+            Err(RtpError::InvalidHeaderExtensionElement)
+        );
+
+        let mut buf = vec![0u8; extension.marshal_size()];
+        extension
+            .to_bytes(&mut octets::Octets::with_slice(&mut buf))
+            .unwrap();
+        assert_eq!(
+            buf,
+            vec![0xBE, 0xDE, 0x00, 0x02, 0x11, b'a', b'b', 0x71, 0x01, 0x02, 0x00, 0x00]
+        );
+
+        // 空のextension
+        let extension = RtpHeaderExtension::new();
+        assert_eq!(extension.marshal_size(), 4);
+        assert_eq!(extension.payload(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn rtp_header_extension_profile_test() {
+        // two-byte headerのappbitsは保持する
+        let mut extension =
+            RtpHeaderExtension::with_payload(0x1003, vec![0x01, 0x01, 0x30, 0x00]).unwrap();
+        assert_eq!(extension.get(1), Some(&b"0"[..]));
+        assert_eq!(extension.profile(), 0x1003);
+        extension.set(2, vec![0xAA]).unwrap();
+        assert_eq!(extension.profile(), 0x1003);
+        assert_eq!(
+            extension.payload(),
+            vec![0x01, 0x01, 0x30, 0x02, 0x01, 0xAA, 0x00, 0x00]
+        );
+
+        // 不明なprofileはopaqueなデータとして保持する
+        let mut extension =
+            RtpHeaderExtension::with_payload(0x0001, vec![0xFF, 0xFF, 0xFF, 0xFF]).unwrap();
+        assert!(extension.is_opaque());
+        assert_eq!(extension.get(15), None);
+        assert!(extension.elements().is_empty());
+        assert_eq!(
+            extension.set(1, vec![0x00]),
+            Err(RtpError::InvalidHeaderExtensionProfile)
+        );
+        assert_eq!(extension.remove(1), None);
+        assert_eq!(extension.profile(), 0x0001);
+        assert_eq!(extension.payload(), vec![0xFF, 0xFF, 0xFF, 0xFF]);
+
+        assert_eq!(
+            RtpHeaderExtension::with_payload(0xBEDE, vec![0x10, 0x30]),
+            Err(RtpError::InvalidPacketHeaderExtensionSize)
+        );
+
+        // ID=15以降のデータも失わずに送り返せる
+        let payload = vec![0x10, 0x30, 0xF0, 0x71, 0x01, 0x02, 0x00, 0x00];
+        let extension = RtpHeaderExtension::with_payload(0xBEDE, payload.clone()).unwrap();
+        assert!(extension.is_opaque());
+        assert_eq!(extension.profile(), 0xBEDE);
+        assert_eq!(extension.payload(), payload);
+    }
+
+    #[test]
+    fn rtp_packet_padding_test() {
+        // padding lengthが0のpacketは不正
+        let mut raw = [
+            0xA0, 0x60, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0xAA, 0x00,
+            0x00, 0x00,
+        ];
+        assert_eq!(
+            RtpPacket::from_slice(&mut raw),
+            Err(RtpError::InvalidPacketPaddingLength)
+        );
+
+        let mut packet = RtpPacketBuilder::new()
+            .padding(2)
+            .payload(vec![0xAA])
+            .build()
+            .unwrap();
+        packet.header.padding = Some(0);
+        let mut buf = vec![0u8; 32];
+        assert_eq!(
+            packet.to_bytes(&mut octets::Octets::with_slice(&mut buf)),
+            Err(RtpError::InvalidPacketPaddingLength)
+        );
+    }
+
+    #[test]
+    fn header_extensions_map_rewrite_test() {
+        let map = extensions_map();
+
+        let (profile, extension) = map
+            .set(&HeaderExtensions {
+                mid: Some("0".to_string()),
+                transport_sequence_number: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        let mut extension = RtpHeaderExtension::with_payload(profile, extension).unwrap();
+        // map に無いextension
+        extension.set(8, vec![0x03]).unwrap();
+
+        let packet = RtpPacketBuilder::new()
+            .payload_type(96)
+            .header_extension(&extension)
+            .payload(vec![0x01])
+            .build()
+            .unwrap();
+        let mut buf = vec![0u8; packet.marshal_size()];
+        packet
+            .to_bytes(&mut octets::Octets::with_slice(&mut buf))
+            .unwrap();
+
+        // transport-wide sequence numberだけを書き換えて転送する
+        let mut packet = RtpPacket::from_slice(&mut buf).unwrap();
+        let values = HeaderExtensions {
+            transport_sequence_number: Some(0x1234),
+            ..Default::default()
+        };
+        map.write(&values, packet.header_mut().extension_mut().unwrap())
+            .unwrap();
+
+        let mut buf = vec![0u8; packet.marshal_size()];
+        packet
+            .to_bytes(&mut octets::Octets::with_slice(&mut buf))
+            .unwrap();
+        let packet = RtpPacket::from_slice(&mut buf).unwrap();
+        let extension = packet.header().extension().unwrap();
+
+        let values = map.read(extension);
+        assert_eq!(values.mid, Some("0".to_string()));
+        assert_eq!(values.transport_sequence_number, Some(0x1234));
+        assert_eq!(extension.get(8), Some(&[0x03][..]));
+        assert_eq!(
+            extension
+                .elements()
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>(),
+            vec![1, 7, 8]
+        );
+    }
 }
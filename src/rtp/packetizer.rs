@@ -1,31 +1,80 @@
-use crate::octets;
-use crate::rtp::packet::RtpHeader;
+use crate::rtp::codecs::Payloader;
+use crate::rtp::packet::{RtpHeader, RtpPacket};
 use rand::Rng;
 
+// RTP Header(12bytes)を除いた部分がPayloadに使える
+const RTP_HEADER_SIZE: usize = 12;
+
 // Payloadの詰め込みと新規StreamのSSRC発行などを行う．
-struct RtpPacketizer {
+pub struct RtpPacketizer {
     mtu: usize,
     payload_type: u8,
     ssrc: u32,
+    sequence_number: u16,
     timestamp: u32,
     clock_rate: u32,
+    payloader: Box<dyn Payloader>,
 }
 
 impl RtpPacketizer {
     // NewPacketizer returns a new instance of a Packetizer for a specific payloader
-    pub fn new(mtu: usize, payload_type: u8, ssrc: u32, clock_rate: u32) -> RtpPacketizer {
+    pub fn new(
+        mtu: usize,
+        payload_type: u8,
+        ssrc: u32,
+        payloader: Box<dyn Payloader>,
+        clock_rate: u32,
+    ) -> RtpPacketizer {
         let mut rng = rand::thread_rng();
         RtpPacketizer {
             mtu,
             payload_type,
             ssrc,
+            sequence_number: rng.gen(),
             timestamp: rng.gen(),
             clock_rate,
+            payloader,
         }
     }
 
-    pub fn pack() -> () {
-        ()
+    pub fn clock_rate(&self) -> u32 {
+        self.clock_rate
+    }
+
+    // frameをmtuに収まるRtpPacketに分割する．
+    // 最後のfragmentにはmarker bitを立て，timestampはsamples分だけ進める．
+    pub fn pack(&mut self, frame: &[u8], samples: u32) -> Vec<RtpPacket> {
+        if frame.is_empty() || self.mtu <= RTP_HEADER_SIZE {
+            return vec![];
+        }
+
+        let payloads = self.payloader.payload(self.mtu - RTP_HEADER_SIZE, frame);
+        let last = payloads.len().saturating_sub(1);
+
+        let packets = payloads
+            .into_iter()
+            .enumerate()
+            .map(|(i, payload)| {
+                let header = RtpHeader {
+                    version: 2,
+                    padding: None,
+                    extension: None,
+                    marker: i == last,
+                    payload_type: self.payload_type,
+                    sequence_number: self.sequence_number,
+                    timestamp: self.timestamp,
+                    ssrc: self.ssrc,
+                    csrc: vec![],
+                };
+                self.sequence_number = self.sequence_number.wrapping_add(1);
+
+                RtpPacket { header, payload }
+            })
+            .collect();
+
+        self.timestamp = self.timestamp.wrapping_add(samples);
+
+        packets
     }
 }
 
@@ -33,6 +82,15 @@ impl RtpPacketizer {
 mod test {
     use super::*;
 
+    // 先頭から順にmtuごとに切り出すだけのPayloader
+    struct ChunkPayloader;
+
+    impl Payloader for ChunkPayloader {
+        fn payload(&mut self, mtu: usize, frame: &[u8]) -> Vec<Vec<u8>> {
+            frame.chunks(mtu).map(|c| c.to_vec()).collect()
+        }
+    }
+
     #[test]
     fn rand() {
         let mut rng = rand::thread_rng();
@@ -41,4 +99,64 @@ mod test {
 
         assert_ne!(rand1, rand2)
     }
+
+    #[test]
+    fn pack_single_packet_test() {
+        let mut packetizer =
+            RtpPacketizer::new(100, 98, 0x1234ABCD, Box::new(ChunkPayloader), 90000);
+        let first_seq = packetizer.sequence_number;
+        let first_ts = packetizer.timestamp;
+
+        let packets = packetizer.pack(&[0x11, 0x12, 0x13, 0x14], 2000);
+
+        assert_eq!(packets.len(), 1);
+        let header = &packets[0].header;
+        assert_eq!(header.version, 2);
+        assert!(header.marker);
+        assert_eq!(header.payload_type, 98);
+        assert_eq!(header.ssrc, 0x1234ABCD);
+        assert_eq!(header.sequence_number, first_seq);
+        assert_eq!(header.timestamp, first_ts);
+        assert_eq!(packets[0].payload, vec![0x11, 0x12, 0x13, 0x14]);
+
+        assert_eq!(packetizer.sequence_number, first_seq.wrapping_add(1));
+        assert_eq!(packetizer.timestamp, first_ts.wrapping_add(2000));
+    }
+
+    #[test]
+    fn pack_fragmented_test() {
+        let mut packetizer =
+            RtpPacketizer::new(RTP_HEADER_SIZE + 4, 96, 1, Box::new(ChunkPayloader), 90000);
+        packetizer.sequence_number = 0xFFFF;
+        packetizer.timestamp = 0xFFFF_FFFF;
+
+        let frame: Vec<u8> = (0..10).collect();
+        let packets = packetizer.pack(&frame, 3000);
+
+        assert_eq!(packets.len(), 3);
+        assert_eq!(
+            packets
+                .iter()
+                .map(|p| p.header.sequence_number)
+                .collect::<Vec<_>>(),
+            vec![0xFFFF, 0, 1]
+        );
+        assert_eq!(
+            packets.iter().map(|p| p.header.marker).collect::<Vec<_>>(),
+            vec![false, false, true]
+        );
+        assert!(packets.iter().all(|p| p.header.timestamp == 0xFFFF_FFFF));
+        assert_eq!(packets[2].payload, vec![8, 9]);
+
+        // 次のframeはsamples分進んだtimestampを使う
+        let packets = packetizer.pack(&[0xAA], 3000);
+        assert_eq!(packets[0].header.sequence_number, 2);
+        assert_eq!(packets[0].header.timestamp, 2999);
+    }
+
+    #[test]
+    fn pack_empty_frame_test() {
+        let mut packetizer = RtpPacketizer::new(100, 96, 1, Box::new(ChunkPayloader), 90000);
+        assert!(packetizer.pack(&[], 3000).is_empty());
+    }
 }
@@ -0,0 +1,405 @@
+use crate::rtp::codecs::Depacketizer;
+use crate::rtp::packet::RtpPacket;
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+// sequence numberのwraparoundを考慮して，aからbまでの距離を返す
+fn seqnum_distance(a: u16, b: u16) -> u16 {
+    b.wrapping_sub(a)
+}
+
+// aがbよりも前のsequence numberかどうか
+fn seqnum_is_before(a: u16, b: u16) -> bool {
+    a != b && seqnum_distance(a, b) < 0x8000
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub data: Vec<u8>,
+    // frameのRTP timestamp
+    pub timestamp: u32,
+    // 次のframeまでの時間
+    pub duration: Duration,
+    // このsampleの前に捨てられたpacket数とframe数
+    pub prev_dropped_packets: usize,
+    pub prev_dropped_frames: usize,
+}
+
+// Out of orderなRtpPacketを受け取り，Depacketizerでframeを組み立てる．
+pub struct SampleBuilder {
+    // 最新のpacketとheadのRTP timestampの差がこれを超えたら古いframeを捨てる
+    max_late: u32,
+    clock_rate: u32,
+    depacketizer: Box<dyn Depacketizer>,
+
+    buffer: HashMap<u16, RtpPacket>,
+    // 保持しているsequence numberの範囲 [head, tail)
+    range: Option<(u16, u16)>,
+    // tailのpacketのRTP timestamp
+    latest_timestamp: u32,
+
+    ready: VecDeque<Sample>,
+    dropped_packets: usize,
+    dropped_frames: usize,
+}
+
+impl SampleBuilder {
+    // max_latencyだけ待っても揃わないframeは捨てる
+    pub fn new(
+        max_latency: Duration,
+        depacketizer: Box<dyn Depacketizer>,
+        clock_rate: u32,
+    ) -> Self {
+        let max_late = max_latency.as_nanos() * u128::from(clock_rate) / 1_000_000_000;
+        SampleBuilder {
+            max_late: max_late.min(u128::from(u32::MAX / 2)) as u32,
+            clock_rate,
+            depacketizer,
+            buffer: HashMap::new(),
+            range: None,
+            latest_timestamp: 0,
+            ready: VecDeque::new(),
+            dropped_packets: 0,
+            dropped_frames: 0,
+        }
+    }
+
+    pub fn push(&mut self, packet: RtpPacket) {
+        let seq = packet.header.sequence_number;
+
+        match self.range {
+            None => {
+                self.range = Some((seq, seq.wrapping_add(1)));
+                self.latest_timestamp = packet.header.timestamp;
+            }
+            Some((head, tail)) => {
+                if seqnum_is_before(seq, head) || self.buffer.contains_key(&seq) {
+                    // 既に出力済みか，重複したpacket
+                    return;
+                }
+                if !seqnum_is_before(seq, tail) {
+                    self.range = Some((head, seq.wrapping_add(1)));
+                    self.latest_timestamp = packet.header.timestamp;
+                }
+            }
+        }
+        self.buffer.insert(seq, packet);
+
+        self.build_samples();
+
+        while let Some(head_timestamp) = self.head_timestamp() {
+            if self.latest_timestamp.wrapping_sub(head_timestamp) <= self.max_late {
+                break;
+            }
+            self.drop_head_frame();
+            self.build_samples();
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Sample> {
+        self.ready.pop_front()
+    }
+
+    // headから見て最初に届いているpacketのtimestamp
+    fn head_timestamp(&self) -> Option<u32> {
+        let (mut seq, tail) = self.range?;
+        while seq != tail {
+            if let Some(packet) = self.buffer.get(&seq) {
+                return Some(packet.header.timestamp);
+            }
+            seq = seq.wrapping_add(1);
+        }
+        None
+    }
+
+    // headから始まるframeを捨てる
+    fn drop_head_frame(&mut self) {
+        let (mut head, tail) = match self.range {
+            Some(v) => v,
+            None => return,
+        };
+
+        let timestamp = match self.buffer.get(&head) {
+            Some(p) => p.header.timestamp,
+            None => {
+                // 届かなかったpacket
+                self.dropped_packets += 1;
+                self.range = Some((head.wrapping_add(1), tail));
+                return;
+            }
+        };
+
+        while head != tail {
+            match self.buffer.get(&head) {
+                Some(p) if p.header.timestamp == timestamp => {
+                    self.buffer.remove(&head);
+                    self.dropped_packets += 1;
+                    head = head.wrapping_add(1);
+                }
+                _ => break,
+            }
+        }
+
+        self.dropped_frames += 1;
+        self.range = Some((head, tail));
+    }
+
+    // 揃っているframeを全てreadyに移す
+    fn build_samples(&mut self) {
+        while let Some(sample) = self.build_sample() {
+            self.ready.push_back(sample);
+        }
+    }
+
+    fn build_sample(&mut self) -> Option<Sample> {
+        // 完成しないframeを捨てながら次のframeを探す
+        loop {
+            let (head, tail) = self.range?;
+
+            let first = self.buffer.get(&head)?;
+            let timestamp = first.header.timestamp;
+
+            if !self.depacketizer.is_partition_head(&first.payload) {
+                // frameの先頭が失われているので，このframeは完成しない
+                self.drop_head_frame();
+                continue;
+            }
+
+            // frameの最後のpacketと，次のframeのtimestampを探す
+            let mut end = head;
+            let mut found_tail = false;
+            let next_timestamp = loop {
+                let packet = self.buffer.get(&end)?;
+                if packet.header.timestamp != timestamp {
+                    break packet.header.timestamp;
+                }
+                if found_tail {
+                    // tailの次のpacketが同じtimestampを持つ場合はdurationを0とする
+                    break timestamp;
+                }
+                if self
+                    .depacketizer
+                    .is_partition_tail(packet.header.marker, &packet.payload)
+                {
+                    found_tail = true;
+                }
+                end = end.wrapping_add(1);
+                if end == tail {
+                    return None;
+                }
+            };
+
+            let mut data = vec![];
+            let mut failed = false;
+            let mut seq = head;
+            while seq != end {
+                if let Some(packet) = self.buffer.remove(&seq) {
+                    match self.depacketizer.depacketize(&packet.payload) {
+                        Ok(v) => data.extend(v),
+                        Err(_) => failed = true,
+                    }
+                }
+                seq = seq.wrapping_add(1);
+            }
+            self.range = Some((end, tail));
+
+            if failed {
+                self.dropped_packets += seqnum_distance(head, end) as usize;
+                self.dropped_frames += 1;
+                continue;
+            }
+
+            let samples = next_timestamp.wrapping_sub(timestamp);
+            let duration = if self.clock_rate > 0 {
+                Duration::from_nanos(
+                    u64::from(samples) * 1_000_000_000 / u64::from(self.clock_rate),
+                )
+            } else {
+                Duration::from_secs(0)
+            };
+
+            let sample = Sample {
+                data,
+                timestamp,
+                duration,
+                prev_dropped_packets: self.dropped_packets,
+                prev_dropped_frames: self.dropped_frames,
+            };
+            self.dropped_packets = 0;
+            self.dropped_frames = 0;
+
+            return Some(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rtp::packet::RtpHeader;
+    use crate::rtp::Result;
+
+    // payloadの先頭byteが1ならpartition head
+    struct FakeDepacketizer;
+
+    impl Depacketizer for FakeDepacketizer {
+        fn depacketize(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+            Ok(payload[1..].to_vec())
+        }
+
+        fn is_partition_head(&self, payload: &[u8]) -> bool {
+            payload[0] == 1
+        }
+
+        fn is_partition_tail(&self, marker: bool, _payload: &[u8]) -> bool {
+            marker
+        }
+    }
+
+    fn packet(sequence_number: u16, timestamp: u32, marker: bool, payload: &[u8]) -> RtpPacket {
+        RtpPacket {
+            header: RtpHeader {
+                version: 2,
+                padding: None,
+                extension: None,
+                marker,
+                payload_type: 96,
+                sequence_number,
+                timestamp,
+                ssrc: 5000,
+                csrc: vec![],
+            },
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn builder(max_latency_ms: u64) -> SampleBuilder {
+        SampleBuilder::new(
+            Duration::from_millis(max_latency_ms),
+            Box::new(FakeDepacketizer),
+            90000,
+        )
+    }
+
+    #[test]
+    fn seqnum_test() {
+        assert_eq!(seqnum_distance(65535, 1), 2);
+        assert!(seqnum_is_before(65535, 0));
+        assert!(!seqnum_is_before(0, 65535));
+        assert!(!seqnum_is_before(5, 5));
+    }
+
+    #[test]
+    fn sample_builder_in_order_test() {
+        let mut sb = builder(500);
+
+        sb.push(packet(5000, 3000, false, &[1, 0x01]));
+        sb.push(packet(5001, 3000, true, &[0, 0x02]));
+        // 次のframeが来るまではdurationが分からない
+        assert_eq!(sb.pop(), None);
+
+        sb.push(packet(5002, 6000, true, &[1, 0x03]));
+        assert_eq!(
+            sb.pop(),
+            Some(Sample {
+                data: vec![0x01, 0x02],
+                timestamp: 3000,
+                duration: Duration::from_nanos(33_333_333),
+                prev_dropped_packets: 0,
+                prev_dropped_frames: 0,
+            })
+        );
+        assert_eq!(sb.pop(), None);
+    }
+
+    #[test]
+    fn sample_builder_out_of_order_wraparound_test() {
+        let mut sb = builder(500);
+
+        sb.push(packet(65534, 1000, false, &[1, 0x01]));
+        sb.push(packet(0, 1000, true, &[0, 0x03]));
+        sb.push(packet(1, 4000, false, &[1, 0x04]));
+        assert_eq!(sb.pop(), None);
+
+        sb.push(packet(65535, 1000, false, &[0, 0x02]));
+        let sample = sb.pop().unwrap();
+        assert_eq!(sample.data, vec![0x01, 0x02, 0x03]);
+        assert_eq!(sample.timestamp, 1000);
+        assert_eq!(sample.duration, Duration::from_nanos(33_333_333));
+
+        sb.push(packet(2, 4000, true, &[0, 0x05]));
+        sb.push(packet(3, 7000, true, &[1, 0x06]));
+        let sample = sb.pop().unwrap();
+        assert_eq!(sample.data, vec![0x04, 0x05]);
+        assert_eq!(sample.timestamp, 4000);
+
+        // 出力済みのpacketは無視する
+        sb.push(packet(1, 4000, false, &[1, 0x04]));
+        assert_eq!(sb.pop(), None);
+    }
+
+    #[test]
+    fn sample_builder_partial_frame_test() {
+        let mut sb = builder(500);
+
+        // 先頭が欠けたframeは捨てる
+        sb.push(packet(10, 1000, true, &[0, 0x01]));
+        sb.push(packet(11, 2000, false, &[1, 0x02]));
+        sb.push(packet(12, 2000, true, &[0, 0x03]));
+        sb.push(packet(13, 3000, true, &[1, 0x04]));
+
+        let sample = sb.pop().unwrap();
+        assert_eq!(sample.data, vec![0x02, 0x03]);
+        assert_eq!(sample.timestamp, 2000);
+        assert_eq!(sample.prev_dropped_packets, 1);
+        assert_eq!(sample.prev_dropped_frames, 1);
+    }
+
+    #[test]
+    fn sample_builder_max_late_test() {
+        // 30ms = 2700
+        let mut sb = builder(30);
+
+        // 21が失われたままlate windowを超える
+        sb.push(packet(20, 1000, false, &[1, 0x01]));
+        sb.push(packet(22, 1000, true, &[0, 0x03]));
+        sb.push(packet(23, 1900, true, &[1, 0x04]));
+        sb.push(packet(24, 2800, true, &[1, 0x05]));
+        sb.push(packet(25, 3700, true, &[1, 0x06]));
+        assert_eq!(sb.pop(), None);
+
+        sb.push(packet(26, 4600, true, &[1, 0x07]));
+        let sample = sb.pop().unwrap();
+        assert_eq!(sample.data, vec![0x04]);
+        assert_eq!(sample.timestamp, 1900);
+        // 20 (欠けたframe), 21 (lost), 22 (先頭の無いframe)
+        assert_eq!(sample.prev_dropped_packets, 3);
+        assert_eq!(sample.prev_dropped_frames, 2);
+
+        assert_eq!(sb.pop().unwrap().timestamp, 2800);
+        assert_eq!(sb.pop().unwrap().timestamp, 3700);
+        assert_eq!(sb.pop(), None);
+    }
+
+    #[test]
+    fn sample_builder_many_partial_frames_test() {
+        let mut sb = builder(500);
+
+        // 1が届くまで，先頭の無いframeが大量に溜まる
+        sb.push(packet(0, 0, false, &[1, 0x01]));
+        for seq in 2..=20001u16 {
+            sb.push(packet(seq, u32::from(seq), true, &[0, 0x02]));
+        }
+        sb.push(packet(1, 0, true, &[0, 0x03]));
+        assert_eq!(sb.pop().unwrap().data, vec![0x01, 0x03]);
+        assert_eq!(sb.pop(), None);
+
+        sb.push(packet(20002, 30000, true, &[1, 0x04]));
+        sb.push(packet(20003, 33000, true, &[1, 0x05]));
+        let sample = sb.pop().unwrap();
+        assert_eq!(sample.data, vec![0x04]);
+        assert_eq!(sample.prev_dropped_packets, 20000);
+        assert_eq!(sample.prev_dropped_frames, 20000);
+    }
+}
@@ -1,6 +1,21 @@
+pub mod negotiation;
+
+#[cfg(test)]
+pub(crate) mod fixtures;
+
 use failure::Fail;
 
-use std::net::IpAddr;
+use webrtc_sdp::attribute_type::SdpAttribute;
+use webrtc_sdp::media_type::SdpMedia;
+use webrtc_sdp::SdpSession;
+
+pub type Result<T> = std::result::Result<T, SdpError>;
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum SdpError {
+    #[fail(display = "No codecs in common with the remote {} m-section.", kind)]
+    NoCommonCodec { kind: String },
+}
 
 /*
 
@@ -28,6 +43,55 @@ pub struct SdpSession {
 }
 */
 
+// webrtc_sdpのDisplayはm-sectionの間に空行を入れるので，m-section毎に繋げる
+pub fn write_sdp(session: &SdpSession) -> String {
+    let mut head = session.clone();
+    head.media.clear();
+    let mut sdp = head.to_string();
+    for media in &session.media {
+        sdp.push_str(&write_media(media));
+    }
+    sdp
+}
+
+// webrtc_sdpのfmtpはprofile-level-idを10進数で書き，parameterを;で区切らないので書き直す
+fn write_media(media: &SdpMedia) -> String {
+    let mut fmtps = media
+        .get_attributes()
+        .iter()
+        .filter_map(|attribute| match attribute {
+            SdpAttribute::Fmtp(fmtp) => Some(fmtp),
+            _ => None,
+        });
+    let mut lines = vec![];
+    for line in media.to_string().split_terminator("\r\n") {
+        if !line.starts_with("a=fmtp:") {
+            lines.push(line.to_owned());
+            continue;
+        }
+        let fmtp = match fmtps.next() {
+            Some(fmtp) => fmtp,
+            None => continue,
+        };
+        let parameters = negotiation::fmtp_parameters(&fmtp.parameters)
+            .into_iter()
+            .map(|(key, value)| match value {
+                Some(value) => format!("{}={}", key, value),
+                None => key,
+            })
+            .collect::<Vec<_>>();
+        // 全て既定値ならfmtpは要らない
+        if !parameters.is_empty() {
+            lines.push(format!(
+                "a=fmtp:{} {}",
+                fmtp.payload_type,
+                parameters.join(";")
+            ));
+        }
+    }
+    lines.iter().map(|line| format!("{}\r\n", line)).collect()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
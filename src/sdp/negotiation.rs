@@ -0,0 +1,736 @@
+// m-sectionとlocalのcapabilityから実際に使うRTPのparameterを決める
+// https://tools.ietf.org/html/rfc8829#section-5.3.1
+
+use super::{Result, SdpError};
+use crate::rtcrtpparameters::{
+    RtcRtcpFeedback, RtcRtcpParameters, RtcRtpCapabilities, RtcRtpCodecCapability,
+    RtcRtpCodecParameter, RtcRtpCodecParameters, RtcRtpCodingParameters, RtcRtpDecodingParameters,
+    RtcRtpHeaderExtensionCapability, RtcRtpHeaderExtensionParameters, RtcRtpParameter,
+    RtcRtpReceiveParameters, RtcRtpRtxParameters,
+};
+
+use webrtc_sdp::attribute_type::*;
+use webrtc_sdp::media_type::*;
+
+// 再送や冗長化，DTMFなど．それだけではmediaを送れない
+const AUXILIARY_CODECS: [&str; 6] = [
+    "rtx",
+    "red",
+    "ulpfec",
+    "flexfec-03",
+    "telephone-event",
+    "CN",
+];
+
+// rtpmapが無くても使える静的なPT (RFC 3551 6)
+const STATIC_PAYLOAD_TYPES: [(usize, &str, u64); 3] =
+    [(0, "PCMU", 8000), (8, "PCMA", 8000), (9, "G722", 8000)];
+
+// H.264のprofile (RFC 6184 8.1)．levelは比べない
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum H264Profile {
+    ConstrainedBaseline,
+    Baseline,
+    Main,
+    ConstrainedHigh,
+    High,
+}
+
+// profile-level-idの上位2byte (profile_idcとprofile-iop) からprofileを決める
+fn h264_profile(profile_level_id: &str) -> Option<H264Profile> {
+    if profile_level_id.len() != 6 {
+        return None;
+    }
+    let profile_idc = u8::from_str_radix(&profile_level_id[0..2], 16).ok()?;
+    let profile_iop = u8::from_str_radix(&profile_level_id[2..4], 16).ok()?;
+    match profile_idc {
+        0x42 if profile_iop & 0x4f == 0x40 => Some(H264Profile::ConstrainedBaseline),
+        0x42 if profile_iop & 0x4f == 0x00 => Some(H264Profile::Baseline),
+        0x4d if profile_iop & 0x8f == 0x80 => Some(H264Profile::ConstrainedBaseline),
+        0x4d if profile_iop & 0xaf == 0x00 => Some(H264Profile::Main),
+        0x58 if profile_iop & 0xcf == 0xc0 => Some(H264Profile::ConstrainedBaseline),
+        0x58 if profile_iop & 0xcf == 0x80 => Some(H264Profile::Baseline),
+        0x64 if profile_iop == 0x0c => Some(H264Profile::ConstrainedHigh),
+        0x64 if profile_iop == 0x00 => Some(H264Profile::High),
+        _ => None,
+    }
+}
+
+// m=の順にrtpmap, fmtp, rtcp-fbを集める
+pub fn remote_codecs(media: &SdpMedia) -> Vec<RtcRtpCodecParameters> {
+    let payload_types = match media.get_formats() {
+        SdpFormatList::Integers(payload_types) => payload_types.clone(),
+        SdpFormatList::Strings(_) => return vec![],
+    };
+    let kind = media.get_type().to_string();
+
+    let mut codecs = vec![];
+    for payload_type in payload_types {
+        let payload_type = payload_type as usize;
+        let rtpmap = media
+            .get_attributes()
+            .iter()
+            .find_map(|attribute| match attribute {
+                SdpAttribute::Rtpmap(rtpmap) if rtpmap.payload_type as usize == payload_type => {
+                    Some(rtpmap)
+                }
+                _ => None,
+            });
+        let mut codec = match rtpmap {
+            Some(rtpmap) => RtcRtpCodecParameters::new(
+                &format!("{}/{}", kind, rtpmap.codec_name),
+                u64::from(rtpmap.frequency),
+                // webrtc_sdpは映像のchannelsを0にする
+                rtpmap
+                    .channels
+                    .filter(|channels| *channels > 0)
+                    .map(|channels| channels as usize),
+                payload_type,
+            ),
+            None => match STATIC_PAYLOAD_TYPES
+                .iter()
+                .find(|(static_type, _, _)| *static_type == payload_type)
+            {
+                Some((_, name, clock_rate)) => RtcRtpCodecParameters::new(
+                    &format!("{}/{}", kind, name),
+                    *clock_rate,
+                    None,
+                    payload_type,
+                ),
+                None => continue,
+            },
+        };
+
+        for attribute in media.get_attributes() {
+            match attribute {
+                SdpAttribute::Fmtp(fmtp) if fmtp.payload_type as usize == payload_type => {
+                    codec.parameters = fmtp_parameters(&fmtp.parameters);
+                }
+                SdpAttribute::Rtcpfb(rtcp_fb) => {
+                    let matches = match rtcp_fb.payload_type {
+                        SdpAttributePayloadType::PayloadType(pt) => pt as usize == payload_type,
+                        SdpAttributePayloadType::Wildcard => true,
+                    };
+                    if matches {
+                        let param = Some(rtcp_fb.parameter.as_str()).filter(|p| !p.is_empty());
+                        codec.rtcp_feedback.push(RtcRtcpFeedback::new(
+                            &rtcp_fb.feedback_type.to_string(),
+                            param,
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        codecs.push(codec);
+    }
+    codecs
+}
+
+// webrtc_sdpのfmtpはparameter毎のfieldに分かれているので，既定値でないものだけ戻す
+pub(crate) fn fmtp_parameters(fmtp: &SdpAttributeFmtpParameters) -> Vec<RtcRtpCodecParameter> {
+    let mut parameters = vec![];
+    let mut push = |key: &str, value: String| parameters.push((key.to_owned(), Some(value)));
+    if fmtp.level_asymmetry_allowed {
+        push("level-asymmetry-allowed", "1".to_owned());
+    }
+    if fmtp.packetization_mode != 0 {
+        push("packetization-mode", fmtp.packetization_mode.to_string());
+    }
+    if fmtp.profile_level_id != 0x0042_0010 {
+        push("profile-level-id", format!("{:06x}", fmtp.profile_level_id));
+    }
+    let numbers = [
+        ("max-fs", fmtp.max_fs, 0),
+        ("max-cpb", fmtp.max_cpb, 0),
+        ("max-dpb", fmtp.max_dpb, 0),
+        ("max-br", fmtp.max_br, 0),
+        ("max-mbps", fmtp.max_mbps, 0),
+        ("max-fr", fmtp.max_fr, 0),
+        ("maxplaybackrate", fmtp.maxplaybackrate, 48000),
+    ];
+    for (key, value, default) in numbers.iter() {
+        if value != default {
+            push(key, value.to_string());
+        }
+    }
+    let flags = [
+        ("usedtx", fmtp.usedtx),
+        ("stereo", fmtp.stereo),
+        ("useinbandfec", fmtp.useinbandfec),
+        ("cbr", fmtp.cbr),
+    ];
+    for (key, value) in flags.iter() {
+        if *value {
+            push(key, "1".to_owned());
+        }
+    }
+
+    // REDの111/111やtelephone-eventの0-16は値の無いparameterにする
+    if !fmtp.encodings.is_empty() {
+        let encodings = fmtp.encodings.iter().map(u8::to_string).collect::<Vec<_>>();
+        parameters.push((encodings.join("/"), None));
+    }
+    if !fmtp.dtmf_tones.is_empty() {
+        parameters.push((fmtp.dtmf_tones.clone(), None));
+    }
+    for token in &fmtp.unknown_tokens {
+        let mut pair = token.splitn(2, '=');
+        let key = pair.next().unwrap_or_default().to_owned();
+        parameters.push((key, pair.next().map(str::to_owned)));
+    }
+    parameters
+}
+
+// fmtp_parametersの逆．知らないparameterと値が読めないものだけunknown_tokensに入れる
+pub(crate) fn fmtp_attribute(
+    payload_type: u8,
+    parameters: &[RtcRtpCodecParameter],
+) -> SdpAttribute {
+    let mut fmtp = SdpAttributeFmtpParameters {
+        packetization_mode: 0,
+        level_asymmetry_allowed: false,
+        profile_level_id: 0x0042_0010,
+        max_fs: 0,
+        max_cpb: 0,
+        max_dpb: 0,
+        max_br: 0,
+        max_mbps: 0,
+        max_fr: 0,
+        maxplaybackrate: 48000,
+        usedtx: false,
+        stereo: false,
+        useinbandfec: false,
+        cbr: false,
+        encodings: vec![],
+        dtmf_tones: String::new(),
+        unknown_tokens: vec![],
+    };
+    for (key, value) in parameters {
+        let number = value.as_deref().and_then(|value| value.parse::<u32>().ok());
+        let flag = number
+            .filter(|number| *number <= 1)
+            .map(|number| number == 1);
+        let typed = match key.to_ascii_lowercase().as_str() {
+            "level-asymmetry-allowed" => flag.map(|flag| fmtp.level_asymmetry_allowed = flag),
+            "packetization-mode" => number.map(|number| fmtp.packetization_mode = number),
+            "profile-level-id" => value
+                .as_deref()
+                .filter(|value| value.len() == 6)
+                .and_then(|value| u32::from_str_radix(value, 16).ok())
+                .map(|id| fmtp.profile_level_id = id),
+            "max-fs" => number.map(|number| fmtp.max_fs = number),
+            "max-cpb" => number.map(|number| fmtp.max_cpb = number),
+            "max-dpb" => number.map(|number| fmtp.max_dpb = number),
+            "max-br" => number.map(|number| fmtp.max_br = number),
+            "max-mbps" => number.map(|number| fmtp.max_mbps = number),
+            "max-fr" => number.map(|number| fmtp.max_fr = number),
+            "maxplaybackrate" => number.map(|number| fmtp.maxplaybackrate = number),
+            "usedtx" => flag.map(|flag| fmtp.usedtx = flag),
+            "stereo" => flag.map(|flag| fmtp.stereo = flag),
+            "useinbandfec" => flag.map(|flag| fmtp.useinbandfec = flag),
+            "cbr" => flag.map(|flag| fmtp.cbr = flag),
+            _ => None,
+        };
+        if typed.is_some() {
+            continue;
+        }
+
+        let encodings = key
+            .split('/')
+            .map(|encoding| encoding.parse::<u8>().ok())
+            .collect::<Option<Vec<_>>>()
+            .filter(|encodings| encodings.len() > 1);
+        match (value, encodings) {
+            (Some(value), _) => fmtp.unknown_tokens.push(format!("{}={}", key, value)),
+            (None, Some(encodings)) if fmtp.encodings.is_empty() => fmtp.encodings = encodings,
+            (None, _) if fmtp.dtmf_tones.is_empty() && !key.contains('/') => {
+                fmtp.dtmf_tones = key.clone()
+            }
+            (None, _) => fmtp.unknown_tokens.push(key.clone()),
+        }
+    }
+    SdpAttribute::Fmtp(SdpAttributeFmtp {
+        payload_type,
+        parameters: fmtp,
+    })
+}
+
+pub fn remote_header_extensions(media: &SdpMedia) -> Vec<RtcRtpHeaderExtensionParameters> {
+    media
+        .get_attributes()
+        .iter()
+        .filter_map(|attribute| match attribute {
+            SdpAttribute::Extmap(extmap) => Some(RtcRtpHeaderExtensionParameters {
+                id: extmap.id as usize,
+                uri: extmap.url.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+// name, clock rate, channelsとcodec毎のfmtpが同じか
+fn is_codec_compatible(local: &RtcRtpCodecCapability, remote: &RtcRtpCodecParameters) -> bool {
+    if !local.mime_type.eq_ignore_ascii_case(&remote.mime_type)
+        || local.clock_rate != remote.clock_rate
+        || local.channels.unwrap_or(1) != remote.channels.unwrap_or(1)
+    {
+        return false;
+    }
+
+    let name = remote.name().to_lowercase();
+    match name.as_str() {
+        "h264" => {
+            let packetization_mode =
+                |codec: Option<&str>| codec.unwrap_or("0").parse::<u8>().unwrap_or(0);
+            // 無い時はconstrained baseline (libwebrtcと同じ)
+            let profile = |codec: Option<&str>| h264_profile(codec.unwrap_or("42e01f"));
+            packetization_mode(local.parameter("packetization-mode"))
+                == packetization_mode(remote.parameter("packetization-mode"))
+                && profile(local.parameter("profile-level-id")).is_some()
+                && profile(local.parameter("profile-level-id"))
+                    == profile(remote.parameter("profile-level-id"))
+        }
+        "vp9" => {
+            local.parameter("profile-id").unwrap_or("0")
+                == remote.parameter("profile-id").unwrap_or("0")
+        }
+        "av1" => {
+            local.parameter("profile").unwrap_or("0") == remote.parameter("profile").unwrap_or("0")
+        }
+        _ => true,
+    }
+}
+
+// remoteの順とPTのまま，localでも使えるcodecを残す．answerはofferと同じPTを使う (RFC 3264 6.1)
+pub fn find_common_codecs(
+    local: &[RtcRtpCodecCapability],
+    remote: &[RtcRtpCodecParameters],
+) -> Vec<RtcRtpCodecParameters> {
+    let mut common = remote
+        .iter()
+        .filter_map(|codec| {
+            let capability = local
+                .iter()
+                .find(|capability| is_codec_compatible(capability, codec))?;
+            let mut codec = codec.clone();
+            codec
+                .rtcp_feedback
+                .retain(|feedback| capability.rtcp_feedback.contains(feedback));
+            Some(codec)
+        })
+        .collect::<Vec<_>>();
+
+    // 対応するcodecが残っていないrtxは使えない
+    let payload_types = common
+        .iter()
+        .filter(|codec| !codec.name().eq_ignore_ascii_case("rtx"))
+        .filter_map(|codec| codec.payload_type)
+        .collect::<Vec<_>>();
+    common.retain(|codec| {
+        !codec.name().eq_ignore_ascii_case("rtx")
+            || codec
+                .parameter("apt")
+                .and_then(|apt| apt.parse::<usize>().ok())
+                .map_or(false, |apt| payload_types.contains(&apt))
+    });
+    common
+}
+
+// IDはremoteのものを使う
+pub fn find_common_header_extensions(
+    local: &[RtcRtpHeaderExtensionCapability],
+    remote: &[RtcRtpHeaderExtensionParameters],
+) -> Vec<RtcRtpHeaderExtensionParameters> {
+    remote
+        .iter()
+        .filter(|extension| {
+            local
+                .iter()
+                .any(|capability| capability.uri == extension.uri)
+        })
+        .cloned()
+        .collect()
+}
+
+// remoteのm-sectionから受信するstreamのparameterを作る
+pub fn negotiate(local: &RtcRtpCapabilities, media: &SdpMedia) -> Result<RtcRtpReceiveParameters> {
+    let codecs = find_common_codecs(&local.codecs, &remote_codecs(media));
+    let primary = codecs.iter().find(|codec| {
+        !AUXILIARY_CODECS
+            .iter()
+            .any(|name| codec.name().eq_ignore_ascii_case(name))
+    });
+    let primary = match primary.and_then(|codec| codec.payload_type) {
+        Some(payload_type) => payload_type,
+        None => {
+            return Err(SdpError::NoCommonCodec {
+                kind: media.get_type().to_string(),
+            })
+        }
+    };
+    let rtx_payload_type = codecs
+        .iter()
+        .find(|codec| {
+            codec.name().eq_ignore_ascii_case("rtx")
+                && codec.parameter("apt") == Some(primary.to_string().as_str())
+        })
+        .and_then(|codec| codec.payload_type);
+
+    let mut ssrcs = vec![];
+    let mut cname = None;
+    let mut rtx_ssrcs = vec![];
+    for attribute in media.get_attributes() {
+        match attribute {
+            SdpAttribute::Ssrc(ssrc) => {
+                if !ssrcs.contains(&ssrc.id) {
+                    ssrcs.push(ssrc.id);
+                }
+                if ssrc.attribute.as_deref() == Some("cname") && cname.is_none() {
+                    cname = ssrc.value.clone();
+                }
+            }
+            // a=ssrc-group:FID <media> <rtx>
+            SdpAttribute::SsrcGroup(group) => {
+                let tokens = group.split_whitespace().collect::<Vec<_>>();
+                if let ["FID", ssrc, rtx] = tokens.as_slice() {
+                    if let (Ok(ssrc), Ok(rtx)) = (ssrc.parse::<u32>(), rtx.parse::<u32>()) {
+                        rtx_ssrcs.push((ssrc, rtx));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    ssrcs.retain(|ssrc| rtx_ssrcs.iter().all(|(_, rtx)| rtx != ssrc));
+
+    let decoding = ssrcs
+        .iter()
+        .map(|ssrc| {
+            let rtx = rtx_payload_type.map(|payload_type| RtcRtpRtxParameters {
+                payload_type,
+                ssrc: rtx_ssrcs
+                    .iter()
+                    .find(|(media, _)| media == ssrc)
+                    .map(|(_, rtx)| *rtx),
+            });
+            RtcRtpDecodingParameters(RtcRtpCodingParameters {
+                ssrc: *ssrc,
+                payload_type: primary,
+                rtx,
+            })
+        })
+        .collect();
+
+    let mux_id = match media.get_attribute(SdpAttributeType::Mid) {
+        Some(SdpAttribute::Mid(mid)) => mid.clone(),
+        _ => String::new(),
+    };
+    let header_extensions =
+        find_common_header_extensions(&local.header_extensions, &remote_header_extensions(media));
+    Ok(RtcRtpReceiveParameters {
+        param: RtcRtpParameter {
+            codecs,
+            header_extensions,
+            mux_id,
+            rtcp: RtcRtcpParameters {
+                cname,
+                mux: media.get_attribute(SdpAttributeType::RtcpMux).is_some(),
+                ssrc: ssrcs.first().cloned(),
+            },
+        },
+        decoding,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sdp::fixtures::{AUDIO_CHROME, VIDEO_CHROME};
+
+    fn capability(
+        mime_type: &str,
+        clock_rate: u64,
+        channels: Option<usize>,
+        parameters: &[(&str, &str)],
+    ) -> RtcRtpCodecCapability {
+        RtcRtpCodecCapability {
+            mime_type: mime_type.to_owned(),
+            clock_rate,
+            channels,
+            rtcp_feedback: vec![
+                RtcRtcpFeedback::new("nack", None),
+                RtcRtcpFeedback::new("nack", Some("pli")),
+                RtcRtcpFeedback::new("transport-cc", None),
+            ],
+            parameters: parameters
+                .iter()
+                .map(|(key, value)| (key.to_string(), Some(value.to_string())))
+                .collect(),
+        }
+    }
+
+    fn header_extensions(uris: &[&str]) -> Vec<RtcRtpHeaderExtensionCapability> {
+        uris.iter()
+            .map(|uri| RtcRtpHeaderExtensionCapability {
+                uri: uri.to_string(),
+            })
+            .collect()
+    }
+
+    fn media(sdp: &str) -> SdpMedia {
+        webrtc_sdp::parse_sdp(sdp, true).unwrap().media.remove(0)
+    }
+
+    #[test]
+    fn remote_codecs_test() {
+        let codecs = remote_codecs(&media(AUDIO_CHROME));
+        assert_eq!(codecs.len(), 13);
+        assert_eq!(codecs[0].mime_type, "audio/opus");
+        assert_eq!(codecs[0].channels, Some(2));
+        assert_eq!(
+            codecs[0].fmtp().as_deref(),
+            Some("useinbandfec=1;minptime=10")
+        );
+        assert_eq!(
+            codecs[0].rtcp_feedback,
+            vec![RtcRtcpFeedback::new("transport-cc", None)]
+        );
+        assert_eq!(codecs[3].to_string(), "G722/8000");
+
+        let codecs = remote_codecs(&media(VIDEO_CHROME));
+        let rtx = codecs
+            .iter()
+            .find(|codec| codec.payload_type == Some(97))
+            .unwrap();
+        assert_eq!(rtx.parameter("apt"), Some("96"));
+        let h264 = codecs
+            .iter()
+            .find(|codec| codec.payload_type == Some(102))
+            .unwrap();
+        assert_eq!(h264.parameter("profile-level-id"), Some("42001f"));
+        assert_eq!(h264.parameter("packetization-mode"), Some("1"));
+        assert_eq!(
+            h264.rtcp_feedback[2],
+            RtcRtcpFeedback::new("ccm", Some("fir"))
+        );
+    }
+
+    #[test]
+    fn audio_negotiate_test() {
+        let local = RtcRtpCapabilities {
+            codecs: vec![
+                capability("audio/PCMU", 8000, None, &[]),
+                capability("audio/opus", 48000, Some(2), &[]),
+                // channelsが違う
+                capability("audio/G722", 8000, Some(2), &[]),
+            ],
+            header_extensions: header_extensions(&[
+                "urn:ietf:params:rtp-hdrext:ssrc-audio-level",
+                "urn:ietf:params:rtp-hdrext:sdes:mid",
+            ]),
+        };
+
+        let parameters = negotiate(&local, &media(AUDIO_CHROME)).unwrap();
+        // remoteの順とPT
+        assert_eq!(
+            parameters
+                .param
+                .codecs
+                .iter()
+                .map(|codec| (codec.payload_type.unwrap(), codec.to_string()))
+                .collect::<Vec<_>>(),
+            vec![
+                (111, "opus/48000/2".to_owned()),
+                (0, "PCMU/8000".to_owned())
+            ]
+        );
+        assert_eq!(
+            parameters.param.codecs[0].rtcp_feedback,
+            vec![RtcRtcpFeedback::new("transport-cc", None)]
+        );
+        assert_eq!(
+            parameters.param.header_extensions,
+            vec![RtcRtpHeaderExtensionParameters {
+                id: 1,
+                uri: "urn:ietf:params:rtp-hdrext:ssrc-audio-level".to_owned(),
+            }]
+        );
+        assert_eq!(parameters.param.mux_id, "audio");
+        assert_eq!(
+            parameters.param.rtcp,
+            RtcRtcpParameters {
+                cname: Some("/vC4ULAr8vHNjXmq".to_owned()),
+                mux: true,
+                ssrc: Some(1944796561),
+            }
+        );
+        assert_eq!(
+            parameters.decoding,
+            vec![RtcRtpDecodingParameters(RtcRtpCodingParameters {
+                ssrc: 1944796561,
+                payload_type: 111,
+                rtx: None,
+            })]
+        );
+
+        let local = RtcRtpCapabilities {
+            codecs: vec![capability("audio/ISAC", 48000, None, &[])],
+            header_extensions: vec![],
+        };
+        assert_eq!(
+            negotiate(&local, &media(AUDIO_CHROME)),
+            Err(SdpError::NoCommonCodec {
+                kind: "audio".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn h264_negotiate_test() {
+        let local = RtcRtpCapabilities {
+            codecs: vec![
+                // constrained baseline (42e0..) はChromeの42001fとは別のprofile
+                capability(
+                    "video/H264",
+                    90000,
+                    None,
+                    &[("packetization-mode", "1"), ("profile-level-id", "42e034")],
+                ),
+                capability(
+                    "video/H264",
+                    90000,
+                    None,
+                    &[("packetization-mode", "1"), ("profile-level-id", "640c1f")],
+                ),
+                capability("video/rtx", 90000, None, &[]),
+            ],
+            header_extensions: header_extensions(&[
+                "urn:ietf:params:rtp-hdrext:sdes:mid",
+                "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01",
+            ]),
+        };
+
+        let parameters = negotiate(&local, &media(VIDEO_CHROME)).unwrap();
+        // packetization-modeが0のものとHigh (640032) は残らない
+        assert_eq!(
+            parameters
+                .param
+                .codecs
+                .iter()
+                .map(|codec| (codec.payload_type.unwrap(), codec.name()))
+                .collect::<Vec<_>>(),
+            vec![(125, "H264".to_owned()), (107, "rtx".to_owned())]
+        );
+        assert_eq!(
+            parameters.param.codecs[0].parameter("profile-level-id"),
+            Some("42e01f")
+        );
+        assert_eq!(
+            parameters.param.codecs[0].rtcp_feedback,
+            vec![
+                RtcRtcpFeedback::new("transport-cc", None),
+                RtcRtcpFeedback::new("nack", None),
+                RtcRtcpFeedback::new("nack", Some("pli")),
+            ]
+        );
+        assert_eq!(
+            parameters
+                .param
+                .header_extensions
+                .iter()
+                .map(|extension| extension.id)
+                .collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+        assert_eq!(
+            parameters.decoding,
+            vec![RtcRtpDecodingParameters(RtcRtpCodingParameters {
+                ssrc: 2231627014,
+                payload_type: 125,
+                rtx: Some(RtcRtpRtxParameters {
+                    payload_type: 107,
+                    ssrc: Some(632943048),
+                }),
+            })]
+        );
+    }
+
+    #[test]
+    fn vp9_negotiate_test() {
+        let local = vec![
+            capability("video/VP9", 90000, None, &[("profile-id", "2")]),
+            capability("video/VP8", 90000, None, &[]),
+            capability("video/rtx", 90000, None, &[]),
+        ];
+
+        let codecs = find_common_codecs(&local, &remote_codecs(&media(VIDEO_CHROME)));
+        assert_eq!(
+            codecs
+                .iter()
+                .map(|codec| codec.payload_type.unwrap())
+                .collect::<Vec<_>>(),
+            vec![96, 97, 100, 101]
+        );
+    }
+
+    #[test]
+    fn h264_profile_test() {
+        assert_eq!(
+            h264_profile("42e01f"),
+            Some(H264Profile::ConstrainedBaseline)
+        );
+        assert_eq!(h264_profile("42001f"), Some(H264Profile::Baseline));
+        assert_eq!(h264_profile("4d0032"), Some(H264Profile::Main));
+        assert_eq!(h264_profile("640c1f"), Some(H264Profile::ConstrainedHigh));
+        assert_eq!(h264_profile("640032"), Some(H264Profile::High));
+        assert_eq!(h264_profile("f4001f"), None);
+        assert_eq!(h264_profile("42"), None);
+    }
+
+    #[test]
+    fn fmtp_attribute_test() {
+        let parameter = |key: &str, value: Option<&str>| (key.to_owned(), value.map(str::to_owned));
+        let parameters = vec![
+            parameter("level-asymmetry-allowed", Some("1")),
+            parameter("packetization-mode", Some("1")),
+            parameter("profile-level-id", Some("42e01f")),
+            parameter("x-google-start-bitrate", Some("800")),
+        ];
+        let fmtp = match fmtp_attribute(125, &parameters) {
+            SdpAttribute::Fmtp(fmtp) => fmtp,
+            _ => unreachable!(),
+        };
+        assert_eq!(fmtp.parameters.profile_level_id, 0x0042_e01f);
+        assert_eq!(fmtp.parameters.packetization_mode, 1);
+        assert!(fmtp.parameters.level_asymmetry_allowed);
+        assert_eq!(
+            fmtp.parameters.unknown_tokens,
+            vec!["x-google-start-bitrate=800".to_owned()]
+        );
+        assert_eq!(fmtp_parameters(&fmtp.parameters), parameters);
+
+        // 値が読めないものは知らないparameterとして残す
+        let parameters = vec![
+            parameter("useinbandfec", Some("yes")),
+            parameter("111/111", None),
+        ];
+        let fmtp = match fmtp_attribute(63, &parameters) {
+            SdpAttribute::Fmtp(fmtp) => fmtp,
+            _ => unreachable!(),
+        };
+        assert!(!fmtp.parameters.useinbandfec);
+        assert_eq!(fmtp.parameters.encodings, vec![111, 111]);
+        assert_eq!(
+            fmtp.parameters.unknown_tokens,
+            vec!["useinbandfec=yes".to_owned()]
+        );
+
+        let fmtp = match fmtp_attribute(110, &[parameter("0-16", None)]) {
+            SdpAttribute::Fmtp(fmtp) => fmtp,
+            _ => unreachable!(),
+        };
+        assert_eq!(fmtp.parameters.dtmf_tones, "0-16");
+    }
+}
@@ -0,0 +1,61 @@
+pub mod context;
+pub mod key_derivation;
+pub mod protection_profile;
+pub mod replay_detector;
+
+use crate::rtp::RtpError;
+use crate::OctetsError;
+use failure::Fail;
+use openssl::error::ErrorStack;
+
+pub type Result<T> = std::result::Result<T, SrtpError>;
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum SrtpError {
+    #[fail(display = "Octets manipulate failed: {:?}", error)]
+    OctetsError { error: OctetsError },
+
+    #[fail(display = "RTP failed: {:?}", error)]
+    RtpError { error: RtpError },
+
+    #[fail(display = "OpenSSL failed: {}", reason)]
+    CryptoError { reason: String },
+
+    #[fail(display = "SRTP master key length is invalid.")]
+    InvalidMasterKeyLength,
+
+    #[fail(display = "SRTP master salt length is invalid.")]
+    InvalidMasterSaltLength,
+
+    #[fail(display = "SRTP protection profile {} is not supported.", id)]
+    UnsupportedProtectionProfile { id: u16 },
+
+    #[fail(display = "SRTP/SRTCP packet is too short.")]
+    PacketTooShort,
+
+    #[fail(display = "SRTP/SRTCP authentication failed.")]
+    AuthenticationFailed,
+
+    #[fail(display = "SRTP/SRTCP packet is replayed. ssrc: {}, index: {}", ssrc, index)]
+    ReplayedPacket { ssrc: u32, index: u64 },
+}
+
+impl From<OctetsError> for SrtpError {
+    fn from(error: OctetsError) -> Self {
+        SrtpError::OctetsError { error }
+    }
+}
+
+impl From<RtpError> for SrtpError {
+    fn from(error: RtpError) -> Self {
+        SrtpError::RtpError { error }
+    }
+}
+
+impl From<ErrorStack> for SrtpError {
+    fn from(error: ErrorStack) -> Self {
+        SrtpError::CryptoError {
+            reason: error.to_string(),
+        }
+    }
+}
@@ -0,0 +1,750 @@
+// https://tools.ietf.org/html/rfc3711
+// https://tools.ietf.org/html/rfc7714
+
+use crate::octets;
+use crate::rtp::packet::{RtpHeader, RtpPacket};
+use crate::srtp::key_derivation::*;
+use crate::srtp::protection_profile::ProtectionProfile;
+use crate::srtp::replay_detector::ReplayDetector;
+use crate::srtp::{Result, SrtpError};
+
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+use std::collections::HashMap;
+
+const REPLAY_WINDOW_SIZE: u64 = 64;
+const SRTCP_HEADER_SIZE: usize = 8;
+const SRTCP_INDEX_SIZE: usize = 4;
+const SRTCP_INDEX_MASK: u32 = 0x7FFF_FFFF;
+const SRTCP_ENCRYPTION_FLAG: u32 = 0x8000_0000;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+struct SessionKeys {
+    key: Vec<u8>,
+    salt: Vec<u8>,
+    auth_key: Vec<u8>,
+}
+
+impl SessionKeys {
+    fn derive(
+        profile: ProtectionProfile,
+        master_key: &[u8],
+        master_salt: &[u8],
+        labels: (u8, u8, u8),
+    ) -> Result<SessionKeys> {
+        let (encryption, authentication, salt) = labels;
+
+        let auth_key = if profile.auth_key_len() > 0 {
+            aes_cm_key_derivation(
+                authentication,
+                master_key,
+                master_salt,
+                profile.auth_key_len(),
+            )?
+        } else {
+            vec![]
+        };
+
+        Ok(SessionKeys {
+            key: aes_cm_key_derivation(encryption, master_key, master_salt, profile.key_len())?,
+            salt: aes_cm_key_derivation(salt, master_key, master_salt, profile.salt_len())?,
+            auth_key,
+        })
+    }
+}
+
+// SSRCごとのrollover counterとreplay window
+#[derive(Debug, Clone)]
+struct SrtpSsrcState {
+    roc: u32,
+    // 最後に受け入れたsequence number (s_l)
+    last_seq: Option<u16>,
+    replay: ReplayDetector,
+}
+
+impl SrtpSsrcState {
+    fn new() -> SrtpSsrcState {
+        SrtpSsrcState {
+            roc: 0,
+            last_seq: None,
+            replay: ReplayDetector::new(REPLAY_WINDOW_SIZE),
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc3711#appendix-A
+    fn estimate_roc(&self, seq: u16) -> u32 {
+        let last_seq = match self.last_seq {
+            Some(v) => i32::from(v),
+            None => return self.roc,
+        };
+        let seq = i32::from(seq);
+
+        if last_seq < 0x8000 {
+            if seq - last_seq > 0x8000 {
+                return self.roc.wrapping_sub(1);
+            }
+        } else if last_seq - 0x8000 > seq {
+            return self.roc.wrapping_add(1);
+        }
+
+        self.roc
+    }
+
+    fn update(&mut self, seq: u16, roc: u32) {
+        match self.last_seq {
+            None => {
+                self.roc = roc;
+                self.last_seq = Some(seq);
+            }
+            Some(last_seq) => {
+                if roc == self.roc.wrapping_add(1) || (roc == self.roc && seq > last_seq) {
+                    self.roc = roc;
+                    self.last_seq = Some(seq);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SrtcpSsrcState {
+    // 最後に送信したSRTCP index
+    index: u32,
+    replay: ReplayDetector,
+}
+
+impl SrtcpSsrcState {
+    fn new() -> SrtcpSsrcState {
+        SrtcpSsrcState {
+            index: 0,
+            replay: ReplayDetector::new(REPLAY_WINDOW_SIZE),
+        }
+    }
+}
+
+// 1つのDTLS-SRTP sessionの片方向分のSRTP/SRTCPの暗号化context．
+// 送信用と受信用で別々のmaster key/saltを使うので，それぞれContextを作る．
+pub struct Context {
+    profile: ProtectionProfile,
+    srtp: SessionKeys,
+    srtcp: SessionKeys,
+    srtp_ssrc_states: HashMap<u32, SrtpSsrcState>,
+    srtcp_ssrc_states: HashMap<u32, SrtcpSsrcState>,
+}
+
+impl Context {
+    pub fn new(
+        master_key: &[u8],
+        master_salt: &[u8],
+        profile: ProtectionProfile,
+    ) -> Result<Context> {
+        if master_key.len() != profile.key_len() {
+            return Err(SrtpError::InvalidMasterKeyLength);
+        }
+        if master_salt.len() != profile.salt_len() {
+            return Err(SrtpError::InvalidMasterSaltLength);
+        }
+
+        let srtp = SessionKeys::derive(
+            profile,
+            master_key,
+            master_salt,
+            (
+                LABEL_SRTP_ENCRYPTION,
+                LABEL_SRTP_AUTHENTICATION,
+                LABEL_SRTP_SALT,
+            ),
+        )?;
+        let srtcp = SessionKeys::derive(
+            profile,
+            master_key,
+            master_salt,
+            (
+                LABEL_SRTCP_ENCRYPTION,
+                LABEL_SRTCP_AUTHENTICATION,
+                LABEL_SRTCP_SALT,
+            ),
+        )?;
+
+        Ok(Context::with_session_keys(profile, srtp, srtcp))
+    }
+
+    fn with_session_keys(
+        profile: ProtectionProfile,
+        srtp: SessionKeys,
+        srtcp: SessionKeys,
+    ) -> Context {
+        Context {
+            profile,
+            srtp,
+            srtcp,
+            srtp_ssrc_states: HashMap::new(),
+            srtcp_ssrc_states: HashMap::new(),
+        }
+    }
+
+    pub fn profile(&self) -> ProtectionProfile {
+        self.profile
+    }
+
+    pub fn roc(&self, ssrc: u32) -> Option<u32> {
+        self.srtp_ssrc_states.get(&ssrc).map(|s| s.roc)
+    }
+
+    // 途中からstreamに参加する場合などに，signalingで得たROCを設定する
+    pub fn set_roc(&mut self, ssrc: u32, roc: u32) {
+        self.srtp_ssrc_states
+            .entry(ssrc)
+            .or_insert_with(SrtpSsrcState::new)
+            .roc = roc;
+    }
+
+    pub fn protect_rtp(&mut self, packet: &RtpPacket) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; packet.marshal_size()];
+        packet.to_bytes(&mut octets::Octets::with_slice(&mut buf))?;
+
+        let header = packet.header();
+        let header_len = header.marshal_size();
+        let ssrc = header.ssrc();
+        let seq = header.sequence_number();
+
+        let state = self
+            .srtp_ssrc_states
+            .entry(ssrc)
+            .or_insert_with(SrtpSsrcState::new);
+        let roc = state.estimate_roc(seq);
+        state.update(seq, roc);
+
+        let (header, payload) = buf.split_at(header_len);
+        let mut out = header.to_vec();
+
+        if self.profile.is_aead() {
+            let iv = self.rtp_aead_iv(ssrc, roc, seq);
+            let mut tag = vec![0u8; self.profile.rtp_auth_tag_len()];
+            out.extend(encrypt_aead(
+                self.aead_cipher(),
+                &self.srtp.key,
+                Some(&iv),
+                header,
+                payload,
+                &mut tag,
+            )?);
+            out.extend(tag);
+        } else {
+            let iv = aes_cm_iv(&self.srtp.salt, ssrc, u64::from(roc) << 16 | u64::from(seq));
+            out.extend(aes_cm_crypt(&self.srtp.key, &iv, payload)?);
+            let tag = self.rtp_auth_tag(&out, roc)?;
+            out.extend(tag);
+        }
+
+        Ok(out)
+    }
+
+    pub fn unprotect_rtp(&mut self, encrypted: &[u8]) -> Result<RtpPacket> {
+        let tag_len = self.profile.rtp_auth_tag_len();
+        let mut buf = encrypted.to_vec();
+
+        let header_len = {
+            let mut bytes = octets::Octets::with_slice(&mut buf);
+            RtpHeader::from_bytes(&mut bytes).map_err(|_| SrtpError::PacketTooShort)?;
+            bytes.off()
+        };
+        if encrypted.len() < header_len + tag_len {
+            return Err(SrtpError::PacketTooShort);
+        }
+
+        let ssrc = u32::from_be_bytes([encrypted[8], encrypted[9], encrypted[10], encrypted[11]]);
+        let seq = u16::from_be_bytes([encrypted[2], encrypted[3]]);
+
+        // 認証に成功するまでは新しいSSRCの状態を登録しない
+        let mut state = self
+            .srtp_ssrc_states
+            .get(&ssrc)
+            .cloned()
+            .unwrap_or_else(SrtpSsrcState::new);
+        let roc = state.estimate_roc(seq);
+        let index = u64::from(roc) << 16 | u64::from(seq);
+        if !state.replay.check(index) {
+            return Err(SrtpError::ReplayedPacket { ssrc, index });
+        }
+
+        let header = &encrypted[..header_len];
+        let mut out = header.to_vec();
+
+        if self.profile.is_aead() {
+            let (ciphertext, tag) =
+                encrypted[header_len..].split_at(encrypted.len() - header_len - tag_len);
+            let iv = self.rtp_aead_iv(ssrc, roc, seq);
+            let payload = decrypt_aead(
+                self.aead_cipher(),
+                &self.srtp.key,
+                Some(&iv),
+                header,
+                ciphertext,
+                tag,
+            )
+            .map_err(|_| SrtpError::AuthenticationFailed)?;
+            out.extend(payload);
+        } else {
+            let (authenticated, tag) = encrypted.split_at(encrypted.len() - tag_len);
+            let expected = self.rtp_auth_tag(authenticated, roc)?;
+            if !openssl::memcmp::eq(&expected, tag) {
+                return Err(SrtpError::AuthenticationFailed);
+            }
+
+            let iv = aes_cm_iv(&self.srtp.salt, ssrc, index);
+            out.extend(aes_cm_crypt(
+                &self.srtp.key,
+                &iv,
+                &authenticated[header_len..],
+            )?);
+        }
+
+        // 認証に成功したのでROCとreplay windowを更新する
+        state.update(seq, roc);
+        state.replay.accept(index);
+        self.srtp_ssrc_states.insert(ssrc, state);
+
+        Ok(RtpPacket::from_slice(&mut out)?)
+    }
+
+    // RTCP compound packetを暗号化する
+    pub fn protect_rtcp(&mut self, decrypted: &[u8]) -> Result<Vec<u8>> {
+        if decrypted.len() < SRTCP_HEADER_SIZE {
+            return Err(SrtpError::PacketTooShort);
+        }
+
+        let ssrc = u32::from_be_bytes([decrypted[4], decrypted[5], decrypted[6], decrypted[7]]);
+        let state = self
+            .srtcp_ssrc_states
+            .entry(ssrc)
+            .or_insert_with(SrtcpSsrcState::new);
+        state.index = (state.index + 1) & SRTCP_INDEX_MASK;
+        let index = state.index;
+        let e_index = (SRTCP_ENCRYPTION_FLAG | index).to_be_bytes();
+
+        let (header, payload) = decrypted.split_at(SRTCP_HEADER_SIZE);
+        let mut out = header.to_vec();
+
+        if self.profile.is_aead() {
+            let iv = self.rtcp_aead_iv(ssrc, index);
+            let mut aad = header.to_vec();
+            aad.extend_from_slice(&e_index);
+
+            let mut tag = vec![0u8; self.profile.rtcp_auth_tag_len()];
+            out.extend(encrypt_aead(
+                self.aead_cipher(),
+                &self.srtcp.key,
+                Some(&iv),
+                &aad,
+                payload,
+                &mut tag,
+            )?);
+            out.extend(tag);
+            out.extend_from_slice(&e_index);
+        } else {
+            let iv = aes_cm_iv(&self.srtcp.salt, ssrc, u64::from(index));
+            out.extend(aes_cm_crypt(&self.srtcp.key, &iv, payload)?);
+            out.extend_from_slice(&e_index);
+            let tag = self.rtcp_auth_tag(&out)?;
+            out.extend(tag);
+        }
+
+        Ok(out)
+    }
+
+    pub fn unprotect_rtcp(&mut self, encrypted: &[u8]) -> Result<Vec<u8>> {
+        let tag_len = self.profile.rtcp_auth_tag_len();
+        if encrypted.len() < SRTCP_HEADER_SIZE + SRTCP_INDEX_SIZE + tag_len {
+            return Err(SrtpError::PacketTooShort);
+        }
+
+        let ssrc = u32::from_be_bytes([encrypted[4], encrypted[5], encrypted[6], encrypted[7]]);
+
+        // AEADではE||SRTCP indexが最後，それ以外はauthentication tagの前にある
+        let index_offset = if self.profile.is_aead() {
+            encrypted.len() - SRTCP_INDEX_SIZE
+        } else {
+            encrypted.len() - tag_len - SRTCP_INDEX_SIZE
+        };
+        let e_index = &encrypted[index_offset..index_offset + SRTCP_INDEX_SIZE];
+        let e_index_value = u32::from_be_bytes([e_index[0], e_index[1], e_index[2], e_index[3]]);
+        let is_encrypted = e_index_value & SRTCP_ENCRYPTION_FLAG > 0;
+        let index = e_index_value & SRTCP_INDEX_MASK;
+
+        let mut state = self
+            .srtcp_ssrc_states
+            .get(&ssrc)
+            .cloned()
+            .unwrap_or_else(SrtcpSsrcState::new);
+        if !state.replay.check(u64::from(index)) {
+            return Err(SrtpError::ReplayedPacket {
+                ssrc,
+                index: u64::from(index),
+            });
+        }
+
+        let header = &encrypted[..SRTCP_HEADER_SIZE];
+        let mut out = header.to_vec();
+
+        if self.profile.is_aead() {
+            let body = &encrypted[SRTCP_HEADER_SIZE..index_offset];
+            let (ciphertext, tag) = body.split_at(body.len() - tag_len);
+            let iv = self.rtcp_aead_iv(ssrc, index);
+
+            // 暗号化されていない場合はpayload全体をAADとして認証だけ行う
+            let mut aad = header.to_vec();
+            let input = if is_encrypted {
+                ciphertext
+            } else {
+                aad.extend_from_slice(ciphertext);
+                &[]
+            };
+            aad.extend_from_slice(e_index);
+
+            let payload = decrypt_aead(
+                self.aead_cipher(),
+                &self.srtcp.key,
+                Some(&iv),
+                &aad,
+                input,
+                tag,
+            )
+            .map_err(|_| SrtpError::AuthenticationFailed)?;
+
+            if is_encrypted {
+                out.extend(payload);
+            } else {
+                out.extend_from_slice(ciphertext);
+            }
+        } else {
+            let (authenticated, tag) = encrypted.split_at(encrypted.len() - tag_len);
+            let expected = self.rtcp_auth_tag(authenticated)?;
+            if !openssl::memcmp::eq(&expected, tag) {
+                return Err(SrtpError::AuthenticationFailed);
+            }
+
+            let payload = &encrypted[SRTCP_HEADER_SIZE..index_offset];
+            if is_encrypted {
+                let iv = aes_cm_iv(&self.srtcp.salt, ssrc, u64::from(index));
+                out.extend(aes_cm_crypt(&self.srtcp.key, &iv, payload)?);
+            } else {
+                out.extend_from_slice(payload);
+            }
+        }
+
+        state.replay.accept(u64::from(index));
+        self.srtcp_ssrc_states.insert(ssrc, state);
+
+        Ok(out)
+    }
+
+    fn aead_cipher(&self) -> Cipher {
+        match self.profile {
+            ProtectionProfile::AeadAes256Gcm => Cipher::aes_256_gcm(),
+            _ => Cipher::aes_128_gcm(),
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc7714#section-8.1
+    fn rtp_aead_iv(&self, ssrc: u32, roc: u32, seq: u16) -> [u8; 12] {
+        let mut iv = [0u8; 12];
+        iv[2..6].copy_from_slice(&ssrc.to_be_bytes());
+        iv[6..10].copy_from_slice(&roc.to_be_bytes());
+        iv[10..12].copy_from_slice(&seq.to_be_bytes());
+        for (v, salt) in iv.iter_mut().zip(&self.srtp.salt) {
+            *v ^= salt;
+        }
+        iv
+    }
+
+    // https://tools.ietf.org/html/rfc7714#section-9.1
+    fn rtcp_aead_iv(&self, ssrc: u32, index: u32) -> [u8; 12] {
+        let mut iv = [0u8; 12];
+        iv[2..6].copy_from_slice(&ssrc.to_be_bytes());
+        iv[8..12].copy_from_slice(&index.to_be_bytes());
+        for (v, salt) in iv.iter_mut().zip(&self.srtcp.salt) {
+            *v ^= salt;
+        }
+        iv
+    }
+
+    // HMAC-SHA1(packet || ROC)
+    fn rtp_auth_tag(&self, packet: &[u8], roc: u32) -> Result<Vec<u8>> {
+        let key = PKey::hmac(&self.srtp.auth_key)?;
+        let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+        signer.update(packet)?;
+        signer.update(&roc.to_be_bytes())?;
+
+        let mut tag = signer.sign_to_vec()?;
+        tag.truncate(self.profile.rtp_auth_tag_len());
+        Ok(tag)
+    }
+
+    // HMAC-SHA1(packet || E || SRTCP index)
+    fn rtcp_auth_tag(&self, packet: &[u8]) -> Result<Vec<u8>> {
+        let key = PKey::hmac(&self.srtcp.auth_key)?;
+        let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+        signer.update(packet)?;
+
+        let mut tag = signer.sign_to_vec()?;
+        tag.truncate(self.profile.rtcp_auth_tag_len());
+        Ok(tag)
+    }
+}
+
+// https://tools.ietf.org/html/rfc3711#section-4.1.1
+// IV = (k_s * 2^16) XOR (SSRC * 2^64) XOR (i * 2^16)
+fn aes_cm_iv(session_salt: &[u8], ssrc: u32, index: u64) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv[4..8].copy_from_slice(&ssrc.to_be_bytes());
+    iv[8..14].copy_from_slice(&index.to_be_bytes()[2..]);
+    for (v, salt) in iv.iter_mut().zip(session_salt) {
+        *v ^= salt;
+    }
+    iv
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // libsrtpのtest vector (master keyはRFC 3711 B.3と同じ)
+    fn aes_cm_context(profile: ProtectionProfile) -> Context {
+        Context::new(
+            &from_hex("e1f97a0d3e018be0d64fa32c06de4139"),
+            &from_hex("0ec675ad498afeebb6960b3aabe6"),
+            profile,
+        )
+        .unwrap()
+    }
+
+    // https://tools.ietf.org/html/rfc7714#section-16.1.1
+    fn aead_context(profile: ProtectionProfile, key: &str) -> Context {
+        let keys = SessionKeys {
+            key: from_hex(key),
+            salt: from_hex("517569642070726f2071756f"),
+            auth_key: vec![],
+        };
+        Context::with_session_keys(profile, keys.clone(), keys)
+    }
+
+    #[test]
+    fn srtp_aes_cm_hmac_sha1_80_test() {
+        let mut plaintext = from_hex("800f1234decafbadcafebabeabababababababababababababababab");
+        let ciphertext = from_hex(
+            "800f1234decafbadcafebabe4e55dc4ce79978d88ca4d215949d2402b78d6acc99ea179b8dbb",
+        );
+        let packet = RtpPacket::from_slice(&mut plaintext).unwrap();
+
+        let mut sender = aes_cm_context(ProtectionProfile::Aes128CmHmacSha1_80);
+        assert_eq!(sender.protect_rtp(&packet).unwrap(), ciphertext);
+        assert_eq!(sender.roc(0xcafebabe), Some(0));
+
+        let mut receiver = aes_cm_context(ProtectionProfile::Aes128CmHmacSha1_80);
+        assert_eq!(receiver.unprotect_rtp(&ciphertext).unwrap(), packet);
+
+        // 同じpacketは受け付けない
+        assert_eq!(
+            receiver.unprotect_rtp(&ciphertext),
+            Err(SrtpError::ReplayedPacket {
+                ssrc: 0xcafebabe,
+                index: 0x1234
+            })
+        );
+
+        // 改ざんされたpacket
+        let mut tampered = ciphertext.clone();
+        tampered[3] = 0x35;
+        assert_eq!(
+            receiver.unprotect_rtp(&tampered),
+            Err(SrtpError::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn srtcp_aes_cm_hmac_sha1_80_test() {
+        let plaintext = from_hex("81c8000bcafebabeabababababababababababababababab");
+        let ciphertext = from_hex(
+            "81c8000bcafebabe7128035be487b9bdbef89041f977a5a880000001993e08cd54d6c1230798",
+        );
+
+        let mut sender = aes_cm_context(ProtectionProfile::Aes128CmHmacSha1_80);
+        assert_eq!(sender.protect_rtcp(&plaintext).unwrap(), ciphertext);
+
+        let mut receiver = aes_cm_context(ProtectionProfile::Aes128CmHmacSha1_80);
+        assert_eq!(receiver.unprotect_rtcp(&ciphertext).unwrap(), plaintext);
+        assert_eq!(
+            receiver.unprotect_rtcp(&ciphertext),
+            Err(SrtpError::ReplayedPacket {
+                ssrc: 0xcafebabe,
+                index: 1
+            })
+        );
+
+        // 次のpacketはindexが進む
+        let encrypted = sender.protect_rtcp(&plaintext).unwrap();
+        assert_eq!(&encrypted[24..28], &[0x80, 0x00, 0x00, 0x02]);
+        assert_eq!(receiver.unprotect_rtcp(&encrypted).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn srtp_aes_cm_hmac_sha1_32_test() {
+        let mut sender = aes_cm_context(ProtectionProfile::Aes128CmHmacSha1_32);
+        let mut receiver = aes_cm_context(ProtectionProfile::Aes128CmHmacSha1_32);
+
+        let mut plaintext = from_hex("800f1234decafbadcafebabeabababababababababababababababab");
+        let packet = RtpPacket::from_slice(&mut plaintext).unwrap();
+        let encrypted = sender.protect_rtp(&packet).unwrap();
+        assert_eq!(encrypted.len(), plaintext.len() + 4);
+        assert_eq!(receiver.unprotect_rtp(&encrypted).unwrap(), packet);
+
+        // SRTCPは80bitのtag
+        let plaintext = from_hex("81c8000bcafebabeabababababababababababababababab");
+        let encrypted = sender.protect_rtcp(&plaintext).unwrap();
+        assert_eq!(encrypted.len(), plaintext.len() + 4 + 10);
+        assert_eq!(receiver.unprotect_rtcp(&encrypted).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn srtp_aead_aes_128_gcm_test() {
+        let mut plaintext = from_hex(
+            "8040f17b8041f8d35501a0b2\
+             47616c6c696120657374206f6d6e69732064697669736120696e207061727465732074726573",
+        );
+        let ciphertext = from_hex(
+            "8040f17b8041f8d35501a0b2\
+             f24de3a3fb34de6cacba861c9d7e4bcabe633bd50d294e6f42a5f47a51c7d19b36de3adf8833\
+             899d7f27beb16a9152cf765ee4390cce",
+        );
+        let packet = RtpPacket::from_slice(&mut plaintext).unwrap();
+
+        let mut sender = aead_context(
+            ProtectionProfile::AeadAes128Gcm,
+            "000102030405060708090a0b0c0d0e0f",
+        );
+        assert_eq!(
+            sender.rtp_aead_iv(0x5501a0b2, 0, 0xf17b).to_vec(),
+            from_hex("51753c6580c2726f20718414")
+        );
+        assert_eq!(sender.protect_rtp(&packet).unwrap(), ciphertext);
+
+        let mut receiver = aead_context(
+            ProtectionProfile::AeadAes128Gcm,
+            "000102030405060708090a0b0c0d0e0f",
+        );
+
+        // 改ざんされたpacketではreplay windowは更新されない
+        let mut tampered = ciphertext.clone();
+        tampered[20] ^= 0x01;
+        assert_eq!(
+            receiver.unprotect_rtp(&tampered),
+            Err(SrtpError::AuthenticationFailed)
+        );
+        assert_eq!(receiver.unprotect_rtp(&ciphertext).unwrap(), packet);
+    }
+
+    #[test]
+    fn srtp_aead_aes_256_gcm_test() {
+        let master_key = (0..32).collect::<Vec<u8>>();
+        let master_salt = (0..12).collect::<Vec<u8>>();
+        let mut sender =
+            Context::new(&master_key, &master_salt, ProtectionProfile::AeadAes256Gcm).unwrap();
+        let mut receiver =
+            Context::new(&master_key, &master_salt, ProtectionProfile::AeadAes256Gcm).unwrap();
+
+        let mut plaintext = from_hex("8040f17b8041f8d35501a0b247616c6c6961");
+        let packet = RtpPacket::from_slice(&mut plaintext).unwrap();
+        let encrypted = sender.protect_rtp(&packet).unwrap();
+        assert_eq!(encrypted.len(), plaintext.len() + 16);
+        assert_eq!(receiver.unprotect_rtp(&encrypted).unwrap(), packet);
+
+        let plaintext = from_hex("81c8000d4d6172734e5450314e545032525450300000042a0000e930");
+        let encrypted = sender.protect_rtcp(&plaintext).unwrap();
+        assert_eq!(encrypted.len(), plaintext.len() + 16 + 4);
+        assert_eq!(receiver.unprotect_rtcp(&encrypted).unwrap(), plaintext);
+
+        // keyの長さが違う
+        assert_eq!(
+            Context::new(
+                &master_key[..16],
+                &master_salt,
+                ProtectionProfile::AeadAes256Gcm
+            )
+            .err(),
+            Some(SrtpError::InvalidMasterKeyLength)
+        );
+    }
+
+    #[test]
+    fn srtp_rollover_test() {
+        let mut sender = aes_cm_context(ProtectionProfile::Aes128CmHmacSha1_80);
+        let mut receiver = aes_cm_context(ProtectionProfile::Aes128CmHmacSha1_80);
+
+        let packet = |seq: u16| {
+            crate::rtp::packet::RtpPacketBuilder::new()
+                .sequence_number(seq)
+                .ssrc(1)
+                .payload(vec![0xAB; 8])
+                .build()
+                .unwrap()
+        };
+
+        let mut encrypted = vec![];
+        for seq in &[65534, 65535, 0, 1] {
+            encrypted.push(sender.protect_rtp(&packet(*seq)).unwrap());
+        }
+        assert_eq!(sender.roc(1), Some(1));
+
+        // wraparound前のpacketが遅れて届いても，ROCを推定して復号できる
+        for idx in &[0, 2, 3, 1] {
+            let decrypted = receiver.unprotect_rtp(&encrypted[*idx]).unwrap();
+            assert_eq!(decrypted, packet([65534, 65535, 0, 1][*idx]));
+        }
+        assert_eq!(receiver.roc(1), Some(1));
+
+        // ROCを知らないreceiverは認証に失敗する
+        let mut late_joiner = aes_cm_context(ProtectionProfile::Aes128CmHmacSha1_80);
+        assert_eq!(
+            late_joiner.unprotect_rtp(&encrypted[3]),
+            Err(SrtpError::AuthenticationFailed)
+        );
+        late_joiner.set_roc(1, 1);
+        assert!(late_joiner.unprotect_rtp(&encrypted[3]).is_ok());
+    }
+
+    #[test]
+    fn srtp_unauthenticated_ssrc_test() {
+        let mut receiver = aes_cm_context(ProtectionProfile::Aes128CmHmacSha1_80);
+
+        // 認証に失敗したpacketのSSRCは登録されない
+        let mut forged = from_hex(
+            "800f1234decafbad000000014e55dc4ce79978d88ca4d215949d2402b78d6acc99ea179b8dbb",
+        );
+        assert_eq!(
+            receiver.unprotect_rtp(&forged),
+            Err(SrtpError::AuthenticationFailed)
+        );
+        assert_eq!(receiver.roc(1), None);
+        assert!(receiver.srtp_ssrc_states.is_empty());
+
+        forged = from_hex(
+            "81c8000b000000017128035be487b9bdbef89041f977a5a880000001993e08cd54d6c1230798",
+        );
+        assert_eq!(
+            receiver.unprotect_rtcp(&forged),
+            Err(SrtpError::AuthenticationFailed)
+        );
+        assert!(receiver.srtcp_ssrc_states.is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+// https://tools.ietf.org/html/rfc3711#section-4.3
+
+use crate::srtp::{Result, SrtpError};
+use openssl::symm::{Cipher, Crypter, Mode};
+
+pub const LABEL_SRTP_ENCRYPTION: u8 = 0x00;
+pub const LABEL_SRTP_AUTHENTICATION: u8 = 0x01;
+pub const LABEL_SRTP_SALT: u8 = 0x02;
+pub const LABEL_SRTCP_ENCRYPTION: u8 = 0x03;
+pub const LABEL_SRTCP_AUTHENTICATION: u8 = 0x04;
+pub const LABEL_SRTCP_SALT: u8 = 0x05;
+
+pub(crate) fn aes_ctr_cipher(key: &[u8]) -> Result<Cipher> {
+    match key.len() {
+        16 => Ok(Cipher::aes_128_ctr()),
+        32 => Ok(Cipher::aes_256_ctr()),
+        _ => Err(SrtpError::InvalidMasterKeyLength),
+    }
+}
+
+// AES-CMでdataを暗号化/復号する (keystreamとのXOR)
+pub(crate) fn aes_cm_crypt(key: &[u8], iv: &[u8; 16], data: &[u8]) -> Result<Vec<u8>> {
+    let mut crypter = Crypter::new(aes_ctr_cipher(key)?, Mode::Encrypt, key, Some(iv))?;
+    let mut out = vec![0u8; data.len() + 16];
+    let mut count = crypter.update(data, &mut out)?;
+    count += crypter.finalize(&mut out[count..])?;
+    out.truncate(count);
+    Ok(out)
+}
+
+// key_derivation_rateは0とするので，index DIV kdrは常に0
+// x = (label || r) XOR master_salt をIVとしてAES-CMのkeystreamを取り出す
+pub fn aes_cm_key_derivation(
+    label: u8,
+    master_key: &[u8],
+    master_salt: &[u8],
+    out_len: usize,
+) -> Result<Vec<u8>> {
+    // AEADのmaster saltは96bitなので，後ろを0で埋めて112bitとして扱う
+    if master_salt.len() > 14 {
+        return Err(SrtpError::InvalidMasterSaltLength);
+    }
+
+    let mut iv = [0u8; 16];
+    iv[..master_salt.len()].copy_from_slice(master_salt);
+    iv[7] ^= label;
+
+    aes_cm_crypt(master_key, &iv, &vec![0u8; out_len])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // https://tools.ietf.org/html/rfc3711#appendix-B.3
+    #[test]
+    fn key_derivation_test() {
+        let master_key = [
+            0xE1, 0xF9, 0x7A, 0x0D, 0x3E, 0x01, 0x8B, 0xE0, 0xD6, 0x4F, 0xA3, 0x2C, 0x06, 0xDE,
+            0x41, 0x39,
+        ];
+        let master_salt = [
+            0x0E, 0xC6, 0x75, 0xAD, 0x49, 0x8A, 0xFE, 0xEB, 0xB6, 0x96, 0x0B, 0x3A, 0xAB, 0xE6,
+        ];
+
+        let key =
+            aes_cm_key_derivation(LABEL_SRTP_ENCRYPTION, &master_key, &master_salt, 16).unwrap();
+        assert_eq!(
+            key,
+            vec![
+                0xC6, 0x1E, 0x7A, 0x93, 0x74, 0x4F, 0x39, 0xEE, 0x10, 0x73, 0x4A, 0xFE, 0x3F, 0xF7,
+                0xA0, 0x87
+            ]
+        );
+
+        let salt = aes_cm_key_derivation(LABEL_SRTP_SALT, &master_key, &master_salt, 14).unwrap();
+        assert_eq!(
+            salt,
+            vec![
+                0x30, 0xCB, 0xBC, 0x08, 0x86, 0x3D, 0x8C, 0x85, 0xD4, 0x9D, 0xB3, 0x4A, 0x9A, 0xE1
+            ]
+        );
+
+        let auth_key =
+            aes_cm_key_derivation(LABEL_SRTP_AUTHENTICATION, &master_key, &master_salt, 20)
+                .unwrap();
+        assert_eq!(
+            auth_key,
+            vec![
+                0xCE, 0xBE, 0x32, 0x1F, 0x6F, 0xF7, 0x71, 0x6B, 0x6F, 0xD4, 0xAB, 0x49, 0xAF, 0x25,
+                0x6A, 0x15, 0x6D, 0x38, 0xBA, 0xA4
+            ]
+        );
+    }
+
+    // https://tools.ietf.org/html/rfc3711#appendix-B.2
+    #[test]
+    fn aes_cm_keystream_test() {
+        let session_key = [
+            0x2B, 0x7E, 0x15, 0x16, 0x28, 0xAE, 0xD2, 0xA6, 0xAB, 0xF7, 0x15, 0x88, 0x09, 0xCF,
+            0x4F, 0x3C,
+        ];
+        let iv = [
+            0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD,
+            0x00, 0x00,
+        ];
+
+        let keystream = aes_cm_crypt(&session_key, &iv, &[0u8; 48]).unwrap();
+        assert_eq!(
+            keystream,
+            vec![
+                0xE0, 0x3E, 0xAD, 0x09, 0x35, 0xC9, 0x5E, 0x80, 0xE1, 0x66, 0xB1, 0x6D, 0xD9, 0x2B,
+                0x4E, 0xB4, 0xD2, 0x35, 0x13, 0x16, 0x2B, 0x02, 0xD0, 0xF7, 0x2A, 0x43, 0xA2, 0xFE,
+                0x4A, 0x5F, 0x97, 0xAB, 0x41, 0xE9, 0x5B, 0x3B, 0xB0, 0xA2, 0xE8, 0xDD, 0x47, 0x79,
+                0x01, 0xE4, 0xFC, 0xA8, 0x94, 0xC0
+            ]
+        );
+    }
+}
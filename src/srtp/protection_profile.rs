@@ -0,0 +1,115 @@
+use crate::srtp::{Result, SrtpError};
+
+// https://www.iana.org/assignments/srtp-protection/srtp-protection.xhtml
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ProtectionProfile {
+    Aes128CmHmacSha1_80,
+    Aes128CmHmacSha1_32,
+    AeadAes128Gcm,
+    AeadAes256Gcm,
+}
+
+impl ProtectionProfile {
+    // DTLS use_srtp extensionで使われるID
+    pub fn from_id(id: u16) -> Result<ProtectionProfile> {
+        match id {
+            0x0001 => Ok(ProtectionProfile::Aes128CmHmacSha1_80),
+            0x0002 => Ok(ProtectionProfile::Aes128CmHmacSha1_32),
+            0x0007 => Ok(ProtectionProfile::AeadAes128Gcm),
+            0x0008 => Ok(ProtectionProfile::AeadAes256Gcm),
+            _ => Err(SrtpError::UnsupportedProtectionProfile { id }),
+        }
+    }
+
+    pub fn id(self) -> u16 {
+        match self {
+            ProtectionProfile::Aes128CmHmacSha1_80 => 0x0001,
+            ProtectionProfile::Aes128CmHmacSha1_32 => 0x0002,
+            ProtectionProfile::AeadAes128Gcm => 0x0007,
+            ProtectionProfile::AeadAes256Gcm => 0x0008,
+        }
+    }
+
+    // OpenSSLのSSL_CTX_set_tlsext_use_srtpに渡す名前
+    pub fn name(self) -> &'static str {
+        match self {
+            ProtectionProfile::Aes128CmHmacSha1_80 => "SRTP_AES128_CM_SHA1_80",
+            ProtectionProfile::Aes128CmHmacSha1_32 => "SRTP_AES128_CM_SHA1_32",
+            ProtectionProfile::AeadAes128Gcm => "SRTP_AEAD_AES_128_GCM",
+            ProtectionProfile::AeadAes256Gcm => "SRTP_AEAD_AES_256_GCM",
+        }
+    }
+
+    pub fn is_aead(self) -> bool {
+        matches!(
+            self,
+            ProtectionProfile::AeadAes128Gcm | ProtectionProfile::AeadAes256Gcm
+        )
+    }
+
+    pub fn key_len(self) -> usize {
+        match self {
+            ProtectionProfile::AeadAes256Gcm => 32,
+            _ => 16,
+        }
+    }
+
+    pub fn salt_len(self) -> usize {
+        if self.is_aead() {
+            12
+        } else {
+            14
+        }
+    }
+
+    pub fn auth_key_len(self) -> usize {
+        if self.is_aead() {
+            0
+        } else {
+            20
+        }
+    }
+
+    // SRTPのauthentication tag (AEADの場合はGCMのtag)
+    pub fn rtp_auth_tag_len(self) -> usize {
+        match self {
+            ProtectionProfile::Aes128CmHmacSha1_80 => 10,
+            ProtectionProfile::Aes128CmHmacSha1_32 => 4,
+            ProtectionProfile::AeadAes128Gcm | ProtectionProfile::AeadAes256Gcm => 16,
+        }
+    }
+
+    // SRTCPは_32でも80bitのtagを使う (RFC 5764 4.1.2)
+    pub fn rtcp_auth_tag_len(self) -> usize {
+        if self.is_aead() {
+            16
+        } else {
+            10
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn protection_profile_test() {
+        for id in &[0x0001, 0x0002, 0x0007, 0x0008] {
+            assert_eq!(ProtectionProfile::from_id(*id).unwrap().id(), *id);
+        }
+        assert_eq!(
+            ProtectionProfile::from_id(0x0005),
+            Err(SrtpError::UnsupportedProtectionProfile { id: 0x0005 })
+        );
+
+        let profile = ProtectionProfile::Aes128CmHmacSha1_32;
+        assert_eq!(profile.rtp_auth_tag_len(), 4);
+        assert_eq!(profile.rtcp_auth_tag_len(), 10);
+
+        let profile = ProtectionProfile::AeadAes256Gcm;
+        assert_eq!(profile.key_len(), 32);
+        assert_eq!(profile.salt_len(), 12);
+        assert_eq!(profile.auth_key_len(), 0);
+    }
+}
@@ -0,0 +1,91 @@
+// https://tools.ietf.org/html/rfc3711#section-3.3.2
+
+// 受信済みのindexをsliding windowで記録する．
+// checkで受け入れ可能か確認し，認証に成功した後にacceptで記録する．
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ReplayDetector {
+    // windowの大きさ (最大64)
+    window_size: u64,
+    latest: Option<u64>,
+    // bit iはlatest - iを受信済みかどうか
+    mask: u64,
+}
+
+impl ReplayDetector {
+    pub fn new(window_size: u64) -> ReplayDetector {
+        ReplayDetector {
+            window_size: window_size.clamp(1, 64),
+            latest: None,
+            mask: 0,
+        }
+    }
+
+    pub fn check(&self, index: u64) -> bool {
+        let latest = match self.latest {
+            Some(v) => v,
+            None => return true,
+        };
+
+        if index > latest {
+            return true;
+        }
+
+        let diff = latest - index;
+        if diff >= self.window_size {
+            // windowより古い
+            return false;
+        }
+
+        self.mask & (1 << diff) == 0
+    }
+
+    pub fn accept(&mut self, index: u64) {
+        let latest = match self.latest {
+            Some(v) => v,
+            None => {
+                self.latest = Some(index);
+                self.mask = 1;
+                return;
+            }
+        };
+
+        if index > latest {
+            let diff = index - latest;
+            self.mask = if diff >= 64 { 0 } else { self.mask << diff };
+            self.mask |= 1;
+            self.latest = Some(index);
+        } else if latest - index < self.window_size {
+            self.mask |= 1 << (latest - index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replay_detector_test() {
+        let mut detector = ReplayDetector::new(64);
+
+        for index in &[10, 12, 11, 100] {
+            assert!(detector.check(*index));
+            detector.accept(*index);
+        }
+
+        // 受信済み
+        assert!(!detector.check(100));
+        // windowの範囲内でまだ受信していない
+        assert!(detector.check(99));
+        assert!(detector.check(37));
+        // windowより古い
+        assert!(!detector.check(36));
+        assert!(!detector.check(12));
+
+        // checkだけでは記録されない
+        assert!(detector.check(101));
+        detector.accept(200);
+        assert!(!detector.check(100));
+        assert!(detector.check(199));
+    }
+}
@@ -0,0 +1,101 @@
+pub mod attribute;
+pub mod message;
+
+use crate::OctetsError;
+use failure::Fail;
+use openssl::error::ErrorStack;
+use openssl::hash::{hash, MessageDigest};
+use std::time::Duration;
+
+pub use attribute::Attribute;
+pub use message::{IntegrityAlgorithm, Message, MessageClass};
+
+pub type Result<T> = std::result::Result<T, StunError>;
+
+// UDPでのrequestの再送 (RFC 5389 7.2.1)
+pub const RTO: Duration = Duration::from_millis(500);
+pub const RC: u32 = 7;
+pub const RM: u32 = 16;
+// TCPでのtransaction timeout (RFC 5389 7.2.2)
+pub const TRANSACTION_TIMEOUT: Duration = Duration::from_millis(39_500);
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum StunError {
+    #[fail(display = "Octets manipulate failed: {:?}", error)]
+    OctetsError { error: OctetsError },
+
+    #[fail(display = "OpenSSL failed: {}", reason)]
+    CryptoError { reason: String },
+
+    #[fail(display = "Packet is not a STUN message.")]
+    NotStunMessage,
+
+    #[fail(display = "STUN message length is invalid.")]
+    InvalidMessageLength,
+
+    #[fail(display = "STUN attribute 0x{:04x} is invalid.", typ)]
+    InvalidAttribute { typ: u16 },
+
+    #[fail(display = "STUN FINGERPRINT does not match.")]
+    FingerprintMismatch,
+}
+
+impl From<OctetsError> for StunError {
+    fn from(error: OctetsError) -> Self {
+        StunError::OctetsError { error }
+    }
+}
+
+impl From<ErrorStack> for StunError {
+    fn from(error: ErrorStack) -> Self {
+        StunError::CryptoError {
+            reason: error.to_string(),
+        }
+    }
+}
+
+// long-term credentialのkey = MD5(username ":" realm ":" SASLprep(password)) (RFC 5389 15.4)
+// SASLprepは呼び出し側で済ませておく
+pub fn long_term_key(username: &str, realm: &str, password: &str) -> Result<Vec<u8>> {
+    let input = format!("{}:{}:{}", username, realm, password);
+    Ok(hash(MessageDigest::md5(), input.as_bytes())?.to_vec())
+}
+
+// requests回目を送ってから次に送る (または諦める) までの時間
+pub fn retransmission_timeout(requests: u32) -> Duration {
+    if requests < RC {
+        RTO * 2u32.pow(requests - 1)
+    } else {
+        RTO * RM
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn retransmission_timeout_test() {
+        assert_eq!(retransmission_timeout(1), RTO);
+        assert_eq!(retransmission_timeout(2), RTO * 2);
+        assert_eq!(retransmission_timeout(6), RTO * 32);
+        // Rc回送った後はRm*RTO待つ (合計39.5秒)
+        assert_eq!(retransmission_timeout(RC), RTO * RM);
+        assert_eq!(
+            (1..=RC).map(retransmission_timeout).sum::<Duration>(),
+            TRANSACTION_TIMEOUT
+        );
+    }
+
+    #[test]
+    fn long_term_key_test() {
+        let key = long_term_key("user", "realm", "pass").unwrap();
+        assert_eq!(
+            key,
+            vec![
+                0x84, 0x93, 0xFB, 0xC5, 0x3B, 0xA5, 0x82, 0xFB, 0x4C, 0x04, 0x4C, 0x45, 0x6B, 0xDC,
+                0x40, 0xEB
+            ]
+        );
+    }
+}
@@ -0,0 +1,341 @@
+// https://tools.ietf.org/html/rfc5389#section-15
+
+use crate::stun::message::MAGIC_COOKIE;
+use crate::stun::{Result, StunError};
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+pub const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+pub const ATTR_USERNAME: u16 = 0x0006;
+pub const ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+pub const ATTR_ERROR_CODE: u16 = 0x0009;
+pub const ATTR_UNKNOWN_ATTRIBUTES: u16 = 0x000A;
+// RFC 8656 18
+pub const ATTR_CHANNEL_NUMBER: u16 = 0x000C;
+pub const ATTR_LIFETIME: u16 = 0x000D;
+pub const ATTR_XOR_PEER_ADDRESS: u16 = 0x0012;
+pub const ATTR_DATA: u16 = 0x0013;
+pub const ATTR_REALM: u16 = 0x0014;
+pub const ATTR_NONCE: u16 = 0x0015;
+pub const ATTR_XOR_RELAYED_ADDRESS: u16 = 0x0016;
+pub const ATTR_REQUESTED_TRANSPORT: u16 = 0x0019;
+pub const ATTR_DONT_FRAGMENT: u16 = 0x001A;
+// RFC 8489 14.6
+pub const ATTR_MESSAGE_INTEGRITY_SHA256: u16 = 0x001C;
+pub const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+// RFC 8445 16.1
+pub const ATTR_PRIORITY: u16 = 0x0024;
+pub const ATTR_USE_CANDIDATE: u16 = 0x0025;
+pub const ATTR_SOFTWARE: u16 = 0x8022;
+pub const ATTR_FINGERPRINT: u16 = 0x8028;
+pub const ATTR_ICE_CONTROLLED: u16 = 0x8029;
+pub const ATTR_ICE_CONTROLLING: u16 = 0x802A;
+
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Attribute {
+    MappedAddress(SocketAddr),
+    Username(String),
+    MessageIntegrity(Vec<u8>),
+    ErrorCode { code: u16, reason: String },
+    UnknownAttributes(Vec<u16>),
+    ChannelNumber(u16),
+    Lifetime(u32),
+    XorPeerAddress(SocketAddr),
+    Data(Vec<u8>),
+    Realm(String),
+    Nonce(String),
+    XorRelayedAddress(SocketAddr),
+    // IANAのprotocol number (UDPは17)
+    RequestedTransport(u8),
+    DontFragment,
+    MessageIntegritySha256(Vec<u8>),
+    XorMappedAddress(SocketAddr),
+    Priority(u32),
+    UseCandidate,
+    Software(String),
+    Fingerprint(u32),
+    IceControlled(u64),
+    IceControlling(u64),
+    // 知らないattributeはそのまま残す
+    Unknown { typ: u16, value: Vec<u8> },
+}
+
+// 0x0000-0x7FFFは理解できなければ420を返す必要がある
+pub fn is_comprehension_required(typ: u16) -> bool {
+    typ < 0x8000
+}
+
+pub fn encode_address(addr: &SocketAddr) -> Vec<u8> {
+    let mut value = vec![0];
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            value.push(FAMILY_IPV4);
+            value.extend_from_slice(&addr.port().to_be_bytes());
+            value.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            value.push(FAMILY_IPV6);
+            value.extend_from_slice(&addr.port().to_be_bytes());
+            value.extend_from_slice(&ip.octets());
+        }
+    }
+    value
+}
+
+pub fn decode_address(typ: u16, value: &[u8]) -> Result<SocketAddr> {
+    if value.len() < 4 {
+        return Err(StunError::InvalidAttribute { typ });
+    }
+    let port = u16::from_be_bytes([value[2], value[3]]);
+    let ip = match (value[1], value.len()) {
+        (FAMILY_IPV4, 8) => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(&value[4..]);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        (FAMILY_IPV6, 20) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&value[4..]);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return Err(StunError::InvalidAttribute { typ }),
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+// portはmagic cookieの上位16bit, addressはmagic cookieとtransaction idでxorする
+fn xor_value(value: &mut [u8], transaction_id: &[u8; 12]) {
+    let mut key = [0u8; 16];
+    key[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    key[4..].copy_from_slice(transaction_id);
+
+    value[2] ^= key[0];
+    value[3] ^= key[1];
+    for (byte, k) in value[4..].iter_mut().zip(key.iter()) {
+        *byte ^= k;
+    }
+}
+
+pub fn encode_xor_address(addr: &SocketAddr, transaction_id: &[u8; 12]) -> Vec<u8> {
+    let mut value = encode_address(addr);
+    xor_value(&mut value, transaction_id);
+    value
+}
+
+pub fn decode_xor_address(typ: u16, value: &[u8], transaction_id: &[u8; 12]) -> Result<SocketAddr> {
+    let mut value = value.to_vec();
+    if value.len() < 4 {
+        return Err(StunError::InvalidAttribute { typ });
+    }
+    xor_value(&mut value, transaction_id);
+    decode_address(typ, &value)
+}
+
+fn decode_string(typ: u16, value: &[u8]) -> Result<String> {
+    String::from_utf8(value.to_vec()).map_err(|_| StunError::InvalidAttribute { typ })
+}
+
+fn decode_u32(typ: u16, value: &[u8]) -> Result<u32> {
+    if value.len() != 4 {
+        return Err(StunError::InvalidAttribute { typ });
+    }
+    Ok(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+}
+
+fn decode_u64(typ: u16, value: &[u8]) -> Result<u64> {
+    if value.len() != 8 {
+        return Err(StunError::InvalidAttribute { typ });
+    }
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(value);
+    Ok(u64::from_be_bytes(bytes))
+}
+
+impl Attribute {
+    pub fn typ(&self) -> u16 {
+        match self {
+            Attribute::MappedAddress(_) => ATTR_MAPPED_ADDRESS,
+            Attribute::Username(_) => ATTR_USERNAME,
+            Attribute::MessageIntegrity(_) => ATTR_MESSAGE_INTEGRITY,
+            Attribute::ErrorCode { .. } => ATTR_ERROR_CODE,
+            Attribute::UnknownAttributes(_) => ATTR_UNKNOWN_ATTRIBUTES,
+            Attribute::ChannelNumber(_) => ATTR_CHANNEL_NUMBER,
+            Attribute::Lifetime(_) => ATTR_LIFETIME,
+            Attribute::XorPeerAddress(_) => ATTR_XOR_PEER_ADDRESS,
+            Attribute::Data(_) => ATTR_DATA,
+            Attribute::Realm(_) => ATTR_REALM,
+            Attribute::Nonce(_) => ATTR_NONCE,
+            Attribute::XorRelayedAddress(_) => ATTR_XOR_RELAYED_ADDRESS,
+            Attribute::RequestedTransport(_) => ATTR_REQUESTED_TRANSPORT,
+            Attribute::DontFragment => ATTR_DONT_FRAGMENT,
+            Attribute::MessageIntegritySha256(_) => ATTR_MESSAGE_INTEGRITY_SHA256,
+            Attribute::XorMappedAddress(_) => ATTR_XOR_MAPPED_ADDRESS,
+            Attribute::Priority(_) => ATTR_PRIORITY,
+            Attribute::UseCandidate => ATTR_USE_CANDIDATE,
+            Attribute::Software(_) => ATTR_SOFTWARE,
+            Attribute::Fingerprint(_) => ATTR_FINGERPRINT,
+            Attribute::IceControlled(_) => ATTR_ICE_CONTROLLED,
+            Attribute::IceControlling(_) => ATTR_ICE_CONTROLLING,
+            Attribute::Unknown { typ, .. } => *typ,
+        }
+    }
+
+    // XOR-*-ADDRESSのためにtransaction idが必要
+    pub fn value(&self, transaction_id: &[u8; 12]) -> Vec<u8> {
+        match self {
+            Attribute::MappedAddress(addr) => encode_address(addr),
+            Attribute::Username(s)
+            | Attribute::Realm(s)
+            | Attribute::Nonce(s)
+            | Attribute::Software(s) => s.as_bytes().to_vec(),
+            Attribute::MessageIntegrity(hmac) | Attribute::MessageIntegritySha256(hmac) => {
+                hmac.clone()
+            }
+            Attribute::ErrorCode { code, reason } => {
+                let mut value = vec![0, 0, (code / 100) as u8, (code % 100) as u8];
+                value.extend_from_slice(reason.as_bytes());
+                value
+            }
+            Attribute::UnknownAttributes(types) => types
+                .iter()
+                .flat_map(|typ| typ.to_be_bytes().to_vec())
+                .collect(),
+            Attribute::ChannelNumber(number) => {
+                let mut value = number.to_be_bytes().to_vec();
+                value.extend_from_slice(&[0, 0]);
+                value
+            }
+            Attribute::Lifetime(lifetime) => lifetime.to_be_bytes().to_vec(),
+            Attribute::Data(data) => data.clone(),
+            Attribute::RequestedTransport(protocol) => vec![*protocol, 0, 0, 0],
+            Attribute::DontFragment => vec![],
+            Attribute::XorMappedAddress(addr)
+            | Attribute::XorPeerAddress(addr)
+            | Attribute::XorRelayedAddress(addr) => encode_xor_address(addr, transaction_id),
+            Attribute::Priority(priority) => priority.to_be_bytes().to_vec(),
+            Attribute::UseCandidate => vec![],
+            Attribute::Fingerprint(crc) => crc.to_be_bytes().to_vec(),
+            Attribute::IceControlled(tie_breaker) | Attribute::IceControlling(tie_breaker) => {
+                tie_breaker.to_be_bytes().to_vec()
+            }
+            Attribute::Unknown { value, .. } => value.clone(),
+        }
+    }
+
+    pub fn from_value(typ: u16, value: &[u8], transaction_id: &[u8; 12]) -> Result<Attribute> {
+        let attribute = match typ {
+            ATTR_MAPPED_ADDRESS => Attribute::MappedAddress(decode_address(typ, value)?),
+            ATTR_USERNAME => Attribute::Username(decode_string(typ, value)?),
+            ATTR_MESSAGE_INTEGRITY => {
+                if value.len() != 20 {
+                    return Err(StunError::InvalidAttribute { typ });
+                }
+                Attribute::MessageIntegrity(value.to_vec())
+            }
+            ATTR_ERROR_CODE => {
+                if value.len() < 4 {
+                    return Err(StunError::InvalidAttribute { typ });
+                }
+                Attribute::ErrorCode {
+                    code: u16::from(value[2] & 0x07) * 100 + u16::from(value[3]),
+                    reason: decode_string(typ, &value[4..])?,
+                }
+            }
+            ATTR_UNKNOWN_ATTRIBUTES => {
+                if value.len() % 2 != 0 {
+                    return Err(StunError::InvalidAttribute { typ });
+                }
+                Attribute::UnknownAttributes(
+                    value
+                        .chunks(2)
+                        .map(|typ| u16::from_be_bytes([typ[0], typ[1]]))
+                        .collect(),
+                )
+            }
+            ATTR_CHANNEL_NUMBER => Attribute::ChannelNumber((decode_u32(typ, value)? >> 16) as u16),
+            ATTR_LIFETIME => Attribute::Lifetime(decode_u32(typ, value)?),
+            ATTR_XOR_PEER_ADDRESS => {
+                Attribute::XorPeerAddress(decode_xor_address(typ, value, transaction_id)?)
+            }
+            ATTR_DATA => Attribute::Data(value.to_vec()),
+            ATTR_REALM => Attribute::Realm(decode_string(typ, value)?),
+            ATTR_NONCE => Attribute::Nonce(decode_string(typ, value)?),
+            ATTR_XOR_RELAYED_ADDRESS => {
+                Attribute::XorRelayedAddress(decode_xor_address(typ, value, transaction_id)?)
+            }
+            ATTR_REQUESTED_TRANSPORT => {
+                Attribute::RequestedTransport((decode_u32(typ, value)? >> 24) as u8)
+            }
+            ATTR_DONT_FRAGMENT => Attribute::DontFragment,
+            ATTR_MESSAGE_INTEGRITY_SHA256 => {
+                // 16byte以上に切り詰めても良い
+                if value.len() < 16 || value.len() > 32 || value.len() % 4 != 0 {
+                    return Err(StunError::InvalidAttribute { typ });
+                }
+                Attribute::MessageIntegritySha256(value.to_vec())
+            }
+            ATTR_XOR_MAPPED_ADDRESS => {
+                Attribute::XorMappedAddress(decode_xor_address(typ, value, transaction_id)?)
+            }
+            ATTR_PRIORITY => Attribute::Priority(decode_u32(typ, value)?),
+            ATTR_USE_CANDIDATE => Attribute::UseCandidate,
+            ATTR_SOFTWARE => Attribute::Software(decode_string(typ, value)?),
+            ATTR_FINGERPRINT => Attribute::Fingerprint(decode_u32(typ, value)?),
+            ATTR_ICE_CONTROLLED => Attribute::IceControlled(decode_u64(typ, value)?),
+            ATTR_ICE_CONTROLLING => Attribute::IceControlling(decode_u64(typ, value)?),
+            _ => Attribute::Unknown {
+                typ,
+                value: value.to_vec(),
+            },
+        };
+        Ok(attribute)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn attribute_value_test() {
+        let transaction_id = [0u8; 12];
+        let attributes = vec![
+            Attribute::MappedAddress("192.0.2.1:3478".parse().unwrap()),
+            Attribute::ErrorCode {
+                code: 420,
+                reason: "Unknown Attribute".to_owned(),
+            },
+            Attribute::UnknownAttributes(vec![0x0003, 0x7FFF]),
+            Attribute::Nonce("f//499k954d6OL34oL9FSTvy64sA".to_owned()),
+            Attribute::XorMappedAddress("[2001:db8::1]:50000".parse().unwrap()),
+            Attribute::IceControlled(0x0102_0304_0506_0708),
+            Attribute::ChannelNumber(0x4001),
+            Attribute::Lifetime(600),
+            Attribute::XorPeerAddress("198.51.100.1:40000".parse().unwrap()),
+            Attribute::XorRelayedAddress("[2001:db8::2]:49152".parse().unwrap()),
+            Attribute::RequestedTransport(17),
+            Attribute::Data(vec![0xDE, 0xAD, 0xBE]),
+            Attribute::Unknown {
+                typ: 0xC001,
+                value: vec![1, 2, 3],
+            },
+        ];
+        for attribute in attributes {
+            let value = attribute.value(&transaction_id);
+            assert_eq!(
+                Attribute::from_value(attribute.typ(), &value, &transaction_id).unwrap(),
+                attribute
+            );
+        }
+
+        assert_eq!(
+            Attribute::from_value(ATTR_PRIORITY, &[0, 1], &transaction_id),
+            Err(StunError::InvalidAttribute { typ: ATTR_PRIORITY })
+        );
+        assert!(is_comprehension_required(ATTR_USERNAME));
+        assert!(!is_comprehension_required(ATTR_SOFTWARE));
+    }
+}
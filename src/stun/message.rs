@@ -0,0 +1,687 @@
+// https://tools.ietf.org/html/rfc5389#section-6
+
+use crate::octets::Octets;
+use crate::stun::attribute::{self, Attribute};
+use crate::stun::{Result, StunError};
+
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use std::net::SocketAddr;
+
+pub const HEADER_SIZE: usize = 20;
+pub const MAGIC_COOKIE: u32 = 0x2112_A442;
+const FINGERPRINT_XOR: u32 = 0x5354_554E;
+
+pub const METHOD_BINDING: u16 = 0x0001;
+
+pub const ERROR_TRY_ALTERNATE: u16 = 300;
+pub const ERROR_BAD_REQUEST: u16 = 400;
+pub const ERROR_UNAUTHORIZED: u16 = 401;
+pub const ERROR_UNKNOWN_ATTRIBUTE: u16 = 420;
+pub const ERROR_STALE_NONCE: u16 = 438;
+// RFC 8445 7.3.1.1
+pub const ERROR_ROLE_CONFLICT: u16 = 487;
+pub const ERROR_SERVER_ERROR: u16 = 500;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MessageClass {
+    Request,
+    Indication,
+    SuccessResponse,
+    ErrorResponse,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IntegrityAlgorithm {
+    // MESSAGE-INTEGRITY
+    Sha1,
+    // MESSAGE-INTEGRITY-SHA256
+    Sha256,
+}
+
+impl IntegrityAlgorithm {
+    fn typ(self) -> u16 {
+        match self {
+            IntegrityAlgorithm::Sha1 => attribute::ATTR_MESSAGE_INTEGRITY,
+            IntegrityAlgorithm::Sha256 => attribute::ATTR_MESSAGE_INTEGRITY_SHA256,
+        }
+    }
+
+    fn digest(self) -> MessageDigest {
+        match self {
+            IntegrityAlgorithm::Sha1 => MessageDigest::sha1(),
+            IntegrityAlgorithm::Sha256 => MessageDigest::sha256(),
+        }
+    }
+
+    fn len(self) -> usize {
+        match self {
+            IntegrityAlgorithm::Sha1 => 20,
+            IntegrityAlgorithm::Sha256 => 32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Message {
+    pub class: MessageClass,
+    pub method: u16,
+    pub transaction_id: [u8; 12],
+    pub attributes: Vec<Attribute>,
+    // 受信したmessageのMESSAGE-INTEGRITY(-SHA256)の位置 (検証用)
+    integrity_offset: Option<usize>,
+    integrity_sha256_offset: Option<usize>,
+}
+
+// 先頭2bitが0でmagic cookieがあればSTUN (RFC 7983でDTLS/RTPと区別する)
+pub fn is_stun_message(bytes: &[u8]) -> bool {
+    bytes.len() >= HEADER_SIZE && bytes[0] & 0xC0 == 0 && bytes[4..8] == MAGIC_COOKIE.to_be_bytes()
+}
+
+// CRC-32 (ISO 3309)
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn hmac(digest: MessageDigest, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(digest, &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+fn padding(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+// headerのlengthをoffsetまでとsize byteのattributeを含めた値にする
+fn set_length(buf: &mut [u8], offset: usize, size: usize) {
+    buf[2..4].copy_from_slice(&((offset - HEADER_SIZE + size) as u16).to_be_bytes());
+}
+
+impl Message {
+    pub fn new(class: MessageClass, method: u16, transaction_id: [u8; 12]) -> Message {
+        Message {
+            class,
+            method,
+            transaction_id,
+            attributes: vec![],
+            integrity_offset: None,
+            integrity_sha256_offset: None,
+        }
+    }
+
+    pub fn random_transaction_id() -> [u8; 12] {
+        rand::random()
+    }
+
+    // requestに対するresponse (transaction idとmethodを引き継ぐ)
+    pub fn response(&self, class: MessageClass) -> Message {
+        Message::new(class, self.method, self.transaction_id)
+    }
+
+    pub fn error_response(&self, code: u16, reason: &str) -> Message {
+        let mut response = self.response(MessageClass::ErrorResponse);
+        response.attributes.push(Attribute::ErrorCode {
+            code,
+            reason: reason.to_owned(),
+        });
+        response
+    }
+
+    pub fn get(&self, typ: u16) -> Option<&Attribute> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.typ() == typ)
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Username(username) => Some(username.as_str()),
+                _ => None,
+            })
+    }
+
+    pub fn realm(&self) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Realm(realm) => Some(realm.as_str()),
+                _ => None,
+            })
+    }
+
+    pub fn nonce(&self) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Nonce(nonce) => Some(nonce.as_str()),
+                _ => None,
+            })
+    }
+
+    pub fn software(&self) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Software(software) => Some(software.as_str()),
+                _ => None,
+            })
+    }
+
+    pub fn priority(&self) -> Option<u32> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Priority(priority) => Some(*priority),
+                _ => None,
+            })
+    }
+
+    pub fn use_candidate(&self) -> bool {
+        self.attributes.contains(&Attribute::UseCandidate)
+    }
+
+    pub fn ice_controlling(&self) -> Option<u64> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::IceControlling(tie_breaker) => Some(*tie_breaker),
+                _ => None,
+            })
+    }
+
+    pub fn ice_controlled(&self) -> Option<u64> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::IceControlled(tie_breaker) => Some(*tie_breaker),
+                _ => None,
+            })
+    }
+
+    pub fn mapped_address(&self) -> Option<SocketAddr> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::MappedAddress(addr) => Some(*addr),
+                _ => None,
+            })
+    }
+
+    pub fn xor_mapped_address(&self) -> Option<SocketAddr> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::XorMappedAddress(addr) => Some(*addr),
+                _ => None,
+            })
+    }
+
+    pub fn lifetime(&self) -> Option<u32> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Lifetime(lifetime) => Some(*lifetime),
+                _ => None,
+            })
+    }
+
+    pub fn channel_number(&self) -> Option<u16> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::ChannelNumber(number) => Some(*number),
+                _ => None,
+            })
+    }
+
+    pub fn xor_peer_addresses(&self) -> Vec<SocketAddr> {
+        self.attributes
+            .iter()
+            .filter_map(|attribute| match attribute {
+                Attribute::XorPeerAddress(addr) => Some(*addr),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn xor_relayed_address(&self) -> Option<SocketAddr> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::XorRelayedAddress(addr) => Some(*addr),
+                _ => None,
+            })
+    }
+
+    pub fn requested_transport(&self) -> Option<u8> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::RequestedTransport(protocol) => Some(*protocol),
+                _ => None,
+            })
+    }
+
+    pub fn data(&self) -> Option<&[u8]> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Data(data) => Some(data.as_slice()),
+                _ => None,
+            })
+    }
+
+    pub fn error_code(&self) -> Option<u16> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::ErrorCode { code, .. } => Some(*code),
+                _ => None,
+            })
+    }
+
+    // 420 Unknown Attributeで返すべきattribute
+    pub fn unknown_comprehension_required(&self) -> Vec<u16> {
+        self.attributes
+            .iter()
+            .filter_map(|attribute| match attribute {
+                Attribute::Unknown { typ, .. } if attribute::is_comprehension_required(*typ) => {
+                    Some(*typ)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn message_type(&self) -> u16 {
+        let class = match self.class {
+            MessageClass::Request => 0b00,
+            MessageClass::Indication => 0b01,
+            MessageClass::SuccessResponse => 0b10,
+            MessageClass::ErrorResponse => 0b11,
+        };
+        // M11..M7 C1 M6..M4 C0 M3..M0
+        let method = self.method & 0x0FFF;
+        (method & 0x000F)
+            | ((class & 0b01) << 4)
+            | ((method & 0x0070) << 1)
+            | ((class & 0b10) << 7)
+            | ((method & 0x0F80) << 2)
+    }
+
+    // keyがあればMESSAGE-INTEGRITYを付け，最後にFINGERPRINTを付ける (ICE)
+    pub fn encode(&self, integrity_key: Option<&[u8]>) -> Result<Vec<u8>> {
+        self.encode_with(integrity_key, &[IntegrityAlgorithm::Sha1], true)
+    }
+
+    // attributesのMESSAGE-INTEGRITY(-SHA256)とFINGERPRINTは無視して計算し直す
+    pub fn encode_with(
+        &self,
+        integrity_key: Option<&[u8]>,
+        algorithms: &[IntegrityAlgorithm],
+        fingerprint: bool,
+    ) -> Result<Vec<u8>> {
+        let attributes = self
+            .attributes
+            .iter()
+            .filter(|attribute| {
+                !matches!(
+                    attribute,
+                    Attribute::MessageIntegrity(_)
+                        | Attribute::MessageIntegritySha256(_)
+                        | Attribute::Fingerprint(_)
+                )
+            })
+            .map(|attribute| (attribute.typ(), attribute.value(&self.transaction_id)))
+            .collect::<Vec<_>>();
+
+        let body_len: usize = attributes
+            .iter()
+            .map(|(_, value)| 4 + value.len() + padding(value.len()))
+            .sum();
+        let integrity_len: usize = match integrity_key {
+            Some(_) => algorithms.iter().map(|algorithm| 4 + algorithm.len()).sum(),
+            None => 0,
+        };
+        let fingerprint_len = if fingerprint { 8 } else { 0 };
+        let mut buf = vec![0u8; HEADER_SIZE + body_len + integrity_len + fingerprint_len];
+
+        {
+            let mut out = Octets::with_slice(&mut buf);
+            out.put_u16(self.message_type())?;
+            out.put_u16(body_len as u16)?;
+            out.put_u32(MAGIC_COOKIE)?;
+            out.put_bytes(&self.transaction_id)?;
+            for (typ, value) in &attributes {
+                out.put_u16(*typ)?;
+                out.put_u16(value.len() as u16)?;
+                out.put_bytes(value)?;
+                out.put_bytes(&[0u8; 3][..padding(value.len())])?;
+            }
+        }
+
+        let mut off = HEADER_SIZE + body_len;
+        if let Some(key) = integrity_key {
+            for algorithm in algorithms {
+                let size = 4 + algorithm.len();
+                set_length(&mut buf, off, size);
+                let hmac = hmac(algorithm.digest(), key, &buf[..off])?;
+                let mut out = Octets::with_slice(&mut buf[off..]);
+                out.put_u16(algorithm.typ())?;
+                out.put_u16(algorithm.len() as u16)?;
+                out.put_bytes(&hmac)?;
+                off += size;
+            }
+        }
+
+        if fingerprint {
+            set_length(&mut buf, off, 8);
+            let crc = crc32(&buf[..off]) ^ FINGERPRINT_XOR;
+            let mut out = Octets::with_slice(&mut buf[off..]);
+            out.put_u16(attribute::ATTR_FINGERPRINT)?;
+            out.put_u16(4)?;
+            out.put_u32(crc)?;
+        } else {
+            set_length(&mut buf, off, 0);
+        }
+
+        Ok(buf)
+    }
+
+    // FINGERPRINTがあれば検証する．MESSAGE-INTEGRITYはcheck_integrityで検証する
+    pub fn decode(bytes: &[u8]) -> Result<Message> {
+        if !is_stun_message(bytes) {
+            return Err(StunError::NotStunMessage);
+        }
+
+        let mut buf = bytes.to_vec();
+        let mut octets = Octets::with_slice(&mut buf);
+        let message_type = octets.get_u16()?;
+        let length = octets.get_u16()? as usize;
+        octets.get_u32()?;
+        let mut transaction_id = [0u8; 12];
+        transaction_id.copy_from_slice(octets.get_bytes(12)?.as_ref());
+
+        if HEADER_SIZE + length != bytes.len() || length % 4 != 0 {
+            return Err(StunError::InvalidMessageLength);
+        }
+
+        let class = match ((message_type >> 7) & 0b10) | ((message_type >> 4) & 0b01) {
+            0b00 => MessageClass::Request,
+            0b01 => MessageClass::Indication,
+            0b10 => MessageClass::SuccessResponse,
+            _ => MessageClass::ErrorResponse,
+        };
+        let method = (message_type & 0x000F)
+            | ((message_type >> 1) & 0x0070)
+            | ((message_type >> 2) & 0x0F80);
+
+        let mut message = Message::new(class, method, transaction_id);
+
+        while octets.cap() > 0 {
+            // FINGERPRINTは最後でなければならない
+            if let Some(Attribute::Fingerprint(_)) = message.attributes.last() {
+                return Err(StunError::InvalidAttribute {
+                    typ: attribute::ATTR_FINGERPRINT,
+                });
+            }
+
+            let offset = octets.off();
+            let typ = octets.get_u16()?;
+            let len = octets.get_u16()? as usize;
+            let value = octets
+                .get_bytes(len)
+                .map_err(|_| StunError::InvalidMessageLength)?
+                .to_vec();
+            octets
+                .get_bytes(padding(len))
+                .map_err(|_| StunError::InvalidMessageLength)?;
+
+            let attribute = Attribute::from_value(typ, &value, &transaction_id)?;
+            match attribute {
+                Attribute::MessageIntegrity(_) => message.integrity_offset = Some(offset),
+                Attribute::MessageIntegritySha256(_) => {
+                    message.integrity_sha256_offset = Some(offset)
+                }
+                Attribute::Fingerprint(crc) if crc32(&bytes[..offset]) ^ FINGERPRINT_XOR != crc => {
+                    return Err(StunError::FingerprintMismatch);
+                }
+                _ => {}
+            }
+            message.attributes.push(attribute);
+        }
+
+        Ok(message)
+    }
+
+    fn check(
+        &self,
+        bytes: &[u8],
+        key: &[u8],
+        algorithm: IntegrityAlgorithm,
+        offset: Option<usize>,
+    ) -> bool {
+        let offset = match offset {
+            Some(offset) if offset + 4 <= bytes.len() => offset,
+            _ => return false,
+        };
+        let len = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+        if offset + 4 + len > bytes.len() {
+            return false;
+        }
+
+        let mut data = bytes[..offset].to_vec();
+        set_length(&mut data, offset, 4 + len);
+        match hmac(algorithm.digest(), key, &data) {
+            Ok(hmac) => openssl::memcmp::eq(&hmac[..len], &bytes[offset + 4..offset + 4 + len]),
+            Err(_) => false,
+        }
+    }
+
+    // decodeした時の生のbytesとkeyでMESSAGE-INTEGRITYを検証する
+    // short-termはpasswordそのもの，long-termはlong_term_keyをkeyにする
+    pub fn check_integrity(&self, bytes: &[u8], key: &[u8]) -> bool {
+        self.check(bytes, key, IntegrityAlgorithm::Sha1, self.integrity_offset)
+    }
+
+    pub fn check_integrity_sha256(&self, bytes: &[u8], key: &[u8]) -> bool {
+        self.check(
+            bytes,
+            key,
+            IntegrityAlgorithm::Sha256,
+            self.integrity_sha256_offset,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stun::long_term_key;
+
+    // https://tools.ietf.org/html/rfc5769#section-2.1
+    const SAMPLE_REQUEST: [u8; 108] = [
+        0x00, 0x01, 0x00, 0x58, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6,
+        0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x10, 0x53, 0x54, 0x55, 0x4e, 0x20, 0x74,
+        0x65, 0x73, 0x74, 0x20, 0x63, 0x6c, 0x69, 0x65, 0x6e, 0x74, 0x00, 0x24, 0x00, 0x04, 0x6e,
+        0x00, 0x01, 0xff, 0x80, 0x29, 0x00, 0x08, 0x93, 0x2f, 0xf9, 0xb1, 0x51, 0x26, 0x3b, 0x36,
+        0x00, 0x06, 0x00, 0x09, 0x65, 0x76, 0x74, 0x6a, 0x3a, 0x68, 0x36, 0x76, 0x59, 0x20, 0x20,
+        0x20, 0x00, 0x08, 0x00, 0x14, 0x9a, 0xea, 0xa7, 0x0c, 0xbf, 0xd8, 0xcb, 0x56, 0x78, 0x1e,
+        0xf2, 0xb5, 0xb2, 0xd3, 0xf2, 0x49, 0xc1, 0xb5, 0x71, 0xa2, 0x80, 0x28, 0x00, 0x04, 0xe5,
+        0x7a, 0x3b, 0xcf,
+    ];
+
+    // https://tools.ietf.org/html/rfc5769#section-2.2
+    const SAMPLE_IPV4_RESPONSE: [u8; 80] = [
+        0x01, 0x01, 0x00, 0x3c, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6,
+        0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74, 0x20, 0x76,
+        0x65, 0x63, 0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1,
+        0x12, 0xa6, 0x43, 0x00, 0x08, 0x00, 0x14, 0x2b, 0x91, 0xf5, 0x99, 0xfd, 0x9e, 0x90, 0xc3,
+        0x8c, 0x74, 0x89, 0xf9, 0x2a, 0xf9, 0xba, 0x53, 0xf0, 0x6b, 0xe7, 0xd7, 0x80, 0x28, 0x00,
+        0x04, 0xc0, 0x7d, 0x4c, 0x96,
+    ];
+
+    // https://tools.ietf.org/html/rfc5769#section-2.3
+    const SAMPLE_IPV6_RESPONSE: [u8; 92] = [
+        0x01, 0x01, 0x00, 0x48, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6,
+        0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74, 0x20, 0x76,
+        0x65, 0x63, 0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00, 0x14, 0x00, 0x02, 0xa1, 0x47, 0x01,
+        0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1, 0x79, 0xbc, 0x25, 0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+        0x00, 0x08, 0x00, 0x14, 0xa3, 0x82, 0x95, 0x4e, 0x4b, 0xe6, 0x7b, 0xf1, 0x17, 0x84, 0xc9,
+        0x7c, 0x82, 0x92, 0xc2, 0x75, 0xbf, 0xe3, 0xed, 0x41, 0x80, 0x28, 0x00, 0x04, 0xc8, 0xfb,
+        0x0b, 0x4c,
+    ];
+
+    // https://tools.ietf.org/html/rfc5769#section-2.4
+    const SAMPLE_LONG_TERM_REQUEST: [u8; 116] = [
+        0x00, 0x01, 0x00, 0x60, 0x21, 0x12, 0xa4, 0x42, 0x78, 0xad, 0x34, 0x33, 0xc6, 0xad, 0x72,
+        0xc0, 0x29, 0xda, 0x41, 0x2e, 0x00, 0x06, 0x00, 0x12, 0xe3, 0x83, 0x9e, 0xe3, 0x83, 0x88,
+        0xe3, 0x83, 0xaa, 0xe3, 0x83, 0x83, 0xe3, 0x82, 0xaf, 0xe3, 0x82, 0xb9, 0x00, 0x00, 0x00,
+        0x15, 0x00, 0x1c, 0x66, 0x2f, 0x2f, 0x34, 0x39, 0x39, 0x6b, 0x39, 0x35, 0x34, 0x64, 0x36,
+        0x4f, 0x4c, 0x33, 0x34, 0x6f, 0x4c, 0x39, 0x46, 0x53, 0x54, 0x76, 0x79, 0x36, 0x34, 0x73,
+        0x41, 0x00, 0x14, 0x00, 0x0b, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x6f, 0x72,
+        0x67, 0x00, 0x00, 0x08, 0x00, 0x14, 0xf6, 0x70, 0x24, 0x65, 0x6d, 0xd6, 0x4a, 0x3e, 0x02,
+        0xb8, 0xe0, 0x71, 0x2e, 0x85, 0xc9, 0xa2, 0x8c, 0xa8, 0x96, 0x66,
+    ];
+
+    const SAMPLE_TRANSACTION_ID: [u8; 12] = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    const SAMPLE_PASSWORD: &[u8] = b"VOkJxbRl1RmTxUk/WvJxBt";
+
+    #[test]
+    fn sample_request_test() {
+        let message = Message::decode(&SAMPLE_REQUEST).unwrap();
+        assert_eq!(message.class, MessageClass::Request);
+        assert_eq!(message.method, METHOD_BINDING);
+        assert_eq!(message.transaction_id, SAMPLE_TRANSACTION_ID);
+        assert_eq!(message.software(), Some("STUN test client"));
+        assert_eq!(message.priority(), Some(0x6e00_01ff));
+        assert_eq!(message.ice_controlled(), Some(0x932f_f9b1_5126_3b36));
+        assert_eq!(message.username(), Some("evtj:h6vY"));
+        assert!(message.check_integrity(&SAMPLE_REQUEST, SAMPLE_PASSWORD));
+        assert!(!message.check_integrity(&SAMPLE_REQUEST, b"wrong password"));
+
+        // paddingの値はMESSAGE-INTEGRITYに影響するので，encodeし直した物で比べる
+        let encoded = message.encode(Some(SAMPLE_PASSWORD)).unwrap();
+        assert_eq!(encoded.len(), SAMPLE_REQUEST.len());
+        let decoded = Message::decode(&encoded).unwrap();
+        assert!(decoded.check_integrity(&encoded, SAMPLE_PASSWORD));
+        assert_eq!(decoded.attributes.len(), message.attributes.len());
+        assert_eq!(decoded.attributes[..4], message.attributes[..4]);
+    }
+
+    #[test]
+    fn sample_response_test() {
+        for (bytes, addr) in &[
+            (&SAMPLE_IPV4_RESPONSE[..], "192.0.2.1:32853"),
+            (
+                &SAMPLE_IPV6_RESPONSE[..],
+                "[2001:db8:1234:5678:11:2233:4455:6677]:32853",
+            ),
+        ] {
+            let message = Message::decode(bytes).unwrap();
+            assert_eq!(message.class, MessageClass::SuccessResponse);
+            assert_eq!(message.method, METHOD_BINDING);
+            assert_eq!(message.software(), Some("test vector"));
+            assert_eq!(message.xor_mapped_address(), Some(addr.parse().unwrap()));
+            assert!(message.check_integrity(bytes, SAMPLE_PASSWORD));
+        }
+    }
+
+    #[test]
+    fn sample_long_term_request_test() {
+        let message = Message::decode(&SAMPLE_LONG_TERM_REQUEST).unwrap();
+        assert_eq!(message.username(), Some("マトリックス"));
+        assert_eq!(message.nonce(), Some("f//499k954d6OL34oL9FSTvy64sA"));
+        assert_eq!(message.realm(), Some("example.org"));
+
+        // "The<U+00AD>M<U+00AA>TR<U+2168>"をSASLprepすると"TheMatrIX"
+        let key = long_term_key("マトリックス", "example.org", "TheMatrIX").unwrap();
+        assert!(message.check_integrity(&SAMPLE_LONG_TERM_REQUEST, &key));
+    }
+
+    #[test]
+    fn fingerprint_test() {
+        let mut bytes = SAMPLE_IPV4_RESPONSE;
+        bytes[25] ^= 0x01;
+        assert_eq!(Message::decode(&bytes), Err(StunError::FingerprintMismatch));
+
+        assert_eq!(
+            Message::decode(&SAMPLE_IPV4_RESPONSE[..40]),
+            Err(StunError::InvalidMessageLength)
+        );
+        assert_eq!(Message::decode(&[0x80; 20]), Err(StunError::NotStunMessage));
+    }
+
+    #[test]
+    fn integrity_sha256_test() {
+        let mut request = Message::new(
+            MessageClass::Request,
+            METHOD_BINDING,
+            Message::random_transaction_id(),
+        );
+        request
+            .attributes
+            .push(Attribute::Username("user".to_owned()));
+
+        let bytes = request
+            .encode_with(
+                Some(b"key"),
+                &[IntegrityAlgorithm::Sha1, IntegrityAlgorithm::Sha256],
+                false,
+            )
+            .unwrap();
+        assert_eq!(bytes.len(), HEADER_SIZE + 8 + 24 + 36);
+
+        let decoded = Message::decode(&bytes).unwrap();
+        assert!(decoded.check_integrity(&bytes, b"key"));
+        assert!(decoded.check_integrity_sha256(&bytes, b"key"));
+        assert!(!decoded.check_integrity_sha256(&bytes, b"other"));
+        assert_eq!(decoded.get(attribute::ATTR_FINGERPRINT), None);
+    }
+
+    #[test]
+    fn unknown_attribute_test() {
+        let mut request = Message::new(
+            MessageClass::Request,
+            METHOD_BINDING,
+            Message::random_transaction_id(),
+        );
+        request.attributes.push(Attribute::Unknown {
+            typ: 0x7F00,
+            value: vec![1, 2, 3, 4, 5],
+        });
+        request.attributes.push(Attribute::Unknown {
+            typ: 0xC057,
+            value: vec![0, 1],
+        });
+
+        let bytes = request.encode(None).unwrap();
+        let decoded = Message::decode(&bytes).unwrap();
+        assert_eq!(decoded.attributes[..2], request.attributes[..]);
+        assert_eq!(decoded.unknown_comprehension_required(), vec![0x7F00]);
+
+        let response = decoded.error_response(ERROR_UNKNOWN_ATTRIBUTE, "Unknown Attribute");
+        assert_eq!(response.class, MessageClass::ErrorResponse);
+        assert_eq!(response.transaction_id, request.transaction_id);
+        assert_eq!(response.error_code(), Some(ERROR_UNKNOWN_ATTRIBUTE));
+    }
+}
@@ -0,0 +1,162 @@
+pub mod channel_data;
+pub mod client;
+pub mod server;
+
+use crate::stun::message::{is_stun_message, HEADER_SIZE};
+use crate::stun::StunError;
+use crate::OctetsError;
+use failure::Fail;
+use std::io::{self, Read};
+use std::time::Duration;
+
+pub use channel_data::ChannelData;
+pub use client::TurnClient;
+pub use server::{
+    AuthHandler, RelayAllocator, ServerConfig, StaticAuthHandler, StaticRelayAllocator, TurnServer,
+};
+
+pub type Result<T> = std::result::Result<T, TurnError>;
+
+// https://tools.ietf.org/html/rfc8656#section-17
+pub const METHOD_ALLOCATE: u16 = 0x0003;
+pub const METHOD_REFRESH: u16 = 0x0004;
+pub const METHOD_SEND: u16 = 0x0006;
+pub const METHOD_DATA: u16 = 0x0007;
+pub const METHOD_CREATE_PERMISSION: u16 = 0x0008;
+pub const METHOD_CHANNEL_BIND: u16 = 0x0009;
+
+// REQUESTED-TRANSPORTに入れるprotocol number
+pub const TRANSPORT_UDP: u8 = 17;
+
+pub const ERROR_FORBIDDEN: u16 = 403;
+pub const ERROR_ALLOCATION_MISMATCH: u16 = 437;
+pub const ERROR_WRONG_CREDENTIALS: u16 = 441;
+pub const ERROR_UNSUPPORTED_TRANSPORT_PROTOCOL: u16 = 442;
+pub const ERROR_PEER_ADDRESS_FAMILY_MISMATCH: u16 = 443;
+pub const ERROR_ALLOCATION_QUOTA_REACHED: u16 = 486;
+pub const ERROR_INSUFFICIENT_CAPACITY: u16 = 508;
+
+pub const DEFAULT_ALLOCATION_LIFETIME: Duration = Duration::from_secs(600);
+pub const MAX_ALLOCATION_LIFETIME: Duration = Duration::from_secs(3600);
+pub const PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
+pub const CHANNEL_LIFETIME: Duration = Duration::from_secs(600);
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum TurnError {
+    #[fail(display = "Octets manipulate failed: {:?}", error)]
+    OctetsError { error: OctetsError },
+
+    #[fail(display = "STUN failed: {:?}", error)]
+    StunError { error: StunError },
+
+    #[fail(display = "TURN socket failed: {}", reason)]
+    IoError { reason: String },
+
+    #[fail(display = "TURN transaction timed out.")]
+    Timeout,
+
+    #[fail(display = "TURN server rejected the credentials.")]
+    AuthenticationFailed,
+
+    #[fail(display = "TURN server returned error {}: {}", code, reason)]
+    ErrorResponse { code: u16, reason: String },
+
+    #[fail(display = "TURN over TLS is not supported.")]
+    UnsupportedTransport,
+
+    #[fail(display = "TURN allocation does not exist.")]
+    NotAllocated,
+
+    #[fail(display = "ChannelData message is invalid.")]
+    InvalidChannelData,
+
+    #[fail(display = "No more TURN channels are available.")]
+    ChannelExhausted,
+
+    #[fail(display = "TURN client is closed.")]
+    Closed,
+}
+
+impl From<OctetsError> for TurnError {
+    fn from(error: OctetsError) -> Self {
+        TurnError::OctetsError { error }
+    }
+}
+
+impl From<StunError> for TurnError {
+    fn from(error: StunError) -> Self {
+        TurnError::StunError { error }
+    }
+}
+
+impl From<std::io::Error> for TurnError {
+    fn from(error: std::io::Error) -> Self {
+        TurnError::IoError {
+            reason: error.to_string(),
+        }
+    }
+}
+
+// TCPのstream上のframe (STUN messageかChannelData) の長さ．分からなければNone
+pub fn frame_len(bytes: &[u8]) -> Option<usize> {
+    if channel_data::is_channel_data(bytes) {
+        let len = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        // TCPではChannelDataも4byte境界までpaddingされる
+        Some(4 + len + (4 - len % 4) % 4)
+    } else if bytes.len() >= HEADER_SIZE && is_stun_message(&bytes[..HEADER_SIZE]) {
+        Some(HEADER_SIZE + u16::from_be_bytes([bytes[2], bytes[3]]) as usize)
+    } else {
+        None
+    }
+}
+
+// TCPのstreamから1つのframeを読む (RFC 8656 12.5)
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut frame = vec![0u8; 4];
+    reader.read_exact(&mut frame)?;
+    if !channel_data::is_channel_data(&frame) {
+        frame.resize(HEADER_SIZE, 0);
+        reader.read_exact(&mut frame[4..])?;
+    }
+    let len = frame_len(&frame)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid TURN frame"))?;
+    let off = frame.len();
+    frame.resize(len, 0);
+    reader.read_exact(&mut frame[off..])?;
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stun::{Message, MessageClass};
+
+    #[test]
+    fn frame_len_test() {
+        let message = Message::new(MessageClass::Request, METHOD_ALLOCATE, [1; 12])
+            .encode(None)
+            .unwrap();
+        assert_eq!(frame_len(&message), Some(message.len()));
+        assert_eq!(frame_len(&message[..10]), None);
+
+        assert_eq!(frame_len(&[0x40, 0x00, 0x00, 0x05]), Some(12));
+        assert_eq!(frame_len(&[0x40, 0x00, 0x00, 0x08]), Some(12));
+        assert_eq!(frame_len(&[0x80, 0x00, 0x00, 0x08]), None);
+    }
+
+    #[test]
+    fn read_frame_test() {
+        let message = Message::new(MessageClass::Indication, METHOD_SEND, [2; 12])
+            .encode_with(None, &[], false)
+            .unwrap();
+        let mut stream = message.clone();
+        stream.extend_from_slice(&[0x40, 0x01, 0x00, 0x01, 0xFF, 0, 0, 0]);
+        let mut reader = &stream[..];
+        assert_eq!(read_frame(&mut reader).unwrap(), message);
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            vec![0x40, 0x01, 0x00, 0x01, 0xFF, 0, 0, 0]
+        );
+        assert!(read_frame(&mut reader).is_err());
+    }
+}
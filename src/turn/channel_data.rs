@@ -0,0 +1,97 @@
+// https://tools.ietf.org/html/rfc8656#section-12.4
+
+use crate::octets::Octets;
+use crate::turn::{Result, TurnError};
+
+// 0x4000-0x4FFFがChannelDataのchannel number
+pub const MIN_CHANNEL_NUMBER: u16 = 0x4000;
+pub const MAX_CHANNEL_NUMBER: u16 = 0x4FFF;
+
+const CHANNEL_DATA_HEADER_SIZE: usize = 4;
+
+pub fn is_channel_data(bytes: &[u8]) -> bool {
+    bytes.len() >= CHANNEL_DATA_HEADER_SIZE && bytes[0] & 0xF0 == 0x40
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ChannelData {
+    pub number: u16,
+    pub data: Vec<u8>,
+}
+
+impl ChannelData {
+    pub fn new(number: u16, data: Vec<u8>) -> ChannelData {
+        ChannelData { number, data }
+    }
+
+    // TCPでも使えるように常に4byte境界までpaddingする
+    pub fn marshal_size(&self) -> usize {
+        CHANNEL_DATA_HEADER_SIZE + self.data.len() + (4 - self.data.len() % 4) % 4
+    }
+
+    pub fn to_bytes(&self, out: &mut Octets) -> Result<()> {
+        if !(MIN_CHANNEL_NUMBER..=MAX_CHANNEL_NUMBER).contains(&self.number) {
+            return Err(TurnError::InvalidChannelData);
+        }
+        out.put_u16(self.number)?;
+        out.put_u16(self.data.len() as u16)?;
+        out.put_bytes(&self.data)?;
+        out.put_bytes(&[0u8; 3][..(4 - self.data.len() % 4) % 4])?;
+        Ok(())
+    }
+
+    // UDPではpaddingが無いこともある
+    pub fn from_bytes(bytes: &mut Octets) -> Result<ChannelData> {
+        let number = bytes.get_u16()?;
+        if !(MIN_CHANNEL_NUMBER..=MAX_CHANNEL_NUMBER).contains(&number) {
+            return Err(TurnError::InvalidChannelData);
+        }
+        let len = bytes.get_u16()? as usize;
+        let data = bytes
+            .get_bytes(len)
+            .map_err(|_| TurnError::InvalidChannelData)?
+            .to_vec();
+        Ok(ChannelData { number, data })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn channel_data_test() {
+        let channel_data = ChannelData::new(0x4001, vec![1, 2, 3, 4, 5]);
+        assert_eq!(channel_data.marshal_size(), 12);
+
+        let mut buf = vec![0u8; channel_data.marshal_size()];
+        channel_data
+            .to_bytes(&mut Octets::with_slice(&mut buf))
+            .unwrap();
+        assert_eq!(buf, vec![0x40, 0x01, 0x00, 0x05, 1, 2, 3, 4, 5, 0, 0, 0]);
+        assert!(is_channel_data(&buf));
+
+        let parsed = ChannelData::from_bytes(&mut Octets::with_slice(&mut buf)).unwrap();
+        assert_eq!(parsed, channel_data);
+
+        // paddingが無くても良い
+        let mut unpadded = buf[..9].to_vec();
+        assert_eq!(
+            ChannelData::from_bytes(&mut Octets::with_slice(&mut unpadded)).unwrap(),
+            channel_data
+        );
+
+        let mut short = buf[..6].to_vec();
+        assert_eq!(
+            ChannelData::from_bytes(&mut Octets::with_slice(&mut short)),
+            Err(TurnError::InvalidChannelData)
+        );
+
+        let invalid = ChannelData::new(0x3FFF, vec![]);
+        let mut buf = vec![0u8; invalid.marshal_size()];
+        assert_eq!(
+            invalid.to_bytes(&mut Octets::with_slice(&mut buf)),
+            Err(TurnError::InvalidChannelData)
+        );
+    }
+}
@@ -0,0 +1,835 @@
+// https://tools.ietf.org/html/rfc8656
+
+use crate::ice::candidate::TransportType;
+use crate::octets::Octets;
+use crate::stun::message::{self, is_stun_message};
+use crate::stun::{self, Attribute, IntegrityAlgorithm, Message, MessageClass};
+use crate::turn::channel_data::{self, ChannelData, MAX_CHANNEL_NUMBER, MIN_CHANNEL_NUMBER};
+use crate::turn::{
+    read_frame, Result, TurnError, DEFAULT_ALLOCATION_LIFETIME, METHOD_ALLOCATE,
+    METHOD_CHANNEL_BIND, METHOD_CREATE_PERMISSION, METHOD_DATA, METHOD_REFRESH, METHOD_SEND,
+    TRANSPORT_UDP,
+};
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream, UdpSocket};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// socketを読むthreadがcloseに気付くまでの時間
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const MAX_DATAGRAM_SIZE: usize = 65535;
+// allocationは期限が切れる1分前 (lifetimeが短ければ半分の時点) にrefreshする
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+// permission (300秒) とchannel (600秒) は期限より前にrefreshする
+const PERMISSION_REFRESH_INTERVAL: Duration = Duration::from_secs(240);
+const CHANNEL_REFRESH_INTERVAL: Duration = Duration::from_secs(540);
+// 438 (Stale Nonce) でやり直す回数
+const MAX_STALE_NONCE_RETRIES: u32 = 3;
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(Mutex<TcpStream>),
+}
+
+// 401で受け取ったrealmとnonceから作るlong-term credential
+struct Credentials {
+    realm: String,
+    nonce: String,
+    key: Vec<u8>,
+}
+
+struct ClientState {
+    closed: bool,
+    credentials: Option<Credentials>,
+    // 送ったrequestのtransaction id -> 受け取ったresponseと生のbytes
+    transactions: HashMap<[u8; 12], Option<(Message, Vec<u8>)>>,
+    relayed_address: Option<SocketAddr>,
+    mapped_address: Option<SocketAddr>,
+    // allocationをrefreshする時刻
+    refresh_at: Option<Instant>,
+    // permissionを作った時刻
+    permissions: HashMap<IpAddr, Instant>,
+    // send_toで必要になってまだ作っていないpermission
+    pending_permissions: HashSet<IpAddr>,
+    // peer -> (channel number, bindした時刻)
+    channels: HashMap<SocketAddr, (u16, Instant)>,
+    next_channel: u16,
+}
+
+struct Inner {
+    server: SocketAddr,
+    transport: TransportType,
+    username: String,
+    password: String,
+    connection: Connection,
+    state: Mutex<ClientState>,
+    changed: Condvar,
+}
+
+pub struct TurnClient {
+    inner: Arc<Inner>,
+    data: Mutex<Receiver<(Vec<u8>, SocketAddr)>>,
+    read_timeout: Mutex<Option<Duration>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl TurnClient {
+    // serverに接続してallocationを作る
+    pub fn connect(
+        server: SocketAddr,
+        transport: TransportType,
+        username: &str,
+        password: &str,
+    ) -> Result<TurnClient> {
+        let (connection, reader) = match transport {
+            TransportType::UDP => {
+                let unspecified = if server.is_ipv4() {
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+                } else {
+                    IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+                };
+                let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+                socket.connect(server)?;
+                socket.set_read_timeout(Some(READ_TIMEOUT))?;
+                let reader = socket.try_clone()?;
+                (Connection::Udp(socket), Connection::Udp(reader))
+            }
+            TransportType::TCP => {
+                let stream = TcpStream::connect(server)?;
+                stream.set_nodelay(true)?;
+                let reader = stream.try_clone()?;
+                (
+                    Connection::Tcp(Mutex::new(stream)),
+                    Connection::Tcp(Mutex::new(reader)),
+                )
+            }
+        };
+
+        let inner = Arc::new(Inner {
+            server,
+            transport,
+            username: username.to_owned(),
+            password: password.to_owned(),
+            connection,
+            state: Mutex::new(ClientState {
+                closed: false,
+                credentials: None,
+                transactions: HashMap::new(),
+                relayed_address: None,
+                mapped_address: None,
+                refresh_at: None,
+                permissions: HashMap::new(),
+                pending_permissions: HashSet::new(),
+                channels: HashMap::new(),
+                next_channel: MIN_CHANNEL_NUMBER,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let (tx, rx) = mpsc::channel();
+        let reader = {
+            let inner = inner.clone();
+            thread::spawn(move || read_loop(inner, reader, tx))
+        };
+        let mut client = TurnClient {
+            inner,
+            data: Mutex::new(rx),
+            read_timeout: Mutex::new(None),
+            threads: vec![reader],
+        };
+
+        client.inner.allocate()?;
+
+        let refresher = {
+            let inner = client.inner.clone();
+            thread::spawn(move || refresh_loop(inner))
+        };
+        client.threads.push(refresher);
+        Ok(client)
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        self.inner.server
+    }
+
+    pub fn transport(&self) -> TransportType {
+        self.inner.transport
+    }
+
+    pub fn relayed_address(&self) -> Option<SocketAddr> {
+        self.inner.state().relayed_address
+    }
+
+    // serverから見たclientのaddress (server reflexive)
+    pub fn mapped_address(&self) -> Option<SocketAddr> {
+        self.inner.state().mapped_address
+    }
+
+    // Noneならdataが届くまでrecv_fromがblockする
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) {
+        *self.read_timeout.lock().unwrap() = timeout;
+    }
+
+    // lifetimeを0にするとallocationを削除する
+    pub fn refresh(&self, lifetime: Duration) -> Result<()> {
+        self.inner.refresh(lifetime)
+    }
+
+    pub fn create_permission(&self, peers: &[SocketAddr]) -> Result<()> {
+        self.inner.create_permission(peers)
+    }
+
+    pub fn channel_bind(&self, peer: SocketAddr) -> Result<u16> {
+        self.inner.channel_bind(peer)
+    }
+
+    // channelがあればChannelData，無ければSend indicationで送る
+    // permissionが無ければ裏で作る (できるまでのdataはserverに捨てられる)
+    pub fn send_to(&self, data: &[u8], peer: SocketAddr) -> Result<usize> {
+        let channel = {
+            let mut state = self.inner.state();
+            if state.closed {
+                return Err(TurnError::Closed);
+            }
+            if state.relayed_address.is_none() {
+                return Err(TurnError::NotAllocated);
+            }
+            if !state.permissions.contains_key(&peer.ip())
+                && state.pending_permissions.insert(peer.ip())
+            {
+                self.inner.changed.notify_all();
+            }
+            state.channels.get(&peer).map(|(number, _)| *number)
+        };
+
+        let bytes = match channel {
+            Some(number) => {
+                let channel_data = ChannelData::new(number, data.to_vec());
+                let mut buf = vec![0u8; channel_data.marshal_size()];
+                channel_data.to_bytes(&mut Octets::with_slice(&mut buf))?;
+                buf
+            }
+            None => {
+                let mut indication = Message::new(
+                    MessageClass::Indication,
+                    METHOD_SEND,
+                    Message::random_transaction_id(),
+                );
+                indication.attributes.push(Attribute::XorPeerAddress(peer));
+                indication.attributes.push(Attribute::Data(data.to_vec()));
+                indication.encode_with(None, &[], false)?
+            }
+        };
+        self.inner.send_raw(&bytes)?;
+        Ok(data.len())
+    }
+
+    // relayされてきたdataと送ってきたpeer
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        let timeout = *self.read_timeout.lock().unwrap();
+        let receiver = self.data.lock().unwrap();
+        let (data, peer) = match timeout {
+            Some(timeout) => receiver.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => TurnError::Timeout,
+                RecvTimeoutError::Disconnected => TurnError::Closed,
+            })?,
+            None => receiver.recv().map_err(|_| TurnError::Closed)?,
+        };
+
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, peer))
+    }
+
+    // allocationを削除して (応答は待たない) threadを止める
+    pub fn close(&mut self) {
+        let allocated = {
+            let state = self.inner.state();
+            !state.closed && state.relayed_address.is_some()
+        };
+        if allocated {
+            let lifetime = vec![Attribute::Lifetime(0)];
+            if let Ok((_, bytes, _)) = self.inner.request(METHOD_REFRESH, &lifetime) {
+                let _ = self.inner.send_raw(&bytes);
+            }
+        }
+
+        {
+            let mut state = self.inner.state();
+            state.closed = true;
+            state.relayed_address = None;
+            self.inner.changed.notify_all();
+        }
+        if let Connection::Tcp(stream) = &self.inner.connection {
+            let _ = stream.lock().unwrap().shutdown(Shutdown::Both);
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for TurnClient {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Inner {
+    fn state(&self) -> MutexGuard<'_, ClientState> {
+        self.state.lock().unwrap()
+    }
+
+    fn send_raw(&self, bytes: &[u8]) -> Result<()> {
+        match &self.connection {
+            Connection::Udp(socket) => {
+                socket.send(bytes)?;
+            }
+            Connection::Tcp(stream) => stream.lock().unwrap().write_all(bytes)?,
+        }
+        Ok(())
+    }
+
+    // credentialがあればUSERNAME, REALM, NONCE, MESSAGE-INTEGRITYを付けたrequest
+    fn request(
+        &self,
+        method: u16,
+        attributes: &[Attribute],
+    ) -> Result<(Message, Vec<u8>, Option<Vec<u8>>)> {
+        let state = self.state();
+        if state.closed {
+            return Err(TurnError::Closed);
+        }
+
+        let mut request = Message::new(
+            MessageClass::Request,
+            method,
+            Message::random_transaction_id(),
+        );
+        request.attributes = attributes.to_vec();
+        let key = match &state.credentials {
+            Some(credentials) => {
+                request
+                    .attributes
+                    .push(Attribute::Username(self.username.clone()));
+                request
+                    .attributes
+                    .push(Attribute::Realm(credentials.realm.clone()));
+                request
+                    .attributes
+                    .push(Attribute::Nonce(credentials.nonce.clone()));
+                Some(credentials.key.clone())
+            }
+            None => None,
+        };
+        let bytes = request.encode_with(key.as_deref(), &[IntegrityAlgorithm::Sha1], true)?;
+        Ok((request, bytes, key))
+    }
+
+    // 401と438に応じてcredentialを更新しながらrequestを送り，成功responseを返す
+    fn transact(&self, method: u16, attributes: &[Attribute]) -> Result<Message> {
+        let mut stale_nonces = 0;
+        loop {
+            let (request, bytes, key) = self.request(method, attributes)?;
+            let response = self.round_trip(request.transaction_id, &bytes, key.as_deref())?;
+            if response.class == MessageClass::SuccessResponse {
+                return Ok(response);
+            }
+
+            match response.error_code() {
+                Some(message::ERROR_UNAUTHORIZED) if key.is_none() => {
+                    self.update_credentials(&response)?
+                }
+                Some(message::ERROR_UNAUTHORIZED) => return Err(TurnError::AuthenticationFailed),
+                Some(message::ERROR_STALE_NONCE) if stale_nonces < MAX_STALE_NONCE_RETRIES => {
+                    stale_nonces += 1;
+                    self.update_credentials(&response)?
+                }
+                _ => return Err(error_response(&response)),
+            }
+        }
+    }
+
+    // UDPではRTOを倍にしながら再送し，TCPではTRANSACTION_TIMEOUTまで待つ
+    fn round_trip(
+        &self,
+        transaction_id: [u8; 12],
+        bytes: &[u8],
+        key: Option<&[u8]>,
+    ) -> Result<Message> {
+        self.state().transactions.insert(transaction_id, None);
+        if let Err(e) = self.send_raw(bytes) {
+            self.state().transactions.remove(&transaction_id);
+            return Err(e);
+        }
+
+        let mut requests = 1;
+        let mut deadline = Instant::now()
+            + match self.transport {
+                TransportType::UDP => stun::retransmission_timeout(requests),
+                TransportType::TCP => stun::TRANSACTION_TIMEOUT,
+            };
+        let mut state = self.state();
+        loop {
+            if state.closed {
+                state.transactions.remove(&transaction_id);
+                return Err(TurnError::Closed);
+            }
+
+            if let Some(Some((response, raw))) = state.transactions.get(&transaction_id) {
+                // 成功responseのMESSAGE-INTEGRITYが合わなければ捨てて待ち続ける
+                let valid = response.class != MessageClass::SuccessResponse
+                    || key.map_or(true, |key| response.check_integrity(raw, key));
+                if valid {
+                    if let Some(Some((response, _))) = state.transactions.remove(&transaction_id) {
+                        return Ok(response);
+                    }
+                }
+                state.transactions.insert(transaction_id, None);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                if self.transport == TransportType::TCP || requests >= stun::RC {
+                    state.transactions.remove(&transaction_id);
+                    return Err(TurnError::Timeout);
+                }
+                drop(state);
+                let _ = self.send_raw(bytes);
+                requests += 1;
+                deadline = now + stun::retransmission_timeout(requests);
+                state = self.state();
+                continue;
+            }
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    fn update_credentials(&self, response: &Message) -> Result<()> {
+        let (realm, nonce) = match (response.realm(), response.nonce()) {
+            (Some(realm), Some(nonce)) => (realm.to_owned(), nonce.to_owned()),
+            _ => return Err(TurnError::AuthenticationFailed),
+        };
+        let key = stun::long_term_key(&self.username, &realm, &self.password)?;
+        self.state().credentials = Some(Credentials { realm, nonce, key });
+        Ok(())
+    }
+
+    // RFC 8656 7.1
+    fn allocate(&self) -> Result<()> {
+        let response = self.transact(
+            METHOD_ALLOCATE,
+            &[Attribute::RequestedTransport(TRANSPORT_UDP)],
+        )?;
+        let relayed_address = response
+            .xor_relayed_address()
+            .ok_or(TurnError::NotAllocated)?;
+        let lifetime = response
+            .lifetime()
+            .map(|lifetime| Duration::from_secs(lifetime.into()))
+            .unwrap_or(DEFAULT_ALLOCATION_LIFETIME);
+
+        let mut state = self.state();
+        state.relayed_address = Some(relayed_address);
+        state.mapped_address = response.xor_mapped_address();
+        state.refresh_at = Some(Instant::now() + refresh_interval(lifetime));
+        Ok(())
+    }
+
+    // RFC 8656 8.1
+    fn refresh(&self, lifetime: Duration) -> Result<()> {
+        if self.state().relayed_address.is_none() {
+            return Err(TurnError::NotAllocated);
+        }
+        let requested = lifetime.as_secs() as u32;
+        let response = self.transact(METHOD_REFRESH, &[Attribute::Lifetime(requested)])?;
+        let granted = response.lifetime().unwrap_or(requested);
+
+        let mut state = self.state();
+        if granted == 0 {
+            state.relayed_address = None;
+            state.refresh_at = None;
+        } else {
+            state.refresh_at =
+                Some(Instant::now() + refresh_interval(Duration::from_secs(granted.into())));
+        }
+        Ok(())
+    }
+
+    // RFC 8656 10.1．permissionはIP addressだけで決まる
+    fn create_permission(&self, peers: &[SocketAddr]) -> Result<()> {
+        let attributes = peers
+            .iter()
+            .map(|peer| Attribute::XorPeerAddress(*peer))
+            .collect::<Vec<_>>();
+        let result = self.transact(METHOD_CREATE_PERMISSION, &attributes);
+
+        let now = Instant::now();
+        let mut state = self.state();
+        for peer in peers {
+            state.pending_permissions.remove(&peer.ip());
+            if result.is_ok() {
+                state.permissions.insert(peer.ip(), now);
+            } else {
+                state.permissions.remove(&peer.ip());
+            }
+        }
+        result.map(|_| ())
+    }
+
+    // RFC 8656 12.1．既にbindしたpeerなら同じchannelでrefreshする
+    fn channel_bind(&self, peer: SocketAddr) -> Result<u16> {
+        let number = {
+            let mut state = self.state();
+            match state.channels.get(&peer) {
+                Some((number, _)) => *number,
+                None => {
+                    if state.next_channel > MAX_CHANNEL_NUMBER {
+                        return Err(TurnError::ChannelExhausted);
+                    }
+                    state.next_channel += 1;
+                    state.next_channel - 1
+                }
+            }
+        };
+
+        let result = self.transact(
+            METHOD_CHANNEL_BIND,
+            &[
+                Attribute::ChannelNumber(number),
+                Attribute::XorPeerAddress(peer),
+            ],
+        );
+
+        let now = Instant::now();
+        let mut state = self.state();
+        match result {
+            Ok(_) => {
+                state.channels.insert(peer, (number, now));
+                state.permissions.insert(peer.ip(), now);
+                Ok(number)
+            }
+            Err(e) => {
+                state.channels.remove(&peer);
+                Err(e)
+            }
+        }
+    }
+
+    fn handle_frame(&self, frame: &[u8], data: &Sender<(Vec<u8>, SocketAddr)>) {
+        if channel_data::is_channel_data(frame) {
+            let mut buf = frame.to_vec();
+            if let Ok(channel_data) = ChannelData::from_bytes(&mut Octets::with_slice(&mut buf)) {
+                let peer = self
+                    .state()
+                    .channels
+                    .iter()
+                    .find(|(_, (number, _))| *number == channel_data.number)
+                    .map(|(peer, _)| *peer);
+                if let Some(peer) = peer {
+                    let _ = data.send((channel_data.data, peer));
+                }
+            }
+            return;
+        }
+
+        if !is_stun_message(frame) {
+            return;
+        }
+        let message = match Message::decode(frame) {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+        match message.class {
+            MessageClass::Indication if message.method == METHOD_DATA => {
+                let peer = message.xor_peer_addresses().first().cloned();
+                if let (Some(peer), Some(payload)) = (peer, message.data()) {
+                    let _ = data.send((payload.to_vec(), peer));
+                }
+            }
+            MessageClass::SuccessResponse | MessageClass::ErrorResponse => {
+                let mut state = self.state();
+                if let Some(slot) = state.transactions.get_mut(&message.transaction_id) {
+                    *slot = Some((message, frame.to_vec()));
+                    self.changed.notify_all();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn refresh_interval(lifetime: Duration) -> Duration {
+    lifetime - REFRESH_MARGIN.min(lifetime / 2)
+}
+
+fn error_response(response: &Message) -> TurnError {
+    response
+        .attributes
+        .iter()
+        .find_map(|attribute| match attribute {
+            Attribute::ErrorCode { code, reason } => Some(TurnError::ErrorResponse {
+                code: *code,
+                reason: reason.clone(),
+            }),
+            _ => None,
+        })
+        .unwrap_or(TurnError::ErrorResponse {
+            code: message::ERROR_SERVER_ERROR,
+            reason: String::new(),
+        })
+}
+
+fn read_loop(inner: Arc<Inner>, reader: Connection, data: Sender<(Vec<u8>, SocketAddr)>) {
+    match reader {
+        Connection::Udp(socket) => {
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+            loop {
+                match socket.recv(&mut buf) {
+                    Ok(len) => inner.handle_frame(&buf[..len], &data),
+                    Err(_) => {
+                        if inner.state().closed {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+        Connection::Tcp(stream) => {
+            let mut stream = stream.into_inner().unwrap();
+            // TCPの接続が切れたらallocationも無くなる
+            while let Ok(frame) = read_frame(&mut stream) {
+                inner.handle_frame(&frame, &data);
+            }
+            let mut state = inner.state();
+            state.closed = true;
+            state.relayed_address = None;
+            inner.changed.notify_all();
+        }
+    }
+}
+
+// allocation, permission, channelを期限が切れる前にrefreshし，足りないpermissionを作る
+fn refresh_loop(inner: Arc<Inner>) {
+    let mut state = inner.state();
+    loop {
+        if state.closed || state.relayed_address.is_none() {
+            return;
+        }
+
+        let now = Instant::now();
+        let refresh_at = state.refresh_at;
+        let mut permissions = state
+            .permissions
+            .iter()
+            .filter(|(_, created_at)| now >= **created_at + PERMISSION_REFRESH_INTERVAL)
+            .map(|(ip, _)| *ip)
+            .collect::<Vec<_>>();
+        permissions.extend(state.pending_permissions.iter().cloned());
+        let channels = state
+            .channels
+            .iter()
+            .filter(|(_, (_, bound_at))| now >= *bound_at + CHANNEL_REFRESH_INTERVAL)
+            .map(|(peer, _)| *peer)
+            .collect::<Vec<_>>();
+        let refresh = refresh_at.map_or(false, |refresh_at| now >= refresh_at);
+
+        if !refresh && permissions.is_empty() && channels.is_empty() {
+            let next = state
+                .permissions
+                .values()
+                .map(|created_at| *created_at + PERMISSION_REFRESH_INTERVAL)
+                .chain(
+                    state
+                        .channels
+                        .values()
+                        .map(|(_, bound_at)| *bound_at + CHANNEL_REFRESH_INTERVAL),
+                )
+                .chain(refresh_at)
+                .min()
+                .unwrap_or(now + CHANNEL_REFRESH_INTERVAL);
+            let wait = next.saturating_duration_since(now);
+            state = inner.changed.wait_timeout(state, wait).unwrap().0;
+            continue;
+        }
+        drop(state);
+
+        if refresh && inner.refresh(DEFAULT_ALLOCATION_LIFETIME).is_err() {
+            // refreshできなければallocationは失われたものとする
+            let mut state = inner.state();
+            state.relayed_address = None;
+            state.refresh_at = None;
+            return;
+        }
+        if !permissions.is_empty() {
+            let peers = permissions
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, 0))
+                .collect::<Vec<_>>();
+            let _ = inner.create_permission(&peers);
+        }
+        for peer in channels {
+            let _ = inner.channel_bind(peer);
+        }
+        state = inner.state();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::turn::{ServerConfig, StaticAuthHandler, StaticRelayAllocator, TurnServer};
+
+    const USERNAME: &str = "user";
+    const PASSWORD: &str = "pass";
+
+    fn config() -> ServerConfig {
+        let mut auth_handler = StaticAuthHandler::new();
+        auth_handler.add_user(USERNAME, PASSWORD);
+        ServerConfig::new(
+            "example.org",
+            Arc::new(auth_handler),
+            Arc::new(StaticRelayAllocator::new(IpAddr::V4(Ipv4Addr::LOCALHOST))),
+        )
+    }
+
+    fn start(config: ServerConfig, transport: TransportType) -> (TurnServer, SocketAddr) {
+        let mut server = TurnServer::new(config);
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let addr = match transport {
+            TransportType::UDP => server.listen_udp(addr).unwrap(),
+            TransportType::TCP => server.listen_tcp(addr).unwrap(),
+        };
+        (server, addr)
+    }
+
+    fn peer() -> UdpSocket {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        peer
+    }
+
+    fn relay_test(transport: TransportType) {
+        let (_server, addr) = start(config(), transport);
+        let client = TurnClient::connect(addr, transport, USERNAME, PASSWORD).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5)));
+        let relayed = client.relayed_address().unwrap();
+        assert_eq!(relayed.ip(), addr.ip());
+        assert!(client.mapped_address().unwrap().ip().is_loopback());
+
+        let peer = peer();
+        client
+            .create_permission(&[peer.local_addr().unwrap()])
+            .unwrap();
+
+        // Send indication -> peer
+        client
+            .send_to(b"hello", peer.local_addr().unwrap())
+            .unwrap();
+        let mut buf = [0u8; 16];
+        let (len, from) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(from, relayed);
+
+        // peer -> Data indication
+        peer.send_to(b"world", relayed).unwrap();
+        let (len, from) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"world");
+        assert_eq!(from, peer.local_addr().unwrap());
+
+        // ChannelData
+        let number = client.channel_bind(peer.local_addr().unwrap()).unwrap();
+        assert_eq!(number, MIN_CHANNEL_NUMBER);
+        assert_eq!(
+            client.channel_bind(peer.local_addr().unwrap()).unwrap(),
+            number
+        );
+        client.send_to(b"abc", peer.local_addr().unwrap()).unwrap();
+        let (len, _) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"abc");
+        peer.send_to(b"defg", relayed).unwrap();
+        let (len, from) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"defg");
+        assert_eq!(from, peer.local_addr().unwrap());
+
+        client.refresh(Duration::from_secs(300)).unwrap();
+    }
+
+    #[test]
+    fn udp_relay_test() {
+        relay_test(TransportType::UDP);
+    }
+
+    #[test]
+    fn tcp_relay_test() {
+        relay_test(TransportType::TCP);
+    }
+
+    #[test]
+    fn permission_test() {
+        let (_server, addr) = start(config(), TransportType::UDP);
+        let client = TurnClient::connect(addr, TransportType::UDP, USERNAME, PASSWORD).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(200)));
+        let relayed = client.relayed_address().unwrap();
+
+        let peer = peer();
+        peer.send_to(b"denied", relayed).unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(client.recv_from(&mut buf), Err(TurnError::Timeout));
+
+        // send_toが裏でpermissionを作るのでいずれpeerに届く
+        let deadline = Instant::now() + Duration::from_secs(5);
+        peer.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        loop {
+            client
+                .send_to(b"hello", peer.local_addr().unwrap())
+                .unwrap();
+            if let Ok((len, _)) = peer.recv_from(&mut buf) {
+                assert_eq!(&buf[..len], b"hello");
+                break;
+            }
+            assert!(Instant::now() < deadline);
+        }
+        peer.send_to(b"allowed", relayed).unwrap();
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"allowed");
+    }
+
+    #[test]
+    fn stale_nonce_test() {
+        let mut config = config();
+        config.nonce_lifetime = Duration::from_millis(200);
+        let (_server, addr) = start(config, TransportType::UDP);
+        let client = TurnClient::connect(addr, TransportType::UDP, USERNAME, PASSWORD).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        client.refresh(Duration::from_secs(300)).unwrap();
+    }
+
+    #[test]
+    fn authentication_failed_test() {
+        let (_server, addr) = start(config(), TransportType::UDP);
+        assert_eq!(
+            TurnClient::connect(addr, TransportType::UDP, USERNAME, "wrong").err(),
+            Some(TurnError::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn close_test() {
+        let (_server, addr) = start(config(), TransportType::TCP);
+        let mut client = TurnClient::connect(addr, TransportType::TCP, USERNAME, PASSWORD).unwrap();
+        let peer = peer();
+        client.close();
+        assert_eq!(
+            client.send_to(b"data", peer.local_addr().unwrap()),
+            Err(TurnError::Closed)
+        );
+        let mut buf = [0u8; 16];
+        assert_eq!(client.recv_from(&mut buf), Err(TurnError::Closed));
+    }
+}
@@ -0,0 +1,1118 @@
+// https://tools.ietf.org/html/rfc8656 のserver側とRFC 5389のBinding
+
+use crate::ice::candidate::TransportType;
+use crate::octets::Octets;
+use crate::stun::attribute::ATTR_MESSAGE_INTEGRITY;
+use crate::stun::message::{self, is_stun_message};
+use crate::stun::{self, Attribute, IntegrityAlgorithm, Message, MessageClass};
+use crate::turn::channel_data::{self, ChannelData, MAX_CHANNEL_NUMBER, MIN_CHANNEL_NUMBER};
+use crate::turn::{
+    read_frame, Result, CHANNEL_LIFETIME, DEFAULT_ALLOCATION_LIFETIME, ERROR_ALLOCATION_MISMATCH,
+    ERROR_ALLOCATION_QUOTA_REACHED, ERROR_INSUFFICIENT_CAPACITY,
+    ERROR_PEER_ADDRESS_FAMILY_MISMATCH, ERROR_UNSUPPORTED_TRANSPORT_PROTOCOL,
+    ERROR_WRONG_CREDENTIALS, MAX_ALLOCATION_LIFETIME, METHOD_ALLOCATE, METHOD_CHANNEL_BIND,
+    METHOD_CREATE_PERMISSION, METHOD_DATA, METHOD_REFRESH, METHOD_SEND, PERMISSION_LIFETIME,
+    TRANSPORT_UDP,
+};
+
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::iter;
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// socketを読むthreadがcloseやallocationの期限切れに気付くまでの時間
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const MAX_DATAGRAM_SIZE: usize = 65535;
+const DEFAULT_NONCE_LIFETIME: Duration = Duration::from_secs(3600);
+
+// long-term credentialのkey (stun::long_term_key) を返す．知らないuserならNone
+pub trait AuthHandler: Send + Sync {
+    fn auth_key(&self, username: &str, realm: &str, client: SocketAddr) -> Option<Vec<u8>>;
+}
+
+// usernameとpasswordの組を覚えておくだけのAuthHandler
+#[derive(Default)]
+pub struct StaticAuthHandler {
+    users: HashMap<String, String>,
+}
+
+impl StaticAuthHandler {
+    pub fn new() -> StaticAuthHandler {
+        StaticAuthHandler::default()
+    }
+
+    pub fn add_user(&mut self, username: &str, password: &str) {
+        self.users.insert(username.to_owned(), password.to_owned());
+    }
+}
+
+impl AuthHandler for StaticAuthHandler {
+    fn auth_key(&self, username: &str, realm: &str, _client: SocketAddr) -> Option<Vec<u8>> {
+        let password = self.users.get(username)?;
+        stun::long_term_key(username, realm, password).ok()
+    }
+}
+
+// allocation毎にrelay用のUDP socketを用意する
+pub trait RelayAllocator: Send + Sync {
+    fn allocate(&self, client: SocketAddr) -> io::Result<UdpSocket>;
+}
+
+// 決まったIP addressのport (範囲が無ければOSに任せる) をrelayに使う
+pub struct StaticRelayAllocator {
+    ip: IpAddr,
+    ports: Option<(u16, u16)>,
+}
+
+impl StaticRelayAllocator {
+    pub fn new(ip: IpAddr) -> StaticRelayAllocator {
+        StaticRelayAllocator { ip, ports: None }
+    }
+
+    pub fn with_port_range(ip: IpAddr, min_port: u16, max_port: u16) -> StaticRelayAllocator {
+        StaticRelayAllocator {
+            ip,
+            ports: Some((min_port, max_port)),
+        }
+    }
+}
+
+impl RelayAllocator for StaticRelayAllocator {
+    fn allocate(&self, _client: SocketAddr) -> io::Result<UdpSocket> {
+        let (min_port, max_port) = match self.ports {
+            Some(ports) => ports,
+            None => return UdpSocket::bind(SocketAddr::new(self.ip, 0)),
+        };
+
+        // 範囲内のどこかから順に空いているportを探す
+        let count = u32::from(max_port.saturating_sub(min_port)) + 1;
+        let start = thread_rng().gen_range(0, count);
+        for i in 0..count {
+            let port = min_port as u32 + (start + i) % count;
+            if let Ok(socket) = UdpSocket::bind(SocketAddr::new(self.ip, port as u16)) {
+                return Ok(socket);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "no relay port is available",
+        ))
+    }
+}
+
+pub struct ServerConfig {
+    pub realm: String,
+    pub auth_handler: Arc<dyn AuthHandler>,
+    pub relay_allocator: Arc<dyn RelayAllocator>,
+    // LIFETIMEが無い時のlifetimeと，要求されても超えないlifetime
+    pub default_lifetime: Duration,
+    pub max_lifetime: Duration,
+    pub nonce_lifetime: Duration,
+    // server全体とuser毎のallocationの上限
+    pub max_allocations: usize,
+    pub max_allocations_per_user: usize,
+}
+
+impl ServerConfig {
+    pub fn new(
+        realm: &str,
+        auth_handler: Arc<dyn AuthHandler>,
+        relay_allocator: Arc<dyn RelayAllocator>,
+    ) -> ServerConfig {
+        ServerConfig {
+            realm: realm.to_owned(),
+            auth_handler,
+            relay_allocator,
+            default_lifetime: DEFAULT_ALLOCATION_LIFETIME,
+            max_lifetime: MAX_ALLOCATION_LIFETIME,
+            nonce_lifetime: DEFAULT_NONCE_LIFETIME,
+            max_allocations: usize::MAX,
+            max_allocations_per_user: usize::MAX,
+        }
+    }
+}
+
+type Reply = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+// allocationはclientとserverのaddressとtransportの組で決まる
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct FiveTuple {
+    client: SocketAddr,
+    server: SocketAddr,
+    transport: TransportType,
+}
+
+struct Allocation {
+    username: String,
+    // Allocateの再送には同じresponseを返す
+    transaction_id: [u8; 12],
+    relay: Arc<UdpSocket>,
+    relayed_address: SocketAddr,
+    expires_at: Instant,
+    // 以下は期限
+    permissions: HashMap<IpAddr, Instant>,
+    channels: HashMap<u16, (SocketAddr, Instant)>,
+    reply: Reply,
+}
+
+impl Allocation {
+    fn has_permission(&self, ip: IpAddr, now: Instant) -> bool {
+        self.permissions
+            .get(&ip)
+            .map_or(false, |expires_at| now < *expires_at)
+    }
+
+    fn channel_peer(&self, number: u16, now: Instant) -> Option<SocketAddr> {
+        self.channels
+            .get(&number)
+            .filter(|(_, expires_at)| now < *expires_at)
+            .map(|(peer, _)| *peer)
+    }
+
+    fn peer_channel(&self, peer: SocketAddr, now: Instant) -> Option<u16> {
+        self.channels
+            .iter()
+            .find(|(_, (bound, expires_at))| *bound == peer && now < *expires_at)
+            .map(|(number, _)| *number)
+    }
+}
+
+struct ServerState {
+    closed: bool,
+    // nonce -> 発行した時刻
+    nonces: HashMap<String, Instant>,
+    allocations: HashMap<FiveTuple, Allocation>,
+    streams: HashMap<SocketAddr, TcpStream>,
+}
+
+impl ServerState {
+    // 期限の切れたallocationは無いものとして消す
+    fn allocation(&mut self, tuple: &FiveTuple, now: Instant) -> Option<&mut Allocation> {
+        if self
+            .allocations
+            .get(tuple)
+            .map_or(false, |allocation| now >= allocation.expires_at)
+        {
+            self.allocations.remove(tuple);
+        }
+        self.allocations.get_mut(tuple)
+    }
+}
+
+struct Shared {
+    config: ServerConfig,
+    state: Mutex<ServerState>,
+}
+
+pub struct TurnServer {
+    shared: Arc<Shared>,
+    listeners: Vec<JoinHandle<()>>,
+}
+
+impl TurnServer {
+    pub fn new(config: ServerConfig) -> TurnServer {
+        TurnServer {
+            shared: Arc::new(Shared {
+                config,
+                state: Mutex::new(ServerState {
+                    closed: false,
+                    nonces: HashMap::new(),
+                    allocations: HashMap::new(),
+                    streams: HashMap::new(),
+                }),
+            }),
+            listeners: vec![],
+        }
+    }
+
+    // bindしたaddressを返す (port 0ならOSが選んだport)
+    pub fn listen_udp(&mut self, addr: SocketAddr) -> Result<SocketAddr> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+        let local_addr = socket.local_addr()?;
+
+        let shared = self.shared.clone();
+        self.listeners
+            .push(thread::spawn(move || udp_loop(shared, Arc::new(socket))));
+        Ok(local_addr)
+    }
+
+    pub fn listen_tcp(&mut self, addr: SocketAddr) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let shared = self.shared.clone();
+        self.listeners
+            .push(thread::spawn(move || tcp_loop(shared, listener)));
+        Ok(local_addr)
+    }
+
+    // 期限の切れていないallocationの数
+    pub fn allocations(&self) -> usize {
+        let now = Instant::now();
+        self.shared
+            .state()
+            .allocations
+            .values()
+            .filter(|allocation| now < allocation.expires_at)
+            .count()
+    }
+
+    pub fn close(&mut self) {
+        {
+            let mut state = self.shared.state();
+            state.closed = true;
+            state.allocations.clear();
+            for stream in state.streams.values() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+        for listener in self.listeners.drain(..) {
+            let _ = listener.join();
+        }
+    }
+}
+
+impl Drop for TurnServer {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, ServerState> {
+        self.state.lock().unwrap()
+    }
+
+    fn handle(self: &Arc<Self>, tuple: FiveTuple, packet: &[u8], reply: &Reply) {
+        if channel_data::is_channel_data(packet) {
+            self.handle_channel_data(tuple, packet);
+            return;
+        }
+        if !is_stun_message(packet) {
+            return;
+        }
+        let message = match Message::decode(packet) {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+
+        match message.class {
+            MessageClass::Request => {
+                if let Some(response) = self.handle_request(tuple, &message, packet, reply) {
+                    reply(&response);
+                }
+            }
+            MessageClass::Indication if message.method == METHOD_SEND => {
+                self.handle_send(tuple, &message)
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_request(
+        self: &Arc<Self>,
+        tuple: FiveTuple,
+        request: &Message,
+        raw: &[u8],
+        reply: &Reply,
+    ) -> Option<Vec<u8>> {
+        // Bindingは認証せずにそのまま答える
+        if request.method == message::METHOD_BINDING {
+            let mut response = request.response(MessageClass::SuccessResponse);
+            response
+                .attributes
+                .push(Attribute::XorMappedAddress(tuple.client));
+            return response.encode_with(None, &[], true).ok();
+        }
+
+        let unknown = request.unknown_comprehension_required();
+        if !unknown.is_empty() {
+            let mut response =
+                request.error_response(message::ERROR_UNKNOWN_ATTRIBUTE, "Unknown Attribute");
+            response
+                .attributes
+                .push(Attribute::UnknownAttributes(unknown));
+            return response.encode_with(None, &[], true).ok();
+        }
+
+        let (username, key) = match self.authenticate(tuple, request, raw) {
+            Ok(authenticated) => authenticated,
+            Err(response) => return response.encode_with(None, &[], true).ok(),
+        };
+
+        let now = Instant::now();
+        let response = match request.method {
+            METHOD_ALLOCATE => self.allocate(tuple, &username, request, reply, now),
+            METHOD_REFRESH => self.refresh(tuple, &username, request, now),
+            METHOD_CREATE_PERMISSION => self.create_permission(tuple, &username, request, now),
+            METHOD_CHANNEL_BIND => self.channel_bind(tuple, &username, request, now),
+            _ => request.error_response(message::ERROR_BAD_REQUEST, "Bad Request"),
+        };
+        response
+            .encode_with(Some(&key), &[IntegrityAlgorithm::Sha1], true)
+            .ok()
+    }
+
+    // RFC 8656 5 (long-term credential) ．失敗したらerror responseを返す
+    fn authenticate(
+        &self,
+        tuple: FiveTuple,
+        request: &Message,
+        raw: &[u8],
+    ) -> std::result::Result<(String, Vec<u8>), Message> {
+        let challenge = |code: u16, reason: &str| {
+            let mut response = request.error_response(code, reason);
+            response
+                .attributes
+                .push(Attribute::Realm(self.config.realm.clone()));
+            response.attributes.push(Attribute::Nonce(self.nonce()));
+            response
+        };
+
+        if request.get(ATTR_MESSAGE_INTEGRITY).is_none() {
+            return Err(challenge(message::ERROR_UNAUTHORIZED, "Unauthorized"));
+        }
+        let (username, realm, nonce) = match (request.username(), request.realm(), request.nonce())
+        {
+            (Some(username), Some(realm), Some(nonce)) => (username, realm, nonce),
+            _ => return Err(request.error_response(message::ERROR_BAD_REQUEST, "Bad Request")),
+        };
+
+        if !self.is_valid_nonce(nonce) {
+            return Err(challenge(message::ERROR_STALE_NONCE, "Stale Nonce"));
+        }
+        let key = match self
+            .config
+            .auth_handler
+            .auth_key(username, realm, tuple.client)
+        {
+            Some(key) if realm == self.config.realm => key,
+            _ => return Err(challenge(message::ERROR_UNAUTHORIZED, "Unauthorized")),
+        };
+        if !request.check_integrity(raw, &key) {
+            return Err(challenge(message::ERROR_UNAUTHORIZED, "Unauthorized"));
+        }
+        Ok((username.to_owned(), key))
+    }
+
+    fn nonce(&self) -> String {
+        let now = Instant::now();
+        let nonce: String = iter::repeat(())
+            .map(|()| thread_rng().sample(Alphanumeric))
+            .take(16)
+            .collect();
+
+        let lifetime = self.config.nonce_lifetime;
+        let mut state = self.state();
+        state
+            .nonces
+            .retain(|_, issued_at| now < *issued_at + lifetime);
+        state.nonces.insert(nonce.clone(), now);
+        nonce
+    }
+
+    fn is_valid_nonce(&self, nonce: &str) -> bool {
+        self.state().nonces.get(nonce).map_or(false, |issued_at| {
+            issued_at.elapsed() < self.config.nonce_lifetime
+        })
+    }
+
+    // 要求されたlifetime (無ければdefault) をmax_lifetimeで抑える
+    fn lifetime(&self, request: &Message) -> Duration {
+        request
+            .lifetime()
+            .map(|lifetime| Duration::from_secs(lifetime.into()))
+            .unwrap_or(self.config.default_lifetime)
+            .min(self.config.max_lifetime)
+    }
+
+    // RFC 8656 7.2
+    fn allocate(
+        self: &Arc<Self>,
+        tuple: FiveTuple,
+        username: &str,
+        request: &Message,
+        reply: &Reply,
+        now: Instant,
+    ) -> Message {
+        let mut state = self.state();
+        if let Some(allocation) = state.allocation(&tuple, now) {
+            if allocation.transaction_id != request.transaction_id {
+                return request.error_response(ERROR_ALLOCATION_MISMATCH, "Allocation Mismatch");
+            }
+            return allocate_response(request, tuple, allocation, now);
+        }
+
+        match request.requested_transport() {
+            Some(TRANSPORT_UDP) => {}
+            Some(_) => {
+                return request.error_response(
+                    ERROR_UNSUPPORTED_TRANSPORT_PROTOCOL,
+                    "Unsupported Transport Protocol",
+                )
+            }
+            None => return request.error_response(message::ERROR_BAD_REQUEST, "Bad Request"),
+        }
+
+        state
+            .allocations
+            .retain(|_, allocation| now < allocation.expires_at);
+        if state.allocations.len() >= self.config.max_allocations {
+            return request.error_response(ERROR_INSUFFICIENT_CAPACITY, "Insufficient Capacity");
+        }
+        let allocations = state
+            .allocations
+            .values()
+            .filter(|allocation| allocation.username == username)
+            .count();
+        if allocations >= self.config.max_allocations_per_user {
+            return request
+                .error_response(ERROR_ALLOCATION_QUOTA_REACHED, "Allocation Quota Reached");
+        }
+
+        let relay = match self
+            .config
+            .relay_allocator
+            .allocate(tuple.client)
+            .and_then(|relay| {
+                relay.set_read_timeout(Some(READ_TIMEOUT))?;
+                let relayed_address = relay.local_addr()?;
+                Ok((Arc::new(relay), relayed_address))
+            }) {
+            Ok(relay) => relay,
+            Err(_) => {
+                return request.error_response(ERROR_INSUFFICIENT_CAPACITY, "Insufficient Capacity")
+            }
+        };
+
+        let allocation = Allocation {
+            username: username.to_owned(),
+            transaction_id: request.transaction_id,
+            relay: relay.0.clone(),
+            relayed_address: relay.1,
+            expires_at: now + self.lifetime(request),
+            permissions: HashMap::new(),
+            channels: HashMap::new(),
+            reply: reply.clone(),
+        };
+        let response = allocate_response(request, tuple, &allocation, now);
+        state.allocations.insert(tuple, allocation);
+
+        let shared = self.clone();
+        thread::spawn(move || relay_loop(shared, tuple, relay.0));
+        response
+    }
+
+    // RFC 8656 8.2．lifetimeが0ならallocationを削除する
+    fn refresh(
+        &self,
+        tuple: FiveTuple,
+        username: &str,
+        request: &Message,
+        now: Instant,
+    ) -> Message {
+        let mut state = self.state();
+        let allocation = match state.allocation(&tuple, now) {
+            Some(allocation) => allocation,
+            None => {
+                return request.error_response(ERROR_ALLOCATION_MISMATCH, "Allocation Mismatch")
+            }
+        };
+        if allocation.username != username {
+            return request.error_response(ERROR_WRONG_CREDENTIALS, "Wrong Credentials");
+        }
+
+        let lifetime = self.lifetime(request);
+        if lifetime == Duration::from_secs(0) {
+            state.allocations.remove(&tuple);
+        } else {
+            allocation.expires_at = now + lifetime;
+        }
+
+        let mut response = request.response(MessageClass::SuccessResponse);
+        response
+            .attributes
+            .push(Attribute::Lifetime(lifetime.as_secs() as u32));
+        response
+    }
+
+    // RFC 8656 10.2
+    fn create_permission(
+        &self,
+        tuple: FiveTuple,
+        username: &str,
+        request: &Message,
+        now: Instant,
+    ) -> Message {
+        let mut state = self.state();
+        let allocation = match state.allocation(&tuple, now) {
+            Some(allocation) => allocation,
+            None => {
+                return request.error_response(ERROR_ALLOCATION_MISMATCH, "Allocation Mismatch")
+            }
+        };
+        if allocation.username != username {
+            return request.error_response(ERROR_WRONG_CREDENTIALS, "Wrong Credentials");
+        }
+
+        let peers = request.xor_peer_addresses();
+        if peers.is_empty() {
+            return request.error_response(message::ERROR_BAD_REQUEST, "Bad Request");
+        }
+        if peers
+            .iter()
+            .any(|peer| peer.is_ipv4() != allocation.relayed_address.is_ipv4())
+        {
+            return request.error_response(
+                ERROR_PEER_ADDRESS_FAMILY_MISMATCH,
+                "Peer Address Family Mismatch",
+            );
+        }
+
+        for peer in peers {
+            allocation
+                .permissions
+                .insert(peer.ip(), now + PERMISSION_LIFETIME);
+        }
+        request.response(MessageClass::SuccessResponse)
+    }
+
+    // RFC 8656 12.2．channelのbindはpermissionも作る
+    fn channel_bind(
+        &self,
+        tuple: FiveTuple,
+        username: &str,
+        request: &Message,
+        now: Instant,
+    ) -> Message {
+        let mut state = self.state();
+        let allocation = match state.allocation(&tuple, now) {
+            Some(allocation) => allocation,
+            None => {
+                return request.error_response(ERROR_ALLOCATION_MISMATCH, "Allocation Mismatch")
+            }
+        };
+        if allocation.username != username {
+            return request.error_response(ERROR_WRONG_CREDENTIALS, "Wrong Credentials");
+        }
+
+        let (number, peer) = match (
+            request.channel_number(),
+            request.xor_peer_addresses().first(),
+        ) {
+            (Some(number), Some(peer))
+                if (MIN_CHANNEL_NUMBER..=MAX_CHANNEL_NUMBER).contains(&number) =>
+            {
+                (number, *peer)
+            }
+            _ => return request.error_response(message::ERROR_BAD_REQUEST, "Bad Request"),
+        };
+        if peer.is_ipv4() != allocation.relayed_address.is_ipv4() {
+            return request.error_response(
+                ERROR_PEER_ADDRESS_FAMILY_MISMATCH,
+                "Peer Address Family Mismatch",
+            );
+        }
+        // channelとpeerはどちらも別の組に使われていてはいけない
+        let bound_peer = allocation.channel_peer(number, now);
+        let bound_number = allocation.peer_channel(peer, now);
+        if bound_peer.map_or(false, |bound| bound != peer)
+            || bound_number.map_or(false, |bound| bound != number)
+        {
+            return request.error_response(message::ERROR_BAD_REQUEST, "Bad Request");
+        }
+
+        allocation
+            .channels
+            .insert(number, (peer, now + CHANNEL_LIFETIME));
+        allocation
+            .permissions
+            .insert(peer.ip(), now + PERMISSION_LIFETIME);
+        request.response(MessageClass::SuccessResponse)
+    }
+
+    // RFC 8656 11.2
+    fn handle_send(&self, tuple: FiveTuple, indication: &Message) {
+        let peer = match indication.xor_peer_addresses().first() {
+            Some(peer) => *peer,
+            None => return,
+        };
+        let data = match indication.data() {
+            Some(data) => data,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let mut state = self.state();
+        if let Some(allocation) = state.allocation(&tuple, now) {
+            if allocation.has_permission(peer.ip(), now) {
+                let _ = allocation.relay.send_to(data, peer);
+            }
+        }
+    }
+
+    // RFC 8656 12.6
+    fn handle_channel_data(&self, tuple: FiveTuple, packet: &[u8]) {
+        let mut buf = packet.to_vec();
+        let channel_data = match ChannelData::from_bytes(&mut Octets::with_slice(&mut buf)) {
+            Ok(channel_data) => channel_data,
+            Err(_) => return,
+        };
+
+        let now = Instant::now();
+        let mut state = self.state();
+        if let Some(allocation) = state.allocation(&tuple, now) {
+            if let Some(peer) = allocation.channel_peer(channel_data.number, now) {
+                if allocation.has_permission(peer.ip(), now) {
+                    let _ = allocation.relay.send_to(&channel_data.data, peer);
+                }
+            }
+        }
+    }
+}
+
+fn allocate_response(
+    request: &Message,
+    tuple: FiveTuple,
+    allocation: &Allocation,
+    now: Instant,
+) -> Message {
+    let lifetime = allocation.expires_at.saturating_duration_since(now);
+    let mut response = request.response(MessageClass::SuccessResponse);
+    response
+        .attributes
+        .push(Attribute::XorRelayedAddress(allocation.relayed_address));
+    response
+        .attributes
+        .push(Attribute::Lifetime(lifetime.as_secs() as u32));
+    response
+        .attributes
+        .push(Attribute::XorMappedAddress(tuple.client));
+    response
+}
+
+fn udp_loop(shared: Arc<Shared>, socket: Arc<UdpSocket>) {
+    let server = match socket.local_addr() {
+        Ok(server) => server,
+        Err(_) => return,
+    };
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, client) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(_) => {
+                if shared.state().closed {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        let tuple = FiveTuple {
+            client,
+            server,
+            transport: TransportType::UDP,
+        };
+        let reply: Reply = {
+            let socket = socket.clone();
+            Arc::new(move |bytes: &[u8]| {
+                let _ = socket.send_to(bytes, client);
+            })
+        };
+        shared.handle(tuple, &buf[..len], &reply);
+    }
+}
+
+fn tcp_loop(shared: Arc<Shared>, listener: TcpListener) {
+    loop {
+        if shared.state().closed {
+            return;
+        }
+        let (stream, client) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(_) => {
+                thread::sleep(READ_TIMEOUT);
+                continue;
+            }
+        };
+        let server = match stream.local_addr() {
+            Ok(server) => server,
+            Err(_) => continue,
+        };
+        let registered = stream.set_nonblocking(false).and_then(|()| {
+            stream.set_nodelay(true)?;
+            let registered = stream.try_clone()?;
+            shared.state().streams.insert(client, registered);
+            Ok(())
+        });
+        if registered.is_err() {
+            continue;
+        }
+
+        let tuple = FiveTuple {
+            client,
+            server,
+            transport: TransportType::TCP,
+        };
+        let shared = shared.clone();
+        thread::spawn(move || tcp_connection(shared, tuple, stream));
+    }
+}
+
+// TCPの接続が切れたらallocationも削除する (RFC 8656 7)
+fn tcp_connection(shared: Arc<Shared>, tuple: FiveTuple, mut stream: TcpStream) {
+    if let Ok(writer) = stream.try_clone() {
+        let writer = Mutex::new(writer);
+        let reply: Reply = Arc::new(move |bytes: &[u8]| {
+            let _ = writer.lock().unwrap().write_all(bytes);
+        });
+        while let Ok(frame) = read_frame(&mut stream) {
+            shared.handle(tuple, &frame, &reply);
+        }
+    }
+
+    let mut state = shared.state();
+    state.allocations.remove(&tuple);
+    state.streams.remove(&tuple.client);
+}
+
+// relay socketに届いたdataをpermissionがあればChannelDataかData indicationでclientに送る
+fn relay_loop(shared: Arc<Shared>, tuple: FiveTuple, relay: Arc<UdpSocket>) {
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let received = relay.recv_from(&mut buf);
+
+        let now = Instant::now();
+        let mut state = shared.state();
+        if state.closed {
+            return;
+        }
+        let allocation = match state.allocation(&tuple, now) {
+            // 同じfive-tupleで作り直されたallocationは別のthreadが扱う
+            Some(allocation) if Arc::ptr_eq(&allocation.relay, &relay) => allocation,
+            _ => return,
+        };
+        let (len, peer) = match received {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        if !allocation.has_permission(peer.ip(), now) {
+            continue;
+        }
+
+        let bytes = match allocation.peer_channel(peer, now) {
+            Some(number) => {
+                let channel_data = ChannelData::new(number, buf[..len].to_vec());
+                let mut out = vec![0u8; channel_data.marshal_size()];
+                if channel_data
+                    .to_bytes(&mut Octets::with_slice(&mut out))
+                    .is_err()
+                {
+                    continue;
+                }
+                out
+            }
+            None => {
+                let mut indication = Message::new(
+                    MessageClass::Indication,
+                    METHOD_DATA,
+                    Message::random_transaction_id(),
+                );
+                indication.attributes.push(Attribute::XorPeerAddress(peer));
+                indication
+                    .attributes
+                    .push(Attribute::Data(buf[..len].to_vec()));
+                match indication.encode_with(None, &[], false) {
+                    Ok(bytes) => bytes,
+                    Err(_) => continue,
+                }
+            }
+        };
+        let reply = allocation.reply.clone();
+        drop(state);
+        reply(&bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::turn::TurnClient;
+    use std::net::Ipv4Addr;
+
+    fn config() -> ServerConfig {
+        let mut auth_handler = StaticAuthHandler::new();
+        auth_handler.add_user("user", "pass");
+        auth_handler.add_user("other", "pass");
+        ServerConfig::new(
+            "example.org",
+            Arc::new(auth_handler),
+            Arc::new(StaticRelayAllocator::new(IpAddr::V4(Ipv4Addr::LOCALHOST))),
+        )
+    }
+
+    fn start(config: ServerConfig) -> (TurnServer, SocketAddr) {
+        let mut server = TurnServer::new(config);
+        let addr = server.listen_udp("127.0.0.1:0".parse().unwrap()).unwrap();
+        (server, addr)
+    }
+
+    // TurnClientを使わずに生のrequestを送る
+    struct RawClient {
+        socket: UdpSocket,
+        username: String,
+        credentials: Option<(String, String, Vec<u8>)>,
+    }
+
+    impl RawClient {
+        fn new(server: SocketAddr, username: &str) -> RawClient {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.connect(server).unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            RawClient {
+                socket,
+                username: username.to_owned(),
+                credentials: None,
+            }
+        }
+
+        fn request(&mut self, method: u16, attributes: Vec<Attribute>) -> Message {
+            let mut request = Message::new(
+                MessageClass::Request,
+                method,
+                Message::random_transaction_id(),
+            );
+            request.attributes = attributes;
+            let key = match &self.credentials {
+                Some((realm, nonce, key)) => {
+                    request
+                        .attributes
+                        .push(Attribute::Username(self.username.clone()));
+                    request.attributes.push(Attribute::Realm(realm.clone()));
+                    request.attributes.push(Attribute::Nonce(nonce.clone()));
+                    Some(key.clone())
+                }
+                None => None,
+            };
+            let bytes = request
+                .encode_with(key.as_deref(), &[IntegrityAlgorithm::Sha1], true)
+                .unwrap();
+            self.socket.send(&bytes).unwrap();
+
+            let mut buf = [0u8; 1500];
+            let len = self.socket.recv(&mut buf).unwrap();
+            let response = Message::decode(&buf[..len]).unwrap();
+            assert_eq!(response.transaction_id, request.transaction_id);
+            if let (Some(realm), Some(nonce)) = (response.realm(), response.nonce()) {
+                let key = stun::long_term_key(&self.username, realm, "pass").unwrap();
+                self.credentials = Some((realm.to_owned(), nonce.to_owned(), key));
+            }
+            response
+        }
+
+        // 401で認証情報を得てからrequestする
+        fn authenticated(&mut self, method: u16, attributes: Vec<Attribute>) -> Message {
+            if self.credentials.is_none() {
+                let response = self.request(method, attributes.clone());
+                assert_eq!(response.error_code(), Some(message::ERROR_UNAUTHORIZED));
+            }
+            self.request(method, attributes)
+        }
+
+        fn allocate(&mut self) -> Message {
+            self.authenticated(
+                METHOD_ALLOCATE,
+                vec![Attribute::RequestedTransport(TRANSPORT_UDP)],
+            )
+        }
+    }
+
+    #[test]
+    fn binding_test() {
+        let (_server, addr) = start(config());
+        let mut client = RawClient::new(addr, "user");
+        let response = client.request(message::METHOD_BINDING, vec![]);
+        assert_eq!(response.class, MessageClass::SuccessResponse);
+        assert_eq!(
+            response.xor_mapped_address(),
+            Some(client.socket.local_addr().unwrap())
+        );
+    }
+
+    #[test]
+    fn allocate_test() {
+        let (server, addr) = start(config());
+        let mut client = RawClient::new(addr, "user");
+        let response = client.allocate();
+        assert_eq!(response.class, MessageClass::SuccessResponse);
+        assert!(response.xor_relayed_address().unwrap().ip().is_loopback());
+        assert_eq!(response.lifetime(), Some(600));
+        assert_eq!(server.allocations(), 1);
+
+        // 別のtransactionでのAllocateは437
+        let response = client.allocate();
+        assert_eq!(response.error_code(), Some(ERROR_ALLOCATION_MISMATCH));
+
+        let response = client.authenticated(METHOD_REFRESH, vec![Attribute::Lifetime(7200)]);
+        assert_eq!(response.lifetime(), Some(3600));
+        let response = client.authenticated(METHOD_REFRESH, vec![Attribute::Lifetime(0)]);
+        assert_eq!(response.class, MessageClass::SuccessResponse);
+        assert_eq!(server.allocations(), 0);
+
+        let response = client.authenticated(METHOD_REFRESH, vec![]);
+        assert_eq!(response.error_code(), Some(ERROR_ALLOCATION_MISMATCH));
+    }
+
+    #[test]
+    fn bad_request_test() {
+        let (_server, addr) = start(config());
+        let mut client = RawClient::new(addr, "user");
+        let response = client.authenticated(METHOD_ALLOCATE, vec![]);
+        assert_eq!(response.error_code(), Some(message::ERROR_BAD_REQUEST));
+        let response =
+            client.authenticated(METHOD_ALLOCATE, vec![Attribute::RequestedTransport(6)]);
+        assert_eq!(
+            response.error_code(),
+            Some(ERROR_UNSUPPORTED_TRANSPORT_PROTOCOL)
+        );
+
+        client.allocate();
+        let peer: SocketAddr = "[::1]:5000".parse().unwrap();
+        let response = client.authenticated(
+            METHOD_CREATE_PERMISSION,
+            vec![Attribute::XorPeerAddress(peer)],
+        );
+        assert_eq!(
+            response.error_code(),
+            Some(ERROR_PEER_ADDRESS_FAMILY_MISMATCH)
+        );
+
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let response = client.authenticated(
+            METHOD_CHANNEL_BIND,
+            vec![
+                Attribute::ChannelNumber(0x3000),
+                Attribute::XorPeerAddress(peer),
+            ],
+        );
+        assert_eq!(response.error_code(), Some(message::ERROR_BAD_REQUEST));
+        let response = client.authenticated(
+            METHOD_CHANNEL_BIND,
+            vec![
+                Attribute::ChannelNumber(0x4000),
+                Attribute::XorPeerAddress(peer),
+            ],
+        );
+        assert_eq!(response.class, MessageClass::SuccessResponse);
+        // 同じpeerを別のchannelにはbindできない
+        let response = client.authenticated(
+            METHOD_CHANNEL_BIND,
+            vec![
+                Attribute::ChannelNumber(0x4001),
+                Attribute::XorPeerAddress(peer),
+            ],
+        );
+        assert_eq!(response.error_code(), Some(message::ERROR_BAD_REQUEST));
+    }
+
+    #[test]
+    fn quota_test() {
+        let mut config = config();
+        config.max_allocations = 2;
+        config.max_allocations_per_user = 1;
+        let (_server, addr) = start(config);
+
+        let mut first = RawClient::new(addr, "user");
+        assert_eq!(first.allocate().class, MessageClass::SuccessResponse);
+        let mut second = RawClient::new(addr, "user");
+        assert_eq!(
+            second.allocate().error_code(),
+            Some(ERROR_ALLOCATION_QUOTA_REACHED)
+        );
+        let mut third = RawClient::new(addr, "other");
+        assert_eq!(third.allocate().class, MessageClass::SuccessResponse);
+        let mut fourth = RawClient::new(addr, "other");
+        assert_eq!(
+            fourth.allocate().error_code(),
+            Some(ERROR_INSUFFICIENT_CAPACITY)
+        );
+    }
+
+    #[test]
+    fn lifetime_test() {
+        let mut config = config();
+        config.default_lifetime = Duration::from_secs(1);
+        config.max_lifetime = Duration::from_secs(1);
+        let (server, addr) = start(config);
+
+        let mut raw = RawClient::new(addr, "user");
+        assert_eq!(raw.allocate().lifetime(), Some(1));
+
+        // TurnClientは期限の前にrefreshし続ける
+        let client = TurnClient::connect(addr, TransportType::UDP, "other", "pass").unwrap();
+        assert_eq!(server.allocations(), 2);
+        thread::sleep(Duration::from_millis(1500));
+        assert_eq!(server.allocations(), 1);
+        assert!(client.relayed_address().is_some());
+
+        let response = raw.authenticated(METHOD_REFRESH, vec![]);
+        assert_eq!(response.error_code(), Some(ERROR_ALLOCATION_MISMATCH));
+    }
+
+    #[test]
+    fn authentication_test() {
+        let (_server, addr) = start(config());
+        let mut client = RawClient::new(addr, "unknown");
+        let response = client.allocate();
+        assert_eq!(response.error_code(), Some(message::ERROR_UNAUTHORIZED));
+        assert!(response.nonce().is_some());
+
+        let mut config = config();
+        config.nonce_lifetime = Duration::from_millis(200);
+        let (_server, addr) = start(config);
+        let mut client = RawClient::new(addr, "user");
+        client.allocate();
+        thread::sleep(Duration::from_millis(300));
+        let response = client.authenticated(METHOD_REFRESH, vec![]);
+        assert_eq!(response.error_code(), Some(message::ERROR_STALE_NONCE));
+        let response = client.authenticated(METHOD_REFRESH, vec![]);
+        assert_eq!(response.class, MessageClass::SuccessResponse);
+    }
+
+    #[test]
+    fn tcp_test() {
+        let mut server = TurnServer::new(config());
+        let addr = server.listen_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut client = TurnClient::connect(addr, TransportType::TCP, "user", "pass").unwrap();
+        assert_eq!(server.allocations(), 1);
+        client.close();
+
+        // 接続が切れたらallocationも消える
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.allocations() > 0 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn relay_allocator_test() {
+        let allocator =
+            StaticRelayAllocator::with_port_range(IpAddr::V4(Ipv4Addr::LOCALHOST), 50000, 50100);
+        let client = "127.0.0.1:1".parse().unwrap();
+        let first = allocator.allocate(client).unwrap();
+        let second = allocator.allocate(client).unwrap();
+        let port = first.local_addr().unwrap().port();
+        assert!((50000..=50100).contains(&port));
+        assert_ne!(port, second.local_addr().unwrap().port());
+    }
+}